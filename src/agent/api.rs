@@ -1,12 +1,15 @@
 use base64;
 use crates::{Crate, GitHubRepo};
 use errors::*;
-use ex::Experiment;
+use ex::{ExStep, Experiment};
 use reqwest::{header, Client, Method, RequestBuilder, StatusCode};
 use results::TestResult;
 use serde::de::DeserializeOwned;
-use server::api_types::{AgentConfig, ApiResponse, CraterToken};
-use toolchain::Toolchain;
+use server::api_types::{
+    AgentConfig, ApiResponse, CrateAttempt, CrateMetadata, CraterToken, RunningLogTail,
+};
+use toolchain::{Toolchain, ToolchainKind};
+use util::Secret;
 
 lazy_static! {
     static ref USER_AGENT: String = format!("crater-agent/{}", ::GIT_REVISION.unwrap_or("unknown"));
@@ -120,6 +123,17 @@ impl AgentApi {
         })
     }
 
+    /// Token of the custom registry used by the experiment currently assigned to the agent.
+    pub fn registry_token(&self) -> Result<Option<Secret>> {
+        self.retry(|this| {
+            let token: Option<String> = this
+                .build_request(Method::Get, "registry-token")
+                .send()?
+                .to_api_response()?;
+            Ok(token.map(Secret::new))
+        })
+    }
+
     pub fn record_progress(
         &self,
         krate: &Crate,
@@ -148,6 +162,41 @@ impl AgentApi {
         })
     }
 
+    pub fn record_metadata(
+        &self,
+        krate: &Crate,
+        toolchain: &Toolchain,
+        metadata: &str,
+    ) -> Result<()> {
+        self.retry(|this| {
+            let _: bool = this
+                .build_request(Method::Post, "record-metadata")
+                .json(&CrateMetadata {
+                    krate: krate.clone(),
+                    toolchain: toolchain.clone(),
+                    metadata: metadata.to_string(),
+                })
+                .send()?
+                .to_api_response()?;
+            Ok(())
+        })
+    }
+
+    /// Record a new attempt of the step of the crate on the server, returning `false` if the
+    /// budget was already exhausted.
+    pub fn use_attempt(&self, krate: &Crate, step: ExStep, budget: u32) -> Result<bool> {
+        self.retry(|this| {
+            this.build_request(Method::Post, "use-attempt")
+                .json(&CrateAttempt {
+                    krate: krate.clone(),
+                    step,
+                    budget,
+                })
+                .send()?
+                .to_api_response()
+        })
+    }
+
     pub fn complete_experiment(&self) -> Result<()> {
         self.retry(|this| {
             let _: bool = this
@@ -158,6 +207,28 @@ impl AgentApi {
         })
     }
 
+    pub fn set_toolchain_kinds(&self, kinds: &[ToolchainKind]) -> Result<()> {
+        self.retry(|this| {
+            let _: bool = this
+                .build_request(Method::Post, "toolchain-kinds")
+                .json(&kinds)
+                .send()?
+                .to_api_response()?;
+            Ok(())
+        })
+    }
+
+    pub fn upload_log_tails(&self, tails: &[RunningLogTail]) -> Result<()> {
+        self.retry(|this| {
+            let _: bool = this
+                .build_request(Method::Post, "log-tails")
+                .json(&tails)
+                .send()?
+                .to_api_response()?;
+            Ok(())
+        })
+    }
+
     pub fn heartbeat(&self) -> Result<()> {
         self.retry(|this| {
             let _: bool = this
@@ -6,19 +6,24 @@ use config::Config;
 use errors::*;
 use ex::{self, Experiment};
 use file;
-use run_graph;
+use log::RunningLogs;
+use results::{HookedResults, ResultHook};
+use run_graph::{self, GraphHandle};
 use serde_json;
+use server::api_types::RunningLogTail;
 use std::time::Duration;
 use std::{fs, thread};
-use util;
+use toolchain::ToolchainKind;
+use util::{self, CancelToken};
 
 struct Agent {
     api: AgentApi,
+    name: String,
     config: Config,
 }
 
 impl Agent {
-    fn new(url: &str, token: &str) -> Result<Self> {
+    fn new(url: &str, token: &str, toolchain_kinds: &[ToolchainKind]) -> Result<Self> {
         info!("connecting to crater server {}...", url);
 
         let api = AgentApi::new(url, token);
@@ -27,8 +32,24 @@ impl Agent {
         info!("connected to the crater server!");
         info!("assigned agent name: {}", config.agent_name);
 
+        if toolchain_kinds.is_empty() {
+            info!("supported toolchain kinds: all");
+        } else {
+            info!(
+                "supported toolchain kinds: {}",
+                toolchain_kinds
+                    .iter()
+                    .map(|k| k.to_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        // Always send the kinds, to clear the restrictions of a previous run of the agent
+        api.set_toolchain_kinds(toolchain_kinds)?;
+
         Ok(Agent {
             api,
+            name: config.agent_name,
             config: config.crater_config,
         })
     }
@@ -45,7 +66,13 @@ impl Agent {
         let json = serde_json::to_string(&from_server)?;
         file::write_string(&ex::config_file(&from_server.name), &json)?;
 
-        Ok(Experiment::load(&from_server.name)?)
+        // The token isn't stored with the experiment, so it's fetched separately
+        let mut ex = Experiment::load(&from_server.name)?;
+        if ex.registry.is_some() {
+            ex.registry_token = self.api.registry_token()?;
+        }
+
+        Ok(ex)
     }
 }
 
@@ -60,16 +87,53 @@ fn run_heartbeat(url: &str, token: &str) {
     });
 }
 
-pub fn run(url: &str, token: &str, threads_count: usize) -> Result<()> {
-    let agent = Agent::new(url, token)?;
-    let db = results::ResultsUploader::new(&agent.api);
+/// Number of seconds between each upload of the tails of the running tasks' logs
+const LOG_TAILS_UPLOAD_INTERVAL: u64 = 10;
+
+fn run_log_tails_upload(url: &str, token: &str, logs: RunningLogs) {
+    let api = AgentApi::new(url, token);
+
+    thread::spawn(move || loop {
+        let tails = logs
+            .all()
+            .into_iter()
+            .map(|(krate, toolchain, lines)| RunningLogTail {
+                krate,
+                toolchain,
+                lines,
+            })
+            .collect::<Vec<_>>();
+        if let Err(e) = api
+            .upload_log_tails(&tails)
+            .chain_err(|| "failed to upload the log tails")
+        {
+            util::report_error(&e);
+        }
+        thread::sleep(Duration::from_secs(LOG_TAILS_UPLOAD_INTERVAL));
+    });
+}
+
+pub fn run(
+    url: &str,
+    token: &str,
+    threads_count: usize,
+    toolchain_kinds: &[ToolchainKind],
+    hooks: Vec<Box<ResultHook>>,
+) -> Result<()> {
+    let agent = Agent::new(url, token, toolchain_kinds)?;
+    let uploader = results::ResultsUploader::new(&agent.api);
+    let db = HookedResults::with_hooks(&uploader, hooks);
 
     run_heartbeat(url, token);
+    run_log_tails_upload(url, token, uploader.running_logs());
 
     loop {
-        let ex = agent.experiment()?;
+        let mut ex = agent.experiment()?;
+        ex.shuffle_crates_for_agent(&agent.name);
 
-        let result = run_graph::run_ex(&ex, &db, threads_count, &agent.config);
+        let cancel = CancelToken::on_signals();
+        let graph = GraphHandle::default();
+        let result = run_graph::run_ex(&ex, &db, threads_count, &agent.config, &cancel, &graph);
 
         // Ensure local data is cleaned up even if the run crashed
         ex::delete_all_target_dirs(&ex.name)?;
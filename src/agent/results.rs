@@ -1,8 +1,8 @@
 use agent::api::AgentApi;
 use crates::{Crate, GitHubRepo};
 use errors::*;
-use ex::Experiment;
-use log;
+use ex::{ExStep, Experiment};
+use log::RunningLogs;
 use results::{TestResult, WriteResults};
 use std::io::Read;
 use std::ops::DerefMut;
@@ -13,6 +13,7 @@ use toolchain::Toolchain;
 pub struct ResultsUploader<'a> {
     api: &'a AgentApi,
     shas: Arc<Mutex<Vec<(GitHubRepo, String)>>>,
+    logs: RunningLogs,
 }
 
 impl<'a> ResultsUploader<'a> {
@@ -20,8 +21,14 @@ impl<'a> ResultsUploader<'a> {
         ResultsUploader {
             api,
             shas: Arc::new(Mutex::new(Vec::new())),
+            logs: RunningLogs::default(),
         }
     }
+
+    /// Tails of the logs of the tasks whose results are being recorded.
+    pub fn running_logs(&self) -> RunningLogs {
+        self.logs.clone()
+    }
 }
 
 impl<'a> WriteResults for ResultsUploader<'a> {
@@ -54,7 +61,7 @@ impl<'a> WriteResults for ResultsUploader<'a> {
         F: FnOnce() -> Result<TestResult>,
     {
         let mut log_file = ::tempfile::NamedTempFile::new()?;
-        let result = log::redirect(log_file.path(), f)?;
+        let result = self.logs.redirect(krate, toolchain, log_file.path(), f)?;
 
         let mut buffer = Vec::new();
         log_file.read_to_end(&mut buffer)?;
@@ -67,4 +74,25 @@ impl<'a> WriteResults for ResultsUploader<'a> {
 
         Ok(result)
     }
+
+    fn record_metadata(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        metadata: &str,
+    ) -> Result<()> {
+        info!("sending metadata to the crater server...");
+        self.api.record_metadata(krate, toolchain, metadata)
+    }
+
+    fn use_attempt(
+        &self,
+        _ex: &Experiment,
+        krate: &Crate,
+        step: ExStep,
+        budget: u32,
+    ) -> Result<bool> {
+        self.api.use_attempt(krate, step, budget)
+    }
 }
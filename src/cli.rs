@@ -15,14 +15,15 @@ use crater::crates::Crate;
 use crater::docker;
 use crater::errors::*;
 use crater::ex;
-use crater::ex::{ExCapLints, ExCrateSelect, ExMode, Experiment};
+use crater::ex::{ExCapLints, ExCrateSelect, ExMode, ExStep, Experiment};
 use crater::ex_run;
 use crater::lists;
 use crater::report;
-use crater::results::FileDB;
-use crater::run_graph;
+use crater::results::{FileDB, HookedResults, IceDetector, StdoutResults};
+use crater::run_graph::{self, GraphHandle};
 use crater::server;
-use crater::toolchain::{Toolchain, MAIN_TOOLCHAIN};
+use crater::toolchain::{Toolchain, ToolchainKind, MAIN_TOOLCHAIN};
+use crater::util::CancelToken;
 use std::env;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -118,6 +119,29 @@ pub enum Crater {
             possible_values_raw = "ExCapLints::possible_values()"
         )]
         cap_lints: ExCapLints,
+        #[structopt(name = "seed", long = "seed")]
+        seed: Option<u64>,
+        #[structopt(
+            name = "force-step",
+            long = "force-step",
+            possible_values_raw = "ExStep::possible_values()"
+        )]
+        force_steps: Vec<ExStep>,
+        #[structopt(
+            name = "allow-same-toolchains",
+            long = "allow-same-toolchains"
+        )]
+        allow_same_toolchains: bool,
+        #[structopt(name = "pre-build", long = "pre-build")]
+        pre_build: Option<String>,
+        #[structopt(name = "capture-metadata", long = "capture-metadata")]
+        capture_metadata: bool,
+        #[structopt(name = "component", long = "component")]
+        components: Vec<String>,
+        #[structopt(name = "expected-regression", long = "expected-regression")]
+        expected_regressions: Vec<Crate>,
+        #[structopt(name = "control-index", long = "control-index", default_value = "0")]
+        control_index: usize,
     },
 
     #[structopt(
@@ -177,6 +201,14 @@ pub enum Crater {
             default_value = "1"
         )]
         threads: usize,
+        #[structopt(name = "prepare-only", long = "prepare-only")]
+        prepare_only: bool,
+        #[structopt(
+            name = "stdout",
+            long = "stdout",
+            help = "Print the results instead of storing them, rerunning all the tasks."
+        )]
+        stdout: bool,
     },
 
     #[structopt(
@@ -213,6 +245,12 @@ pub enum Crater {
     #[structopt(name = "server")]
     Server,
 
+    #[structopt(
+        name = "validate-experiments",
+        about = "check that all the experiments stored by the server can be loaded"
+    )]
+    ValidateExperiments,
+
     #[structopt(name = "agent")]
     Agent {
         #[structopt(name = "url")]
@@ -226,6 +264,12 @@ pub enum Crater {
             default_value = "1"
         )]
         threads: usize,
+        #[structopt(
+            name = "toolchain-kind",
+            long = "toolchain-kind",
+            possible_values_raw = "ToolchainKind::possible_values()"
+        )]
+        toolchain_kinds: Vec<ToolchainKind>,
     },
 
     #[structopt(
@@ -257,6 +301,14 @@ impl Crater {
                 ref mode,
                 ref crates,
                 ref cap_lints,
+                seed,
+                ref force_steps,
+                allow_same_toolchains,
+                ref pre_build,
+                capture_metadata,
+                ref components,
+                ref expected_regressions,
+                control_index,
             } => {
                 let config = Config::load()?;
 
@@ -266,7 +318,15 @@ impl Crater {
                         toolchains: [tc1.clone(), tc2.clone()],
                         mode: *mode,
                         crates: *crates,
+                        sample_seed: seed,
                         cap_lints: *cap_lints,
+                        force_steps: force_steps.clone(),
+                        allow_same_toolchains,
+                        pre_build: pre_build.clone(),
+                        capture_metadata,
+                        components: components.clone(),
+                        expected_regressions: expected_regressions.clone(),
+                        control_index,
                     },
                     &config,
                 )?;
@@ -288,11 +348,28 @@ impl Crater {
                 ref tc,
                 ref krate,
             } => ex_run::delete_result(&ex.0, tc.as_ref(), krate)?,
-            Crater::RunGraph { ref ex, threads } => {
-                let config = Config::load()?;
+            Crater::RunGraph {
+                ref ex,
+                threads,
+                prepare_only,
+                stdout,
+            } => {
+                let mut config = Config::load()?;
+                config.prepare_only |= prepare_only;
                 let experiment = Experiment::load(&ex.0)?;
-                let db = FileDB::default();
-                run_graph::run_ex(&experiment, &db, threads, &config)?;
+                let cancel = CancelToken::on_signals();
+                let graph = GraphHandle::default();
+                if stdout {
+                    let db = StdoutResults::default();
+                    let mut db = HookedResults::new(&db);
+                    db.register(IceDetector);
+                    run_graph::run_ex(&experiment, &db, threads, &config, &cancel, &graph)?;
+                } else {
+                    let db = FileDB::default();
+                    let mut db = HookedResults::new(&db);
+                    db.register(IceDetector);
+                    run_graph::run_ex(&experiment, &db, threads, &config, &cancel, &graph)?;
+                }
             }
             Crater::GenReport { ref ex, ref dest } => {
                 let config = Config::load()?;
@@ -303,6 +380,8 @@ impl Crater {
                     &ex,
                     &report::FileWriter::create(dest.0.clone())?,
                     &config,
+                    ::GIT_REVISION,
+                    None,
                 )?;
             }
             Crater::PublishReport {
@@ -326,18 +405,30 @@ impl Crater {
                     &ex,
                     &report::S3Writer::create(client, s3_prefix)?,
                     &config,
+                    ::GIT_REVISION,
+                    None,
                 )?;
             }
             Crater::Server => {
                 let config = Config::load()?;
                 server::run(config)?;
             }
+            Crater::ValidateExperiments => {
+                server::validate_experiments()?;
+            }
             Crater::Agent {
                 ref url,
                 ref token,
                 threads,
+                ref toolchain_kinds,
             } => {
-                agent::run(url, token, threads)?;
+                agent::run(
+                    url,
+                    token,
+                    threads,
+                    toolchain_kinds,
+                    vec![Box::new(IceDetector)],
+                )?;
             }
             Crater::DumpTasksGraph { ref dest, ref ex } => {
                 let config = Config::load()?;
@@ -1,5 +1,6 @@
 use crates::Crate;
 use errors::*;
+use ex::{ExCapLints, ExMode};
 use regex::Regex;
 use serde_regex;
 use std::collections::HashMap;
@@ -9,6 +10,20 @@ use util::Size;
 
 static CONFIG_FILE: &'static str = "config.toml";
 
+string_enum!(pub enum ToolchainOrder {
+    StartFirst => "start-first",
+    EndFirst => "end-first",
+});
+
+string_enum!(pub enum Scheduling {
+    DepthFirst => "depth-first",
+    BreadthFirst => "breadth-first",
+});
+
+string_enum!(pub enum SandboxKind {
+    Docker => "docker",
+});
+
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct CrateConfig {
@@ -22,6 +37,9 @@ pub struct CrateConfig {
     pub update_lockfile: bool,
     #[serde(default = "default_false")]
     pub broken: bool,
+    /// Extra arguments passed to the test binaries, after the `--` of `cargo test`
+    #[serde(default)]
+    pub test_args: Vec<String>,
 }
 
 fn default_false() -> bool {
@@ -33,6 +51,24 @@ fn default_false() -> bool {
 pub struct ServerConfig {
     pub bot_acl: Vec<String>,
     pub labels: ServerLabels,
+    #[serde(default)]
+    pub report_retries: u32,
+    /// Lints cap used by new experiments that don't specify one
+    #[serde(default)]
+    pub default_cap_lints: Option<ExCapLints>,
+    /// Maximum number of crates accepted in an experiment
+    #[serde(default)]
+    pub max_crates: Option<usize>,
+    /// Number of days the logs of completed experiments are kept for
+    #[serde(default)]
+    pub log_retention_days: Option<i64>,
+    /// Minimum number of seconds between the progress updates posted on the GitHub issues, if
+    /// unset the progress is not posted
+    #[serde(default)]
+    pub progress_comment_interval: Option<u64>,
+    /// Number of reports generated concurrently, one if unset
+    #[serde(default)]
+    pub report_workers: Option<usize>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -55,6 +91,13 @@ pub struct DemoCrates {
 #[serde(rename_all = "kebab-case")]
 pub struct SandboxConfig {
     pub memory_limit: Size,
+    #[serde(default = "default_false")]
+    pub tag_output_streams: bool,
+    #[serde(default = "default_false")]
+    pub offline_builds: bool,
+    /// Sandbox the builds are executed in, Docker if unset
+    #[serde(default)]
+    pub kind: Option<SandboxKind>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -65,6 +108,43 @@ pub struct Config {
     pub github_repos: HashMap<String, CrateConfig>,
     pub server: ServerConfig,
     pub sandbox: SandboxConfig,
+    /// Maximum number of crates kept in the tasks graph at the same time
+    #[serde(default)]
+    pub tasks_graph_window: Option<usize>,
+    /// Toolchain whose builds are dispatched first, if unset the order is not specified
+    #[serde(default)]
+    pub toolchain_order: Option<ToolchainOrder>,
+    /// How the tasks of different crates are dispatched, depth-first if unset
+    #[serde(default)]
+    pub scheduling: Option<Scheduling>,
+    /// Maximum number of toolchains prepared at the same time by this process
+    #[serde(default)]
+    pub max_preparing_toolchains: Option<usize>,
+    /// Number of seconds between each save of the tasks graph progress, if enabled
+    #[serde(default)]
+    pub graph_autosave_interval: Option<u64>,
+    /// Number of seconds all the workers can wait with no running tasks before the run fails
+    #[serde(default)]
+    pub deadlock_grace_period: Option<u64>,
+    /// Maximum number of times each step of a crate is executed across the restarts of an
+    /// experiment, counting the builds with each toolchain, before the crate is marked as failed
+    #[serde(default)]
+    pub crate_attempts: Option<u32>,
+    /// Only run the prepare step of the crates, to prefetch their sources ahead of a run
+    #[serde(default)]
+    pub prepare_only: bool,
+    /// Run the crates after their dependencies, according to the crates.io index
+    #[serde(default)]
+    pub dependency_order: bool,
+    /// Build the crates only once when both toolchains of an experiment are identical
+    #[serde(default)]
+    pub dedupe_toolchains: bool,
+    /// Remove the target directories of an experiment as soon as it's completed
+    #[serde(default)]
+    pub cleanup_target_dirs: bool,
+    /// Average size of the target directory of a crate built with one toolchain, for each mode
+    #[serde(default)]
+    pub target_dir_sizes: HashMap<ExMode, Size>,
 }
 
 impl Config {
@@ -72,7 +152,18 @@ impl Config {
         let mut buffer = String::new();
         File::open(CONFIG_FILE)?.read_to_string(&mut buffer)?;
 
-        Ok(::toml::from_str(&buffer)?)
+        let config: Config = ::toml::from_str(&buffer)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for (name, krate) in self.crates.iter().chain(self.github_repos.iter()) {
+            validate_test_args(&krate.test_args)
+                .chain_err(|| format!("invalid test args for {}", name))?;
+        }
+
+        Ok(())
     }
 
     fn crate_config(&self, c: &Crate) -> Option<&CrateConfig> {
@@ -104,6 +195,12 @@ impl Config {
         self.crate_config(c).map(|c| c.broken).unwrap_or(false)
     }
 
+    pub fn test_args(&self, c: &Crate) -> Vec<String> {
+        self.crate_config(c)
+            .map(|c| c.test_args.clone())
+            .unwrap_or_default()
+    }
+
     pub fn demo_crates(&self) -> &DemoCrates {
         &self.demo_crates
     }
@@ -121,9 +218,30 @@ impl Default for Config {
             github_repos: HashMap::new(),
             sandbox: SandboxConfig {
                 memory_limit: Size::Gigabytes(2),
+                tag_output_streams: false,
+                offline_builds: false,
+                kind: None,
             },
+            tasks_graph_window: None,
+            toolchain_order: None,
+            scheduling: None,
+            max_preparing_toolchains: None,
+            graph_autosave_interval: None,
+            deadlock_grace_period: None,
+            crate_attempts: None,
+            prepare_only: false,
+            dependency_order: false,
+            dedupe_toolchains: false,
+            cleanup_target_dirs: false,
+            target_dir_sizes: HashMap::new(),
             server: ServerConfig {
                 bot_acl: Vec::new(),
+                report_retries: 0,
+                default_cap_lints: None,
+                max_crates: None,
+                log_retention_days: None,
+                progress_comment_interval: None,
+                report_workers: None,
                 labels: ServerLabels {
                     remove: Regex::new("^$").unwrap(),
                     experiment_queued: "".into(),
@@ -134,6 +252,22 @@ impl Default for Config {
     }
 }
 
+/// The test args are added to the shell command running `cargo test` after the `--` separator, so
+/// only plain flags and values are allowed to avoid passing anything else to cargo or the shell.
+fn validate_test_args(args: &[String]) -> Result<()> {
+    for arg in args {
+        if arg == "--" {
+            bail!("the `--` separator is added automatically");
+        }
+        let allowed = |c: char| c.is_ascii_alphanumeric() || "-_=.,:/+".contains(c);
+        if arg.is_empty() || !arg.chars().all(allowed) {
+            bail!("unsupported test argument: {:?}", arg);
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::Config;
@@ -1,17 +1,26 @@
+use chrono::{DateTime, Utc};
 use dirs::{CRATES_DIR, GH_MIRRORS_DIR};
 use dl;
 use errors::*;
 use flate2::read::GzDecoder;
+use semver::VersionReq;
+use serde_json;
+use std::cmp::Ordering;
 use std::fmt;
-use std::fs;
-use std::io::Read;
+use std::fs::{self, File};
+use std::io::{self, Read};
 use std::path::Path;
 use std::path::PathBuf;
 use std::str::FromStr;
 use tar::Archive;
+use url::form_urlencoded;
 use util;
 
 const CRATES_ROOT: &str = "https://crates-io.s3-us-west-1.amazonaws.com/crates";
+const CRATES_IO_API: &str = "https://crates.io/api/v1/crates";
+const CRATES_IO_PER_PAGE: usize = 100;
+/// Number of most downloaded crates considered by `weighted_sample`
+const WEIGHTED_SAMPLE_CANDIDATES: usize = 5000;
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Clone)]
 pub struct GitHubRepo {
@@ -64,7 +73,55 @@ pub enum Crate {
     GitHub(GitHubRepo),
 }
 
+string_enum!(pub enum CrateSourceKind {
+    Registry => "registry",
+    GitHub => "github",
+});
+
 impl Crate {
+    /// Name of the crate, or of the repository for GitHub crates.
+    pub fn name(&self) -> &str {
+        match *self {
+            Crate::Registry(ref details) => &details.name,
+            Crate::GitHub(ref repo) => &repo.name,
+        }
+    }
+
+    /// Version of the crate, if the crate comes from a registry.
+    pub fn version(&self) -> Option<&str> {
+        match *self {
+            Crate::Registry(ref details) => Some(&details.version),
+            Crate::GitHub(_) => None,
+        }
+    }
+
+    /// Copy of the crate without the SemVer build metadata in its version, as it doesn't identify
+    /// a different release of the crate. Pre-release identifiers are kept.
+    pub fn normalized(&self) -> Crate {
+        match *self {
+            Crate::Registry(ref details) => Crate::Registry(RegistryCrate {
+                name: details.name.clone(),
+                version: details.version.splitn(2, '+').next().unwrap().to_string(),
+            }),
+            Crate::GitHub(_) => self.clone(),
+        }
+    }
+
+    pub fn source_kind(&self) -> CrateSourceKind {
+        match *self {
+            Crate::Registry(_) => CrateSourceKind::Registry,
+            Crate::GitHub(_) => CrateSourceKind::GitHub,
+        }
+    }
+
+    /// Place the source code of the crate is fetched from.
+    pub fn source(&self) -> &CrateSource {
+        match *self {
+            Crate::Registry(ref details) => details,
+            Crate::GitHub(ref repo) => repo,
+        }
+    }
+
     pub fn registry(&self) -> Option<&RegistryCrate> {
         if let Crate::Registry(ref krate) = *self {
             Some(krate)
@@ -125,25 +182,360 @@ impl FromStr for Crate {
     }
 }
 
-pub fn prepare_crate(krate: &Crate) -> Result<()> {
-    let dir = krate.dir();
-    match *krate {
-        Crate::Registry(ref details) => {
-            // crates.io doesn't rate limit. Go fast
-            dl_registry(&details.name, &details.version, &dir)
-                .chain_err(|| format!("unable to download {}", krate))?;
+/// Rule to pick the crates of an experiment, resolved from the crates.io metadata.
+#[derive(Debug, PartialEq, Clone)]
+pub enum CrateSelector {
+    TopDownloads(usize),
+    Category(String),
+    Keyword(String),
+    RecentlyUpdated(usize),
+}
+
+impl CrateSelector {
+    fn query(&self) -> String {
+        let encode = |value: &str| -> String {
+            form_urlencoded::byte_serialize(value.as_bytes()).collect()
+        };
+        match *self {
+            CrateSelector::TopDownloads(_) => "sort=downloads".into(),
+            CrateSelector::Category(ref category) => format!("category={}", encode(category)),
+            CrateSelector::Keyword(ref keyword) => format!("keyword={}", encode(keyword)),
+            CrateSelector::RecentlyUpdated(_) => "sort=recent-updates".into(),
+        }
+    }
+
+    fn limit(&self) -> Option<usize> {
+        match *self {
+            CrateSelector::TopDownloads(count) | CrateSelector::RecentlyUpdated(count) => {
+                Some(count)
+            }
+            CrateSelector::Category(_) | CrateSelector::Keyword(_) => None,
         }
-        Crate::GitHub(ref repo) => {
-            info!(
-                "cloning GitHub repo {} to {}...",
-                repo.slug(),
-                dir.display()
-            );
-            util::copy_dir(&repo.mirror_dir(), &dir)?;
+    }
+}
+
+impl fmt::Display for CrateSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CrateSelector::TopDownloads(count) => write!(f, "top-downloads:{}", count),
+            CrateSelector::Category(ref category) => write!(f, "category:{}", category),
+            CrateSelector::Keyword(ref keyword) => write!(f, "keyword:{}", keyword),
+            CrateSelector::RecentlyUpdated(count) => write!(f, "recently-updated:{}", count),
         }
     }
+}
 
-    Ok(())
+impl FromStr for CrateSelector {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let mut parts = input.splitn(2, ':');
+        let kind = parts.next().unwrap();
+        let value = parts
+            .next()
+            .ok_or_else(|| format!("missing value in crate selector: {}", input))?;
+
+        Ok(match kind {
+            "top-downloads" => CrateSelector::TopDownloads(value.parse()?),
+            "category" => CrateSelector::Category(value.to_string()),
+            "keyword" => CrateSelector::Keyword(value.to_string()),
+            "recently-updated" => CrateSelector::RecentlyUpdated(value.parse()?),
+            _ => bail!("unknown crate selector: {}", kind),
+        })
+    }
+}
+
+string_enum!(pub enum PriorResult {
+    Failed => "failed",
+    Passed => "passed",
+    Regressed => "regressed",
+});
+
+/// Rule to pick the crates of an experiment from their results in a previous experiment, such as
+/// `failed:foo`. With a `!` prefix all the crates except the matching ones are picked.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PriorRunSelector {
+    pub experiment: String,
+    pub result: PriorResult,
+    pub exclude: bool,
+}
+
+impl fmt::Display for PriorRunSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.exclude {
+            write!(f, "!")?;
+        }
+        write!(f, "{}:{}", self.result.to_str(), self.experiment)
+    }
+}
+
+impl FromStr for PriorRunSelector {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let exclude = input.starts_with('!');
+        let rule = if exclude { &input[1..] } else { input };
+
+        let mut parts = rule.splitn(2, ':');
+        let result = parts.next().unwrap().parse()?;
+        let experiment = parts.next().unwrap_or("");
+        if experiment.is_empty() {
+            bail!("missing experiment in prior run selector: {}", input);
+        }
+
+        Ok(PriorRunSelector {
+            experiment: experiment.to_string(),
+            result,
+            exclude,
+        })
+    }
+}
+
+/// Rust version declared in the `rust-version` field of a crate's manifest, such as `1.56`. The
+/// missing components are considered to be zero.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub struct RustVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl fmt::Display for RustVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for RustVersion {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let parts = input
+            .trim()
+            .split('.')
+            .map(|part| part.parse::<u64>())
+            .collect::<::std::result::Result<Vec<_>, _>>()
+            .chain_err(|| format!("invalid rust version: {}", input))?;
+        if parts.len() > 3 {
+            bail!("invalid rust version: {}", input);
+        }
+
+        let part = |idx: usize| parts.get(idx).cloned().unwrap_or(0);
+        Ok(RustVersion {
+            major: part(0),
+            minor: part(1),
+            patch: part(2),
+        })
+    }
+}
+
+/// Registry crate whose version is resolved from the index when the experiment is created, picking
+/// the highest published version matching the requirement. Written as `name@requirement`, for
+/// example `serde@^1.0`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CrateRequirement {
+    pub name: String,
+    pub req: VersionReq,
+}
+
+impl fmt::Display for CrateRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}", self.name, self.req)
+    }
+}
+
+impl FromStr for CrateRequirement {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let mut parts = input.splitn(2, '@');
+        let name = parts.next().unwrap_or("");
+        let req = parts
+            .next()
+            .ok_or_else(|| format!("missing version requirement for crate {}", input))?;
+        if name.is_empty() {
+            bail!("missing crate name in {}", input);
+        }
+
+        Ok(CrateRequirement {
+            name: name.to_string(),
+            req: VersionReq::parse(req)
+                .map_err(|e| format!("invalid version requirement {}: {}", req, e))?,
+        })
+    }
+}
+
+/// List of crate requirements, separated by `;` since the requirements can contain commas.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CrateRequirements(pub Vec<CrateRequirement>);
+
+impl FromStr for CrateRequirements {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        Ok(CrateRequirements(
+            input
+                .split(';')
+                .filter(|part| !part.trim().is_empty())
+                .map(|part| part.trim().parse())
+                .collect::<Result<_>>()?,
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+struct CratesIoPage {
+    crates: Vec<CratesIoCrate>,
+}
+
+#[derive(Deserialize)]
+struct CratesIoCrate {
+    name: String,
+    max_version: String,
+    downloads: u64,
+    updated_at: DateTime<Utc>,
+}
+
+/// Fetch the list of crates matching the selector from the crates.io API.
+pub fn select(spec: &CrateSelector) -> Result<Vec<Crate>> {
+    let candidates = fetch_crates_io(&spec.query(), spec.limit())?;
+    Ok(resolve_selector(spec, candidates))
+}
+
+/// Sample `count` crates with a probability proportional to their downloads, picking them among
+/// the most downloaded crates. The same seed always returns the same sample.
+pub fn weighted_sample(count: usize, seed: u64) -> Result<Vec<Crate>> {
+    let candidates = fetch_crates_io("sort=downloads", Some(WEIGHTED_SAMPLE_CANDIDATES))?;
+    Ok(weighted_sample_from(candidates, count, seed))
+}
+
+fn weighted_sample_from(
+    mut candidates: Vec<CratesIoCrate>,
+    count: usize,
+    seed: u64,
+) -> Vec<Crate> {
+    use rand::{Isaac64Rng, Rng, SeedableRng};
+
+    // The list is sorted first to avoid depending on the order of the input
+    candidates.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // Weighted sampling without replacement (Efraimidis-Spirakis): each crate gets the key
+    // `u^(1/weight)` with `u` uniform in [0, 1), and the crates with the biggest keys are picked.
+    // The logarithm of the key is used to avoid underflows with big weights.
+    let mut rng = Isaac64Rng::from_seed(&[seed]);
+    let mut keyed = candidates
+        .into_iter()
+        .map(|krate| {
+            let weight = (krate.downloads + 1) as f64;
+            (rng.gen::<f64>().ln() / weight, krate)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    keyed.truncate(count);
+
+    let mut sample = keyed
+        .into_iter()
+        .map(|(_, krate)| {
+            Crate::Registry(RegistryCrate {
+                name: krate.name,
+                version: krate.max_version,
+            })
+        })
+        .collect::<Vec<_>>();
+    sample.sort();
+    sample
+}
+
+fn fetch_crates_io(query: &str, limit: Option<usize>) -> Result<Vec<CratesIoCrate>> {
+    let mut candidates = Vec::new();
+    for page in 1.. {
+        let url = format!(
+            "{}?{}&per_page={}&page={}",
+            CRATES_IO_API, query, CRATES_IO_PER_PAGE, page
+        );
+        info!("downloading {}", url);
+
+        let mut body = String::new();
+        dl::download(&url)?.read_to_string(&mut body)?;
+        let page: CratesIoPage = serde_json::from_str(&body)?;
+
+        let fetched = page.crates.len();
+        candidates.extend(page.crates.into_iter());
+
+        let enough = limit.map(|l| candidates.len() >= l).unwrap_or(false);
+        if fetched < CRATES_IO_PER_PAGE || enough {
+            break;
+        }
+    }
+
+    Ok(candidates)
+}
+
+fn resolve_selector(spec: &CrateSelector, mut candidates: Vec<CratesIoCrate>) -> Vec<Crate> {
+    match *spec {
+        CrateSelector::TopDownloads(count) => {
+            candidates.sort_by(|a, b| b.downloads.cmp(&a.downloads));
+            candidates.truncate(count);
+        }
+        CrateSelector::RecentlyUpdated(count) => {
+            candidates.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+            candidates.truncate(count);
+        }
+        // The filtering was already done by crates.io
+        CrateSelector::Category(_) | CrateSelector::Keyword(_) => {}
+    }
+
+    candidates
+        .into_iter()
+        .map(|krate| {
+            Crate::Registry(RegistryCrate {
+                name: krate.name,
+                version: krate.max_version,
+            })
+        })
+        .collect()
+}
+
+/// Location the source code of crates can be fetched from. New kinds of crates only need to
+/// implement this trait to be prepared.
+pub trait CrateSource {
+    /// Identifier of the crate in this source, used in the logs.
+    fn id(&self) -> String;
+
+    /// Fetch the source code of the crate into `dest`.
+    fn fetch_into(&self, dest: &Path) -> Result<()>;
+}
+
+impl CrateSource for RegistryCrate {
+    fn id(&self) -> String {
+        format!("{}-{}", self.name, self.version)
+    }
+
+    fn fetch_into(&self, dest: &Path) -> Result<()> {
+        // crates.io doesn't rate limit. Go fast
+        dl_registry(&self.name, &self.version, dest)
+    }
+}
+
+impl CrateSource for GitHubRepo {
+    fn id(&self) -> String {
+        self.slug()
+    }
+
+    fn fetch_into(&self, dest: &Path) -> Result<()> {
+        info!("cloning GitHub repo {} to {}...", self.slug(), dest.display());
+        util::copy_dir(&self.mirror_dir(), dest)
+    }
+}
+
+pub fn prepare_crate(krate: &Crate) -> Result<()> {
+    prepare_source(krate.source(), &krate.dir())
+}
+
+fn prepare_source(source: &CrateSource, dest: &Path) -> Result<()> {
+    source
+        .fetch_into(dest)
+        .chain_err(|| format!("unable to download {}", source.id()))
 }
 
 fn dl_registry(name: &str, vers: &str, dir: &Path) -> Result<()> {
@@ -158,20 +550,36 @@ fn dl_registry(name: &str, vers: &str, dir: &Path) -> Result<()> {
     }
     info!("downloading crate {}-{} to {}", name, vers, dir.display());
     let url = format!("{0}/{1}/{1}-{2}.crate", CRATES_ROOT, name, vers);
-    let bin = dl::download(&url).chain_err(|| format!("unable to download {}", url))?;
+    let mut archive = dir.as_os_str().to_os_string();
+    archive.push(".crate");
+    let archive = PathBuf::from(archive);
+
+    if let Some(parent) = archive.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    dl::download_resumable(&url, &archive, check_crate_archive)
+        .chain_err(|| format!("unable to download {}", url))?;
 
     fs::create_dir_all(&dir)?;
 
-    let mut tar = Archive::new(GzDecoder::new(bin));
+    let mut tar = Archive::new(GzDecoder::new(File::open(&archive)?));
     let r = unpack_without_first_dir(&mut tar, dir).chain_err(|| "unable to unpack crate tarball");
 
     if r.is_err() {
         let _ = util::remove_dir_all(dir);
     }
+    let _ = fs::remove_file(&archive);
 
     r
 }
 
+/// Ensure the downloaded crate is a complete gzip stream, whose checksum matches its content.
+fn check_crate_archive(path: &Path) -> Result<()> {
+    let mut decoder = GzDecoder::new(File::open(path)?);
+    io::copy(&mut decoder, &mut io::sink()).chain_err(|| "corrupt crate archive")?;
+    Ok(())
+}
+
 fn unpack_without_first_dir<R: Read>(archive: &mut Archive<R>, path: &Path) -> Result<()> {
     let entries = archive.entries()?;
     for entry in entries {
@@ -193,3 +601,197 @@ fn unpack_without_first_dir<R: Read>(archive: &mut Archive<R>, path: &Path) -> R
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        prepare_source, resolve_selector, weighted_sample_from, CratesIoCrate, CratesIoPage, Crate,
+        CrateSelector, CrateSource, CrateSourceKind, GitHubRepo, PriorResult, PriorRunSelector,
+        RegistryCrate,
+    };
+    use chrono::Utc;
+    use errors::*;
+    use serde_json;
+    use std::cell::RefCell;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_crate_accessors() {
+        let reg = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0.0".into(),
+        });
+        assert_eq!(reg.name(), "lazy_static");
+        assert_eq!(reg.version(), Some("1.0.0"));
+        assert_eq!(reg.source_kind(), CrateSourceKind::Registry);
+        assert_eq!(reg.source().id(), "lazy_static-1.0.0");
+
+        let gh = Crate::GitHub(GitHubRepo {
+            org: "brson".into(),
+            name: "hello-rs".into(),
+        });
+        assert_eq!(gh.name(), "hello-rs");
+        assert_eq!(gh.version(), None);
+        assert_eq!(gh.source_kind(), CrateSourceKind::GitHub);
+        assert_eq!(gh.source().id(), "brson/hello-rs");
+        assert_eq!(gh.normalized(), gh);
+    }
+
+    #[test]
+    fn test_normalized_crate() {
+        let krate = |version: &str| {
+            Crate::Registry(RegistryCrate {
+                name: "foo".into(),
+                version: version.into(),
+            })
+        };
+
+        assert_eq!(krate("1.0.0+meta").normalized(), krate("1.0.0"));
+        assert_eq!(krate("1.0.0").normalized(), krate("1.0.0"));
+        assert_eq!(krate("1.0.0-beta.1+meta").normalized(), krate("1.0.0-beta.1"));
+        assert_ne!(krate("1.0.0-beta.1").normalized(), krate("1.0.0"));
+    }
+
+    #[derive(Default)]
+    struct StubSource {
+        fetched: RefCell<Vec<PathBuf>>,
+    }
+
+    impl CrateSource for StubSource {
+        fn id(&self) -> String {
+            "stub".into()
+        }
+
+        fn fetch_into(&self, dest: &Path) -> Result<()> {
+            self.fetched.borrow_mut().push(dest.to_path_buf());
+            Ok(())
+        }
+    }
+
+    struct BrokenSource;
+
+    impl CrateSource for BrokenSource {
+        fn id(&self) -> String {
+            "broken".into()
+        }
+
+        fn fetch_into(&self, _dest: &Path) -> Result<()> {
+            bail!("server unreachable");
+        }
+    }
+
+    #[test]
+    fn test_prepare_custom_source() {
+        let source = StubSource::default();
+        prepare_source(&source, Path::new("/crates/stub")).unwrap();
+        assert_eq!(
+            *source.fetched.borrow(),
+            vec![PathBuf::from("/crates/stub")]
+        );
+
+        let err = prepare_source(&BrokenSource, Path::new("/crates/broken")).unwrap_err();
+        assert_eq!(err.to_string(), "unable to download broken");
+    }
+
+    #[test]
+    fn test_crate_selector_from_str() {
+        assert_eq!(
+            "top-downloads:100".parse::<CrateSelector>().unwrap(),
+            CrateSelector::TopDownloads(100)
+        );
+        assert_eq!(
+            "category:parsing".parse::<CrateSelector>().unwrap(),
+            CrateSelector::Category("parsing".into())
+        );
+        assert!("top-downloads:many".parse::<CrateSelector>().is_err());
+        assert!("stars:10".parse::<CrateSelector>().is_err());
+        assert!("keyword".parse::<CrateSelector>().is_err());
+    }
+
+    #[test]
+    fn test_prior_run_selector_from_str() {
+        assert_eq!(
+            "!regressed:pr-1234".parse::<PriorRunSelector>().unwrap(),
+            PriorRunSelector {
+                experiment: "pr-1234".into(),
+                result: PriorResult::Regressed,
+                exclude: true,
+            }
+        );
+        assert_eq!(
+            "failed:foo".parse::<PriorRunSelector>().unwrap().to_string(),
+            "failed:foo"
+        );
+        assert!("failed".parse::<PriorRunSelector>().is_err());
+        assert!("flaky:foo".parse::<PriorRunSelector>().is_err());
+    }
+
+    #[test]
+    fn test_resolve_top_downloads() {
+        let page: CratesIoPage = serde_json::from_str(
+            r#"{"crates": [
+                {"name": "bitflags", "max_version": "1.0.4", "downloads": 500,
+                 "updated_at": "2018-07-01T10:00:00Z"},
+                {"name": "rand", "max_version": "0.5.5", "downloads": 900,
+                 "updated_at": "2018-08-01T10:00:00Z"},
+                {"name": "tiny", "max_version": "0.1.0", "downloads": 10,
+                 "updated_at": "2018-09-01T10:00:00Z"},
+                {"name": "libc", "max_version": "0.2.43", "downloads": 1000,
+                 "updated_at": "2018-06-01T10:00:00Z"}
+            ]}"#,
+        ).unwrap();
+
+        let krate = |name: &str, version: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: version.into(),
+            })
+        };
+        assert_eq!(
+            resolve_selector(&CrateSelector::TopDownloads(3), page.crates),
+            vec![
+                krate("libc", "0.2.43"),
+                krate("rand", "0.5.5"),
+                krate("bitflags", "1.0.4"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_weighted_sample() {
+        let candidates = || {
+            vec![("popular", 100_000), ("common", 1000), ("rare", 10), ("unused", 0)]
+                .into_iter()
+                .map(|(name, downloads)| CratesIoCrate {
+                    name: name.into(),
+                    max_version: "1.0.0".into(),
+                    downloads,
+                    updated_at: Utc::now(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The same seed always returns the same sample, regardless of the input order
+        let mut reversed = candidates();
+        reversed.reverse();
+        assert_eq!(
+            weighted_sample_from(candidates(), 2, 42),
+            weighted_sample_from(reversed, 2, 42)
+        );
+        assert_eq!(weighted_sample_from(candidates(), 2, 42).len(), 2);
+
+        // Crates with more downloads are picked more often
+        let mut popular = 0;
+        let mut rare = 0;
+        for seed in 0..200 {
+            let sample = weighted_sample_from(candidates(), 1, seed);
+            match sample[0].name() {
+                "popular" => popular += 1,
+                "rare" => rare += 1,
+                _ => {}
+            }
+        }
+        assert!(popular > 150);
+        assert!(rare < 10);
+    }
+}
@@ -1,6 +1,6 @@
 use std::env;
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 lazy_static! {
     pub static ref WORK_DIR: PathBuf = {
@@ -8,7 +8,12 @@ lazy_static! {
             .unwrap_or_else(|| OsStr::new("./work").to_os_string())
             .into()
     };
-    pub static ref LOCAL_DIR: PathBuf = WORK_DIR.join("local");
+    // Set when multiple agents share the same work directory, to isolate their local files
+    pub static ref AGENT_ID: Option<String> = env::var("CRATER_AGENT_ID")
+        .ok()
+        .and_then(|id| if id.is_empty() { None } else { Some(id) });
+    pub static ref LOCAL_DIR: PathBuf =
+        local_dir(&WORK_DIR, AGENT_ID.as_ref().map(|id| id.as_str()));
 
     pub static ref CARGO_HOME: String = LOCAL_DIR.join("cargo-home").to_string_lossy().into();
     pub static ref RUSTUP_HOME: String = LOCAL_DIR.join("rustup-home").to_string_lossy().into();
@@ -21,6 +26,9 @@ lazy_static! {
     // in docker containers
     pub static ref TEST_SOURCE_DIR: PathBuf = LOCAL_DIR.join("test-source");
 
+    // Snapshots of the crates.io index pinned by experiments
+    pub static ref INDEX_SNAPSHOTS_DIR: PathBuf = LOCAL_DIR.join("index-snapshots");
+
     // Where GitHub crate mirrors are stored
     pub static ref GH_MIRRORS_DIR: PathBuf = LOCAL_DIR.join("gh-mirrors");
 
@@ -33,3 +41,39 @@ lazy_static! {
     pub static ref EXPERIMENT_DIR: PathBuf = WORK_DIR.join("ex");
     pub static ref LOG_DIR: PathBuf = WORK_DIR.join("logs");
 }
+
+/// Get the directory containing the files local to an agent. Agents sharing the work directory
+/// get different subtrees if their id is set, while without an id the old layout is used.
+pub fn local_dir(work_dir: &Path, agent_id: Option<&str>) -> PathBuf {
+    match agent_id {
+        Some(id) => work_dir.join("agents").join(id).join("local"),
+        None => work_dir.join("local"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::local_dir;
+    use std::path::Path;
+
+    #[test]
+    fn test_agents_local_dirs() {
+        let work = Path::new("/work");
+        let target_dir = |agent: Option<&str>| {
+            local_dir(work, agent)
+                .join("target-dirs")
+                .join("pr-1234")
+                .join("worker-0")
+        };
+
+        assert_eq!(target_dir(None), Path::new("/work/local/target-dirs/pr-1234/worker-0"));
+        let dirs = [target_dir(None), target_dir(Some("a")), target_dir(Some("b"))];
+        for (i, first) in dirs.iter().enumerate() {
+            for (j, second) in dirs.iter().enumerate() {
+                if i != j {
+                    assert!(!first.starts_with(second));
+                }
+            }
+        }
+    }
+}
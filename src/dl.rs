@@ -1,5 +1,10 @@
 use errors::*;
-use reqwest;
+use reqwest::header::{ByteRangeSpec, Range};
+use reqwest::{self, StatusCode};
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 use util;
 
 const MAX_REDIRECTS: usize = 4;
@@ -12,11 +17,147 @@ pub fn download_limit(url: &str, ms: usize) -> Result<reqwest::Response> {
     util::try_hard_limit(ms, || download_no_retry(url))
 }
 
-pub fn download_no_retry(url: &str) -> Result<reqwest::Response> {
-    debug!{"Downloading {}", url};
-    let client = reqwest::ClientBuilder::new()
+fn client() -> reqwest::Client {
+    reqwest::ClientBuilder::new()
         .redirect(reqwest::RedirectPolicy::limited(MAX_REDIRECTS))
         .build()
-        .expect("could not setup https client");
-    client.get(url).send().map_err(|e| e.into())
+        .expect("could not setup https client")
+}
+
+pub fn download_no_retry(url: &str) -> Result<reqwest::Response> {
+    debug!{"Downloading {}", url};
+    client().get(url).send().map_err(|e| e.into())
+}
+
+/// Content of a file requested starting from an offset.
+enum Fetched {
+    /// The content starting from the requested offset
+    Range(Box<Read>),
+    /// The whole content, because ranges are not supported
+    Full(Box<Read>),
+    /// The requested offset is past the end of the file
+    OutOfRange,
+}
+
+fn fetch_range(url: &str, offset: u64) -> Result<Fetched> {
+    debug!{"Downloading {} from byte {}", url, offset};
+    let resp = client()
+        .get(url)
+        .header(Range::Bytes(vec![ByteRangeSpec::AllFrom(offset)]))
+        .send()?;
+
+    match resp.status() {
+        StatusCode::PartialContent => Ok(Fetched::Range(Box::new(resp))),
+        StatusCode::RangeNotSatisfiable => Ok(Fetched::OutOfRange),
+        status if status.is_success() => Ok(Fetched::Full(Box::new(resp))),
+        status => bail!("failed to download {}: status {}", url, status),
+    }
+}
+
+/// Download `url` to `dest`, storing the content in a `.partial` file until it's complete. If a
+/// previous download was interrupted it's resumed from the partial file, and if the resulting
+/// file is rejected by `validate` the partial file is discarded and downloaded again.
+pub fn download_resumable<V>(url: &str, dest: &Path, validate: V) -> Result<()>
+where
+    V: Fn(&Path) -> Result<()>,
+{
+    util::try_hard(|| resume_download(dest, |offset| fetch_range(url, offset), &validate))
+}
+
+fn partial_path(dest: &Path) -> PathBuf {
+    let mut path = OsString::from(dest.as_os_str());
+    path.push(".partial");
+    path.into()
+}
+
+fn resume_download<F, V>(dest: &Path, fetch: F, validate: V) -> Result<()>
+where
+    F: Fn(u64) -> Result<Fetched>,
+    V: Fn(&Path) -> Result<()>,
+{
+    let partial = partial_path(dest);
+
+    // The second attempt is only done after discarding a corrupt partial file
+    for _ in 0..2 {
+        let offset = fs::metadata(&partial).map(|m| m.len()).unwrap_or(0);
+        let mut file = OpenOptions::new().create(true).append(true).open(&partial)?;
+
+        match fetch(offset)? {
+            Fetched::Range(mut body) => {
+                io::copy(&mut body, &mut file)?;
+            }
+            Fetched::Full(mut body) => {
+                file.set_len(0)?;
+                io::copy(&mut body, &mut file)?;
+            }
+            Fetched::OutOfRange => {
+                warn!("discarding the partial download {}", partial.display());
+                fs::remove_file(&partial)?;
+                continue;
+            }
+        }
+        drop(file);
+
+        if let Err(err) = validate(&partial) {
+            fs::remove_file(&partial)?;
+            if offset == 0 {
+                return Err(err);
+            }
+
+            warn!("discarding the corrupt partial download {}", partial.display());
+            continue;
+        }
+
+        fs::rename(&partial, dest)?;
+        return Ok(());
+    }
+
+    bail!("unable to complete the download of {}", dest.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{partial_path, resume_download, Fetched};
+    use errors::*;
+    use file;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::path::Path;
+    use tempdir::TempDir;
+
+    const CONTENT: &[u8] = b"hello world";
+
+    #[test]
+    fn test_resume_download() {
+        let tmp = TempDir::new("crater").unwrap();
+        let dest = tmp.path().join("file");
+
+        let offsets = RefCell::new(Vec::new());
+        let fetch = |offset: u64| -> Result<Fetched> {
+            offsets.borrow_mut().push(offset);
+            let rest = CONTENT[offset as usize..].to_vec();
+            Ok(Fetched::Range(Box::new(Cursor::new(rest))))
+        };
+        let validate = |path: &Path| -> Result<()> {
+            if file::read_string(path)?.as_bytes() != CONTENT {
+                bail!("corrupt file");
+            }
+            Ok(())
+        };
+
+        // An interrupted download is resumed from where it stopped
+        file::write_string(&partial_path(&dest), "hello ").unwrap();
+        resume_download(&dest, &fetch, &validate).unwrap();
+        assert_eq!(*offsets.borrow(), vec![6]);
+        assert_eq!(file::read_string(&dest).unwrap().as_bytes(), CONTENT);
+        assert!(!partial_path(&dest).exists());
+
+        // A corrupt partial file is discarded and the download restarted
+        offsets.borrow_mut().clear();
+        file::write_string(&partial_path(&dest), "garbage").unwrap();
+        resume_download(&dest, &fetch, &validate).unwrap();
+        assert_eq!(*offsets.borrow(), vec![7, 0]);
+        assert_eq!(file::read_string(&dest).unwrap().as_bytes(), CONTENT);
+        assert!(!partial_path(&dest).exists());
+    }
 }
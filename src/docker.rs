@@ -4,7 +4,7 @@ use std::env;
 use std::fmt::{self, Display, Formatter};
 use std::fs;
 use std::path::{Path, PathBuf};
-use util::{self, Size};
+use util::{self, Secret, Size};
 
 pub static IMAGE_NAME: &'static str = "crater";
 
@@ -20,7 +20,7 @@ pub fn build_container(docker_env: &str) -> Result<()> {
         .run()
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum MountPerms {
     ReadWrite,
     ReadOnly,
@@ -51,7 +51,10 @@ pub struct ContainerBuilder<'a> {
     image: &'a str,
     mounts: Vec<MountConfig<'a>>,
     env: Vec<(&'static str, String)>,
+    secrets: Vec<Secret>,
     memory_limit: Option<Size>,
+    tag_streams: bool,
+    enable_networking: bool,
 }
 
 impl<'a> ContainerBuilder<'a> {
@@ -60,7 +63,10 @@ impl<'a> ContainerBuilder<'a> {
             image,
             mounts: Vec::new(),
             env: Vec::new(),
+            secrets: Vec::new(),
             memory_limit: None,
+            tag_streams: false,
+            enable_networking: true,
         }
     }
 
@@ -78,16 +84,51 @@ impl<'a> ContainerBuilder<'a> {
         self
     }
 
+    /// Add an environment variable whose value is redacted from the logs.
+    pub fn secret_env(mut self, key: &'static str, value: Secret) -> Self {
+        self.env.push((key, value.expose().to_string()));
+        self.secrets.push(value);
+        self
+    }
+
     pub fn memory_limit(mut self, limit: Size) -> Self {
         self.memory_limit = Some(limit);
         self
     }
 
+    pub fn tag_streams(mut self, tag_streams: bool) -> Self {
+        self.tag_streams = tag_streams;
+        self
+    }
+
+    pub fn enable_networking(mut self, enable: bool) -> Self {
+        self.enable_networking = enable;
+        self
+    }
+
     pub fn create(self) -> Result<Container> {
+        for mount in &self.mounts {
+            fs::create_dir_all(&mount.host_path)?;
+        }
+
+        let args = self.create_args();
+        let mut command = RunCommand::new("docker", &*args);
+        for secret in &self.secrets {
+            command = command.redact(secret.expose());
+        }
+
+        let (out, _) = command.run_capture()?;
+        Ok(Container {
+            id: out[0].clone(),
+            tag_streams: self.tag_streams,
+            secrets: self.secrets.clone(),
+        })
+    }
+
+    pub fn create_args(&self) -> Vec<String> {
         let mut args: Vec<String> = vec!["create".into()];
 
         for mount in &self.mounts {
-            fs::create_dir_all(&mount.host_path)?;
             args.push("-v".into());
             args.push(mount.to_arg())
         }
@@ -102,13 +143,24 @@ impl<'a> ContainerBuilder<'a> {
             args.push(limit.to_string());
         }
 
-        args.push(self.image.into());
+        if !self.enable_networking {
+            args.push("--network".into());
+            args.push("none".into());
+        }
 
-        let (out, _) = RunCommand::new("docker", &*args).run_capture()?;
-        Ok(Container { id: out[0].clone() })
+        args.push(self.image.into());
+        args
     }
 
     pub fn run(self, quiet: bool) -> Result<()> {
+        self.with_container(|container| container.run(quiet))
+    }
+
+    pub fn run_capture_stderr(self, quiet: bool) -> Result<(bool, Vec<String>)> {
+        self.with_container(|container| container.run_capture_stderr(quiet))
+    }
+
+    fn with_container<R, F: FnOnce(&Container) -> Result<R>>(self, f: F) -> Result<R> {
         let container = self.create()?;
 
         // Ensure the container is properly deleted even if something panics
@@ -118,8 +170,7 @@ impl<'a> ContainerBuilder<'a> {
             }
         }}
 
-        container.run(quiet)?;
-        Ok(())
+        f(&container)
     }
 }
 
@@ -136,6 +187,10 @@ fn absolute(path: &Path) -> PathBuf {
 pub struct Container {
     // Docker container ID
     id: String,
+    #[serde(default)]
+    tag_streams: bool,
+    #[serde(skip)]
+    secrets: Vec<Secret>,
 }
 
 impl Display for Container {
@@ -146,9 +201,28 @@ impl Display for Container {
 
 impl Container {
     pub fn run(&self, quiet: bool) -> Result<()> {
-        RunCommand::new("docker", &["start", "-a", &self.id])
+        let args = ["start", "-a", self.id.as_str()];
+        let mut command = RunCommand::new("docker", &args)
             .quiet(quiet)
-            .run()
+            .tag_streams(self.tag_streams);
+        for secret in &self.secrets {
+            command = command.redact(secret.expose());
+        }
+        command.run()
+    }
+
+    /// Run the container, returning whether it exited successfully and what it wrote to stderr.
+    pub fn run_capture_stderr(&self, quiet: bool) -> Result<(bool, Vec<String>)> {
+        let args = ["start", "-a", self.id.as_str()];
+        let mut command = RunCommand::new("docker", &args)
+            .quiet(quiet)
+            .tag_streams(self.tag_streams);
+        for secret in &self.secrets {
+            command = command.redact(secret.expose());
+        }
+        command
+            .run_capture_status()
+            .map(|(success, _, stderr)| (success, stderr))
     }
 
     pub fn delete(&self) -> Result<()> {
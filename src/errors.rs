@@ -21,6 +21,7 @@ error_chain! {
         Base64Decode(::base64::DecodeError);
         Tera(::tera::Error);
         Utf8(::std::string::FromUtf8Error);
+        Fmt(::std::fmt::Error);
     }
 
     links {
@@ -36,6 +37,10 @@ error_chain! {
             display("process killed after {} {}s", what, when)
         }
         Download{}
+        PrepareIncomplete(details: String) {
+            description("the prepare step didn't fetch all the dependencies")
+            display("the prepare step didn't fetch all the dependencies: {}", details)
+        }
         BadS3Uri {
             description("the S3 URI could not be parsed.")
         }
@@ -50,5 +55,9 @@ error_chain! {
             description("invalid toolchain source name")
             display("invalid toolchain source name: {}", name)
         }
+        InvalidToolchainFlag(name: String) {
+            description("invalid toolchain flag")
+            display("invalid toolchain flag: {}", name)
+        }
     }
 }
@@ -1,27 +1,79 @@
 use config::Config;
 use crates::{Crate, RegistryCrate};
-use dirs::{EXPERIMENT_DIR, TEST_SOURCE_DIR};
+use dirs::{EXPERIMENT_DIR, TARGET_DIR, TEST_SOURCE_DIR};
 use errors::*;
 use file;
 use git;
 use lists::{self, List};
+use registry;
 use results::WriteResults;
 use run::RunCommand;
 use serde_json;
+use std::borrow::Cow;
 use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use toml::value::Table;
+use toml::{self, Value};
 use toml_frobber;
 use toolchain::{self, CargoState, Toolchain};
-use util;
+use url::Url;
+use util::{self, CancelToken, Secret, Semaphore, Size};
 
 string_enum!(pub enum ExMode {
     BuildAndTest => "build-and-test",
     BuildOnly => "build-only",
     CheckOnly => "check-only",
     UnstableFeatures => "unstable-features",
+    Clippy => "clippy",
 });
 
+string_enum!(pub enum ExStep {
+    Prepare => "prepare",
+    BuildAndTest => "build-and-test",
+    BuildOnly => "build-only",
+    CheckOnly => "check-only",
+    UnstableFeatures => "unstable-features",
+    Clippy => "clippy",
+});
+
+/// Comma-separated list of experiment steps, as accepted by the bot commands.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExSteps(pub Vec<ExStep>);
+
+impl FromStr for ExSteps {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        Ok(ExSteps(
+            input
+                .split(',')
+                .filter(|step| !step.is_empty())
+                .map(|step| step.parse())
+                .collect::<Result<_>>()?,
+        ))
+    }
+}
+
+/// Comma-separated list of rustup components, as accepted by the bot commands.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExComponents(pub Vec<String>);
+
+impl FromStr for ExComponents {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        Ok(ExComponents(
+            input
+                .split(',')
+                .filter(|component| !component.is_empty())
+                .map(|component| component.to_string())
+                .collect(),
+        ))
+    }
+}
+
 string_enum!(pub enum ExCrateSelect {
     Full => "full",
     Demo => "demo",
@@ -44,6 +96,16 @@ pub fn config_file(ex_name: &str) -> PathBuf {
     EXPERIMENT_DIR.join(ex_name).join("config.json")
 }
 
+/// File recording the seed the crates of the experiment were randomly sampled with.
+pub fn sample_seed_file(ex_name: &str) -> PathBuf {
+    EXPERIMENT_DIR.join(ex_name).join("sample-seed")
+}
+
+/// File the progress of the tasks graph is periodically saved to while running the experiment.
+pub fn graph_progress_file(ex_name: &str) -> PathBuf {
+    EXPERIMENT_DIR.join(ex_name).join("graph-progress.json")
+}
+
 fn froml_dir(ex_name: &str) -> PathBuf {
     EXPERIMENT_DIR.join(ex_name).join("fromls")
 }
@@ -59,6 +121,40 @@ pub struct Experiment {
     pub toolchains: [Toolchain; 2],
     pub mode: ExMode,
     pub cap_lints: ExCapLints,
+    #[serde(default)]
+    pub registry: Option<String>,
+    /// Token used to authenticate with the custom registry, which is redacted from the logs. It's
+    /// never serialized, so it has to be provided separately every time the experiment is loaded
+    #[serde(skip)]
+    pub registry_token: Option<Secret>,
+    /// Steps executed even if a result for them is already present
+    #[serde(default)]
+    pub force_steps: Vec<ExStep>,
+    /// Allow both toolchains to be the same, for example to look for flaky crates
+    #[serde(default)]
+    pub allow_same_toolchains: bool,
+    /// Command executed in the source directory before building each crate
+    #[serde(default)]
+    pub pre_build: Option<String>,
+    /// Capture the `cargo metadata` output of each crate, to detect dependency resolution changes
+    #[serde(default)]
+    pub capture_metadata: bool,
+    /// Additional rustup components installed in the toolchains
+    #[serde(default)]
+    pub components: Vec<String>,
+    /// Commit of the crates.io index used to resolve the dependencies
+    #[serde(default)]
+    pub index_commit: Option<String>,
+    /// Prior experiment providing the results of the start toolchain, which is then not run
+    #[serde(default)]
+    pub baseline: Option<String>,
+    /// Crates expected to regress, which are reported separately from the other regressions
+    #[serde(default)]
+    pub expected_regressions: Vec<Crate>,
+    /// Index of the control toolchain, which the results of the other toolchains are compared
+    /// against when looking for regressions
+    #[serde(default)]
+    pub control_index: usize,
 }
 
 pub struct ExOpts {
@@ -66,27 +162,58 @@ pub struct ExOpts {
     pub toolchains: [Toolchain; 2],
     pub mode: ExMode,
     pub crates: ExCrateSelect,
+    /// Seed used to sample the crates randomly, a new one is generated if missing
+    pub sample_seed: Option<u64>,
     pub cap_lints: ExCapLints,
+    pub force_steps: Vec<ExStep>,
+    pub allow_same_toolchains: bool,
+    pub pre_build: Option<String>,
+    pub capture_metadata: bool,
+    pub components: Vec<String>,
+    pub expected_regressions: Vec<Crate>,
+    pub control_index: usize,
 }
 
-pub fn get_crates(crates: ExCrateSelect, config: &Config) -> Result<Vec<Crate>> {
+/// Get the list of crates to test. The seed is used by the selections sampling crates randomly,
+/// so the same seed always results in the same list.
+pub fn get_crates(
+    crates: ExCrateSelect,
+    config: &Config,
+    sample_seed: u64,
+) -> Result<Vec<Crate>> {
     match crates {
         ExCrateSelect::Full => lists::read_all_lists(),
         ExCrateSelect::Demo => demo_list(config),
-        ExCrateSelect::SmallRandom => small_random(),
+        ExCrateSelect::SmallRandom => small_random(sample_seed),
         ExCrateSelect::Top100 => top_100(),
     }
 }
 
 pub fn define(opts: ExOpts, config: &Config) -> Result<()> {
     delete(&opts.name)?;
+    let seed = opts.sample_seed.unwrap_or_else(::rand::random);
     define_(
         &opts.name,
         opts.toolchains,
-        get_crates(opts.crates, config)?,
+        get_crates(opts.crates, config, seed)?,
         opts.mode,
         opts.cap_lints,
-    )
+        opts.force_steps,
+        opts.allow_same_toolchains,
+        opts.pre_build,
+        opts.capture_metadata,
+        opts.components,
+        opts.expected_regressions,
+        opts.control_index,
+    )?;
+
+    // Record the seed, so the same sample can be selected again
+    if opts.crates == ExCrateSelect::SmallRandom {
+        info!("crates sampled with seed {}", seed);
+        file::write_string(&sample_seed_file(&opts.name), &seed.to_string())?;
+    }
+
+    Ok(())
 }
 
 pub fn demo_list(config: &Config) -> Result<Vec<Crate>> {
@@ -118,19 +245,24 @@ pub fn demo_list(config: &Config) -> Result<Vec<Crate>> {
     Ok(result)
 }
 
-fn small_random() -> Result<Vec<Crate>> {
-    use rand::{thread_rng, Rng};
-
+fn small_random(seed: u64) -> Result<Vec<Crate>> {
     const COUNT: usize = 20;
 
-    let mut crates = lists::read_all_lists()?;
-    let mut rng = thread_rng();
+    Ok(sample_crates(lists::read_all_lists()?, COUNT, seed))
+}
+
+fn sample_crates(mut crates: Vec<Crate>, count: usize, seed: u64) -> Vec<Crate> {
+    use rand::{Isaac64Rng, Rng, SeedableRng};
+
+    // The list is sorted first to avoid depending on the order of the input
+    crates.sort();
+    let mut rng = Isaac64Rng::from_seed(&[seed]);
     rng.shuffle(&mut crates);
 
-    crates.truncate(COUNT);
+    crates.truncate(count);
     crates.sort();
 
-    Ok(crates)
+    crates
 }
 
 fn top_100() -> Result<Vec<Crate>> {
@@ -139,12 +271,20 @@ fn top_100() -> Result<Vec<Crate>> {
     Ok(crates)
 }
 
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
 pub fn define_(
     ex_name: &str,
     toolchains: [Toolchain; 2],
     crates: Vec<Crate>,
     mode: ExMode,
     cap_lints: ExCapLints,
+    force_steps: Vec<ExStep>,
+    allow_same_toolchains: bool,
+    pre_build: Option<String>,
+    capture_metadata: bool,
+    components: Vec<String>,
+    expected_regressions: Vec<Crate>,
+    control_index: usize,
 ) -> Result<()> {
     info!(
         "defining experiment {} for {} crates",
@@ -157,6 +297,17 @@ pub fn define_(
         toolchains,
         mode,
         cap_lints,
+        registry: None,
+        registry_token: None,
+        force_steps,
+        allow_same_toolchains,
+        pre_build,
+        capture_metadata,
+        components,
+        index_commit: None,
+        baseline: None,
+        expected_regressions,
+        control_index,
     };
 
     ex.validate()?;
@@ -168,15 +319,155 @@ pub fn define_(
     Ok(())
 }
 
+fn default_target_dir_size(mode: ExMode) -> Size {
+    match mode {
+        ExMode::BuildAndTest => Size::Megabytes(600),
+        ExMode::BuildOnly => Size::Megabytes(400),
+        ExMode::CheckOnly => Size::Megabytes(150),
+        ExMode::UnstableFeatures => Size::Megabytes(10),
+        ExMode::Clippy => Size::Megabytes(150),
+    }
+}
+
+/// Get the rustup components needed to run an experiment in the mode.
+pub fn required_components(mode: ExMode) -> Vec<String> {
+    match mode {
+        ExMode::Clippy => vec!["clippy".into()],
+        ExMode::BuildAndTest
+        | ExMode::BuildOnly
+        | ExMode::CheckOnly
+        | ExMode::UnstableFeatures => Vec::new(),
+    }
+}
+
+/// Add the rustup components needed by the mode which are missing from the list.
+pub fn add_required_components(mode: ExMode, components: &mut Vec<String>) {
+    for component in required_components(mode) {
+        if !components.contains(&component) {
+            components.push(component);
+        }
+    }
+}
+
 impl Experiment {
     pub fn validate(&self) -> Result<()> {
-        if self.toolchains[0] == self.toolchains[1] {
-            bail!("reusing the same toolchain isn't supported");
+        // Toolchains differing only in their image would record their results in the same place,
+        // overwriting each other, since the image isn't part of the results keys
+        let same_toolchains = self.toolchains[0].to_string() == self.toolchains[1].to_string();
+        if same_toolchains && self.toolchains[0].image != self.toolchains[1].image {
+            bail!(
+                "the start and end toolchains only differ in their image ({}), \
+                 which is not supported: use the same image or different toolchains",
+                self.toolchains[0].to_string()
+            );
+        }
+        if same_toolchains && !self.allow_same_toolchains {
+            bail!(
+                "the start and end toolchains are the same ({}), \
+                 set `allow-same-toolchains` if this is intended",
+                self.toolchains[0].to_string()
+            );
+        }
+
+        for component in required_components(self.mode) {
+            if !self.components.contains(&component) {
+                bail!(
+                    "the {} mode requires the `{}` component",
+                    self.mode.to_str(),
+                    component
+                );
+            }
+        }
+
+        if let Some(ref pre_build) = self.pre_build {
+            if pre_build.trim().is_empty() {
+                bail!("the pre-build command can't be empty");
+            }
+        }
+
+        if let Some(ref commit) = self.index_commit {
+            if self.registry.is_some() {
+                bail!("the crates.io index can't be pinned when a custom registry is used");
+            }
+            if commit.len() != 40 || !commit.chars().all(|c| c.is_digit(16)) {
+                bail!("invalid crates.io index commit: {}", commit);
+            }
+        }
+
+        if self.control_index >= self.toolchains.len() {
+            bail!(
+                "invalid control toolchain index {}, the experiment has {} toolchains",
+                self.control_index,
+                self.toolchains.len()
+            );
+        }
+
+        if self.baseline.as_ref() == Some(&self.name) {
+            bail!("an experiment can't be its own baseline");
+        }
+
+        if let Some(ref registry) = self.registry {
+            let url = Url::parse(registry)
+                .chain_err(|| format!("invalid registry index URL: {}", registry))?;
+            match url.scheme() {
+                "http" | "https" | "git" | "file" => {}
+                scheme => bail!("unsupported registry index URL scheme: {}", scheme),
+            }
+        } else if self.registry_token.is_some() {
+            bail!("a registry token can only be used with a custom registry");
         }
 
         Ok(())
     }
 
+    /// Roughly estimate the disk space in bytes used by the target directories of the experiment,
+    /// from the average size configured for its mode.
+    pub fn estimated_disk(&self, config: &Config) -> u64 {
+        let per_crate = config
+            .target_dir_sizes
+            .get(&self.mode)
+            .cloned()
+            .unwrap_or_else(|| default_target_dir_size(self.mode));
+
+        per_crate.to_bytes() * self.crates.len() as u64 * self.run_toolchains().len() as u64
+    }
+
+    /// Toolchains actually executed by the experiment: the start toolchain is skipped when its
+    /// results come from a baseline experiment.
+    pub fn run_toolchains(&self) -> &[Toolchain] {
+        if self.baseline.is_some() {
+            &self.toolchains[1..]
+        } else {
+            &self.toolchains
+        }
+    }
+
+    /// Experiment the results of the toolchain at `index` should be loaded from.
+    pub fn results_source(&self, index: usize) -> Cow<Experiment> {
+        match self.baseline {
+            Some(ref baseline) if index == 0 => Cow::Owned(Experiment {
+                name: baseline.clone(),
+                ..self.clone()
+            }),
+            _ => Cow::Borrowed(self),
+        }
+    }
+
+    /// Reorder the crates deterministically for the agent, so different agents running the same
+    /// experiment don't contend on the same crate downloads.
+    pub fn shuffle_crates_for_agent(&mut self, agent: &str) {
+        use rand::{Isaac64Rng, Rng, SeedableRng};
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        agent.hash(&mut hasher);
+        self.name.hash(&mut hasher);
+
+        let mut rng = Isaac64Rng::from_seed(&[hasher.finish()]);
+        rng.shuffle(&mut self.crates);
+    }
+
     pub fn fetch_repo_crates(&self) -> Result<()> {
         for repo in self.crates.iter().filter_map(|krate| krate.github()) {
             if let Err(e) = git::shallow_clone_or_pull(&repo.url(), &repo.mirror_dir()) {
@@ -237,6 +528,66 @@ pub fn capture_shas<DB: WriteResults>(ex: &Experiment, crates: &[Crate], db: &DB
     Ok(())
 }
 
+/// Replace the crates.io source with the custom registry index of the experiment, if any.
+pub fn with_registry_override(ex: &Experiment, path: &Path) -> Result<()> {
+    let registry = if let Some(ref registry) = ex.registry {
+        Some(registry.clone())
+    } else if ex.index_commit.is_some() {
+        // The snapshot is mounted in the container by `Toolchain::run_cargo`
+        Some(format!("file://{}", toolchain::INDEX_SNAPSHOT_MOUNT))
+    } else {
+        None
+    };
+
+    if let Some(registry) = registry {
+        let cargo_dir = path.join(".cargo");
+        fs::create_dir_all(&cargo_dir)?;
+        let config_path = cargo_dir.join("config");
+
+        // Keep the configuration shipped with the crate, only replacing the crates.io source
+        let mut config: Table = if config_path.exists() {
+            toml::from_str(&file::read_string(&config_path)?).chain_err(|| {
+                format!("unable to parse the cargo config {}", config_path.display())
+            })?
+        } else {
+            Table::new()
+        };
+
+        info!("using custom registry index {}", registry);
+        let mut crates_io = Table::new();
+        crates_io.insert("replace-with".into(), Value::String("crater-registry".into()));
+        let mut crater_registry = Table::new();
+        crater_registry.insert("registry".into(), Value::String(registry.clone()));
+        {
+            let sources = config_table(&mut config, "source")?;
+            sources.insert("crates-io".into(), Value::Table(crates_io));
+            sources.insert("crater-registry".into(), Value::Table(crater_registry));
+        }
+
+        // The token is provided through the environment by `Toolchain::run_cargo`
+        if ex.registry_token.is_some() {
+            let mut index = Table::new();
+            index.insert("index".into(), Value::String(registry));
+            config_table(&mut config, "registries")?
+                .insert("crater-registry".into(), Value::Table(index));
+        }
+
+        file::write_string(&config_path, &Value::Table(config).to_string())?;
+    }
+
+    Ok(())
+}
+
+fn config_table<'a>(config: &'a mut Table, key: &str) -> Result<&'a mut Table> {
+    match *config
+        .entry(key.to_string())
+        .or_insert_with(|| Value::Table(Table::new()))
+    {
+        Value::Table(ref mut table) => Ok(table),
+        _ => bail!("`{}` in the cargo config is not a table", key),
+    }
+}
+
 pub fn with_frobbed_toml(ex: &Experiment, krate: &Crate, path: &Path) -> Result<()> {
     let (crate_name, crate_vers) = match *krate {
         Crate::Registry(ref details) => (details.name.clone(), details.version.clone()),
@@ -316,6 +667,7 @@ pub fn capture_lockfile(
 
     with_work_crate(ex, toolchain, krate, |path| {
         with_frobbed_toml(ex, krate, path)?;
+        with_registry_override(ex, path)?;
         capture_lockfile_inner(config, ex, krate, path, toolchain)
     }).chain_err(|| format!("failed to generate lockfile for {}", krate))?;
 
@@ -395,6 +747,7 @@ pub fn fetch_crate_deps(
     with_work_crate(ex, toolchain, krate, |path| {
         with_frobbed_toml(ex, krate, path)?;
         with_captured_lockfile(config, ex, krate, path)?;
+        with_registry_override(ex, path)?;
 
         let args = &["fetch", "--locked", "--manifest-path", "Cargo.toml"];
         toolchain
@@ -405,9 +758,58 @@ pub fn fetch_crate_deps(
     })
 }
 
-pub fn prepare_all_toolchains(ex: &Experiment) -> Result<()> {
-    for tc in &ex.toolchains {
-        tc.prepare()?;
+pub fn prepare_index_snapshot(ex: &Experiment) -> Result<()> {
+    if let Some(ref commit) = ex.index_commit {
+        registry::prepare_index_snapshot(commit)?;
+    }
+
+    Ok(())
+}
+
+lazy_static! {
+    /// Toolchains being prepared by this process, shared between all the experiments.
+    static ref PREPARING_TOOLCHAINS: Semaphore = Semaphore::default();
+}
+
+/// Prepare all the toolchains of the experiment, calling `progress` with the percentage of the
+/// toolchains already prepared when each of them starts. The preparation stops before the next
+/// toolchain if `cancel` is triggered.
+pub fn prepare_all_toolchains(
+    ex: &Experiment,
+    config: &Config,
+    cancel: &CancelToken,
+    progress: &Fn(&Toolchain, u8),
+) -> Result<()> {
+    prepare_toolchains_limited(
+        ex,
+        &PREPARING_TOOLCHAINS,
+        config.max_preparing_toolchains,
+        cancel,
+        progress,
+        |tc| tc.prepare_with_components(&ex.components),
+    )
+}
+
+fn prepare_toolchains_limited<F: Fn(&Toolchain) -> Result<()>>(
+    ex: &Experiment,
+    semaphore: &Semaphore,
+    limit: Option<usize>,
+    cancel: &CancelToken,
+    progress: &Fn(&Toolchain, u8),
+    prepare: F,
+) -> Result<()> {
+    let toolchains = ex.run_toolchains();
+    for (i, tc) in toolchains.iter().enumerate() {
+        let _permit = match limit {
+            Some(limit) => Some(semaphore.acquire_cancellable(limit, cancel)?),
+            None => None,
+        };
+        if cancel.is_cancelled() {
+            bail!("the preparation of the toolchains was cancelled");
+        }
+
+        progress(tc, (i * 100 / toolchains.len()) as u8);
+        prepare(tc)?;
     }
 
     Ok(())
@@ -437,6 +839,25 @@ pub fn delete_all_target_dirs(ex_name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Remove the target directories of a completed experiment, if enabled in the configuration.
+pub fn cleanup_completed(ex_name: &str, config: &Config) -> Result<()> {
+    cleanup_completed_in(&TARGET_DIR, ex_name, config)
+}
+
+fn cleanup_completed_in(target_dirs: &Path, ex_name: &str, config: &Config) -> Result<()> {
+    if !config.cleanup_target_dirs {
+        return Ok(());
+    }
+
+    let target_dir = target_dirs.join(ex_name);
+    if target_dir.exists() {
+        info!("removing the target directories of experiment {}", ex_name);
+        util::remove_dir_all(&target_dir)?;
+    }
+
+    Ok(())
+}
+
 pub fn delete(ex_name: &str) -> Result<()> {
     let ex_dir = ex_dir(ex_name);
     if ex_dir.exists() {
@@ -448,8 +869,204 @@ pub fn delete(ex_name: &str) -> Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use super::{ExCapLints, ExMode, Experiment};
+    use super::{
+        cleanup_completed_in, prepare_toolchains_limited, sample_crates, with_registry_override,
+        ExCapLints, ExMode, Experiment,
+    };
+    use config::Config;
+    use crates::{Crate, RegistryCrate};
+    use crossbeam;
+    use file;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+    use tempdir::TempDir;
     use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+    use util::{CancelToken, Semaphore, Size};
+
+    #[test]
+    fn test_prepare_toolchains_limited() {
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        let semaphore = Semaphore::default();
+        let preparing = AtomicUsize::new(0);
+        let max_preparing = Mutex::new(0);
+        let prepared = AtomicUsize::new(0);
+
+        // Multiple experiments preparing their toolchains at the same time
+        crossbeam::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let cancel = CancelToken::default();
+                    prepare_toolchains_limited(
+                        &ex,
+                        &semaphore,
+                        Some(1),
+                        &cancel,
+                        &|_, _| {},
+                        |_| {
+                            let current = preparing.fetch_add(1, Ordering::SeqCst) + 1;
+                            {
+                                let mut max = max_preparing.lock().unwrap();
+                                if current > *max {
+                                    *max = current;
+                                }
+                            }
+                            thread::sleep(Duration::from_millis(10));
+                            preparing.fetch_sub(1, Ordering::SeqCst);
+                            prepared.fetch_add(1, Ordering::SeqCst);
+                            Ok(())
+                        },
+                    ).unwrap();
+                });
+            }
+        });
+
+        assert_eq!(prepared.load(Ordering::SeqCst), 8);
+        assert_eq!(*max_preparing.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_prepare_toolchains_cancel() {
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let semaphore = Semaphore::default();
+
+        // The progress is reported when each toolchain starts
+        let events = Mutex::new(Vec::new());
+        prepare_toolchains_limited(
+            &ex,
+            &semaphore,
+            None,
+            &CancelToken::default(),
+            &|tc, percent| events.lock().unwrap().push((tc.clone(), percent)),
+            |_| Ok(()),
+        ).unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![(MAIN_TOOLCHAIN.clone(), 0), (TEST_TOOLCHAIN.clone(), 50)]
+        );
+
+        // Cancelling while a toolchain is prepared stops before the next one
+        let cancel = CancelToken::default();
+        let prepared = Mutex::new(Vec::new());
+        let result =
+            prepare_toolchains_limited(&ex, &semaphore, None, &cancel, &|_, _| {}, |tc| {
+                prepared.lock().unwrap().push(tc.clone());
+                cancel.cancel();
+                Ok(())
+            });
+        assert!(result.is_err());
+        assert_eq!(*prepared.lock().unwrap(), vec![MAIN_TOOLCHAIN.clone()]);
+    }
+
+    #[test]
+    fn test_sample_crates() {
+        let crates = (0..100)
+            .map(|i| {
+                Crate::Registry(RegistryCrate {
+                    name: format!("crate-{}", i),
+                    version: "1.0.0".into(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let first = sample_crates(crates.clone(), 20, 42);
+        assert_eq!(first.len(), 20);
+
+        // The same seed always picks the same crates, regardless of the input order
+        let mut reversed = crates.clone();
+        reversed.reverse();
+        assert_eq!(sample_crates(reversed, 20, 42), first);
+
+        // Another seed picks different crates
+        assert_ne!(sample_crates(crates, 20, 1337), first);
+    }
+
+    #[test]
+    fn test_shuffle_crates_for_agent() {
+        let crates = (0..1000)
+            .map(|i| {
+                Crate::Registry(RegistryCrate {
+                    name: format!("crate-{}", i),
+                    version: "1.0.0".into(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: crates.clone(),
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        let shuffled = |agent: &str| {
+            let mut ex = ex.clone();
+            ex.shuffle_crates_for_agent(agent);
+            ex.crates
+        };
+        let first = shuffled("agent-1");
+        let second = shuffled("agent-2");
+
+        // The order is deterministic, but different for each agent
+        assert_eq!(shuffled("agent-1"), first);
+        assert_ne!(first[0], second[0]);
+
+        // All the crates are still tested
+        let mut expected = crates;
+        expected.sort();
+        for mut order in vec![first, second] {
+            order.sort();
+            assert_eq!(order, expected);
+        }
+    }
 
     #[test]
     fn test_validate_experiment() {
@@ -461,6 +1078,17 @@ mod tests {
                 toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
                 mode: ExMode::BuildAndTest,
                 cap_lints: ExCapLints::Forbid,
+                registry: None,
+                registry_token: None,
+                force_steps: Vec::new(),
+                allow_same_toolchains: false,
+                pre_build: None,
+                capture_metadata: false,
+                components: Vec::new(),
+                index_commit: None,
+                baseline: None,
+                expected_regressions: Vec::new(),
+                control_index: 0,
             }.validate()
                 .is_ok()
         );
@@ -473,8 +1101,363 @@ mod tests {
                 toolchains: [MAIN_TOOLCHAIN.clone(), MAIN_TOOLCHAIN.clone()],
                 mode: ExMode::BuildAndTest,
                 cap_lints: ExCapLints::Forbid,
+                registry: None,
+                registry_token: None,
+                force_steps: Vec::new(),
+                allow_same_toolchains: false,
+                pre_build: None,
+                capture_metadata: false,
+                components: Vec::new(),
+                index_commit: None,
+                baseline: None,
+                expected_regressions: Vec::new(),
+                control_index: 0,
+            }.validate()
+                .is_err()
+        );
+
+        // Experiment with the same toolchain, explicitly allowed
+        assert!(
+            Experiment {
+                name: "foo".to_string(),
+                crates: vec![],
+                toolchains: [MAIN_TOOLCHAIN.clone(), MAIN_TOOLCHAIN.clone()],
+                mode: ExMode::BuildAndTest,
+                cap_lints: ExCapLints::Forbid,
+                registry: None,
+                registry_token: None,
+                force_steps: Vec::new(),
+                allow_same_toolchains: true,
+                pre_build: None,
+                capture_metadata: false,
+                components: Vec::new(),
+                index_commit: None,
+                baseline: None,
+                expected_regressions: Vec::new(),
+                control_index: 0,
+            }.validate()
+                .is_ok()
+        );
+
+        // Experiment with a pinned index and a custom registry
+        assert!(
+            Experiment {
+                name: "foo".to_string(),
+                crates: vec![],
+                toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+                mode: ExMode::BuildAndTest,
+                cap_lints: ExCapLints::Forbid,
+                registry: Some("https://example.com/index".into()),
+                registry_token: None,
+                force_steps: Vec::new(),
+                allow_same_toolchains: false,
+                pre_build: None,
+                capture_metadata: false,
+                components: Vec::new(),
+                index_commit: Some("0123456789abcdef0123456789abcdef01234567".into()),
+                baseline: None,
+                expected_regressions: Vec::new(),
+                control_index: 0,
+            }.validate()
+                .is_err()
+        );
+
+        // Experiment with an empty pre-build command
+        assert!(
+            Experiment {
+                name: "foo".to_string(),
+                crates: vec![],
+                toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+                mode: ExMode::BuildAndTest,
+                cap_lints: ExCapLints::Forbid,
+                registry: None,
+                registry_token: None,
+                force_steps: Vec::new(),
+                allow_same_toolchains: false,
+                pre_build: Some("  ".into()),
+                capture_metadata: false,
+                components: Vec::new(),
+                index_commit: None,
+                baseline: None,
+                expected_regressions: Vec::new(),
+                control_index: 0,
+            }.validate()
+                .is_err()
+        );
+
+        // Experiment with a custom registry
+        assert!(
+            Experiment {
+                name: "foo".to_string(),
+                crates: vec![],
+                toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+                mode: ExMode::BuildAndTest,
+                cap_lints: ExCapLints::Forbid,
+                registry: Some("https://example.com/index".into()),
+                registry_token: None,
+                force_steps: Vec::new(),
+                allow_same_toolchains: false,
+                pre_build: None,
+                capture_metadata: false,
+                components: Vec::new(),
+                index_commit: None,
+                baseline: None,
+                expected_regressions: Vec::new(),
+                control_index: 0,
+            }.validate()
+                .is_ok()
+        );
+
+        // Experiment with an invalid registry
+        assert!(
+            Experiment {
+                name: "foo".to_string(),
+                crates: vec![],
+                toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+                mode: ExMode::BuildAndTest,
+                cap_lints: ExCapLints::Forbid,
+                registry: Some("not a url".into()),
+                registry_token: None,
+                force_steps: Vec::new(),
+                allow_same_toolchains: false,
+                pre_build: None,
+                capture_metadata: false,
+                components: Vec::new(),
+                index_commit: None,
+                baseline: None,
+                expected_regressions: Vec::new(),
+                control_index: 0,
+            }.validate()
+                .is_err()
+        );
+
+        // An experiment can't be its own baseline
+        assert!(
+            Experiment {
+                name: "foo".to_string(),
+                crates: vec![],
+                toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+                mode: ExMode::BuildAndTest,
+                cap_lints: ExCapLints::Forbid,
+                registry: None,
+                registry_token: None,
+                force_steps: Vec::new(),
+                allow_same_toolchains: false,
+                pre_build: None,
+                capture_metadata: false,
+                components: Vec::new(),
+                index_commit: None,
+                baseline: Some("foo".into()),
+                expected_regressions: Vec::new(),
+                control_index: 0,
+            }.validate()
+                .is_err()
+        );
+
+        // The control toolchain must be one of the toolchains of the experiment
+        assert!(
+            Experiment {
+                name: "foo".to_string(),
+                crates: vec![],
+                toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+                mode: ExMode::BuildAndTest,
+                cap_lints: ExCapLints::Forbid,
+                registry: None,
+                registry_token: None,
+                force_steps: Vec::new(),
+                allow_same_toolchains: false,
+                pre_build: None,
+                capture_metadata: false,
+                components: Vec::new(),
+                index_commit: None,
+                baseline: None,
+                expected_regressions: Vec::new(),
+                control_index: 2,
             }.validate()
                 .is_err()
         );
     }
+
+    #[test]
+    fn test_validate_mode_components() {
+        let mut ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::Clippy,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: vec!["rust-src".into()],
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        assert!(ex.validate().is_err());
+
+        ex.components.push("clippy".into());
+        assert!(ex.validate().is_ok());
+
+        // The other modes don't require any component
+        ex.mode = ExMode::CheckOnly;
+        ex.components.clear();
+        assert!(ex.validate().is_ok());
+    }
+
+    #[test]
+    fn test_estimated_disk() {
+        let crates = (0..30)
+            .map(|i| {
+                Crate::Registry(RegistryCrate {
+                    name: format!("crate-{}", i),
+                    version: "1.0.0".into(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut ex = Experiment {
+            name: "foo".to_string(),
+            crates: crates[..10].to_vec(),
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::CheckOnly,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        let mut config = Config::default();
+        config
+            .target_dir_sizes
+            .insert(ExMode::CheckOnly, Size::Megabytes(100));
+        assert_eq!(ex.estimated_disk(&config), 10 * 2 * 100 * 1024 * 1024);
+
+        // The estimate grows with the number of crates
+        let small = ex.estimated_disk(&config);
+        ex.crates = crates.clone();
+        assert_eq!(ex.estimated_disk(&config), small * 3);
+
+        // Modes missing from the configuration use the default sizes
+        ex.mode = ExMode::BuildAndTest;
+        assert!(ex.estimated_disk(&config) > small * 3);
+    }
+
+    #[test]
+    fn test_registry_override() {
+        let mut ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let source = TempDir::new("crater").unwrap();
+        let cargo_config = source.path().join(".cargo").join("config");
+
+        // Without a custom registry the crates.io source is used
+        with_registry_override(&ex, source.path()).unwrap();
+        assert!(!cargo_config.exists());
+
+        ex.registry = Some("https://example.com/index".into());
+        with_registry_override(&ex, source.path()).unwrap();
+        let content = file::read_string(&cargo_config).unwrap();
+        assert!(content.contains("replace-with = \"crater-registry\""));
+        assert!(content.contains("registry = \"https://example.com/index\""));
+
+        // A pinned index uses the snapshot mounted in the container
+        ex.registry = None;
+        ex.index_commit = Some("0123456789abcdef0123456789abcdef01234567".into());
+        with_registry_override(&ex, source.path()).unwrap();
+        let content = file::read_string(&cargo_config).unwrap();
+        assert!(content.contains("registry = \"file:///index-snapshot\""));
+
+        // The configuration shipped with the crate is preserved
+        file::write_string(&cargo_config, "[build]\nrustflags = [\"--cfg\", \"foo\"]\n").unwrap();
+        with_registry_override(&ex, source.path()).unwrap();
+        let content = file::read_string(&cargo_config).unwrap();
+        assert!(content.contains("rustflags = [\"--cfg\", \"foo\"]"));
+        assert!(content.contains("replace-with = \"crater-registry\""));
+    }
+
+    #[test]
+    fn test_validate_toolchains_with_images() {
+        let ex = |start: &str, end: &str, allow_same_toolchains| Experiment {
+            name: "foo".to_string(),
+            crates: vec![],
+            toolchains: [start.parse().unwrap(), end.parse().unwrap()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        // Different toolchains can use different images
+        assert!(ex("stable", "beta+image=crater-old", false).validate().is_ok());
+        assert!(ex("stable+image=foo", "stable+image=foo", true).validate().is_ok());
+
+        // The image isn't part of the results keys, so toolchains differing only in their image
+        // are rejected even if the same toolchains are allowed
+        for &allow_same_toolchains in &[false, true] {
+            let err = ex("stable", "stable+image=crater-old", allow_same_toolchains)
+                .validate()
+                .unwrap_err();
+            assert!(err.to_string().contains("only differ in their image"));
+        }
+    }
+
+    #[test]
+    fn test_cleanup_completed() {
+        let work = TempDir::new("crater").unwrap();
+        let target_dirs = work.path().join("target-dirs");
+        let logs = work.path().join("logs").join("foo");
+        let target_dir = target_dirs.join("foo").join("worker-0").join("stable");
+        let other_target_dir = target_dirs.join("bar").join("worker-0").join("stable");
+        for dir in &[&logs, &target_dir, &other_target_dir] {
+            fs::create_dir_all(dir).unwrap();
+            file::write_string(&dir.join("file"), "content").unwrap();
+        }
+
+        // The target directories are kept by default
+        let mut config = Config::default();
+        cleanup_completed_in(&target_dirs, "foo", &config).unwrap();
+        assert!(target_dir.exists());
+
+        config.cleanup_target_dirs = true;
+        cleanup_completed_in(&target_dirs, "foo", &config).unwrap();
+        assert!(!target_dirs.join("foo").exists());
+        assert!(other_target_dir.join("file").exists());
+        assert!(logs.join("file").exists());
+    }
 }
@@ -6,8 +6,10 @@ use file;
 use ref_slice::ref_slice;
 use results::{DeleteResults, FileDB, TestResult, WriteResults};
 use std::collections::HashSet;
+use std::fs;
 use std::path::Path;
 use toolchain::{CargoState, Toolchain};
+use util;
 
 pub fn delete_all_results(ex_name: &str) -> Result<()> {
     let ex = &Experiment::load(ex_name)?;
@@ -33,17 +35,27 @@ pub struct RunTestResult {
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
-pub fn run_test<DB: WriteResults>(
+pub fn run_test<DB: WriteResults, F>(
     config: &Config,
     action: &str,
     ex: &Experiment,
     tc: &Toolchain,
     krate: &Crate,
     db: &DB,
+    force: bool,
     quiet: bool,
-    test_fn: fn(&Config, &Experiment, &Path, &Toolchain, bool) -> Result<TestResult>,
-) -> Result<RunTestResult> {
-    if let Some(res) = db.get_result(ex, tc, krate)? {
+    test_fn: F,
+) -> Result<RunTestResult>
+where
+    F: Fn(&Config, &Experiment, &Path, &Toolchain, bool) -> Result<TestResult>,
+{
+    // Forced steps replace the existing result instead of being skipped
+    let existing = if force {
+        None
+    } else {
+        db.get_result(ex, tc, krate)?
+    };
+    if let Some(res) = existing {
         info!("skipping crate {}. existing result: {}", krate, res);
         Ok(RunTestResult {
             result: res,
@@ -53,17 +65,35 @@ pub fn run_test<DB: WriteResults>(
         with_work_crate(ex, tc, krate, |source_path| {
             with_frobbed_toml(ex, krate, source_path)?;
             with_captured_lockfile(config, ex, krate, source_path)?;
+            with_registry_override(ex, source_path)?;
 
-            db.record_result(ex, tc, krate, || {
-                info!(
-                    "{} {} against {} for {}",
-                    action,
-                    krate,
-                    tc.to_string(),
-                    ex.name
-                );
-                test_fn(config, ex, source_path, tc, quiet)
-            })
+            let result = db.record_result(ex, tc, krate, || {
+                run_with_pre_build(
+                    ex,
+                    |command| tc.run_command(config, ex, source_path, command, quiet),
+                    || {
+                        info!(
+                            "{} {} against {} for {}",
+                            action,
+                            krate,
+                            tc.to_string(),
+                            ex.name
+                        );
+                        test_fn(config, ex, source_path, tc, quiet)
+                    },
+                )
+            })?;
+
+            // The metadata is only informative, so failing to capture it doesn't fail the task
+            if ex.capture_metadata {
+                let metadata = capture_metadata(config, ex, source_path, tc, quiet)
+                    .and_then(|metadata| db.record_metadata(ex, tc, krate, &metadata));
+                if let Err(err) = metadata {
+                    util::report_error(&err);
+                }
+            }
+
+            Ok(result)
         }).map(|result| RunTestResult {
             result,
             skipped: false,
@@ -71,6 +101,49 @@ pub fn run_test<DB: WriteResults>(
     }
 }
 
+/// Run the experiment's pre-build command before the build. A failure of the command is recorded
+/// as an error, like other failures happening while preparing the crate.
+fn run_with_pre_build<P, B>(ex: &Experiment, pre_build: P, build: B) -> Result<TestResult>
+where
+    P: FnOnce(&str) -> Result<()>,
+    B: FnOnce() -> Result<TestResult>,
+{
+    if let Some(ref command) = ex.pre_build {
+        info!("running the pre-build command for {}", ex.name);
+        if let Err(err) = pre_build(command) {
+            error!("the pre-build command failed, skipping the build");
+            util::report_error(&err);
+            return Ok(TestResult::Error);
+        }
+    }
+
+    build()
+}
+
+/// Get the `cargo metadata` output of the crate, as resolved by the toolchain.
+fn capture_metadata(
+    config: &Config,
+    ex: &Experiment,
+    source_path: &Path,
+    toolchain: &Toolchain,
+    quiet: bool,
+) -> Result<String> {
+    const OUTPUT: &str = "crater-metadata.json";
+
+    let command = format!(
+        "cargo +{} metadata --frozen --format-version 1 > {}",
+        toolchain.rustup_name(),
+        OUTPUT
+    );
+    toolchain.run_command(config, ex, source_path, &command, quiet)?;
+
+    let path = source_path.join(OUTPUT);
+    let metadata = file::read_string(&path)?;
+    fs::remove_file(&path)?;
+
+    Ok(metadata)
+}
+
 fn build(
     config: &Config,
     ex: &Experiment,
@@ -99,44 +172,96 @@ fn build(
     Ok(())
 }
 
+/// Get the arguments of the `cargo test` invocation, passing the extra arguments to the test
+/// binaries.
+fn cargo_test_args(test_args: &[String]) -> Vec<&str> {
+    let mut args = vec!["test", "--frozen"];
+    if !test_args.is_empty() {
+        args.push("--");
+        args.extend(test_args.iter().map(|arg| arg.as_str()));
+    }
+    args
+}
+
 fn test(
     config: &Config,
     ex: &Experiment,
     source_path: &Path,
     toolchain: &Toolchain,
     quiet: bool,
+    test_args: &[String],
 ) -> Result<()> {
     toolchain.run_cargo(
         config,
         ex,
         source_path,
-        &["test", "--frozen"],
+        &cargo_test_args(test_args),
         CargoState::Locked,
         quiet,
         false,
     )
 }
 
+/// Check whether a step succeeded, propagating the failures caused by an incomplete prepare step
+/// instead of recording them as a failure of the crate.
+fn succeeded(r: Result<()>) -> Result<bool> {
+    match r {
+        Ok(()) => Ok(true),
+        Err(err) => {
+            let prepare_incomplete = match *err.kind() {
+                ErrorKind::PrepareIncomplete(_) => true,
+                _ => false,
+            };
+            if prepare_incomplete {
+                Err(err)
+            } else {
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Get the result to record for a failed step, distinguishing timeouts from other failures.
+/// Returns `None` if the step succeeded.
+fn failed_step(
+    r: Result<()>,
+    failed: TestResult,
+    timed_out: TestResult,
+) -> Result<Option<TestResult>> {
+    if let Err(ref err) = r {
+        if let ErrorKind::Timeout(..) = *err.kind() {
+            return Ok(Some(timed_out));
+        }
+    }
+
+    Ok(if succeeded(r)? { None } else { Some(failed) })
+}
+
+fn build_and_test<B, T>(build: B, test: T) -> Result<TestResult>
+where
+    B: FnOnce() -> Result<()>,
+    T: FnOnce() -> Result<()>,
+{
+    if let Some(res) = failed_step(build(), TestResult::BuildFail, TestResult::BuildTimeout)? {
+        return Ok(res);
+    }
+
+    Ok(failed_step(test(), TestResult::TestFail, TestResult::TestTimeout)?
+        .unwrap_or(TestResult::TestPass))
+}
+
 pub fn test_build_and_test(
     config: &Config,
     ex: &Experiment,
     source_path: &Path,
     toolchain: &Toolchain,
     quiet: bool,
+    test_args: &[String],
 ) -> Result<TestResult> {
-    let build_r = build(config, ex, source_path, toolchain, quiet);
-    let test_r = if build_r.is_ok() {
-        Some(test(config, ex, source_path, toolchain, quiet))
-    } else {
-        None
-    };
-
-    Ok(match (build_r, test_r) {
-        (Err(_), None) => TestResult::BuildFail,
-        (Ok(_), Some(Err(_))) => TestResult::TestFail,
-        (Ok(_), Some(Ok(_))) => TestResult::TestPass,
-        (_, _) => unreachable!(),
-    })
+    build_and_test(
+        || build(config, ex, source_path, toolchain, quiet),
+        || test(config, ex, source_path, toolchain, quiet, test_args),
+    )
 }
 
 pub fn test_build_only(
@@ -147,11 +272,10 @@ pub fn test_build_only(
     quiet: bool,
 ) -> Result<TestResult> {
     let r = build(config, ex, source_path, toolchain, quiet);
-    if r.is_ok() {
-        Ok(TestResult::TestSkipped)
-    } else {
-        Ok(TestResult::BuildFail)
-    }
+    Ok(
+        failed_step(r, TestResult::BuildFail, TestResult::BuildTimeout)?
+            .unwrap_or(TestResult::TestSkipped),
+    )
 }
 
 pub fn test_check_only(
@@ -171,11 +295,33 @@ pub fn test_check_only(
         false,
     );
 
-    if r.is_ok() {
-        Ok(TestResult::TestPass)
-    } else {
-        Ok(TestResult::BuildFail)
-    }
+    Ok(
+        failed_step(r, TestResult::BuildFail, TestResult::BuildTimeout)?
+            .unwrap_or(TestResult::TestPass),
+    )
+}
+
+pub fn test_clippy(
+    config: &Config,
+    ex: &Experiment,
+    source_path: &Path,
+    toolchain: &Toolchain,
+    quiet: bool,
+) -> Result<TestResult> {
+    let r = toolchain.run_cargo(
+        config,
+        ex,
+        source_path,
+        &["clippy", "--frozen", "--all", "--all-targets"],
+        CargoState::Locked,
+        quiet,
+        false,
+    );
+
+    Ok(
+        failed_step(r, TestResult::BuildFail, TestResult::BuildTimeout)?
+            .unwrap_or(TestResult::TestPass),
+    )
 }
 
 pub fn test_find_unstable_features(
@@ -277,3 +423,150 @@ fn parse_features(path: &Path) -> Result<Vec<String>> {
 
     Ok(features)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{build_and_test, cargo_test_args, run_with_pre_build, succeeded};
+    use config::{Config, CrateConfig};
+    use crates::{Crate, RegistryCrate};
+    use errors::*;
+    use ex::{ExCapLints, ExMode, Experiment};
+    use results::TestResult;
+    use std::cell::Cell;
+    use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+
+    #[test]
+    fn test_run_with_pre_build() {
+        let mut ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: Some("./generate.sh".into()),
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        // The pre-build command runs before the build
+        let executed = Cell::new(None);
+        let result = run_with_pre_build(
+            &ex,
+            |command| {
+                executed.set(Some(command == "./generate.sh"));
+                Ok(())
+            },
+            || {
+                assert_eq!(executed.get(), Some(true));
+                Ok(TestResult::BuildFail)
+            },
+        ).unwrap();
+        assert_eq!(result, TestResult::BuildFail);
+
+        // A failing pre-build command is not recorded as a build failure
+        let result = run_with_pre_build(
+            &ex,
+            |_| bail!("pre-build failed"),
+            || panic!("the build shouldn't run"),
+        ).unwrap();
+        assert_eq!(result, TestResult::Error);
+
+        // Without a pre-build command only the build is executed
+        ex.pre_build = None;
+        let result = run_with_pre_build(
+            &ex,
+            |_| panic!("no pre-build command should run"),
+            || Ok(TestResult::TestPass),
+        ).unwrap();
+        assert_eq!(result, TestResult::TestPass);
+    }
+
+    #[test]
+    fn test_succeeded() {
+        assert!(succeeded(Ok(())).unwrap());
+        assert!(!succeeded(Err("cargo failed".into())).unwrap());
+
+        // Missing dependencies are not the crate's fault
+        let err = succeeded(Err(ErrorKind::PrepareIncomplete("failed to download".into()).into()))
+            .unwrap_err();
+        match *err.kind() {
+            ErrorKind::PrepareIncomplete(_) => {}
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_build_and_test_timeouts() {
+        let timeout = || -> Result<()> { Err(ErrorKind::Timeout("max time of", 900).into()) };
+
+        assert_eq!(
+            build_and_test(timeout, || Ok(())).unwrap(),
+            TestResult::BuildTimeout
+        );
+        assert_eq!(
+            build_and_test(|| Ok(()), timeout).unwrap(),
+            TestResult::TestTimeout
+        );
+
+        // Other failures are still recorded as normal failures
+        assert_eq!(
+            build_and_test(|| Err("cargo failed".into()), || Ok(())).unwrap(),
+            TestResult::BuildFail
+        );
+        assert_eq!(
+            build_and_test(|| Ok(()), || Err("cargo failed".into())).unwrap(),
+            TestResult::TestFail
+        );
+        assert_eq!(
+            build_and_test(|| Ok(()), || Ok(())).unwrap(),
+            TestResult::TestPass
+        );
+    }
+
+    #[test]
+    fn test_cargo_test_args() {
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0".into(),
+            })
+        };
+
+        let mut config = Config::default();
+        config.crates.insert(
+            "lazy_static".into(),
+            CrateConfig {
+                skip: false,
+                skip_tests: false,
+                quiet: false,
+                update_lockfile: false,
+                broken: false,
+                test_args: vec!["--test-threads=1".into()],
+            },
+        );
+        assert!(config.validate().is_ok());
+
+        // The args are passed to the test binaries of the affected crate only
+        assert_eq!(
+            cargo_test_args(&config.test_args(&krate("lazy_static"))),
+            vec!["test", "--frozen", "--", "--test-threads=1"]
+        );
+        assert_eq!(
+            cargo_test_args(&config.test_args(&krate("bitflags"))),
+            vec!["test", "--frozen"]
+        );
+
+        for invalid in &["--", "", "--test-threads=1; cargo publish", "--skip foo"] {
+            config.crates.get_mut("lazy_static").unwrap().test_args = vec![invalid.to_string()];
+            assert!(config.validate().is_err());
+        }
+    }
+}
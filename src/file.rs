@@ -2,6 +2,7 @@ use errors::*;
 use std::fs::File;
 use std::io::{BufReader, Read, Write};
 use std::path::Path;
+use tempfile::NamedTempFile;
 
 pub fn write_string(path: &Path, s: &str) -> Result<()> {
     let mut f = File::create(path)?;
@@ -9,6 +10,17 @@ pub fn write_string(path: &Path, s: &str) -> Result<()> {
     Ok(())
 }
 
+/// Same as `write_string`, but the content is written to a temporary file in the same directory
+/// which then replaces the destination, so readers never see a partially written file.
+pub fn write_string_atomic(path: &Path, s: &str) -> Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut f = NamedTempFile::new_in(dir)?;
+    f.write_all(s.as_bytes())?;
+    f.as_file().sync_all()?;
+    f.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
 pub fn read_string(path: &Path) -> Result<String> {
     let mut f = BufReader::new(File::open(path)?);
     let mut buf = String::new();
@@ -97,6 +97,7 @@ pub mod report;
 pub mod results;
 mod run;
 pub mod run_graph;
+pub mod sandbox;
 pub mod server;
 mod tasks;
 mod toml_frobber;
@@ -1,9 +1,11 @@
 use chrono::Utc;
+use crates::Crate;
 use dirs::LOG_DIR;
 use errors::*;
 use slog::{self, Drain};
 use slog_scope;
 use slog_term;
+use std::collections::{HashMap, VecDeque};
 use std::env;
 use std::fs;
 use std::fs::{File, OpenOptions};
@@ -11,6 +13,10 @@ use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
+use toolchain::Toolchain;
+
+/// Number of lines kept in the tail of the log of each running task.
+const LOG_TAIL_LINES: usize = 100;
 
 fn global_log_name() -> &'static Path {
     lazy_static! {
@@ -31,6 +37,104 @@ where
     slog_scope::scope(&slog::Logger::root(drain, slog_o!()), f)
 }
 
+/// Same as `redirect`, while still sending the lines to the logger currently in scope.
+pub fn tee<F, R>(path: &Path, f: F) -> Result<R>
+where
+    F: FnOnce() -> Result<R>,
+{
+    let file = file_drain(path);
+    let drain = slog::Duplicate(slog_scope::logger(), file).fuse();
+    slog_scope::scope(&slog::Logger::root(drain, slog_o!()), f)
+}
+
+/// Tails of the logs of the tasks currently running, so they can be watched live before the whole
+/// log is stored with the result. The agents periodically upload them to the server.
+#[derive(Clone, Default)]
+pub struct RunningLogs {
+    tails: Arc<Mutex<HashMap<(Crate, Toolchain), LogTail>>>,
+}
+
+impl RunningLogs {
+    /// Same as `redirect`, also keeping the latest lines logged by `f` while it runs.
+    pub fn redirect<F, R>(
+        &self,
+        krate: &Crate,
+        toolchain: &Toolchain,
+        path: &Path,
+        f: F,
+    ) -> Result<R>
+    where
+        F: FnOnce() -> Result<R>,
+    {
+        let key = (krate.clone(), toolchain.clone());
+        let tail = LogTail::default();
+        self.tails.lock().unwrap().insert(key.clone(), tail.clone());
+
+        // Ensure the tail is removed even if `f` panics
+        defer! {{
+            self.tails.lock().unwrap().remove(&key);
+        }}
+
+        let file = file_drain(path);
+        let term = TERM_DRAIN.clone();
+        let drain = slog::Duplicate(slog::Duplicate(term, file), TailDrain(tail)).fuse();
+        slog_scope::scope(&slog::Logger::root(drain, slog_o!()), f)
+    }
+
+    /// Get the latest lines logged while building the crate with the toolchain, if it's running.
+    pub fn tail(&self, krate: &Crate, toolchain: &Toolchain) -> Option<Vec<String>> {
+        self.tails
+            .lock()
+            .unwrap()
+            .get(&(krate.clone(), toolchain.clone()))
+            .map(|tail| tail.lines())
+    }
+
+    /// Get the latest lines logged by all the running tasks.
+    pub fn all(&self) -> Vec<(Crate, Toolchain, Vec<String>)> {
+        self.tails
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&(ref krate, ref tc), tail)| (krate.clone(), tc.clone(), tail.lines()))
+            .collect()
+    }
+}
+
+/// Ring buffer with the latest lines written to a log.
+#[derive(Clone, Default)]
+struct LogTail(Arc<Mutex<VecDeque<String>>>);
+
+impl LogTail {
+    fn push(&self, line: String) {
+        let mut lines = self.0.lock().unwrap();
+        if lines.len() == LOG_TAIL_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+struct TailDrain(LogTail);
+
+impl Drain for TailDrain {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(
+        &self,
+        record: &slog::Record,
+        _values: &slog::OwnedKVList,
+    ) -> ::std::result::Result<(), slog::Never> {
+        self.0.push(format!("{}", record.msg()));
+        Ok(())
+    }
+}
+
 lazy_static! {
     static ref START_TIME: Instant = Instant::now();
 }
@@ -85,3 +189,72 @@ pub fn finish() {
     info!("logs: {}", global_log_name().display());
     info!("duration: {}", duration);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{RunningLogs, LOG_TAIL_LINES};
+    use crates::{Crate, RegistryCrate};
+    use errors::*;
+    use std::fs;
+    use std::panic::{self, AssertUnwindSafe};
+    use tempdir::TempDir;
+    use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+
+    #[test]
+    fn test_running_logs_tail() {
+        let dir = TempDir::new("crater-log").unwrap();
+        let path = dir.path().join("log.txt");
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+        let logs = RunningLogs::default();
+
+        logs.redirect(&krate, &MAIN_TOOLCHAIN, &path, || {
+            info!("first line");
+            assert_eq!(
+                logs.tail(&krate, &MAIN_TOOLCHAIN),
+                Some(vec!["first line".to_string()])
+            );
+            assert!(logs.tail(&krate, &TEST_TOOLCHAIN).is_none());
+
+            // Only the latest lines are kept while the task is running
+            for i in 0..LOG_TAIL_LINES {
+                info!("line {}", i);
+            }
+            let lines = logs.tail(&krate, &MAIN_TOOLCHAIN).unwrap();
+            assert_eq!(lines.len(), LOG_TAIL_LINES);
+            assert_eq!(lines[0], "line 0");
+            assert_eq!(lines[LOG_TAIL_LINES - 1], format!("line {}", LOG_TAIL_LINES - 1));
+
+            Ok(())
+        }).unwrap();
+
+        // Once the task finishes the whole log is only available in the file
+        assert!(logs.tail(&krate, &MAIN_TOOLCHAIN).is_none());
+        let log = fs::read_to_string(&path).unwrap();
+        assert!(log.contains("first line"));
+        assert!(log.contains(&format!("line {}", LOG_TAIL_LINES - 1)));
+    }
+
+    #[test]
+    fn test_running_logs_tail_removed_on_panic() {
+        let dir = TempDir::new("crater-log").unwrap();
+        let path = dir.path().join("log.txt");
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+        let logs = RunningLogs::default();
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            logs.redirect(&krate, &MAIN_TOOLCHAIN, &path, || -> Result<()> {
+                info!("first line");
+                panic!("the task crashed");
+            })
+        }));
+        assert!(res.is_err());
+        assert!(logs.tail(&krate, &MAIN_TOOLCHAIN).is_none());
+        assert!(logs.all().is_empty());
+    }
+}
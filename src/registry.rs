@@ -1,14 +1,20 @@
+use crates::{Crate, CrateRequirement, RegistryCrate, RustVersion};
 use crates_index;
-use dirs::LOCAL_DIR;
+use dirs::{INDEX_SNAPSHOTS_DIR, LOCAL_DIR};
 use errors::*;
-use std::path::PathBuf;
+use run::RunCommand;
+use semver::Version;
+use serde_json;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-fn repo_path() -> PathBuf {
+pub fn crates_index_dir() -> PathBuf {
     LOCAL_DIR.join("crates.io-index")
 }
 
 pub fn crates_index_registry() -> Result<crates_index::Index> {
-    let index = crates_index::Index::new(repo_path());
+    let index = crates_index::Index::new(crates_index_dir());
     if index.exists() {
         info!("Fetching latest 'crates.io-index' repository commits");
         index.update()?;
@@ -18,3 +24,407 @@ pub fn crates_index_registry() -> Result<crates_index::Index> {
     }
     Ok(index)
 }
+
+/// Path of the local copy of the crates.io index, without updating it. An error is returned if the
+/// index wasn't downloaded yet.
+pub fn existing_index_dir() -> Result<PathBuf> {
+    let dir = crates_index_dir();
+    if !crates_index::Index::new(&dir).exists() {
+        bail!("the crates.io index is not available yet, try again later");
+    }
+    Ok(dir)
+}
+
+/// Remove the registry crates whose version is yanked in the index at `index_dir`.
+pub fn remove_yanked_crates(index_dir: &Path, crates: Vec<Crate>) -> Vec<Crate> {
+    crates
+        .into_iter()
+        .filter(|krate| {
+            if let Crate::Registry(ref details) = *krate {
+                let yanked = load_versions(index_dir, &details.name)
+                    .into_iter()
+                    .any(|version| version.vers == details.version && version.yanked);
+                if yanked {
+                    info!("removing {} from the experiment: the version is yanked", krate);
+                    return false;
+                }
+            }
+            true
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct IndexVersion {
+    vers: String,
+    #[serde(default)]
+    yanked: bool,
+    #[serde(default)]
+    rust_version: Option<String>,
+    #[serde(default)]
+    deps: Vec<IndexDependency>,
+}
+
+#[derive(Deserialize)]
+struct IndexDependency {
+    name: String,
+    /// Name of the crate on the registry, if the dependency is renamed
+    #[serde(default)]
+    package: Option<String>,
+}
+
+/// Path of the file listing all the versions of the crate in the index.
+fn index_file(index_dir: &Path, name: &str) -> PathBuf {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => index_dir.join("1").join(&name),
+        2 => index_dir.join("2").join(&name),
+        3 => index_dir.join("3").join(&name[..1]).join(&name),
+        _ => index_dir.join(&name[..2]).join(&name[2..4]).join(&name),
+    }
+}
+
+/// Load all the versions of the crate recorded in the index, or none if the crate is missing.
+fn load_versions(index_dir: &Path, name: &str) -> Vec<IndexVersion> {
+    match fs::read_to_string(index_file(index_dir, name)) {
+        Ok(content) => content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Load the `rust-version` declared by the registry crate, if the index records it.
+fn load_rust_version(index_dir: &Path, details: &RegistryCrate) -> Option<RustVersion> {
+    let content = fs::read_to_string(index_file(index_dir, &details.name)).ok()?;
+    content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<IndexVersion>(line).ok())
+        .find(|version| version.vers == details.version)
+        .and_then(|version| version.rust_version)
+        .and_then(|rust_version| rust_version.parse().ok())
+}
+
+/// Keep only the crates declaring a `rust-version` of at least `min` in the index at `index_dir`.
+/// The crates without one, including all the GitHub crates, are kept if `include_missing` is set.
+pub fn select_by_rust_version(
+    index_dir: &Path,
+    crates: Vec<Crate>,
+    min: RustVersion,
+    include_missing: bool,
+) -> Vec<Crate> {
+    crates
+        .into_iter()
+        .filter(|krate| {
+            let rust_version = match *krate {
+                Crate::Registry(ref details) => load_rust_version(index_dir, details),
+                Crate::GitHub(_) => None,
+            };
+            match rust_version {
+                Some(version) => version >= min,
+                None => include_missing,
+            }
+        })
+        .collect()
+}
+
+/// Resolve the requirement to the highest version of the crate matching it in the index at
+/// `index_dir`, ignoring the yanked versions. An error is returned if no version matches.
+pub fn resolve_requirement(index_dir: &Path, requirement: &CrateRequirement) -> Result<Crate> {
+    let content = fs::read_to_string(index_file(index_dir, &requirement.name))
+        .chain_err(|| format!("crate {} not found in the index", requirement.name))?;
+
+    let best = content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<IndexVersion>(line).ok())
+        .filter(|version| !version.yanked)
+        .filter_map(|version| Version::parse(&version.vers).ok())
+        .filter(|version| requirement.req.matches(version))
+        .max();
+
+    match best {
+        Some(version) => Ok(Crate::Registry(RegistryCrate {
+            name: requirement.name.clone(),
+            version: version.to_string(),
+        })),
+        None => bail!("no published version of {} matches", requirement),
+    }
+}
+
+/// Replace the versions of the crates with the ones resolved from the requirements, adding the
+/// crates missing from the list.
+pub fn apply_requirements(
+    index_dir: &Path,
+    mut crates: Vec<Crate>,
+    requirements: &[CrateRequirement],
+) -> Result<Vec<Crate>> {
+    let resolved = requirements
+        .iter()
+        .map(|requirement| resolve_requirement(index_dir, requirement))
+        .collect::<Result<Vec<_>>>()?;
+
+    crates.retain(|krate| match *krate {
+        Crate::Registry(ref details) => requirements.iter().all(|r| r.name != details.name),
+        Crate::GitHub(_) => true,
+    });
+    crates.extend(resolved);
+    Ok(crates)
+}
+
+/// Load the dependencies of each registry crate from the index at `index_dir`, keeping only the
+/// ones which are also part of the list. All the kinds of dependencies are included.
+pub fn load_dependencies(index_dir: &Path, crates: &[Crate]) -> HashMap<Crate, Vec<Crate>> {
+    let mut by_name = HashMap::new();
+    for krate in crates {
+        if let Crate::Registry(ref details) = *krate {
+            by_name
+                .entry(details.name.clone())
+                .or_insert_with(Vec::new)
+                .push(krate.clone());
+        }
+    }
+
+    let mut dependencies = HashMap::new();
+    for (name, versions) in &by_name {
+        for version in load_versions(index_dir, name) {
+            let krate = versions.iter().find(|krate| match **krate {
+                Crate::Registry(ref details) => details.version == version.vers,
+                Crate::GitHub(_) => false,
+            });
+            if let Some(krate) = krate {
+                let mut deps = version
+                    .deps
+                    .iter()
+                    .filter_map(|dep| by_name.get(dep.package.as_ref().unwrap_or(&dep.name)))
+                    .flat_map(|deps| deps.iter())
+                    .filter(|dep| *dep != krate)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                deps.sort();
+                deps.dedup();
+                dependencies.insert(krate.clone(), deps);
+            }
+        }
+    }
+
+    dependencies
+}
+
+pub fn index_snapshot_dir(commit: &str) -> PathBuf {
+    INDEX_SNAPSHOTS_DIR.join(commit)
+}
+
+/// Create a copy of the crates.io index with `master` pointing to the provided commit, which can
+/// then be used by Cargo as a registry.
+pub fn prepare_index_snapshot(commit: &str) -> Result<()> {
+    let dest = index_snapshot_dir(commit);
+    if dest.exists() {
+        info!("crates.io index snapshot {} already present", commit);
+        return Ok(());
+    }
+
+    // Ensure the local copy of the index contains the commit
+    crates_index_registry()?;
+
+    info!("creating crates.io index snapshot {}", commit);
+    fs::create_dir_all(&*INDEX_SNAPSHOTS_DIR)?;
+    let r = RunCommand::new(
+        "git",
+        &[
+            "clone",
+            &crates_index_dir().to_string_lossy(),
+            &dest.to_string_lossy(),
+        ],
+    ).run()
+        .and_then(|_| {
+            RunCommand::new("git", &["checkout", "-B", "master", commit])
+                .cd(&dest)
+                .run()
+        })
+        .chain_err(|| format!("unable to create the index snapshot {}", commit));
+
+    if r.is_err() && dest.exists() {
+        fs::remove_dir_all(&dest)?;
+    }
+
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        apply_requirements, load_dependencies, remove_yanked_crates, resolve_requirement,
+        select_by_rust_version,
+    };
+    use crates::{Crate, GitHubRepo, RegistryCrate};
+    use std::fs;
+    use tempdir::TempDir;
+
+    fn index_entry(name: &str, version: &str, yanked: bool) -> String {
+        format!(
+            "{{\"name\":\"{}\",\"vers\":\"{}\",\"deps\":[],\"cksum\":\"\",\
+             \"features\":{{}},\"yanked\":{}}}\n",
+            name, version, yanked
+        )
+    }
+
+    #[test]
+    fn test_remove_yanked_crates() {
+        let dir = TempDir::new("crater-index").unwrap();
+        fs::create_dir_all(dir.path().join("3/l")).unwrap();
+        fs::write(
+            dir.path().join("3/l/lib"),
+            index_entry("lib", "1.0.0", false) + &index_entry("lib", "1.0.1", true),
+        ).unwrap();
+        fs::create_dir_all(dir.path().join("ot/he")).unwrap();
+        fs::write(
+            dir.path().join("ot/he/other"),
+            index_entry("other", "0.1.0", false),
+        ).unwrap();
+
+        let registry = |name: &str, version: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: version.into(),
+            })
+        };
+        let github = Crate::GitHub(GitHubRepo {
+            org: "brson".into(),
+            name: "hello-rs".into(),
+        });
+
+        let crates = remove_yanked_crates(
+            dir.path(),
+            vec![
+                registry("lib", "1.0.0"),
+                registry("lib", "1.0.1"),
+                registry("other", "0.1.0"),
+                github.clone(),
+            ],
+        );
+        assert_eq!(
+            crates,
+            vec![registry("lib", "1.0.0"), registry("other", "0.1.0"), github]
+        );
+    }
+
+    #[test]
+    fn test_load_dependencies() {
+        let dir = TempDir::new("crater-index").unwrap();
+        fs::create_dir_all(dir.path().join("3/a")).unwrap();
+        fs::write(
+            dir.path().join("3/a/app"),
+            index_entry("app", "1.0.0", false).replace(
+                "\"deps\":[]",
+                "\"deps\":[{\"name\":\"renamed\",\"package\":\"lib\"},{\"name\":\"missing\"}]",
+            ),
+        ).unwrap();
+        fs::create_dir_all(dir.path().join("3/l")).unwrap();
+        fs::write(dir.path().join("3/l/lib"), index_entry("lib", "1.0.0", false)).unwrap();
+
+        let registry = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0.0".into(),
+            })
+        };
+
+        // Only the dependencies which are part of the list are returned, even if renamed
+        let deps = load_dependencies(dir.path(), &[registry("app"), registry("lib")]);
+        assert_eq!(deps[&registry("app")], vec![registry("lib")]);
+        assert!(deps[&registry("lib")].is_empty());
+    }
+
+    #[test]
+    fn test_select_by_rust_version() {
+        let dir = TempDir::new("crater-index").unwrap();
+        let entry = |name: &str, version: &str, rust_version: Option<&str>| {
+            let mut entry = index_entry(name, version, false);
+            if let Some(rust_version) = rust_version {
+                entry = entry.replace(
+                    "\"yanked\"",
+                    &format!("\"rust_version\":\"{}\",\"yanked\"", rust_version),
+                );
+            }
+            entry
+        };
+        fs::create_dir_all(dir.path().join("3/n")).unwrap();
+        fs::write(
+            dir.path().join("3/n/new"),
+            entry("new", "1.0.0", Some("1.50")) + &entry("new", "2.0.0", Some("1.60.1")),
+        ).unwrap();
+        fs::create_dir_all(dir.path().join("ol/d-")).unwrap();
+        fs::write(dir.path().join("ol/d-/old-crate"), entry("old-crate", "0.1.0", None)).unwrap();
+
+        let registry = |name: &str, version: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: version.into(),
+            })
+        };
+        let github = Crate::GitHub(GitHubRepo {
+            org: "brson".into(),
+            name: "hello-rs".into(),
+        });
+        let crates = vec![
+            registry("new", "1.0.0"),
+            registry("new", "2.0.0"),
+            registry("old-crate", "0.1.0"),
+            github.clone(),
+        ];
+        let min = "1.56".parse().unwrap();
+
+        // Only the version declaring a recent enough rust-version is selected
+        assert_eq!(
+            select_by_rust_version(dir.path(), crates.clone(), min, false),
+            vec![registry("new", "2.0.0")]
+        );
+
+        // The crates without a rust-version can be kept
+        assert_eq!(
+            select_by_rust_version(dir.path(), crates, min, true),
+            vec![registry("new", "2.0.0"), registry("old-crate", "0.1.0"), github]
+        );
+    }
+
+    #[test]
+    fn test_resolve_requirement() {
+        let dir = TempDir::new("crater-index").unwrap();
+        fs::create_dir_all(dir.path().join("se/rd")).unwrap();
+        fs::write(
+            dir.path().join("se/rd/serde"),
+            index_entry("serde", "0.9.0", false)
+                + &index_entry("serde", "1.0.0", false)
+                + &index_entry("serde", "1.2.0", false)
+                + &index_entry("serde", "1.3.0", true)
+                + &index_entry("serde", "1.4.0-beta", false)
+                + &index_entry("serde", "2.0.0", false),
+        ).unwrap();
+
+        let serde = |version: &str| {
+            Crate::Registry(RegistryCrate {
+                name: "serde".into(),
+                version: version.into(),
+            })
+        };
+
+        // The highest matching version is picked, skipping the yanked ones and the pre-releases
+        let caret = "serde@^1.0".parse().unwrap();
+        assert_eq!(resolve_requirement(dir.path(), &caret).unwrap(), serde("1.2.0"));
+
+        assert!(resolve_requirement(dir.path(), &"serde@^3".parse().unwrap()).is_err());
+        assert!(resolve_requirement(dir.path(), &"missing@^1".parse().unwrap()).is_err());
+
+        // The pinned version of the crate is replaced by the resolved one
+        let github = Crate::GitHub(GitHubRepo {
+            org: "brson".into(),
+            name: "hello-rs".into(),
+        });
+        assert_eq!(
+            apply_requirements(dir.path(), vec![serde("0.9.0"), github.clone()], &[caret])
+                .unwrap(),
+            vec![github, serde("1.2.0")]
+        );
+    }
+}
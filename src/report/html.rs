@@ -21,6 +21,7 @@ impl ResultColor for Comparison {
     fn color(&self) -> Color {
         match self {
             Comparison::Regressed => Color::Single("#db3026"),
+            Comparison::ExpectedRegression => Color::Striped("#db3026", "#e4554c"),
             Comparison::Fixed => Color::Single("#5630db"),
             Comparison::Skipped => Color::Striped("#494b4a", "#555555"),
             Comparison::Unknown => Color::Single("#494b4a"),
@@ -29,6 +30,7 @@ impl ResultColor for Comparison {
             Comparison::SameTestSkipped => Color::Striped("#72a156", "#80b65f"),
             Comparison::SameTestPass => Color::Single("#72a156"),
             Comparison::Error => Color::Single("#d77026"),
+            Comparison::SpuriousFailure => Color::Striped("#d77026", "#e0883f"),
         }
     }
 }
@@ -38,8 +40,11 @@ impl ResultColor for TestResult {
         match self {
             TestResult::BuildFail => Color::Single("#db3026"),
             TestResult::TestFail => Color::Single("#65461e"),
+            TestResult::BuildTimeout => Color::Striped("#db3026", "#e25a52"),
+            TestResult::TestTimeout => Color::Striped("#65461e", "#7a5a30"),
             TestResult::TestSkipped | TestResult::TestPass => Color::Single("#62a156"),
             TestResult::Error => Color::Single("#d77026"),
+            TestResult::Cancelled | TestResult::Ignored => Color::Striped("#494b4a", "#555555"),
         }
     }
 }
@@ -50,6 +55,10 @@ struct Context<'a> {
     categories: HashMap<Comparison, Vec<CrateResult>>,
     full: bool,
     crates_count: usize,
+    crater_version: Option<&'a str>,
+    note: Option<&'a str>,
+    /// Whether the standard error of the runs is also available as a separate log
+    stderr_logs: bool,
 
     comparison_colors: HashMap<Comparison, Color>,
     result_colors: HashMap<TestResult, Color>,
@@ -57,6 +66,9 @@ struct Context<'a> {
 
 fn write_report<W: ReportWriter>(
     ex: &Experiment,
+    crater_version: Option<&str>,
+    note: Option<&str>,
+    stderr_logs: bool,
     res: &TestResults,
     full: bool,
     to: &str,
@@ -96,6 +108,9 @@ fn write_report<W: ReportWriter>(
         categories,
         full,
         crates_count: res.crates.len(),
+        crater_version,
+        note,
+        stderr_logs,
 
         comparison_colors,
         result_colors,
@@ -110,13 +125,34 @@ fn write_report<W: ReportWriter>(
 
 pub fn write_html_report<W: ReportWriter>(
     ex: &Experiment,
+    crater_version: Option<&str>,
+    note: Option<&str>,
+    stderr_logs: bool,
     res: &TestResults,
     dest: &W,
 ) -> Result<()> {
     let js_in = assets::load("report.js")?;
     let css_in = assets::load("report.css")?;
-    write_report(ex, res, false, "index.html", dest)?;
-    write_report(ex, res, true, "full.html", dest)?;
+    write_report(
+        ex,
+        crater_version,
+        note,
+        stderr_logs,
+        res,
+        false,
+        "index.html",
+        dest,
+    )?;
+    write_report(
+        ex,
+        crater_version,
+        note,
+        stderr_logs,
+        res,
+        true,
+        "full.html",
+        dest,
+    )?;
 
     info!("copying static assets");
     dest.write_bytes("report.js", js_in.content()?.into_owned(), js_in.mime())?;
@@ -4,7 +4,8 @@ use errors::*;
 use ex::Experiment;
 use file;
 use mime::{self, Mime};
-use results::{ReadResults, TestResult};
+use run;
+use results::{self, ReadResults, ResultOverride, TestCounts, TestResult};
 use serde_json;
 use std::borrow::Cow;
 #[cfg(test)]
@@ -47,12 +48,14 @@ struct CrateResult {
 }
 
 #[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Copy, Clone, Debug)]
-enum Comparison {
+pub enum Comparison {
     Regressed,
+    ExpectedRegression,
     Fixed,
     Skipped,
     Unknown,
     Error,
+    SpuriousFailure,
     SameBuildFail,
     SameTestFail,
     SameTestSkipped,
@@ -62,9 +65,12 @@ enum Comparison {
 impl Comparison {
     fn show_in_summary(self) -> bool {
         match self {
-            Comparison::Regressed | Comparison::Fixed | Comparison::Unknown | Comparison::Error => {
-                true
-            }
+            Comparison::Regressed
+            | Comparison::ExpectedRegression
+            | Comparison::Fixed
+            | Comparison::Unknown
+            | Comparison::Error
+            | Comparison::SpuriousFailure => true,
             Comparison::Skipped
             | Comparison::SameBuildFail
             | Comparison::SameTestFail
@@ -78,6 +84,17 @@ impl Comparison {
 struct BuildTestResult {
     res: TestResult,
     log: String,
+    #[serde(default)]
+    overridden: Option<OverriddenResult>,
+    /// Outcome of the individual tests, if they were run
+    #[serde(default)]
+    tests: Option<TestCounts>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct OverriddenResult {
+    original: TestResult,
+    reason: String,
 }
 
 fn crate_to_path_fragment(toolchain: &Toolchain, krate: &Crate, encode: bool) -> PathBuf {
@@ -114,36 +131,110 @@ fn crate_to_path_fragment(toolchain: &Toolchain, krate: &Crate, encode: bool) ->
     path
 }
 
+/// Path of the log of the crate built with the toolchain, relative to the root of the report.
+pub fn log_url_path(toolchain: &Toolchain, krate: &Crate) -> String {
+    crate_to_path_fragment(toolchain, krate, true)
+        .join("log.txt")
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
 pub fn generate_report<DB: ReadResults>(
     db: &DB,
     config: &Config,
     ex: &Experiment,
 ) -> Result<TestResults> {
     let shas = db.load_all_shas(ex)?;
+    let sources = [ex.results_source(0), ex.results_source(1)];
     let res = ex
         .crates
         .clone()
         .into_iter()
         .map(|krate| {
             // Any errors here will turn into unknown results
-            let crate_results = ex.toolchains.iter().map(|tc| -> Result<BuildTestResult> {
-                let res = db
-                    .load_test_result(ex, tc, &krate)?
-                    .ok_or_else(|| "no result")?;
-
-                Ok(BuildTestResult {
-                    res,
-                    log: crate_to_path_fragment(tc, &krate, true)
-                        .to_str()
-                        .unwrap()
-                        .to_string(),
-                })
-            });
+            let crate_results = ex
+                .toolchains
+                .iter()
+                .zip(sources.iter())
+                .map(|(tc, source)| -> Result<BuildTestResult> {
+                    let mut res = db
+                        .load_test_result(source, tc, &krate)?
+                        .ok_or_else(|| "no result")?;
+
+                    // Reviewers can override spurious results, keeping track of the original one
+                    let overridden = if let Some(ResultOverride { result, reason }) =
+                        db.load_result_override(source, tc, &krate)?
+                    {
+                        let original = res;
+                        res = result;
+                        Some(OverriddenResult { original, reason })
+                    } else {
+                        None
+                    };
+
+                    let tests = match res {
+                        TestResult::TestPass | TestResult::TestFail => {
+                            db.load_test_counts(source, tc, &krate)?
+                        }
+                        _ => None,
+                    };
+
+                    Ok(BuildTestResult {
+                        res,
+                        log: crate_to_path_fragment(tc, &krate, true)
+                            .to_str()
+                            .unwrap()
+                            .to_string(),
+                        overridden,
+                        tests,
+                    })
+                });
             // Convert errors to Nones
             let mut crate_results = crate_results.map(|r| r.ok()).collect::<Vec<_>>();
             let crate2 = crate_results.pop().expect("");
             let crate1 = crate_results.pop().expect("");
-            let comp = compare(config, &krate, &crate1, &crate2);
+            let mut comp = compare_to_control(
+                config,
+                &krate,
+                &[
+                    crate1.as_ref().map(|r| r.res),
+                    crate2.as_ref().map(|r| r.res),
+                ],
+                ex.control_index,
+            );
+
+            // Failures caused by the infrastructure are not real regressions, so they're moved to
+            // their own category to avoid confusing the reviewers
+            if comp == Comparison::Regressed || comp == Comparison::Error {
+                let runs = [&crate1, &crate2];
+                let spurious = ex
+                    .toolchains
+                    .iter()
+                    .zip(sources.iter())
+                    .zip(runs.iter())
+                    .any(|((tc, source), run)| match **run {
+                        Some(BuildTestResult {
+                            res,
+                            overridden: None,
+                            ..
+                        }) if res.is_failure() => db
+                            .load_log(source, tc, &krate)
+                            .ok()
+                            .and_then(|log| log)
+                            .map(|log| results::is_infra_failure(&log))
+                            .unwrap_or(false),
+                        _ => false,
+                    });
+                if spurious {
+                    comp = Comparison::SpuriousFailure;
+                }
+            }
+
+            // Regressions the maintainers already know about are listed on their own
+            if comp == Comparison::Regressed && ex.expected_regressions.contains(&krate) {
+                comp = Comparison::ExpectedRegression;
+            }
 
             Ok(CrateResult {
                 name: crate_to_name(&krate, &shas)?,
@@ -167,6 +258,7 @@ fn write_logs<DB: ReadResults, W: ReportWriter>(
 ) -> Result<()> {
     let num_crates = ex.crates.len();
     let progress_every = (num_crates / PROGRESS_FRACTION) + 1;
+    let sources = [ex.results_source(0), ex.results_source(1)];
     for (i, krate) in ex.crates.iter().enumerate() {
         if i % progress_every == 0 {
             info!("wrote logs for {}/{} crates", i, num_crates)
@@ -176,10 +268,10 @@ fn write_logs<DB: ReadResults, W: ReportWriter>(
             continue;
         }
 
-        for tc in &ex.toolchains {
+        for (tc, source) in ex.toolchains.iter().zip(sources.iter()) {
             let log_path = crate_to_path_fragment(tc, krate, false).join("log.txt");
             let content = db
-                .load_log(ex, tc, krate)
+                .load_log(source, tc, krate)
                 .and_then(|c| c.ok_or_else(|| "missing logs".into()))
                 .chain_err(|| format!("failed to read log of {} on {}", krate, tc.to_string()));
             let content = match content {
@@ -189,17 +281,33 @@ fn write_logs<DB: ReadResults, W: ReportWriter>(
                     continue;
                 }
             };
+            if config.sandbox.tag_output_streams {
+                let stderr_path = crate_to_path_fragment(tc, krate, false).join("stderr.txt");
+                dest.write_bytes(stderr_path, stderr_log(&content), &mime::TEXT_PLAIN_UTF_8)?;
+            }
             dest.write_bytes(log_path, content, &mime::TEXT_PLAIN_UTF_8)?;
         }
     }
     Ok(())
 }
 
+/// Keep only the lines of a log written to the standard error, without their tag.
+fn stderr_log(log: &[u8]) -> Vec<u8> {
+    let mut stderr = String::new();
+    for line in run::tagged_lines(&String::from_utf8_lossy(log), run::STDERR_TAG) {
+        stderr.push_str(line);
+        stderr.push('\n');
+    }
+    stderr.into_bytes()
+}
+
 pub fn gen<DB: ReadResults, W: ReportWriter + Display>(
     db: &DB,
     ex: &Experiment,
     dest: &W,
     config: &Config,
+    crater_version: Option<&str>,
+    note: Option<&str>,
 ) -> Result<()> {
     let res = generate_report(db, config, ex)?;
 
@@ -217,7 +325,14 @@ pub fn gen<DB: ReadResults, W: ReportWriter + Display>(
     )?;
 
     info!("writing html files");
-    html::write_html_report(ex, &res, dest)?;
+    html::write_html_report(
+        ex,
+        crater_version,
+        note,
+        config.sandbox.tag_output_streams,
+        &res,
+        dest,
+    )?;
     info!("writing logs");
     write_logs(db, ex, dest, config)?;
 
@@ -253,18 +368,27 @@ fn crate_to_url(c: &Crate, shas: &HashMap<GitHubRepo, String>) -> Result<String>
     })
 }
 
-fn compare(
+/// Compare the results of the crate with the two toolchains of the experiment. This is the
+/// comparison shown in the report, so it should be used everywhere regressions are looked for.
+pub fn compare(
     config: &Config,
     krate: &Crate,
-    r1: &Option<BuildTestResult>,
-    r2: &Option<BuildTestResult>,
+    r1: Option<TestResult>,
+    r2: Option<TestResult>,
 ) -> Comparison {
     use results::TestResult::*;
+
+    // Timeouts are compared as the failures of the step they happened in
+    fn without_timeout(res: TestResult) -> TestResult {
+        match res {
+            BuildTimeout => BuildFail,
+            TestTimeout => TestFail,
+            other => other,
+        }
+    }
+
     match (r1, r2) {
-        (
-            &Some(BuildTestResult { res: ref res1, .. }),
-            &Some(BuildTestResult { res: ref res2, .. }),
-        ) => match (res1, res2) {
+        (Some(res1), Some(res2)) => match (&without_timeout(res1), &without_timeout(res2)) {
             (&BuildFail, &BuildFail) => Comparison::SameBuildFail,
             (&TestFail, &TestFail) => Comparison::SameTestFail,
             (&TestSkipped, &TestSkipped) => Comparison::SameTestSkipped,
@@ -277,12 +401,18 @@ fn compare(
             | (&TestPass, &BuildFail)
             | (&TestSkipped, &BuildFail)
             | (&TestFail, &BuildFail) => Comparison::Regressed,
+            (&Cancelled, _) | (_, &Cancelled) | (&Ignored, _) | (_, &Ignored) => {
+                Comparison::Skipped
+            }
             (&Error, _) | (_, &Error) => Comparison::Error,
+            // The tests were only run with one of the toolchains (for example because the
+            // configuration changed in the meantime), so the outcomes can't be compared
             (&TestFail, &TestSkipped)
             | (&TestPass, &TestSkipped)
             | (&TestSkipped, &TestFail)
-            | (&TestSkipped, &TestPass) => {
-                panic!("can't compare {} and {}", res1, res2);
+            | (&TestSkipped, &TestPass) => Comparison::Unknown,
+            (&BuildTimeout, _) | (_, &BuildTimeout) | (&TestTimeout, _) | (_, &TestTimeout) => {
+                unreachable!()
             }
         },
         _ if config.should_skip(krate) => Comparison::Skipped,
@@ -290,6 +420,43 @@ fn compare(
     }
 }
 
+/// Order in which the comparisons with the control toolchain are picked when they differ between
+/// the other toolchains: a regression is reported even if another toolchain fixed the crate.
+const CONTROL_PRECEDENCE: &[Comparison] = &[
+    Comparison::Regressed,
+    Comparison::Error,
+    Comparison::Fixed,
+    Comparison::Unknown,
+    Comparison::Skipped,
+    Comparison::SameBuildFail,
+    Comparison::SameTestFail,
+    Comparison::SameTestSkipped,
+    Comparison::SameTestPass,
+];
+
+/// Compare the result of each toolchain with the one of the toolchain at `control`, which is the
+/// baseline the regressions are looked for against. The results of the other toolchains are never
+/// compared between themselves.
+pub fn compare_to_control(
+    config: &Config,
+    krate: &Crate,
+    results: &[Option<TestResult>],
+    control: usize,
+) -> Comparison {
+    let comparisons = results
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != control)
+        .map(|(_, &res)| compare(config, krate, results[control], res))
+        .collect::<Vec<_>>();
+
+    CONTROL_PRECEDENCE
+        .iter()
+        .cloned()
+        .find(|comp| comparisons.contains(comp))
+        .unwrap_or(Comparison::Unknown)
+}
+
 pub trait ReportWriter {
     fn write_bytes<P: AsRef<Path>>(&self, path: P, b: Vec<u8>, mime: &Mime) -> Result<()>;
     fn write_string<P: AsRef<Path>>(&self, path: P, s: Cow<str>, mime: &Mime) -> Result<()>;
@@ -342,10 +509,19 @@ mod tests {
     use config::{Config, CrateConfig};
     use crates::{Crate, GitHubRepo, RegistryCrate};
     use ex::{ExCapLints, ExMode, Experiment};
-    use results::{DummyDB, TestResult};
+    use results::{DummyDB, ResultOverride, TestResult};
     use std::collections::HashMap;
     use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
 
+    #[test]
+    fn test_stderr_log() {
+        let log = "Oct 16 00:58:05.123 INFO [stdout] Compiling foo\n\
+                   Oct 16 00:58:05.124 INFO [stderr] error: aborting\n\
+                   Oct 16 00:58:05.125 INFO [stdout] [stderr] not stderr\n\
+                   Oct 16 00:58:05.126 INFO running `cargo build`\n";
+        assert_eq!(stderr_log(log.as_bytes()), b"error: aborting\n".to_vec());
+    }
+
     #[derive(Default)]
     pub struct DummyWriter {
         results: RefCell<HashMap<(PathBuf, Mime), Vec<u8>>>,
@@ -486,14 +662,8 @@ mod tests {
                         $cmp(
                             $config,
                             $reg,
-                            &Some(BuildTestResult {
-                                res: TestResult::$a,
-                                log: String::with_capacity(0),
-                            }),
-                            &Some(BuildTestResult {
-                                res: TestResult::$b,
-                                log: String::with_capacity(0),
-                            }),
+                            Some(TestResult::$a),
+                            Some(TestResult::$b),
                         ),
                         Comparison::$c
                     );
@@ -532,10 +702,23 @@ mod tests {
                 TestSkipped + Error = Error,
                 TestFail + Error = Error,
                 BuildFail + Error = Error,
+                Cancelled + TestPass = Skipped,
+                TestPass + Cancelled = Skipped,
+                Cancelled + Error = Skipped,
+                Ignored + TestPass = Skipped,
+                TestPass + Ignored = Skipped,
+                Ignored + Error = Skipped,
+                TestPass + BuildTimeout = Regressed,
+                TestPass + TestTimeout = Regressed,
+                BuildTimeout + TestPass = Fixed,
+                BuildTimeout + BuildFail = SameBuildFail,
+                TestTimeout + TestTimeout = SameTestFail,
+                TestPass + TestSkipped = Unknown,
+                TestSkipped + TestFail = Unknown,
             ]
         );
 
-        assert_eq!(compare(&config, &reg, &None, &None), Comparison::Unknown);
+        assert_eq!(compare(&config, &reg, None, None), Comparison::Unknown);
 
         config.crates.insert(
             "lazy_static".into(),
@@ -545,9 +728,43 @@ mod tests {
                 quiet: false,
                 update_lockfile: false,
                 broken: false,
+                test_args: Vec::new(),
             },
         );
-        assert_eq!(compare(&config, &reg, &None, &None), Comparison::Skipped);
+        assert_eq!(compare(&config, &reg, None, None), Comparison::Skipped);
+    }
+
+    #[test]
+    fn test_compare_to_control() {
+        use results::TestResult::*;
+
+        let config = Config::default();
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+        let compare_to = |results: &[Option<TestResult>], control| {
+            compare_to_control(&config, &krate, results, control)
+        };
+
+        // With two toolchains the control one is compared with the other one
+        let results = [Some(TestPass), Some(TestFail)];
+        assert_eq!(compare_to(&results, 0), Comparison::Regressed);
+        assert_eq!(compare_to(&results, 1), Comparison::Fixed);
+
+        // The second toolchain regressed against the control one, even if the third one passes
+        let results = [Some(TestPass), Some(TestFail), Some(TestPass)];
+        assert_eq!(compare_to(&results, 0), Comparison::Regressed);
+
+        // The second toolchain regressed against the first one, but the toolchains are only
+        // compared with the control one, against which the first toolchain fixed the crate
+        let results = [Some(TestPass), Some(TestFail), Some(TestFail)];
+        assert_eq!(compare_to(&results, 2), Comparison::Fixed);
+        assert_eq!(compare_to(&results, 1), Comparison::Fixed);
+
+        // A toolchain without results can't be compared with the control one
+        let results = [Some(TestPass), Some(TestPass), None];
+        assert_eq!(compare_to(&results, 0), Comparison::Unknown);
     }
 
     #[test]
@@ -566,6 +783,17 @@ mod tests {
             toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
             mode: ExMode::BuildAndTest,
             cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
         };
 
         let mut db = DummyDB::default();
@@ -594,15 +822,35 @@ mod tests {
             TEST_TOOLCHAIN.clone(),
             b"beta log".to_vec(),
         );
+        let counts = TestCounts {
+            passed: 2,
+            failed: 0,
+            ignored: 1,
+        };
+        db.add_dummy_test_counts(&ex, gh.clone(), MAIN_TOOLCHAIN.clone(), counts);
 
         let writer = DummyWriter::default();
-        gen(&db, &ex, &writer, &config).unwrap();
+        gen(
+            &db,
+            &ex,
+            &writer,
+            &config,
+            Some("f00ba7"),
+            Some("Contact <foo@example.com>"),
+        ).unwrap();
 
         assert_eq!(
             writer.get("config.json", &mime::APPLICATION_JSON),
             serde_json::to_vec(&ex).unwrap()
         );
 
+        let index = String::from_utf8(writer.get("index.html", &mime::TEXT_HTML)).unwrap();
+        assert!(index.contains("f00ba7"));
+
+        // The note is escaped before being rendered
+        assert!(index.contains("Contact &lt;foo@example.com&gt;"));
+        assert!(!index.contains("<foo@example.com>"));
+
         assert_eq!(
             &writer.get("stable/gh/brson.hello-rs/log.txt", &mime::TEXT_PLAIN_UTF_8),
             b"stable log"
@@ -640,5 +888,313 @@ mod tests {
             (&crate_result.runs[1]).as_ref().unwrap().log.as_str(),
             "beta/gh/brson.hello-rs"
         );
+        assert_eq!((&crate_result.runs[0]).as_ref().unwrap().tests, Some(counts));
+        assert_eq!((&crate_result.runs[1]).as_ref().unwrap().tests, None);
+    }
+
+    #[test]
+    fn test_report_with_overrides() {
+        let config = Config::default();
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![krate.clone()],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        let mut db = DummyDB::default();
+        db.add_dummy_result(
+            &ex,
+            krate.clone(),
+            MAIN_TOOLCHAIN.clone(),
+            TestResult::TestPass,
+        );
+        db.add_dummy_result(
+            &ex,
+            krate.clone(),
+            TEST_TOOLCHAIN.clone(),
+            TestResult::BuildFail,
+        );
+        db.add_dummy_override(
+            &ex,
+            krate.clone(),
+            TEST_TOOLCHAIN.clone(),
+            ResultOverride {
+                result: TestResult::TestPass,
+                reason: "spurious failure".into(),
+            },
+        );
+
+        let result = generate_report(&db, &config, &ex).unwrap();
+        let crate_result = &result.crates[0];
+
+        // The crate isn't a regression anymore, but the original result is still shown
+        assert_eq!(crate_result.res, Comparison::SameTestPass);
+        assert!((&crate_result.runs[0]).as_ref().unwrap().overridden.is_none());
+        let run = (&crate_result.runs[1]).as_ref().unwrap();
+        assert_eq!(run.res, TestResult::TestPass);
+        let overridden = run.overridden.as_ref().unwrap();
+        assert_eq!(overridden.original, TestResult::BuildFail);
+        assert_eq!(overridden.reason.as_str(), "spurious failure");
+    }
+
+    #[test]
+    fn test_report_with_ignored_crate() {
+        let config = Config::default();
+
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0".into(),
+            })
+        };
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![krate("ignored"), krate("regressed")],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        let mut db = DummyDB::default();
+        for name in &["ignored", "regressed"] {
+            db.add_dummy_result(&ex, krate(name), MAIN_TOOLCHAIN.clone(), TestResult::TestPass);
+            db.add_dummy_result(&ex, krate(name), TEST_TOOLCHAIN.clone(), TestResult::BuildFail);
+        }
+        for tc in &[MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()] {
+            db.add_dummy_override(
+                &ex,
+                krate("ignored"),
+                tc.clone(),
+                ResultOverride {
+                    result: TestResult::Ignored,
+                    reason: "depends on a removed feature".into(),
+                },
+            );
+        }
+
+        // The ignored crate is not counted as a regression, nor as a pass or a failure
+        let result = generate_report(&db, &config, &ex).unwrap();
+        let comparisons = result
+            .crates
+            .iter()
+            .map(|c| (c.name.as_str(), c.res))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            comparisons,
+            vec![
+                ("ignored-1.0", Comparison::Skipped),
+                ("regressed-1.0", Comparison::Regressed),
+            ]
+        );
+
+        // The original results are still available
+        let run = (&result.crates[0].runs[1]).as_ref().unwrap();
+        assert_eq!(run.res, TestResult::Ignored);
+        assert_eq!(
+            run.overridden.as_ref().unwrap().original,
+            TestResult::BuildFail
+        );
+    }
+
+    #[test]
+    fn test_report_with_spurious_failure() {
+        let config = Config::default();
+
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0".into(),
+            })
+        };
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![krate("network"), krate("regressed")],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        let mut db = DummyDB::default();
+        for name in &["network", "regressed"] {
+            db.add_dummy_result(&ex, krate(name), MAIN_TOOLCHAIN.clone(), TestResult::TestPass);
+            db.add_dummy_result(&ex, krate(name), TEST_TOOLCHAIN.clone(), TestResult::BuildFail);
+        }
+        db.add_dummy_log(
+            &ex,
+            krate("network"),
+            TEST_TOOLCHAIN.clone(),
+            b"error: failed to download `lazy_static v1.0.0`".to_vec(),
+        );
+        db.add_dummy_log(
+            &ex,
+            krate("regressed"),
+            TEST_TOOLCHAIN.clone(),
+            b"thread 'fixtures' panicked at 'failed to download the fixtures'\n\
+              error: Could not compile `regressed`.\n\n\
+              Caused by:\n  process didn't exit successfully (exit code: 101)"
+                .to_vec(),
+        );
+
+        let result = generate_report(&db, &config, &ex).unwrap();
+        let comparisons = result
+            .crates
+            .iter()
+            .map(|c| (c.name.as_str(), c.res))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            comparisons,
+            vec![
+                ("network-1.0", Comparison::SpuriousFailure),
+                ("regressed-1.0", Comparison::Regressed),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report_with_expected_regressions() {
+        let config = Config::default();
+
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0".into(),
+            })
+        };
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![krate("expected"), krate("unexpected")],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: vec![krate("expected")],
+            control_index: 0,
+        };
+
+        let mut db = DummyDB::default();
+        for name in &["expected", "unexpected"] {
+            db.add_dummy_result(&ex, krate(name), MAIN_TOOLCHAIN.clone(), TestResult::TestPass);
+            db.add_dummy_result(&ex, krate(name), TEST_TOOLCHAIN.clone(), TestResult::BuildFail);
+        }
+
+        let result = generate_report(&db, &config, &ex).unwrap();
+        let comparisons = result
+            .crates
+            .iter()
+            .map(|c| (c.name.as_str(), c.res))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            comparisons,
+            vec![
+                ("expected-1.0", Comparison::ExpectedRegression),
+                ("unexpected-1.0", Comparison::Regressed),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report_with_baseline() {
+        let config = Config::default();
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![krate.clone()],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: Some("base".into()),
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let baseline = Experiment {
+            name: "base".to_string(),
+            baseline: None,
+            ..ex.clone()
+        };
+
+        let mut db = DummyDB::default();
+        db.add_dummy_result(
+            &baseline,
+            krate.clone(),
+            MAIN_TOOLCHAIN.clone(),
+            TestResult::TestPass,
+        );
+        db.add_dummy_result(
+            &ex,
+            krate.clone(),
+            TEST_TOOLCHAIN.clone(),
+            TestResult::BuildFail,
+        );
+
+        // The start toolchain results are loaded from the baseline experiment
+        let result = generate_report(&db, &config, &ex).unwrap();
+        let crate_result = &result.crates[0];
+        assert_eq!(crate_result.res, Comparison::Regressed);
+        assert_eq!(
+            (&crate_result.runs[0]).as_ref().unwrap().res,
+            TestResult::TestPass
+        );
     }
 }
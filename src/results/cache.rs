@@ -0,0 +1,237 @@
+use crates::{Crate, GitHubRepo};
+use errors::*;
+use ex::{ExStep, Experiment};
+use results::{ReadResults, ResultOverride, TestCounts, TestResult, WriteResults};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use toolchain::Toolchain;
+
+type CachedResults = HashMap<(Crate, Toolchain), TestResult>;
+
+/// Wrapper around a results database keeping all the results of an experiment in memory after
+/// they're first loaded with a single query. The cache of an experiment is dropped when a new
+/// result is recorded through it, but results stored directly in the database (for example with
+/// `ResultsDB::store`) are not noticed: the cache should only live while the results can't change,
+/// like during the generation of a single report.
+pub struct ResultCache<'a, DB: 'a> {
+    db: &'a DB,
+    experiments: Mutex<HashMap<String, CachedResults>>,
+}
+
+impl<'a, DB: ReadResults> ResultCache<'a, DB> {
+    pub fn new(db: &'a DB) -> Self {
+        ResultCache {
+            db,
+            experiments: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn invalidate(&self, ex: &Experiment) {
+        self.experiments.lock().unwrap().remove(&ex.name);
+    }
+
+    fn preload(&self, ex: &Experiment) -> Result<CachedResults> {
+        self.db.load_all_results(ex)
+    }
+}
+
+impl<'a, DB: ReadResults> ReadResults for ResultCache<'a, DB> {
+    fn load_all_shas(&self, ex: &Experiment) -> Result<HashMap<GitHubRepo, String>> {
+        self.db.load_all_shas(ex)
+    }
+
+    fn load_log(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<Vec<u8>>> {
+        self.db.load_log(ex, toolchain, krate)
+    }
+
+    fn load_test_result(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<TestResult>> {
+        let mut experiments = self.experiments.lock().unwrap();
+        if !experiments.contains_key(&ex.name) {
+            let results = self.preload(ex)?;
+            experiments.insert(ex.name.clone(), results);
+        }
+
+        Ok(experiments[&ex.name]
+            .get(&(krate.clone(), toolchain.clone()))
+            .cloned())
+    }
+
+    fn load_result_override(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<ResultOverride>> {
+        self.db.load_result_override(ex, toolchain, krate)
+    }
+
+    fn load_metadata(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<String>> {
+        self.db.load_metadata(ex, toolchain, krate)
+    }
+
+    fn load_test_counts(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<TestCounts>> {
+        self.db.load_test_counts(ex, toolchain, krate)
+    }
+
+    fn load_all_results(&self, ex: &Experiment) -> Result<CachedResults> {
+        let mut experiments = self.experiments.lock().unwrap();
+        if !experiments.contains_key(&ex.name) {
+            let results = self.preload(ex)?;
+            experiments.insert(ex.name.clone(), results);
+        }
+
+        Ok(experiments[&ex.name].clone())
+    }
+}
+
+impl<'a, DB: ReadResults + WriteResults> WriteResults for ResultCache<'a, DB> {
+    fn get_result(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<TestResult>> {
+        self.db.get_result(ex, toolchain, krate)
+    }
+
+    fn record_sha(&self, ex: &Experiment, repo: &GitHubRepo, sha: &str) -> Result<()> {
+        self.db.record_sha(ex, repo, sha)
+    }
+
+    fn record_result<F>(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        f: F,
+    ) -> Result<TestResult>
+    where
+        F: FnOnce() -> Result<TestResult>,
+    {
+        let result = self.db.record_result(ex, toolchain, krate, f);
+        self.invalidate(ex);
+        result
+    }
+
+    fn record_metadata(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        metadata: &str,
+    ) -> Result<()> {
+        self.db.record_metadata(ex, toolchain, krate, metadata)
+    }
+
+    fn use_attempt(
+        &self,
+        ex: &Experiment,
+        krate: &Crate,
+        step: ExStep,
+        budget: u32,
+    ) -> Result<bool> {
+        self.db.use_attempt(ex, krate, step, budget)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResultCache;
+    use crates::{Crate, RegistryCrate};
+    use ex::{ExCapLints, ExMode, Experiment};
+    use results::{DummyDB, ReadResults, TestResult, WriteResults};
+    use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+
+    #[test]
+    fn test_result_cache() {
+        let first = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+        let second = Crate::Registry(RegistryCrate {
+            name: "bitflags".into(),
+            version: "1.0".into(),
+        });
+
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![first.clone(), second.clone()],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        let mut db = DummyDB::default();
+        db.add_dummy_result(
+            &ex,
+            first.clone(),
+            MAIN_TOOLCHAIN.clone(),
+            TestResult::TestPass,
+        );
+        db.add_dummy_result(
+            &ex,
+            first.clone(),
+            TEST_TOOLCHAIN.clone(),
+            TestResult::BuildFail,
+        );
+        db.add_dummy_result(
+            &ex,
+            second.clone(),
+            MAIN_TOOLCHAIN.clone(),
+            TestResult::TestFail,
+        );
+
+        // The cache returns the same results as the database
+        let cache = ResultCache::new(&db);
+        for krate in &ex.crates {
+            for tc in &ex.toolchains {
+                assert_eq!(
+                    cache.load_test_result(&ex, tc, krate).unwrap(),
+                    db.load_test_result(&ex, tc, krate).unwrap()
+                );
+            }
+        }
+
+        // Recording a new result invalidates the cache
+        cache
+            .record_result(&ex, &TEST_TOOLCHAIN, &second, || Ok(TestResult::TestPass))
+            .unwrap();
+        assert_eq!(
+            cache
+                .load_test_result(&ex, &TEST_TOOLCHAIN, &second)
+                .unwrap(),
+            Some(TestResult::TestPass)
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use config::Config;
+use crates::Crate;
+use errors::*;
+use ex::Experiment;
+use report::{self, Comparison};
+use results::{ReadResults, TestResult};
+
+/// Load the result of a crate, taking into account the overrides set by the reviewers.
+fn load_result<DB: ReadResults>(
+    db: &DB,
+    ex: &Experiment,
+    index: usize,
+    krate: &Crate,
+) -> Result<Option<TestResult>> {
+    let source = ex.results_source(index);
+    let tc = &ex.toolchains[index];
+
+    if let Some(over) = db.load_result_override(&source, tc, krate)? {
+        Ok(Some(over.result))
+    } else {
+        db.load_test_result(&source, tc, krate)
+    }
+}
+
+/// Return the crates where the start and end toolchains produced different outcomes, along with
+/// the two results. This is the union of the regressions and the fixes shown in the report:
+/// crates missing a result, or with errored, skipped or cancelled builds, are not included.
+pub fn disagreements<DB: ReadResults>(
+    db: &DB,
+    config: &Config,
+    ex: &Experiment,
+) -> Result<Vec<(Crate, TestResult, TestResult)>> {
+    let mut result = Vec::new();
+    for krate in &ex.crates {
+        let start = load_result(db, ex, 0, krate)?;
+        let end = load_result(db, ex, 1, krate)?;
+
+        if let (Some(start), Some(end)) = (start, end) {
+            match report::compare(config, krate, Some(start), Some(end)) {
+                Comparison::Regressed | Comparison::Fixed => {
+                    result.push((krate.clone(), start, end));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disagreements;
+    use config::Config;
+    use crates::{Crate, RegistryCrate};
+    use ex::{ExCapLints, ExMode, Experiment};
+    use results::{DummyDB, TestResult};
+    use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+
+    #[test]
+    fn test_disagreements() {
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0".into(),
+            })
+        };
+        let crates = vec![
+            (krate("regressed"), TestResult::TestPass, TestResult::BuildFail),
+            (krate("fixed"), TestResult::TestFail, TestResult::TestPass),
+            (krate("timeout"), TestResult::TestPass, TestResult::TestTimeout),
+            (krate("same-pass"), TestResult::TestPass, TestResult::TestPass),
+            (krate("same-fail"), TestResult::BuildFail, TestResult::BuildTimeout),
+            (krate("skipped"), TestResult::TestPass, TestResult::TestSkipped),
+            (krate("error"), TestResult::TestPass, TestResult::Error),
+        ];
+
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: crates
+                .iter()
+                .map(|&(ref krate, _, _)| krate.clone())
+                .chain(Some(krate("missing")))
+                .collect(),
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        let mut db = DummyDB::default();
+        for &(ref krate, start, end) in &crates {
+            db.add_dummy_result(&ex, krate.clone(), MAIN_TOOLCHAIN.clone(), start);
+            db.add_dummy_result(&ex, krate.clone(), TEST_TOOLCHAIN.clone(), end);
+        }
+        db.add_dummy_result(
+            &ex,
+            krate("missing"),
+            MAIN_TOOLCHAIN.clone(),
+            TestResult::TestPass,
+        );
+
+        assert_eq!(
+            disagreements(&db, &Config::default(), &ex).unwrap(),
+            vec![
+                (krate("regressed"), TestResult::TestPass, TestResult::BuildFail),
+                (krate("fixed"), TestResult::TestFail, TestResult::TestPass),
+                (krate("timeout"), TestResult::TestPass, TestResult::TestTimeout),
+            ]
+        );
+    }
+}
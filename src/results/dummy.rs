@@ -1,8 +1,9 @@
 use crates::{Crate, GitHubRepo};
 use errors::*;
-use ex::Experiment;
-use results::{ReadResults, TestResult};
+use ex::{ExStep, Experiment};
+use results::{ReadResults, ResultOverride, TestCounts, TestResult, WriteResults};
 use std::collections::HashMap;
+use std::sync::Mutex;
 use toolchain::Toolchain;
 
 #[derive(Default)]
@@ -10,35 +11,39 @@ struct DummyData {
     shas: HashMap<GitHubRepo, String>,
     logs: HashMap<(Crate, Toolchain), Vec<u8>>,
     results: HashMap<(Crate, Toolchain), TestResult>,
+    overrides: HashMap<(Crate, Toolchain), ResultOverride>,
+    metadata: HashMap<(Crate, Toolchain), String>,
+    test_counts: HashMap<(Crate, Toolchain), TestCounts>,
+    attempts: HashMap<(Crate, ExStep), u32>,
 }
 
 #[derive(Default)]
 pub struct DummyDB {
-    experiments: HashMap<String, DummyData>,
+    experiments: Mutex<HashMap<String, DummyData>>,
 }
 
 impl DummyDB {
-    fn get_data(&self, ex: &Experiment) -> Result<&DummyData> {
-        Ok(self
-            .experiments
+    fn with_data<T, F: FnOnce(&DummyData) -> T>(&self, ex: &Experiment, f: F) -> Result<T> {
+        let experiments = self.experiments.lock().unwrap();
+        let data = experiments
             .get(&ex.name)
-            .ok_or_else(|| format!("missing experiment {}", ex.name))?)
+            .ok_or_else(|| format!("missing experiment {}", ex.name))?;
+        Ok(f(data))
     }
 
-    pub fn add_dummy_sha(&mut self, ex: &Experiment, repo: GitHubRepo, sha: String) {
-        self.experiments
+    fn with_data_mut<T, F: FnOnce(&mut DummyData) -> T>(&self, ex: &Experiment, f: F) -> T {
+        let mut experiments = self.experiments.lock().unwrap();
+        f(experiments
             .entry(ex.name.to_string())
-            .or_insert_with(DummyData::default)
-            .shas
-            .insert(repo, sha);
+            .or_insert_with(DummyData::default))
+    }
+
+    pub fn add_dummy_sha(&mut self, ex: &Experiment, repo: GitHubRepo, sha: String) {
+        self.with_data_mut(ex, |data| data.shas.insert(repo, sha));
     }
 
     pub fn add_dummy_log(&mut self, ex: &Experiment, krate: Crate, tc: Toolchain, log: Vec<u8>) {
-        self.experiments
-            .entry(ex.name.to_string())
-            .or_insert_with(DummyData::default)
-            .logs
-            .insert((krate, tc), log);
+        self.with_data_mut(ex, |data| data.logs.insert((krate, tc), log));
     }
 
     pub fn add_dummy_result(
@@ -48,17 +53,33 @@ impl DummyDB {
         tc: Toolchain,
         res: TestResult,
     ) {
-        self.experiments
-            .entry(ex.name.to_string())
-            .or_insert_with(DummyData::default)
-            .results
-            .insert((krate, tc), res);
+        self.with_data_mut(ex, |data| data.results.insert((krate, tc), res));
+    }
+
+    pub fn add_dummy_override(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        tc: Toolchain,
+        over: ResultOverride,
+    ) {
+        self.with_data_mut(ex, |data| data.overrides.insert((krate, tc), over));
+    }
+
+    pub fn add_dummy_test_counts(
+        &mut self,
+        ex: &Experiment,
+        krate: Crate,
+        tc: Toolchain,
+        counts: TestCounts,
+    ) {
+        self.with_data_mut(ex, |data| data.test_counts.insert((krate, tc), counts));
     }
 }
 
 impl ReadResults for DummyDB {
     fn load_all_shas(&self, ex: &Experiment) -> Result<HashMap<GitHubRepo, String>> {
-        Ok(self.get_data(ex)?.shas.clone())
+        self.with_data(ex, |data| data.shas.clone())
     }
 
     fn load_log(
@@ -67,11 +88,11 @@ impl ReadResults for DummyDB {
         toolchain: &Toolchain,
         krate: &Crate,
     ) -> Result<Option<Vec<u8>>> {
-        Ok(self
-            .get_data(ex)?
-            .logs
-            .get(&(krate.clone(), toolchain.clone()))
-            .cloned())
+        self.with_data(ex, |data| {
+            data.logs
+                .get(&(krate.clone(), toolchain.clone()))
+                .cloned()
+        })
     }
 
     fn load_test_result(
@@ -79,11 +100,118 @@ impl ReadResults for DummyDB {
         ex: &Experiment,
         toolchain: &Toolchain,
         krate: &Crate,
+    ) -> Result<Option<TestResult>> {
+        self.with_data(ex, |data| {
+            data.results
+                .get(&(krate.clone(), toolchain.clone()))
+                .cloned()
+        })
+    }
+
+    fn load_result_override(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<ResultOverride>> {
+        self.with_data(ex, |data| {
+            data.overrides
+                .get(&(krate.clone(), toolchain.clone()))
+                .cloned()
+        })
+    }
+
+    fn load_metadata(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<String>> {
+        self.with_data(ex, |data| {
+            data.metadata
+                .get(&(krate.clone(), toolchain.clone()))
+                .cloned()
+        })
+    }
+
+    fn load_test_counts(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<TestCounts>> {
+        self.with_data(ex, |data| {
+            data.test_counts
+                .get(&(krate.clone(), toolchain.clone()))
+                .cloned()
+        })
+    }
+}
+
+impl WriteResults for DummyDB {
+    fn get_result(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
     ) -> Result<Option<TestResult>> {
         Ok(self
-            .get_data(ex)?
-            .results
-            .get(&(krate.clone(), toolchain.clone()))
-            .cloned())
+            .load_test_result(ex, toolchain, krate)
+            .unwrap_or(None))
+    }
+
+    fn record_sha(&self, ex: &Experiment, repo: &GitHubRepo, sha: &str) -> Result<()> {
+        self.with_data_mut(ex, |data| data.shas.insert(repo.clone(), sha.to_string()));
+        Ok(())
+    }
+
+    fn record_result<F>(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        f: F,
+    ) -> Result<TestResult>
+    where
+        F: FnOnce() -> Result<TestResult>,
+    {
+        let result = f()?;
+        self.with_data_mut(ex, |data| {
+            data.results
+                .insert((krate.clone(), toolchain.clone()), result)
+        });
+        Ok(result)
+    }
+
+    fn record_metadata(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        metadata: &str,
+    ) -> Result<()> {
+        self.with_data_mut(ex, |data| {
+            data.metadata
+                .insert((krate.clone(), toolchain.clone()), metadata.to_string())
+        });
+        Ok(())
+    }
+
+    fn use_attempt(
+        &self,
+        ex: &Experiment,
+        krate: &Crate,
+        step: ExStep,
+        budget: u32,
+    ) -> Result<bool> {
+        Ok(self.with_data_mut(ex, |data| {
+            let attempts = data.attempts.entry((krate.clone(), step)).or_insert(0);
+            if *attempts >= budget {
+                false
+            } else {
+                *attempts += 1;
+                true
+            }
+        }))
     }
 }
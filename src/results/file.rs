@@ -1,9 +1,11 @@
 use crates::{Crate, GitHubRepo};
 use errors::*;
-use ex::{ex_dir, Experiment};
+use ex::{ex_dir, ExStep, Experiment};
 use file;
 use log;
-use results::{DeleteResults, ReadResults, TestResult, WriteResults};
+use results::{
+    self, DeleteResults, ReadResults, ResultOverride, TestCounts, TestResult, WriteResults,
+};
 use serde_json;
 use std::collections::HashMap;
 use std::fs::{self, File};
@@ -16,6 +18,14 @@ use util;
 #[derive(Clone, Default)]
 pub struct FileDB {
     shafile_lock: Arc<Mutex<()>>,
+    attempts_lock: Arc<Mutex<()>>,
+}
+
+fn crate_path(krate: &Crate) -> String {
+    match *krate {
+        Crate::Registry(ref details) => format!("reg/{}-{}", details.name, details.version),
+        Crate::GitHub(ref repo) => format!("gh/{}.{}", repo.org, repo.name),
+    }
 }
 
 impl FileDB {
@@ -24,15 +34,20 @@ impl FileDB {
     }
 
     pub fn result_dir(&self, ex: &Experiment, toolchain: &Toolchain, krate: &Crate) -> PathBuf {
-        let crate_path = match *krate {
-            Crate::Registry(ref details) => format!("reg/{}-{}", details.name, details.version),
-            Crate::GitHub(ref repo) => format!("gh/{}.{}", repo.org, repo.name),
-        };
-
         ex_dir(&ex.name)
             .join("res")
             .join(toolchain.to_string())
-            .join(crate_path)
+            .join(crate_path(krate))
+    }
+
+    fn attempts_dir(&self, ex: &Experiment) -> PathBuf {
+        ex_dir(&ex.name).join("attempts")
+    }
+
+    fn attempts_file(&self, ex: &Experiment, krate: &Crate, step: ExStep) -> PathBuf {
+        self.attempts_dir(ex)
+            .join(crate_path(krate))
+            .join(step.to_str())
     }
 
     fn result_file(&self, ex: &Experiment, toolchain: &Toolchain, krate: &Crate) -> PathBuf {
@@ -42,6 +57,19 @@ impl FileDB {
     fn result_log(&self, ex: &Experiment, toolchain: &Toolchain, krate: &Crate) -> PathBuf {
         self.result_dir(ex, toolchain, krate).join("log.txt")
     }
+
+    fn result_metadata(&self, ex: &Experiment, toolchain: &Toolchain, krate: &Crate) -> PathBuf {
+        self.result_dir(ex, toolchain, krate).join("metadata.json")
+    }
+
+    fn result_test_counts(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> PathBuf {
+        self.result_dir(ex, toolchain, krate).join("tests.json")
+    }
 }
 
 impl ReadResults for FileDB {
@@ -87,6 +115,46 @@ impl ReadResults for FileDB {
             Ok(None)
         }
     }
+
+    fn load_result_override(
+        &self,
+        _ex: &Experiment,
+        _toolchain: &Toolchain,
+        _krate: &Crate,
+    ) -> Result<Option<ResultOverride>> {
+        // Overrides are only supported by the server
+        Ok(None)
+    }
+
+    fn load_metadata(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<String>> {
+        let path = self.result_metadata(ex, toolchain, krate);
+
+        if path.exists() {
+            Ok(Some(file::read_string(&path)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn load_test_counts(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<TestCounts>> {
+        let path = self.result_test_counts(ex, toolchain, krate);
+
+        if path.exists() {
+            Ok(Some(serde_json::from_str(&file::read_string(&path)?)?))
+        } else {
+            Ok(None)
+        }
+    }
 }
 
 impl WriteResults for FileDB {
@@ -137,15 +205,64 @@ impl WriteResults for FileDB {
         let result = log::redirect(&log_file, f)?;
         file::write_string(&result_file, &result.to_string())?;
 
+        // The test counts are parsed once here, instead of every time a report is generated
+        if let Some(log) = self.load_log(ex, toolchain, krate)? {
+            if let Some(counts) = results::parse_test_counts(&log) {
+                let path = self.result_test_counts(ex, toolchain, krate);
+                file::write_string(&path, &serde_json::to_string(&counts)?)?;
+            }
+        }
+
         Ok(result)
     }
+
+    fn record_metadata(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        metadata: &str,
+    ) -> Result<()> {
+        fs::create_dir_all(&self.result_dir(ex, toolchain, krate))?;
+        file::write_string(&self.result_metadata(ex, toolchain, krate), metadata)
+    }
+
+    fn use_attempt(
+        &self,
+        ex: &Experiment,
+        krate: &Crate,
+        step: ExStep,
+        budget: u32,
+    ) -> Result<bool> {
+        // This avoids two builds of the same crate using the same attempt
+        let _lock = self.attempts_lock.lock().unwrap();
+
+        let path = self.attempts_file(ex, krate, step);
+        let attempts: u32 = if path.exists() {
+            file::read_string(&path)?.trim().parse()?
+        } else {
+            0
+        };
+        if attempts >= budget {
+            return Ok(false);
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        file::write_string_atomic(&path, &(attempts + 1).to_string())?;
+        Ok(true)
+    }
 }
 
 impl DeleteResults for FileDB {
     fn delete_all_results(&self, ex: &Experiment) -> Result<()> {
-        let dir = ex_dir(&ex.name).join("res");
-        if dir.exists() {
-            util::remove_dir_all(&dir)?;
+        // The attempts are reset too, otherwise the crates would fail when the experiment is run
+        // again after deleting its results
+        for dir in &[ex_dir(&ex.name).join("res"), self.attempts_dir(ex)] {
+            if dir.exists() {
+                util::remove_dir_all(dir)?;
+            }
         }
 
         Ok(())
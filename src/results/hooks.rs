@@ -0,0 +1,265 @@
+use crates::{Crate, GitHubRepo};
+use errors::*;
+use ex::{ExStep, Experiment};
+use log;
+use results::{TestResult, WriteResults};
+use std::fs;
+use std::time::Instant;
+use tempfile::NamedTempFile;
+use toolchain::Toolchain;
+use util;
+
+/// Hooks taking longer than this are reported, since they delay the worker running the task.
+const SLOW_HOOK_THRESHOLD_SECS: u64 = 5;
+
+/// Custom processing of the results, executed as soon as each of them is recorded.
+///
+/// Hooks run on the worker thread which recorded the result, so they should return quickly.
+/// Errors returned by a hook are logged, but don't cause the task to fail.
+pub trait ResultHook: Send + Sync {
+    fn result_recorded(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        result: TestResult,
+        log: &[u8],
+    ) -> Result<()>;
+}
+
+/// Hook warning about the results whose log contains an internal compiler error.
+pub struct IceDetector;
+
+impl ResultHook for IceDetector {
+    fn result_recorded(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        _result: TestResult,
+        log: &[u8],
+    ) -> Result<()> {
+        if String::from_utf8_lossy(log).contains("internal compiler error") {
+            warn!("{} on {} hit an internal compiler error", krate, toolchain.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Wrapper around a results database calling all the registered hooks after a result is recorded.
+pub struct HookedResults<'a, DB: 'a> {
+    db: &'a DB,
+    hooks: Vec<Box<ResultHook>>,
+}
+
+impl<'a, DB: WriteResults> HookedResults<'a, DB> {
+    pub fn new(db: &'a DB) -> Self {
+        HookedResults::with_hooks(db, Vec::new())
+    }
+
+    pub fn with_hooks(db: &'a DB, hooks: Vec<Box<ResultHook>>) -> Self {
+        HookedResults { db, hooks }
+    }
+
+    pub fn register<H: ResultHook + 'static>(&mut self, hook: H) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    fn run_hooks(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        result: TestResult,
+        log: &[u8],
+    ) {
+        for hook in &self.hooks {
+            let start = Instant::now();
+            if let Err(err) = hook.result_recorded(ex, toolchain, krate, result, log) {
+                error!("result hook failed for {} on {}", krate, toolchain.to_string());
+                util::report_error(&err);
+            }
+
+            let elapsed = start.elapsed().as_secs();
+            if elapsed >= SLOW_HOOK_THRESHOLD_SECS {
+                warn!("result hook for {} took {} seconds", krate, elapsed);
+            }
+        }
+    }
+}
+
+impl<'a, DB: WriteResults> WriteResults for HookedResults<'a, DB> {
+    fn get_result(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<TestResult>> {
+        self.db.get_result(ex, toolchain, krate)
+    }
+
+    fn record_sha(&self, ex: &Experiment, repo: &GitHubRepo, sha: &str) -> Result<()> {
+        self.db.record_sha(ex, repo, sha)
+    }
+
+    fn record_result<F>(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        f: F,
+    ) -> Result<TestResult>
+    where
+        F: FnOnce() -> Result<TestResult>,
+    {
+        if self.hooks.is_empty() {
+            return self.db.record_result(ex, toolchain, krate, f);
+        }
+
+        // Keep a copy of the log for the hooks while it's being recorded by the database
+        let log_file = NamedTempFile::new()?;
+        let result = self
+            .db
+            .record_result(ex, toolchain, krate, || log::tee(log_file.path(), f))?;
+
+        // The result is already stored, so failing to read the log shouldn't fail the task
+        match fs::read(log_file.path()) {
+            Ok(log) => self.run_hooks(ex, toolchain, krate, result, &log),
+            Err(err) => util::report_error(&err.into()),
+        }
+
+        Ok(result)
+    }
+
+    fn record_metadata(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        metadata: &str,
+    ) -> Result<()> {
+        self.db.record_metadata(ex, toolchain, krate, metadata)
+    }
+
+    fn use_attempt(
+        &self,
+        ex: &Experiment,
+        krate: &Crate,
+        step: ExStep,
+        budget: u32,
+    ) -> Result<bool> {
+        self.db.use_attempt(ex, krate, step, budget)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HookedResults, ResultHook};
+    use crates::{Crate, RegistryCrate};
+    use errors::*;
+    use ex::{ExCapLints, ExMode, Experiment};
+    use results::{DummyDB, TestResult, WriteResults};
+    use std::sync::{Arc, Mutex};
+    use toolchain::{Toolchain, MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+
+    #[derive(Clone, Default)]
+    struct RecordingHook {
+        seen: Arc<Mutex<Vec<(Crate, Toolchain, TestResult, Vec<u8>)>>>,
+    }
+
+    impl ResultHook for RecordingHook {
+        fn result_recorded(
+            &self,
+            _ex: &Experiment,
+            toolchain: &Toolchain,
+            krate: &Crate,
+            result: TestResult,
+            log: &[u8],
+        ) -> Result<()> {
+            self.seen.lock().unwrap().push((
+                krate.clone(),
+                toolchain.clone(),
+                result,
+                log.to_vec(),
+            ));
+            Ok(())
+        }
+    }
+
+    struct FailingHook;
+
+    impl ResultHook for FailingHook {
+        fn result_recorded(
+            &self,
+            _ex: &Experiment,
+            _toolchain: &Toolchain,
+            _krate: &Crate,
+            _result: TestResult,
+            _log: &[u8],
+        ) -> Result<()> {
+            bail!("hook failed");
+        }
+    }
+
+    #[test]
+    fn test_result_hooks() {
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![krate.clone()],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        let db = DummyDB::default();
+        let hook = RecordingHook::default();
+        let mut hooked = HookedResults::new(&db);
+        hooked.register(FailingHook);
+        hooked.register(hook.clone());
+
+        // A failing hook doesn't prevent the result from being recorded, or other hooks from
+        // being executed
+        hooked
+            .record_result(&ex, &MAIN_TOOLCHAIN, &krate, || {
+                info!("internal compiler error: unexpected panic");
+                Ok(TestResult::BuildFail)
+            })
+            .unwrap();
+        hooked
+            .record_result(&ex, &TEST_TOOLCHAIN, &krate, || Ok(TestResult::TestPass))
+            .unwrap();
+
+        // The hooks receive the log of the recorded result
+        let seen = hook.seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(
+            (&seen[0].0, &seen[0].1, seen[0].2),
+            (&krate, &*MAIN_TOOLCHAIN, TestResult::BuildFail)
+        );
+        assert!(String::from_utf8_lossy(&seen[0].3).contains("unexpected panic"));
+        assert_eq!(
+            (&seen[1].0, &seen[1].1, seen[1].2),
+            (&krate, &*TEST_TOOLCHAIN, TestResult::TestPass)
+        );
+        assert!(seen[1].3.is_empty());
+        assert_eq!(
+            db.get_result(&ex, &MAIN_TOOLCHAIN, &krate).unwrap(),
+            Some(TestResult::BuildFail)
+        );
+    }
+}
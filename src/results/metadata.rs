@@ -0,0 +1,157 @@
+use crates::Crate;
+use errors::*;
+use ex::Experiment;
+use results::ReadResults;
+use serde_json;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    version: String,
+}
+
+/// Dependency resolved to different versions by the two toolchains of an experiment.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DependencyChange {
+    pub name: String,
+    /// Versions resolved by the start toolchain, empty if the dependency wasn't present
+    pub start: Vec<String>,
+    /// Versions resolved by the end toolchain, empty if the dependency wasn't present
+    pub end: Vec<String>,
+}
+
+fn resolved_versions(metadata: &str) -> Result<BTreeMap<String, BTreeSet<String>>> {
+    let metadata: Metadata =
+        serde_json::from_str(metadata).chain_err(|| "invalid cargo metadata output")?;
+
+    let mut versions = BTreeMap::new();
+    for package in metadata.packages {
+        versions
+            .entry(package.name)
+            .or_insert_with(BTreeSet::new)
+            .insert(package.version);
+    }
+
+    Ok(versions)
+}
+
+/// Compare the `cargo metadata` output of two builds, returning the dependencies resolved
+/// differently by them.
+pub fn diff_metadata(start: &str, end: &str) -> Result<Vec<DependencyChange>> {
+    let start = resolved_versions(start)?;
+    let end = resolved_versions(end)?;
+    let empty = BTreeSet::new();
+
+    let names = start.keys().chain(end.keys()).collect::<BTreeSet<_>>();
+    Ok(names
+        .into_iter()
+        .filter_map(|name| {
+            let start = start.get(name).unwrap_or(&empty);
+            let end = end.get(name).unwrap_or(&empty);
+            if start == end {
+                None
+            } else {
+                Some(DependencyChange {
+                    name: name.clone(),
+                    start: start.iter().cloned().collect(),
+                    end: end.iter().cloned().collect(),
+                })
+            }
+        })
+        .collect())
+}
+
+/// Load the metadata captured for a crate with both toolchains of the experiment and compare
+/// it. `None` is returned if the metadata wasn't captured for one of the toolchains.
+pub fn metadata_diff<DB: ReadResults>(
+    db: &DB,
+    ex: &Experiment,
+    krate: &Crate,
+) -> Result<Option<Vec<DependencyChange>>> {
+    let start = db.load_metadata(ex, &ex.toolchains[0], krate)?;
+    let end = db.load_metadata(ex, &ex.toolchains[1], krate)?;
+
+    if let (Some(start), Some(end)) = (start, end) {
+        Ok(Some(diff_metadata(&start, &end)?))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{metadata_diff, DependencyChange};
+    use crates::{Crate, RegistryCrate};
+    use ex::{ExCapLints, ExMode, Experiment};
+    use results::{DummyDB, ReadResults, WriteResults};
+    use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+
+    const START: &str = r#"{"packages": [
+        {"name": "foo", "version": "0.1.0", "id": "foo 0.1.0"},
+        {"name": "libc", "version": "0.2.40", "id": "libc 0.2.40"},
+        {"name": "log", "version": "0.4.1", "id": "log 0.4.1"}
+    ], "resolve": null}"#;
+
+    const END: &str = r#"{"packages": [
+        {"name": "foo", "version": "0.1.0", "id": "foo 0.1.0"},
+        {"name": "libc", "version": "0.2.42", "id": "libc 0.2.42"},
+        {"name": "log", "version": "0.4.1", "id": "log 0.4.1"}
+    ], "resolve": null}"#;
+
+    #[test]
+    fn test_metadata_diff() {
+        let krate = Crate::Registry(RegistryCrate {
+            name: "foo".into(),
+            version: "0.1.0".into(),
+        });
+        let ex = Experiment {
+            name: "foo".into(),
+            crates: vec![krate.clone()],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: true,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        let db = DummyDB::default();
+        db.record_metadata(&ex, &MAIN_TOOLCHAIN, &krate, START)
+            .unwrap();
+        assert_eq!(metadata_diff(&db, &ex, &krate).unwrap(), None);
+        db.record_metadata(&ex, &TEST_TOOLCHAIN, &krate, END)
+            .unwrap();
+
+        // The captured metadata round-trips
+        assert_eq!(
+            db.load_metadata(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap()
+                .as_ref()
+                .map(|s| s.as_str()),
+            Some(START)
+        );
+
+        assert_eq!(
+            metadata_diff(&db, &ex, &krate).unwrap(),
+            Some(vec![DependencyChange {
+                name: "libc".into(),
+                start: vec!["0.2.40".into()],
+                end: vec!["0.2.42".into()],
+            }])
+        );
+    }
+}
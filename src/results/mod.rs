@@ -1,13 +1,27 @@
+mod cache;
+mod disagreements;
 #[cfg(test)]
 mod dummy;
 mod file;
+mod hooks;
+mod metadata;
+mod stdout;
+mod test_counts;
 
 use crates::{Crate, GitHubRepo};
 use errors::*;
-use ex::Experiment;
+use ex::{ExStep, Experiment};
+use regex::Regex;
+use run;
+pub use results::cache::ResultCache;
+pub use results::disagreements::disagreements;
 #[cfg(test)]
 pub use results::dummy::DummyDB;
 pub use results::file::FileDB;
+pub use results::hooks::{HookedResults, IceDetector, ResultHook};
+pub use results::metadata::{diff_metadata, metadata_diff, DependencyChange};
+pub use results::stdout::StdoutResults;
+pub use results::test_counts::{parse_test_counts, TestCounts};
 use std::collections::HashMap;
 use toolchain::Toolchain;
 
@@ -25,6 +39,37 @@ pub trait ReadResults {
         toolchain: &Toolchain,
         krate: &Crate,
     ) -> Result<Option<TestResult>>;
+    fn load_result_override(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<ResultOverride>>;
+    fn load_metadata(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<String>>;
+    fn load_test_counts(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<TestCounts>>;
+
+    fn load_all_results(&self, ex: &Experiment) -> Result<HashMap<(Crate, Toolchain), TestResult>> {
+        let mut results = HashMap::new();
+        for krate in &ex.crates {
+            for tc in &ex.toolchains {
+                if let Some(res) = self.load_test_result(ex, tc, krate)? {
+                    results.insert((krate.clone(), tc.clone()), res);
+                }
+            }
+        }
+
+        Ok(results)
+    }
 }
 
 pub trait WriteResults {
@@ -44,6 +89,22 @@ pub trait WriteResults {
     ) -> Result<TestResult>
     where
         F: FnOnce() -> Result<TestResult>;
+    fn record_metadata(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        metadata: &str,
+    ) -> Result<()>;
+    /// Record a new attempt of the step of the crate, returning `false` without recording it if
+    /// the `budget` was already exhausted, even by the previous runs of the experiment.
+    fn use_attempt(
+        &self,
+        ex: &Experiment,
+        krate: &Crate,
+        step: ExStep,
+        budget: u32,
+    ) -> Result<bool>;
 }
 
 pub trait DeleteResults {
@@ -54,7 +115,146 @@ pub trait DeleteResults {
 string_enum!(pub enum TestResult {
     BuildFail => "build-fail",
     TestFail => "test-fail",
+    BuildTimeout => "build-timeout",
+    TestTimeout => "test-timeout",
     TestSkipped => "test-skipped",
     TestPass => "test-pass",
     Error => "error",
+    Cancelled => "cancelled",
+    Ignored => "ignored",
+});
+
+impl TestResult {
+    pub fn is_failure(self) -> bool {
+        match self {
+            TestResult::BuildFail
+            | TestResult::TestFail
+            | TestResult::BuildTimeout
+            | TestResult::TestTimeout
+            | TestResult::Error => true,
+            TestResult::TestSkipped
+            | TestResult::TestPass
+            | TestResult::Cancelled
+            | TestResult::Ignored => false,
+        }
+    }
+}
+
+string_enum!(pub enum ErrorCategory {
+    Spurious => "spurious",
+    Timeout => "timeout",
+    BuildFailure => "build-failure",
+    TestFailure => "test-failure",
+    Error => "error",
 });
+
+/// Errors emitted by Cargo when it can't fetch the dependencies of the crate.
+const FETCH_FAILURES: &[&str] = &[
+    "failed to download",
+    "failed to fetch",
+    "failed to load source for a dependency",
+];
+
+/// Messages in the logs caused by problems of the infrastructure instead of the crate itself,
+/// in addition to the `FETCH_FAILURES`.
+const INFRA_FAILURES: &[&str] = &[
+    "Couldn't resolve host name",
+    "Connection reset by peer",
+    "Connection timed out",
+    "spurious network error",
+    "No space left on device",
+    "Cannot connect to the Docker daemon",
+];
+
+/// Prefixes of the messages emitted by Cargo and Docker themselves, as opposed to the output of the
+/// crate's build scripts and tests, which could mention the infrastructure failures as well.
+const TOOL_MESSAGES: &[&str] = &["error: ", "warning: spurious network error", "docker: "];
+
+pub fn is_infra_failure(log: &[u8]) -> bool {
+    let log = String::from_utf8_lossy(log);
+
+    // The indented lines after Cargo's "Caused by:" are part of the same error
+    let mut in_cause = false;
+    for line in log.lines() {
+        let message = run::log_message(line);
+        let from_tools = if in_cause && message.starts_with(' ') {
+            true
+        } else {
+            in_cause = message == "Caused by:";
+            TOOL_MESSAGES.iter().any(|prefix| message.starts_with(prefix))
+        };
+
+        if from_tools
+            && FETCH_FAILURES
+                .iter()
+                .chain(INFRA_FAILURES)
+                .any(|msg| message.contains(msg))
+        {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Check whether the line is an error emitted by Cargo because a dependency couldn't be fetched.
+pub fn is_fetch_failure(line: &str) -> bool {
+    let line = line.trim_left();
+    line.starts_with("error: ")
+        && FETCH_FAILURES
+            .iter()
+            .any(|msg| line["error: ".len()..].starts_with(msg))
+}
+
+lazy_static! {
+    static ref ERROR_LINE_RE: Regex = Regex::new(r"\berror(\[E[0-9]+\])?: .*$").unwrap();
+    static ref ERROR_SPAN_RE: Regex = Regex::new(r":[0-9]+(:[0-9]+)?").unwrap();
+    static ref ERROR_PATH_RE: Regex = Regex::new(r"[^\s`'\x22:]*/[^\s`'\x22:]*").unwrap();
+}
+
+/// Errors emitted by cargo after the actual compiler errors, which are not useful for triage.
+const SUMMARY_ERRORS: &[&str] = &[
+    "error: aborting due to",
+    "error: Could not compile",
+    "error: could not compile",
+    "error: build failed",
+];
+
+/// Extract the first error message from a log, stripping the paths and spans from it so that the
+/// same error happening in different places or crates results in the same message.
+pub fn primary_error(log: &[u8]) -> Option<String> {
+    let log = String::from_utf8_lossy(log);
+    log.lines()
+        .filter_map(|line| ERROR_LINE_RE.find(line))
+        .map(|error| error.as_str())
+        .find(|error| !SUMMARY_ERRORS.iter().any(|summary| error.starts_with(summary)))
+        .map(|error| {
+            let error = ERROR_SPAN_RE.replace_all(error, "");
+            ERROR_PATH_RE.replace_all(&error, "<path>").trim().to_string()
+        })
+}
+
+/// Find out why a crate failed from its result and log, returning `None` if it didn't fail.
+pub fn classify_failure(result: TestResult, log: &[u8]) -> Option<ErrorCategory> {
+    if !result.is_failure() {
+        return None;
+    }
+
+    Some(if is_infra_failure(log) {
+        ErrorCategory::Spurious
+    } else {
+        match result {
+            TestResult::BuildTimeout | TestResult::TestTimeout => ErrorCategory::Timeout,
+            TestResult::BuildFail => ErrorCategory::BuildFailure,
+            TestResult::TestFail => ErrorCategory::TestFailure,
+            _ => ErrorCategory::Error,
+        }
+    })
+}
+
+/// Result manually set by a reviewer, replacing the recorded one in the reports.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ResultOverride {
+    pub result: TestResult,
+    pub reason: String,
+}
@@ -0,0 +1,150 @@
+use crates::{Crate, GitHubRepo};
+use errors::*;
+use ex::{ExStep, Experiment};
+use results::{TestResult, WriteResults};
+use std::io::{self, Stdout, Write};
+use std::sync::Mutex;
+use toolchain::Toolchain;
+
+/// Results sink for quick local runs, printing each result as soon as it's produced instead of
+/// storing it. Since nothing is persisted all the tasks are always executed.
+pub struct StdoutResults<W: Write + Send = Stdout> {
+    out: Mutex<W>,
+}
+
+impl Default for StdoutResults {
+    fn default() -> Self {
+        StdoutResults::with_writer(io::stdout())
+    }
+}
+
+impl<W: Write + Send> StdoutResults<W> {
+    pub fn with_writer(out: W) -> Self {
+        StdoutResults {
+            out: Mutex::new(out),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> WriteResults for StdoutResults<W> {
+    fn get_result(
+        &self,
+        _ex: &Experiment,
+        _toolchain: &Toolchain,
+        _krate: &Crate,
+    ) -> Result<Option<TestResult>> {
+        Ok(None)
+    }
+
+    fn record_sha(&self, _ex: &Experiment, _repo: &GitHubRepo, _sha: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn record_result<F>(
+        &self,
+        _ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+        f: F,
+    ) -> Result<TestResult>
+    where
+        F: FnOnce() -> Result<TestResult>,
+    {
+        let result = f()?;
+        let mut out = self.out.lock().unwrap();
+        writeln!(out, "{} on {}: {}", krate, toolchain.to_string(), result)?;
+        out.flush()?;
+        Ok(result)
+    }
+
+    fn record_metadata(
+        &self,
+        _ex: &Experiment,
+        _toolchain: &Toolchain,
+        _krate: &Crate,
+        _metadata: &str,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn use_attempt(
+        &self,
+        _ex: &Experiment,
+        _krate: &Crate,
+        _step: ExStep,
+        _budget: u32,
+    ) -> Result<bool> {
+        // The attempts of the previous runs are not known, so the tasks are always executed
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StdoutResults;
+    use config::Config;
+    use crates::{Crate, RegistryCrate};
+    use ex::{ExCapLints, ExMode, ExStep, Experiment};
+    use run_graph::{self, WalkResult};
+    use std::sync::Mutex;
+    use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+    use util::CancelToken;
+
+    #[test]
+    fn test_run_tasks_with_stdout_results() {
+        let config = Config::default();
+        let ex = Experiment {
+            name: "stdout-results".to_string(),
+            crates: vec![Crate::Registry(RegistryCrate {
+                name: "crater-missing-source".into(),
+                version: "1.0".into(),
+            })],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let db = StdoutResults::with_writer(Vec::new());
+
+        // The prepare step is completed without downloading the crate, so the builds fail right
+        // away and are recorded as errors instead of running in Docker
+        let mut graph = run_graph::build_graph(&ex, &config);
+        match graph.next_task(&ex, &db) {
+            WalkResult::Task(id, ref task) if task.step.kind() == ExStep::Prepare => {
+                graph.mark_as_completed(id);
+            }
+            res => panic!("unexpected walk result: {:?}", res),
+        }
+        let graph = Mutex::new(graph);
+
+        let cancel = CancelToken::default();
+        run_graph::run_tasks(&graph, &ex, &db, 2, &config, &cancel).unwrap();
+        assert!(graph.lock().unwrap().next_task(&ex, &db).is_finished());
+
+        // A line is printed for the build with each toolchain
+        let out = String::from_utf8(db.into_inner()).unwrap();
+        let mut lines = out.lines().collect::<Vec<_>>();
+        lines.sort();
+        let mut expected = ex
+            .toolchains
+            .iter()
+            .map(|tc| format!("crater-missing-source-1.0 on {}: error", tc.to_string()))
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(lines, expected);
+    }
+}
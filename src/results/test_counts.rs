@@ -0,0 +1,65 @@
+use regex::Regex;
+
+lazy_static! {
+    static ref SUMMARY_RE: Regex = Regex::new(
+        r"test result: \w+\. ([0-9]+) passed; ([0-9]+) failed; ([0-9]+) ignored"
+    ).unwrap();
+}
+
+/// Number of tests with each outcome, summed across all the test binaries of a crate.
+///
+/// The counts only add detail to the result of the crate: it's `TestFail` if any test failed,
+/// but also if a test binary crashed or didn't compile, so a `TestFail` result can come with zero
+/// failed tests. A `TestPass` result always has zero failed tests.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct TestCounts {
+    pub passed: u32,
+    pub failed: u32,
+    pub ignored: u32,
+}
+
+/// Parse the summaries printed by `cargo test` in a log, returning `None` if no test was run.
+pub fn parse_test_counts(log: &[u8]) -> Option<TestCounts> {
+    let log = String::from_utf8_lossy(log);
+
+    let mut counts = None;
+    for captures in SUMMARY_RE.captures_iter(&log) {
+        let count = |i| captures[i].parse::<u32>().unwrap_or(0);
+        let counts = counts.get_or_insert_with(TestCounts::default);
+        counts.passed += count(1);
+        counts.failed += count(2);
+        counts.ignored += count(3);
+    }
+
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_test_counts, TestCounts};
+
+    #[test]
+    fn test_parse_test_counts() {
+        let log = b"\
+            [stdout] running 3 tests\n\
+            [stdout] test foo ... ok\n\
+            [stdout] test bar ... FAILED\n\
+            [stdout] test baz ... ignored\n\
+            [stdout] test result: FAILED. 148 passed; 2 failed; 1 ignored; 0 measured; \
+            0 filtered out\n\
+            [stdout] test result: ok. 0 passed; 0 failed; 0 ignored; 0 measured; \
+            0 filtered out\n\
+            [stdout] test result: ok. 12 passed; 0 failed; 3 ignored; 0 measured; \
+            0 filtered out\n";
+
+        assert_eq!(
+            parse_test_counts(log),
+            Some(TestCounts {
+                passed: 160,
+                failed: 2,
+                ignored: 4,
+            })
+        );
+        assert_eq!(parse_test_counts(b"error: could not compile `foo`"), None);
+    }
+}
@@ -23,8 +23,18 @@ pub struct RunCommand<'a, S: AsRef<OsStr> + 'a> {
     cd: Option<&'a Path>,
     quiet: bool,
     enable_timeout: bool,
+    tag_streams: bool,
+    redacted: Vec<&'a str>,
 }
 
+/// Placeholder replacing the redacted secrets in the logs.
+pub const REDACTED: &str = "[redacted]";
+
+/// Prefix added to the logged lines of the command's standard output when tagging is enabled.
+pub const STDOUT_TAG: &str = "[stdout] ";
+/// Prefix added to the logged lines of the command's standard error when tagging is enabled.
+pub const STDERR_TAG: &str = "[stderr] ";
+
 impl<'a, S: AsRef<OsStr>> RunCommand<'a, S> {
     pub fn new(name: &'a str, args: &'a [S]) -> Self {
         RunCommand {
@@ -34,6 +44,8 @@ impl<'a, S: AsRef<OsStr>> RunCommand<'a, S> {
             cd: None,
             quiet: false,
             enable_timeout: true,
+            tag_streams: false,
+            redacted: Vec::new(),
         }
     }
 
@@ -57,6 +69,20 @@ impl<'a, S: AsRef<OsStr>> RunCommand<'a, S> {
         self
     }
 
+    /// Tag each logged line with the stream it was written to, instead of using the legacy
+    /// interleaved format.
+    pub fn tag_streams(mut self, tag_streams: bool) -> Self {
+        self.tag_streams = tag_streams;
+        self
+    }
+
+    /// Replace the secret with a placeholder everywhere it appears in the logged command and in
+    /// its output.
+    pub fn redact(mut self, secret: &'a str) -> Self {
+        self.redacted.push(secret);
+        self
+    }
+
     pub fn local_rustup(self) -> Self {
         self.env("CARGO_HOME", &*CARGO_HOME)
             .env("RUSTUP_HOME", &*RUSTUP_HOME)
@@ -72,7 +98,22 @@ impl<'a, S: AsRef<OsStr>> RunCommand<'a, S> {
         Ok((out.stdout, out.stderr))
     }
 
+    /// Run the command and capture its output, without treating a non-zero exit code as an error.
+    pub fn run_capture_status(self) -> Result<(bool, Vec<String>, Vec<String>)> {
+        let (out, _) = self.spawn(true)?;
+        Ok((out.status.success(), out.stdout, out.stderr))
+    }
+
     fn run_inner(&self, capture: bool) -> Result<ProcessOutput> {
+        let (out, cmdstr) = self.spawn(capture)?;
+        if out.status.success() {
+            Ok(out)
+        } else {
+            Err(format!("command `{}` failed", cmdstr).into())
+        }
+    }
+
+    fn spawn(&self, capture: bool) -> Result<(ProcessOutput, String)> {
         let mut cmd = Command::new(self.name);
 
         cmd.args(self.args);
@@ -80,24 +121,76 @@ impl<'a, S: AsRef<OsStr>> RunCommand<'a, S> {
             cmd.env(k, v);
         }
 
-        let cmdstr = format!("{:?}", cmd);
+        let cmdstr = redact(&format!("{:?}", cmd), &self.redacted);
 
         if let Some(cd) = self.cd {
             cmd.current_dir(cd);
         }
 
         info!("running `{}`", cmdstr);
-        let out = log_command(cmd, capture, self.quiet, self.enable_timeout).map_err(|e| {
+        let out = log_command(
+            cmd,
+            capture,
+            self.quiet,
+            self.enable_timeout,
+            self.tag_streams,
+            self.redacted.iter().map(|s| s.to_string()).collect(),
+        ).map_err(|e| {
             info!("error running command: {}", e);
             e
         })?;
 
-        if out.status.success() {
-            Ok(out)
-        } else {
-            Err(format!("command `{}` failed", cmdstr).into())
+        Ok((out, cmdstr))
+    }
+}
+
+fn redact<S: AsRef<str>>(text: &str, secrets: &[S]) -> String {
+    let mut text = text.to_string();
+    for secret in secrets {
+        let secret = secret.as_ref();
+        if !secret.is_empty() {
+            text = text.replace(secret, REDACTED);
         }
     }
+    text
+}
+
+/// Separator between the timestamp and the message of the lines of the captured logs.
+const LOG_LEVEL: &str = " INFO ";
+
+/// Strip the timestamp and the level from a line of the captured logs.
+fn strip_log_header(line: &str) -> &str {
+    match line.find(LOG_LEVEL) {
+        Some(idx) => &line[idx + LOG_LEVEL.len()..],
+        None => line,
+    }
+}
+
+/// Extract the message from a line of the captured logs, without the stream tag if present.
+pub fn log_message(line: &str) -> &str {
+    let message = strip_log_header(line);
+    for tag in &[STDOUT_TAG, STDERR_TAG] {
+        if message.starts_with(tag) {
+            return &message[tag.len()..];
+        }
+    }
+    message
+}
+
+/// Extract from a captured log the lines written to the stream identified by `tag`, when the
+/// command was run with `tag_streams` enabled. Only the lines whose message starts with the tag are
+/// returned, so output of other streams containing it is not mistaken for a tagged line.
+pub fn tagged_lines<'a>(log: &'a str, tag: &str) -> Vec<&'a str> {
+    log.lines()
+        .filter_map(|line| {
+            let message = strip_log_header(line);
+            if message.starts_with(tag) {
+                Some(&message[tag.len()..])
+            } else {
+                None
+            }
+        })
+        .collect()
 }
 
 struct ProcessOutput {
@@ -114,6 +207,8 @@ fn log_command(
     capture: bool,
     quiet: bool,
     enable_timeout: bool,
+    tag_streams: bool,
+    redacted: Vec<String>,
 ) -> Result<ProcessOutput> {
     let (max_timeout, heartbeat_timeout) = if enable_timeout {
         let max_timeout = Duration::from_secs(MAX_TIMEOUT_SECS);
@@ -148,15 +243,26 @@ fn log_command(
     let logger = slog_scope::logger();
     let stdout = lines(BufReader::new(stdout)).map({
         let logger = logger.clone();
+        let redacted = redacted.clone();
         move |line| {
-            slog_info!(logger, "blam! {}", line);
+            let line = redact(&line, &redacted);
+            if tag_streams {
+                slog_info!(logger, "{}{}", STDOUT_TAG, line);
+            } else {
+                slog_info!(logger, "blam! {}", line);
+            }
             line
         }
     });
     let stderr = lines(BufReader::new(stderr)).map({
         let logger = logger.clone();
         move |line| {
-            slog_info!(logger, "kablam! {}", line);
+            let line = redact(&line, &redacted);
+            if tag_streams {
+                slog_info!(logger, "{}{}", STDERR_TAG, line);
+            } else {
+                slog_info!(logger, "kablam! {}", line);
+            }
             line
         }
     });
@@ -271,3 +377,43 @@ where
             }),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{tagged_lines, RunCommand, REDACTED, STDERR_TAG, STDOUT_TAG};
+    use file;
+    use log;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_tag_streams() {
+        let log_file = NamedTempFile::new().unwrap();
+        log::redirect(log_file.path(), || {
+            RunCommand::new("sh", &["-c", "echo hello; echo world >&2; echo '[stderr] no'"])
+                .tag_streams(true)
+                .run()
+        }).unwrap();
+
+        let log = file::read_string(log_file.path()).unwrap();
+        assert_eq!(tagged_lines(&log, STDOUT_TAG), vec!["hello", "[stderr] no"]);
+        assert_eq!(tagged_lines(&log, STDERR_TAG), vec!["world"]);
+    }
+
+    #[test]
+    fn test_redact() {
+        let log_file = NamedTempFile::new().unwrap();
+        let (stdout, stderr) = log::redirect(log_file.path(), || {
+            RunCommand::new("sh", &["-c", "echo token=hunter2; echo hunter2 >&2"])
+                .redact("hunter2")
+                .run_capture()
+        }).unwrap();
+
+        assert_eq!(stdout, vec![format!("token={}", REDACTED)]);
+        assert_eq!(stderr, vec![REDACTED.to_string()]);
+
+        // The secret is also removed from the logged command
+        let log = file::read_string(log_file.path()).unwrap();
+        assert!(!log.contains("hunter2"));
+        assert!(log.contains(REDACTED));
+    }
+}
@@ -15,21 +15,33 @@
 //          +---+ crate-complete <---+             +---+ prepare
 //                                   |             |
 //                                   +---+ tc2 <---+
+//
+// For huge experiments the graph can be built incrementally instead: only a window of crates is
+// added to it at the start, and more crates are added as the previous ones complete.
+//
+// When the crates are run in dependency order, the prepare step of each crate also depends on the
+// prepare steps of its dependencies in the experiment, if they're still in the graph.
 
-use config::Config;
+use config::{Config, Scheduling, ToolchainOrder};
+use crates::Crate;
 use crossbeam;
 use errors::*;
 use ex::{self, ExMode, Experiment};
 use file;
 use petgraph::{dot::Dot, graph::NodeIndex, stable_graph::StableDiGraph, Direction};
+use registry;
 use results::{TestResult, WriteResults};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use serde_json;
 use std::fmt;
+use std::fs;
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 use tasks::{Task, TaskStep};
-use util;
+use util::{self, CancelToken};
 
 pub enum Node {
     Task { task: Arc<Task>, running: bool },
@@ -70,10 +82,38 @@ impl WalkResult {
     }
 }
 
+/// State of a graph built incrementally, tracking which crates still need to be added.
+struct Window {
+    size: usize,
+    next_crate: usize,
+    config: Config,
+}
+
+/// Progress of the tasks graph at a point in time, periodically saved to disk during a run.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct GraphProgress {
+    pub completed_tasks: usize,
+    /// Crates with tasks still to be executed, including the ones not added to the graph yet
+    pub pending_crates: Vec<Crate>,
+}
+
 #[derive(Default)]
 pub struct TasksGraph {
     graph: StableDiGraph<Node, ()>,
     root: NodeIndex,
+    window: Option<Window>,
+    toolchain_order: Option<ToolchainOrder>,
+    scheduling: Option<Scheduling>,
+    completed_tasks: usize,
+    /// Dependencies of each crate, if the crates are run in dependency order
+    dependencies: Option<HashMap<Crate, Vec<Crate>>>,
+    prepare_tasks: HashMap<Crate, NodeIndex>,
+    /// Number of tasks being executed for each crate
+    running_crates: HashMap<Crate, usize>,
+    /// Number of tasks without dependencies left that are not being executed yet
+    runnable_tasks: usize,
+    /// Crates cancelled while the graph is executed, which are never added to it again
+    cancelled_crates: HashSet<Crate>,
 }
 
 impl TasksGraph {
@@ -81,7 +121,19 @@ impl TasksGraph {
         let mut graph = StableDiGraph::new();
         let root = graph.add_node(Node::Root);
 
-        TasksGraph { graph, root }
+        TasksGraph {
+            graph,
+            root,
+            window: None,
+            toolchain_order: None,
+            scheduling: None,
+            completed_tasks: 0,
+            dependencies: None,
+            prepare_tasks: HashMap::new(),
+            running_crates: HashMap::new(),
+            runnable_tasks: 0,
+            cancelled_crates: HashSet::new(),
+        }
     }
 
     pub fn add_task(&mut self, task: Task, deps: &[NodeIndex]) -> NodeIndex {
@@ -106,13 +158,47 @@ impl TasksGraph {
         for dep in deps {
             self.graph.add_edge(id, *dep, ());
         }
+        if self.is_runnable(id) {
+            self.runnable_tasks += 1;
+        }
 
         id
     }
 
     pub fn next_task<DB: WriteResults>(&mut self, ex: &Experiment, db: &DB) -> WalkResult {
         let root = self.root;
-        self.walk_graph(root, ex, db)
+        loop {
+            self.fill_window(ex);
+            match self.walk_graph(root, ex, db) {
+                // All the crates in the window were completed during the walk, but there are
+                // more crates to add to the graph
+                WalkResult::Finished if self.has_pending_crates(ex) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Add crates to the graph until the window is full, if the graph is built incrementally.
+    fn fill_window(&mut self, ex: &Experiment) {
+        if let Some(mut window) = self.window.take() {
+            let mut crates_in_graph = self.graph.neighbors(self.root).count();
+            while crates_in_graph < window.size && window.next_crate < ex.crates.len() {
+                let krate = &ex.crates[window.next_crate];
+                window.next_crate += 1;
+
+                if add_crate_tasks(self, ex, &window.config, krate) {
+                    crates_in_graph += 1;
+                }
+            }
+            self.window = Some(window);
+        }
+    }
+
+    fn has_pending_crates(&self, ex: &Experiment) -> bool {
+        self.window
+            .as_ref()
+            .map(|window| window.next_crate < ex.crates.len())
+            .unwrap_or(false)
     }
 
     fn walk_graph<DB: WriteResults>(
@@ -140,6 +226,25 @@ impl TasksGraph {
         // Try to check for the dependencies of this node
         // The list is collected to make the borrowchecker happy
         let mut neighbors = self.graph.neighbors(node).collect::<Vec<_>>();
+        if let Some(order) = self.toolchain_order {
+            let preferred = match order {
+                ToolchainOrder::StartFirst => &ex.toolchains[0],
+                ToolchainOrder::EndFirst => &ex.toolchains[1],
+            };
+            let graph = &self.graph;
+            neighbors.sort_by_key(|id| match graph[*id] {
+                Node::Task { ref task, .. } => task.step.toolchain() != Some(preferred),
+                _ => true,
+            });
+        }
+        if node == self.root && self.scheduling == Some(Scheduling::BreadthFirst) {
+            // Start new crates before dispatching more tasks of the crates already in progress
+            let (idle, busy): (Vec<_>, Vec<_>) = neighbors
+                .into_iter()
+                .partition(|id| !self.is_crate_running(*id));
+            neighbors = idle;
+            neighbors.extend(busy);
+        }
         let mut blocked = false;
         for neighbor in neighbors.drain(..) {
             match self.walk_graph(neighbor, ex, db) {
@@ -155,6 +260,7 @@ impl TasksGraph {
             return WalkResult::Blocked;
         }
 
+        let was_runnable = self.is_runnable(node);
         let mut delete = false;
         let result = match self.graph[node] {
             Node::Task { running: true, .. } => WalkResult::Blocked,
@@ -163,6 +269,10 @@ impl TasksGraph {
                 ref mut running,
             } => {
                 *running = true;
+                if was_runnable {
+                    self.runnable_tasks -= 1;
+                }
+                *self.running_crates.entry(task.krate.clone()).or_insert(0) += 1;
                 WalkResult::Task(node, task.clone())
             }
             Node::CrateCompleted => {
@@ -182,7 +292,99 @@ impl TasksGraph {
     }
 
     pub fn mark_as_completed(&mut self, node: NodeIndex) {
-        self.graph.remove_node(node);
+        if self.is_runnable(node) {
+            self.runnable_tasks -= 1;
+        }
+        let dependents = self
+            .graph
+            .neighbors_directed(node, Direction::Incoming)
+            .collect::<Vec<_>>();
+
+        if let Some(Node::Task { task, running }) = self.graph.remove_node(node) {
+            if let TaskStep::Prepare = task.step {
+                self.prepare_tasks.remove(&task.krate);
+            }
+            if running {
+                let finished = match self.running_crates.get_mut(&task.krate) {
+                    Some(count) => {
+                        *count -= 1;
+                        *count == 0
+                    }
+                    None => false,
+                };
+                if finished {
+                    self.running_crates.remove(&task.krate);
+                }
+            }
+            self.completed_tasks += 1;
+        }
+
+        // The tasks that were only waiting for this node can now be started
+        for id in dependents {
+            if self.is_runnable(id) {
+                self.runnable_tasks += 1;
+            }
+        }
+    }
+
+    /// Check whether `node` is a task without dependencies left that is not being executed yet.
+    fn is_runnable(&self, node: NodeIndex) -> bool {
+        match self.graph.node_weight(node) {
+            Some(&Node::Task { running: false, .. }) => self.graph.neighbors(node).next().is_none(),
+            _ => false,
+        }
+    }
+
+    /// Check whether any task of the crate completed by `node` is being executed.
+    fn is_crate_running(&self, node: NodeIndex) -> bool {
+        self.graph
+            .neighbors(node)
+            .filter_map(|id| match self.graph[id] {
+                Node::Task { ref task, .. } => Some(&task.krate),
+                Node::CrateCompleted | Node::Root => None,
+            })
+            .next()
+            .map(|krate| self.running_crates.contains_key(krate))
+            .unwrap_or(false)
+    }
+
+    pub fn progress(&self, ex: &Experiment) -> GraphProgress {
+        let mut pending = BTreeSet::new();
+        for id in self.graph.node_indices() {
+            if let Node::Task { ref task, .. } = self.graph[id] {
+                pending.insert(task.krate.clone());
+            }
+        }
+        if let Some(ref window) = self.window {
+            pending.extend(ex.crates[window.next_crate..].iter().cloned());
+        }
+
+        GraphProgress {
+            completed_tasks: self.completed_tasks,
+            pending_crates: pending.into_iter().collect(),
+        }
+    }
+
+    /// Count the tasks without dependencies left that are not being executed yet, which the
+    /// workers can start right away. Returns `None` if all the workers should look for tasks
+    /// instead, either because the graph finished or because more crates can be added to it.
+    fn runnable_tasks(&self, ex: &Experiment) -> Option<usize> {
+        if self.graph.node_count() == 1 || self.has_pending_crates(ex) {
+            return None;
+        }
+
+        // The count is kept up to date as the graph changes, so the workers don't have to scan
+        // the whole graph while holding its lock after each task
+        Some(self.runnable_tasks)
+    }
+
+    /// Describe all the nodes still in the graph, except for the root.
+    fn pending_nodes(&self) -> Vec<String> {
+        self.graph
+            .node_indices()
+            .filter(|&id| id != self.root)
+            .map(|id| format!("{:?}", self.graph[id]))
+            .collect()
     }
 
     pub fn mark_as_failed<DB: WriteResults>(
@@ -193,9 +395,23 @@ impl TasksGraph {
         error: &Error,
         result: TestResult,
     ) -> Result<()> {
+        // The node might have been removed already, for example if its crate was cancelled
+        if self.graph.node_weight(node).is_none() {
+            return Ok(());
+        }
+
+        // Crates depending on this one wait for it to complete, but they don't fail with it
+        let krate = match self.graph[node] {
+            Node::Task { ref task, .. } => Some(task.krate.clone()),
+            Node::CrateCompleted | Node::Root => None,
+        };
         let mut children = self
             .graph
             .neighbors_directed(node, Direction::Incoming)
+            .filter(|child| match self.graph[*child] {
+                Node::Task { ref task, .. } => Some(&task.krate) == krate.as_ref(),
+                Node::CrateCompleted | Node::Root => true,
+            })
             .collect::<Vec<_>>();
         for child in children.drain(..) {
             self.mark_as_failed(child, ex, db, error, result)?;
@@ -209,62 +425,309 @@ impl TasksGraph {
         self.mark_as_completed(node);
         Ok(())
     }
+
+    /// Remove the tasks of a crate not executed yet from the graph, recording them as cancelled.
+    /// The tasks already running are not interrupted, and they record their results as usual.
+    pub fn cancel_crate<DB: WriteResults>(
+        &mut self,
+        krate: &Crate,
+        ex: &Experiment,
+        db: &DB,
+    ) -> Result<()> {
+        let error: Error = format!("crate {} cancelled", krate).into();
+
+        // Crates not added to the windowed graph yet are recorded as cancelled right away
+        if let Some(ref window) = self.window {
+            let pending = ex.crates[window.next_crate..].contains(krate);
+            if pending && !window.config.should_skip(krate) {
+                for step in crate_build_steps(ex, &window.config, krate) {
+                    let task = Task {
+                        krate: krate.clone(),
+                        step,
+                    };
+                    task.mark_as_failed(ex, db, &error, TestResult::Cancelled)?;
+                }
+            }
+        }
+        self.cancelled_crates.insert(krate.clone());
+
+        // Running tasks are kept in the graph, so their nodes are not reused before they finish
+        let nodes = self
+            .graph
+            .node_indices()
+            .filter(|&id| match self.graph[id] {
+                Node::Task {
+                    ref task,
+                    running: false,
+                } => task.krate == *krate,
+                _ => false,
+            })
+            .collect::<Vec<_>>();
+        for node in nodes {
+            if let Node::Task { ref task, .. } = self.graph[node] {
+                task.mark_as_failed(ex, db, &error, TestResult::Cancelled)?;
+            }
+            self.mark_as_completed(node);
+        }
+
+        Ok(())
+    }
+}
+
+/// Shared handle to the tasks graph executed by `run_ex`, allowing other threads to cancel single
+/// crates while the experiment is running.
+#[derive(Clone, Default)]
+pub struct GraphHandle {
+    graph: Arc<Mutex<TasksGraph>>,
+}
+
+impl GraphHandle {
+    /// Cancel all the tasks of the crate not executed yet, including the ones of a graph that is
+    /// still being built.
+    pub fn cancel_crate<DB: WriteResults>(
+        &self,
+        krate: &Crate,
+        ex: &Experiment,
+        db: &DB,
+    ) -> Result<()> {
+        self.graph.lock().unwrap().cancel_crate(krate, ex, db)
+    }
+
+    /// Replace the graph with a newly built one, cancelling again the crates cancelled so far.
+    fn replace<DB: WriteResults>(
+        &self,
+        mut graph: TasksGraph,
+        ex: &Experiment,
+        db: &DB,
+    ) -> Result<()> {
+        let mut current = self.graph.lock().unwrap();
+        for krate in &current.cancelled_crates {
+            graph.cancel_crate(krate, ex, db)?;
+        }
+        *current = graph;
+        Ok(())
+    }
 }
 
-fn build_graph(ex: &Experiment, config: &Config) -> TasksGraph {
+pub(crate) fn build_graph(ex: &Experiment, config: &Config) -> TasksGraph {
     let mut graph = TasksGraph::new();
+    graph.toolchain_order = config.toolchain_order;
+    graph.scheduling = config.scheduling;
 
     for krate in &ex.crates {
-        if config.should_skip(krate) {
-            continue;
+        add_crate_tasks(&mut graph, ex, config, krate);
+    }
+
+    graph
+}
+
+/// Build a graph containing only the crates that still need to be tested, without allocating the
+/// nodes of the crates completed before the experiment was resumed.
+fn build_graph_incremental<DB: WriteResults>(
+    ex: &Experiment,
+    config: &Config,
+    db: &DB,
+    dependencies: Option<HashMap<Crate, Vec<Crate>>>,
+) -> TasksGraph {
+    let mut graph = TasksGraph::new();
+    graph.toolchain_order = config.toolchain_order;
+    graph.scheduling = config.scheduling;
+    graph.dependencies = dependencies;
+
+    for krate in &ex.crates {
+        // Nothing is recorded by the prepare step, so all the crates are prepared again
+        let completed = !config.prepare_only
+            && crate_build_steps(ex, config, krate)
+                .into_iter()
+                .all(|step| {
+                    let task = Task {
+                        krate: krate.clone(),
+                        step,
+                    };
+                    !task.needs_exec(ex, db)
+                });
+        if !completed {
+            add_crate_tasks(&mut graph, ex, config, krate);
         }
+    }
 
-        let prepare_id = graph.add_task(
-            Task {
-                krate: krate.clone(),
-                step: TaskStep::Prepare,
-            },
-            &[],
-        );
+    graph
+}
 
-        let quiet = config.is_quiet(krate);
-        let mut builds = Vec::new();
-        for tc in &ex.toolchains {
-            let build_id = graph.add_task(
+/// Build a graph containing at most `window` crates at a time, adding the other crates only when
+/// the previous ones are completed. This keeps the memory usage bounded for huge experiments.
+fn build_windowed_graph(
+    ex: &Experiment,
+    config: &Config,
+    window: usize,
+    dependencies: Option<HashMap<Crate, Vec<Crate>>>,
+) -> TasksGraph {
+    let mut graph = TasksGraph::new();
+    graph.toolchain_order = config.toolchain_order;
+    graph.scheduling = config.scheduling;
+    graph.dependencies = dependencies;
+    graph.window = Some(Window {
+        size: window,
+        next_crate: 0,
+        config: config.clone(),
+    });
+    graph.fill_window(ex);
+
+    graph
+}
+
+/// Add all the tasks needed to test a crate to the graph, returning `false` if the crate is
+/// skipped.
+fn add_crate_tasks(
+    graph: &mut TasksGraph,
+    ex: &Experiment,
+    config: &Config,
+    krate: &Crate,
+) -> bool {
+    // Cancelled crates are never added back to the graph
+    if config.should_skip(krate) || graph.cancelled_crates.contains(krate) {
+        return false;
+    }
+
+    let prepare_deps = graph
+        .dependencies
+        .as_ref()
+        .and_then(|dependencies| dependencies.get(krate))
+        .map(|deps| {
+            deps.iter()
+                .filter_map(|dep| graph.prepare_tasks.get(dep).cloned())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let prepare_id = graph.add_task(
+        Task {
+            krate: krate.clone(),
+            step: TaskStep::Prepare,
+        },
+        &prepare_deps,
+    );
+    graph.prepare_tasks.insert(krate.clone(), prepare_id);
+
+    // When only preparing the crates they're completed as soon as the prepare step finishes
+    if config.prepare_only {
+        graph.add_crate(&[prepare_id]);
+        return true;
+    }
+
+    // The builds for each toolchain only depend on the prepare step, and use different work
+    // and target directories: this allows them to be executed concurrently by two workers.
+    let builds = crate_build_steps(ex, config, krate)
+        .into_iter()
+        .map(|step| {
+            graph.add_task(
                 Task {
                     krate: krate.clone(),
-                    step: match ex.mode {
-                        ExMode::BuildOnly => TaskStep::BuildOnly {
-                            tc: tc.clone(),
-                            quiet,
-                        },
-                        ExMode::BuildAndTest if config.should_skip_tests(krate) => {
-                            TaskStep::BuildOnly {
-                                tc: tc.clone(),
-                                quiet,
-                            }
-                        }
-                        ExMode::BuildAndTest => TaskStep::BuildAndTest {
-                            tc: tc.clone(),
-                            quiet,
-                        },
-                        ExMode::CheckOnly => TaskStep::CheckOnly {
-                            tc: tc.clone(),
-                            quiet,
-                        },
-                        ExMode::UnstableFeatures => TaskStep::UnstableFeatures { tc: tc.clone() },
-                    },
+                    step,
                 },
                 &[prepare_id],
-            );
+            )
+        })
+        .collect::<Vec<_>>();
 
-            builds.push(build_id);
+    graph.add_crate(&builds);
+
+    true
+}
+
+/// Sort the crates so that each one comes after its dependencies. Dependency cycles are broken by
+/// picking the smallest crate among the ones left, so the order is always the same.
+fn dependency_order(crates: &[Crate], dependencies: &HashMap<Crate, Vec<Crate>>) -> Vec<Crate> {
+    let mut remaining = crates.iter().collect::<BTreeSet<_>>();
+
+    // Each crate keeps track of how many of its dependencies are still to be ordered, and it's
+    // ready as soon as there are none left
+    let mut pending_deps = HashMap::new();
+    let mut dependents: HashMap<&Crate, Vec<&Crate>> = HashMap::new();
+    for &krate in &remaining {
+        let deps = dependencies
+            .get(krate)
+            .map(|deps| {
+                deps.iter()
+                    .filter(|dep| remaining.contains(dep))
+                    .collect::<BTreeSet<_>>()
+            })
+            .unwrap_or_default();
+        for &dep in &deps {
+            dependents.entry(dep).or_insert_with(Vec::new).push(krate);
         }
+        pending_deps.insert(krate, deps.len());
+    }
+    let mut ready = pending_deps
+        .iter()
+        .filter(|&(_, &count)| count == 0)
+        .map(|(&krate, _)| krate)
+        .collect::<BTreeSet<_>>();
+
+    let mut ordered = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        // If no crate is ready all the remaining ones are part of a cycle
+        let next = ready
+            .iter()
+            .next()
+            .cloned()
+            .unwrap_or_else(|| *remaining.iter().next().unwrap());
+        ready.remove(next);
+        remaining.remove(next);
+        ordered.push(next.clone());
 
-        graph.add_crate(&builds);
+        for &dependent in dependents.get(next).map(|d| d.as_slice()).unwrap_or(&[]) {
+            let count = pending_deps.get_mut(&dependent).unwrap();
+            *count -= 1;
+            // Crates in a cycle might have already been ordered before their dependencies
+            if *count == 0 && remaining.contains(dependent) {
+                ready.insert(dependent);
+            }
+        }
     }
 
-    graph
+    ordered
+}
+
+/// Get the steps needed to build the crate with each of the toolchains of the experiment.
+fn crate_build_steps(ex: &Experiment, config: &Config, krate: &Crate) -> Vec<TaskStep> {
+    let quiet = config.is_quiet(krate);
+
+    // Identical toolchains record their results in the same place, so the repeated builds would
+    // only overwrite the results of the first one
+    let mut seen = HashSet::new();
+    let toolchains = ex
+        .run_toolchains()
+        .iter()
+        .filter(|tc| !config.dedupe_toolchains || seen.insert(*tc))
+        .collect::<Vec<_>>();
+
+    toolchains
+        .into_iter()
+        .map(|tc| match ex.mode {
+            ExMode::BuildOnly => TaskStep::BuildOnly {
+                tc: tc.clone(),
+                quiet,
+            },
+            ExMode::BuildAndTest if config.should_skip_tests(krate) => TaskStep::BuildOnly {
+                tc: tc.clone(),
+                quiet,
+            },
+            ExMode::BuildAndTest => TaskStep::BuildAndTest {
+                tc: tc.clone(),
+                quiet,
+                test_args: config.test_args(krate),
+            },
+            ExMode::CheckOnly => TaskStep::CheckOnly {
+                tc: tc.clone(),
+                quiet,
+            },
+            ExMode::UnstableFeatures => TaskStep::UnstableFeatures { tc: tc.clone() },
+            ExMode::Clippy => TaskStep::Clippy {
+                tc: tc.clone(),
+                quiet,
+            },
+        })
+        .collect()
 }
 
 pub fn run_ex<DB: WriteResults + Sync>(
@@ -272,20 +735,91 @@ pub fn run_ex<DB: WriteResults + Sync>(
     db: &DB,
     threads_count: usize,
     config: &Config,
+    cancel: &CancelToken,
+    handle: &GraphHandle,
 ) -> Result<()> {
+    let ordered;
+    let (ex, dependencies) = if config.dependency_order {
+        info!("sorting the crates in dependency order...");
+        registry::crates_index_registry()?;
+        let dependencies = registry::load_dependencies(&registry::crates_index_dir(), &ex.crates);
+
+        let mut sorted = ex.clone();
+        sorted.crates = dependency_order(&ex.crates, &dependencies);
+        ordered = sorted;
+        (&ordered, Some(dependencies))
+    } else {
+        (ex, None)
+    };
+
     info!("computing the tasks graph...");
-    let graph = Mutex::new(build_graph(ex, config));
+    let graph = if let Some(window) = config.tasks_graph_window {
+        build_windowed_graph(ex, config, window, dependencies)
+    } else {
+        build_graph_incremental(ex, config, db, dependencies)
+    };
+    handle.replace(graph, ex, db)?;
+    let graph = &handle.graph;
 
     info!("preparing the execution...");
-    ex::prepare_all_toolchains(ex)?;
+    ex::prepare_all_toolchains(ex, config, cancel, &|tc, percent| {
+        info!("preparing toolchain {} ({}%)", tc.to_string(), percent);
+    })?;
+    ex::prepare_index_snapshot(ex)?;
 
     info!("running tasks in {} threads...", threads_count);
+    run_tasks(graph, ex, db, threads_count, config, cancel)?;
+
+    // Only the root node must be present
+    let mut g = graph.lock().unwrap();
+    assert!(g.next_task(ex, db).is_finished());
+    assert_eq!(g.graph.neighbors(g.root).count(), 0);
+
+    ex::cleanup_completed(&ex.name, config)?;
 
+    Ok(())
+}
+
+/// Execute all the tasks of the graph in `threads_count` worker threads.
+///
+/// If a grace period is configured and all the workers are waiting for a task with none of them
+/// running one for longer than that, the graph can't make progress anymore and an error listing
+/// the pending nodes is returned instead of hanging forever.
+///
+/// If `cancel` is triggered the workers stop picking up new tasks, and an error is returned once
+/// the running ones are finished.
+pub(crate) fn run_tasks<DB: WriteResults + Sync>(
+    graph: &Mutex<TasksGraph>,
+    ex: &Experiment,
+    db: &DB,
+    threads_count: usize,
+    config: &Config,
+    cancel: &CancelToken,
+) -> Result<()> {
     // An HashMap is used instead of an HashSet because Thread is not Eq+Hash
     let parked_threads: Mutex<HashMap<thread::ThreadId, thread::Thread>> =
         Mutex::new(HashMap::new());
 
+    let grace_period = config.deadlock_grace_period.map(Duration::from_secs);
+    let running_tasks = AtomicUsize::new(0);
+    let stuck_since: Mutex<Option<Instant>> = Mutex::new(None);
+
     crossbeam::scope(|scope| -> Result<()> {
+        // Dropping the sender stops the autosave thread
+        let (stop_autosave, autosave_stopped) = mpsc::channel();
+        if let Some(interval) = config.graph_autosave_interval {
+            let dest = ex::graph_progress_file(&ex.name);
+            scope.spawn(move || {
+                autosave(
+                    graph,
+                    ex,
+                    &dest,
+                    Duration::from_secs(interval),
+                    &autosave_stopped,
+                )
+            });
+        }
+
         let mut threads = Vec::new();
 
         for i in 0..threads_count {
@@ -293,16 +827,50 @@ pub fn run_ex<DB: WriteResults + Sync>(
             let join = scope.builder().name(name).spawn(|| -> Result<()> {
                 // This uses a `loop` instead of a `while let` to avoid locking the graph too much
                 loop {
-                    let walk_result = graph.lock().unwrap().next_task(ex, db);
+                    if cancel.is_cancelled() {
+                        // Let the parked threads notice the cancellation too
+                        unpark_threads(&mut parked_threads.lock().unwrap(), None);
+                        bail!("the experiment was cancelled");
+                    }
+
+                    let walk_result = {
+                        let mut graph = graph.lock().unwrap();
+                        let walk_result = graph.next_task(ex, db);
+                        if let WalkResult::Task(..) = walk_result {
+                            running_tasks.fetch_add(1, Ordering::SeqCst);
+                            *stuck_since.lock().unwrap() = None;
+                        }
+                        walk_result
+                    };
                     match walk_result {
                         WalkResult::Task(id, task) => {
-                            info!("running task: {:?}", task);
-                            if let Err(e) = task.run(config, ex, db) {
+                            // The attempts are kept across the restarts of the run, so a crate
+                            // crashing it every time is marked as failed instead of retried
+                            let allowed = match config.crate_attempts {
+                                Some(budget) => {
+                                    db.use_attempt(ex, &task.krate, task.step.kind(), budget)?
+                                }
+                                None => true,
+                            };
+                            let res = if allowed {
+                                info!("running task: {:?}", task);
+                                task.run(config, ex, db)
+                            } else {
+                                Err(format!("no attempts left for task {:?}", task).into())
+                            };
+                            if let Err(e) = res {
                                 error!("task failed, marking childs as failed too: {:?}", task);
                                 util::report_error(&e);
 
+                                let timed_out = match *e.kind() {
+                                    ErrorKind::Timeout(..) => true,
+                                    _ => false,
+                                };
+                                // Only the steps running the tests can record a test timeout
                                 let result = if config.is_broken(&task.krate) {
                                     TestResult::BuildFail
+                                } else if timed_out {
+                                    task.step.timeout_result()
                                 } else {
                                     TestResult::Error
                                 };
@@ -313,26 +881,58 @@ pub fn run_ex<DB: WriteResults + Sync>(
                             } else {
                                 graph.lock().unwrap().mark_as_completed(id);
                             }
+                            let runnable = graph.lock().unwrap().runnable_tasks(ex);
+                            running_tasks.fetch_sub(1, Ordering::SeqCst);
 
-                            // Unpark all the threads
-                            let mut parked = parked_threads.lock().unwrap();
-                            for (_id, thread) in parked.drain() {
-                                thread.unpark();
-                            }
+                            // Waking up more threads than the tasks they can pick up would only
+                            // make them contend on the graph lock before being parked again
+                            unpark_threads(&mut parked_threads.lock().unwrap(), runnable);
                         }
                         WalkResult::Blocked => {
                             // Wait until another thread finished before looking for tasks again
                             // If the thread spuriously wake up (parking does not guarantee no
                             // spurious wakeups) it's not a big deal, it will just get parked again
-                            {
+                            let all_parked = {
                                 let mut parked_threads = parked_threads.lock().unwrap();
                                 let current = thread::current();
                                 parked_threads.insert(current.id(), current);
+                                parked_threads.len() == threads_count
+                                    && running_tasks.load(Ordering::SeqCst) == 0
+                            };
+
+                            if let Some(grace_period) = grace_period {
+                                if all_parked {
+                                    stuck_since
+                                        .lock()
+                                        .unwrap()
+                                        .get_or_insert_with(Instant::now);
+                                }
+                                thread::park_timeout(grace_period);
+
+                                let deadlocked = stuck_since
+                                    .lock()
+                                    .unwrap()
+                                    .map(|since| since.elapsed() >= grace_period)
+                                    .unwrap_or(false);
+                                if deadlocked {
+                                    bail!(
+                                        "the tasks graph is deadlocked: all the workers have been \
+                                         waiting for more than {} seconds with no running \
+                                         tasks (pending nodes: {})",
+                                        grace_period.as_secs(),
+                                        graph.lock().unwrap().pending_nodes().join(", ")
+                                    );
+                                }
+                            } else {
+                                thread::park();
                             }
-                            thread::park();
                         }
                         WalkResult::NotBlocked => unreachable!("NotBlocked leaked from the run"),
-                        WalkResult::Finished => break,
+                        WalkResult::Finished => {
+                            // The other threads might still be parked waiting for a task
+                            unpark_threads(&mut parked_threads.lock().unwrap(), None);
+                            break;
+                        }
                     }
                 }
 
@@ -344,16 +944,61 @@ pub fn run_ex<DB: WriteResults + Sync>(
         for thread in threads.drain(..) {
             thread.join()?;
         }
+        drop(stop_autosave);
 
         Ok(())
-    })?;
+    })
+}
 
-    // Only the root node must be present
-    let mut g = graph.lock().unwrap();
-    assert!(g.next_task(ex, db).is_finished());
-    assert_eq!(g.graph.neighbors(g.root).count(), 0);
+/// Unpark up to `count` of the parked threads, or all of them if `count` is `None`, returning
+/// how many threads were unparked.
+fn unpark_threads(
+    parked: &mut HashMap<thread::ThreadId, thread::Thread>,
+    count: Option<usize>,
+) -> usize {
+    let count = count.unwrap_or_else(|| parked.len()).min(parked.len());
+    let ids = parked.keys().take(count).cloned().collect::<Vec<_>>();
+    for id in &ids {
+        if let Some(thread) = parked.remove(id) {
+            thread.unpark();
+        }
+    }
+    count
+}
 
-    Ok(())
+/// Periodically save the progress of the graph to `dest`, until the other end of the channel is
+/// dropped. The lock on the graph is only held while computing the progress, not while writing it.
+fn autosave(
+    graph: &Mutex<TasksGraph>,
+    ex: &Experiment,
+    dest: &Path,
+    interval: Duration,
+    stop: &mpsc::Receiver<()>,
+) {
+    loop {
+        let stopped = match stop.recv_timeout(interval) {
+            Err(mpsc::RecvTimeoutError::Timeout) => false,
+            Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => true,
+        };
+
+        let progress = graph.lock().unwrap().progress(ex);
+        let result = serde_json::to_string(&progress)
+            .map_err(Error::from)
+            .and_then(|serialized| {
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                file::write_string_atomic(dest, &serialized)
+            });
+        if let Err(err) = result {
+            error!("failed to save the progress of the tasks graph");
+            util::report_error(&err);
+        }
+
+        if stopped {
+            break;
+        }
+    }
 }
 
 pub fn dump_dot(ex_name: &str, config: &Config, dest: &Path) -> Result<()> {
@@ -369,3 +1014,1167 @@ pub fn dump_dot(ex_name: &str, config: &Config, dest: &Path) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        autosave, build_graph, build_graph_incremental, build_windowed_graph, dependency_order,
+        run_tasks, unpark_threads, GraphHandle, GraphProgress, Node, TasksGraph, WalkResult,
+    };
+    use config::{Config, Scheduling, ToolchainOrder};
+    use crates::{Crate, GitHubRepo, RegistryCrate};
+    use crossbeam;
+    use errors::*;
+    use ex::{ExCapLints, ExMode, ExStep, Experiment};
+    use file;
+    use results::{DummyDB, TestResult, WriteResults};
+    use serde_json;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{mpsc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use tasks::TaskStep;
+    use tempdir::TempDir;
+    use toolchain::{Toolchain, MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+    use util::CancelToken;
+
+    #[test]
+    fn test_force_steps() {
+        let config = Config::default();
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+
+        let mut ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![krate.clone()],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        let mut db = DummyDB::default();
+        for tc in &[MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()] {
+            db.add_dummy_result(&ex, krate.clone(), tc.clone(), TestResult::TestPass);
+        }
+
+        // All the results are present, so nothing is executed
+        let mut graph = build_graph(&ex, &config);
+        assert!(graph.next_task(&ex, &db).is_finished());
+
+        // Forced steps are executed even if their results are present
+        ex.force_steps.push(ExStep::BuildAndTest);
+        let mut graph = build_graph(&ex, &config);
+        let mut executed = Vec::new();
+        loop {
+            match graph.next_task(&ex, &db) {
+                WalkResult::Task(id, task) => {
+                    executed.push(task.step.kind());
+                    graph.mark_as_completed(id);
+                }
+                WalkResult::Finished => break,
+                res => panic!("unexpected walk result: {:?}", res),
+            }
+        }
+        assert_eq!(
+            executed,
+            vec![ExStep::Prepare, ExStep::BuildAndTest, ExStep::BuildAndTest]
+        );
+
+        // The forced builds really run again: the prepare step is completed without downloading
+        // the crate, so they fail and their new results replace the old ones
+        let mut graph = build_graph(&ex, &config);
+        match graph.next_task(&ex, &db) {
+            WalkResult::Task(id, ref task) if task.step.kind() == ExStep::Prepare => {
+                graph.mark_as_completed(id);
+            }
+            res => panic!("unexpected walk result: {:?}", res),
+        }
+        let graph = Mutex::new(graph);
+        run_tasks(&graph, &ex, &db, 2, &config, &CancelToken::default()).unwrap();
+        for tc in &[MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()] {
+            assert_eq!(
+                db.get_result(&ex, tc, &krate).unwrap(),
+                Some(TestResult::Error)
+            );
+        }
+    }
+
+    /// Database keeping track of the tasks executed, which can also pause the first one until the
+    /// test resumes it, so the test can act while the experiment is running.
+    struct PausingDB {
+        inner: DummyDB,
+        lookups: Mutex<HashMap<(Crate, Toolchain), usize>>,
+        executed: Mutex<Vec<Crate>>,
+        pause: Mutex<Option<(mpsc::Sender<Crate>, mpsc::Receiver<()>)>>,
+    }
+
+    impl WriteResults for PausingDB {
+        fn get_result(
+            &self,
+            ex: &Experiment,
+            toolchain: &Toolchain,
+            krate: &Crate,
+        ) -> Result<Option<TestResult>> {
+            let lookups = {
+                let mut lookups = self.lookups.lock().unwrap();
+                let count = lookups
+                    .entry((krate.clone(), toolchain.clone()))
+                    .or_insert(0);
+                *count += 1;
+                *count
+            };
+
+            // The first lookup is done while walking the graph, the second one when the task is
+            // executed, without holding the graph lock
+            if lookups == 2 {
+                self.executed.lock().unwrap().push(krate.clone());
+                if let Some((started, resume)) = self.pause.lock().unwrap().take() {
+                    started.send(krate.clone()).unwrap();
+                    resume.recv().unwrap();
+                }
+            }
+
+            self.inner.get_result(ex, toolchain, krate)
+        }
+
+        fn record_sha(&self, ex: &Experiment, repo: &GitHubRepo, sha: &str) -> Result<()> {
+            self.inner.record_sha(ex, repo, sha)
+        }
+
+        fn record_result<F>(
+            &self,
+            ex: &Experiment,
+            toolchain: &Toolchain,
+            krate: &Crate,
+            f: F,
+        ) -> Result<TestResult>
+        where
+            F: FnOnce() -> Result<TestResult>,
+        {
+            self.inner.record_result(ex, toolchain, krate, f)
+        }
+
+        fn record_metadata(
+            &self,
+            ex: &Experiment,
+            toolchain: &Toolchain,
+            krate: &Crate,
+            metadata: &str,
+        ) -> Result<()> {
+            self.inner.record_metadata(ex, toolchain, krate, metadata)
+        }
+
+        fn use_attempt(
+            &self,
+            ex: &Experiment,
+            krate: &Crate,
+            step: ExStep,
+            budget: u32,
+        ) -> Result<bool> {
+            self.inner.use_attempt(ex, krate, step, budget)
+        }
+    }
+
+    /// Database failing to record any result, as if the run crashed before recording them.
+    struct CrashingDB<'a>(&'a DummyDB);
+
+    impl<'a> WriteResults for CrashingDB<'a> {
+        fn get_result(
+            &self,
+            ex: &Experiment,
+            toolchain: &Toolchain,
+            krate: &Crate,
+        ) -> Result<Option<TestResult>> {
+            self.0.get_result(ex, toolchain, krate)
+        }
+
+        fn record_sha(&self, ex: &Experiment, repo: &GitHubRepo, sha: &str) -> Result<()> {
+            self.0.record_sha(ex, repo, sha)
+        }
+
+        fn record_result<F>(
+            &self,
+            _ex: &Experiment,
+            _toolchain: &Toolchain,
+            _krate: &Crate,
+            _f: F,
+        ) -> Result<TestResult>
+        where
+            F: FnOnce() -> Result<TestResult>,
+        {
+            bail!("the run crashed");
+        }
+
+        fn record_metadata(
+            &self,
+            ex: &Experiment,
+            toolchain: &Toolchain,
+            krate: &Crate,
+            metadata: &str,
+        ) -> Result<()> {
+            self.0.record_metadata(ex, toolchain, krate, metadata)
+        }
+
+        fn use_attempt(
+            &self,
+            ex: &Experiment,
+            krate: &Crate,
+            step: ExStep,
+            budget: u32,
+        ) -> Result<bool> {
+            self.0.use_attempt(ex, krate, step, budget)
+        }
+    }
+
+    #[test]
+    fn test_cancel_crate() {
+        let config = Config::default();
+        let crates = (0..2)
+            .map(|i| {
+                Crate::Registry(RegistryCrate {
+                    name: format!("crate-{}", i),
+                    version: "1.0".into(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let ex = Experiment {
+            name: "cancel-crate".to_string(),
+            crates: crates.clone(),
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        let (started, started_recv) = mpsc::channel();
+        let (resume_send, resume) = mpsc::channel();
+        let db = PausingDB {
+            inner: DummyDB::default(),
+            lookups: Mutex::new(HashMap::new()),
+            executed: Mutex::new(Vec::new()),
+            pause: Mutex::new(Some((started, resume))),
+        };
+
+        // The prepare steps are completed without downloading the crates, so the builds fail
+        let mut graph = build_graph(&ex, &config);
+        for _ in 0..2 {
+            match graph.next_task(&ex, &db.inner) {
+                WalkResult::Task(id, ref task) if task.step.kind() == ExStep::Prepare => {
+                    graph.mark_as_completed(id);
+                }
+                res => panic!("unexpected walk result: {:?}", res),
+            }
+        }
+        let handle = GraphHandle::default();
+        handle.replace(graph, &ex, &db.inner).unwrap();
+
+        let cancelled = crossbeam::scope(|scope| {
+            let run = scope.spawn(|| {
+                run_tasks(&handle.graph, &ex, &db, 1, &config, &CancelToken::default())
+            });
+
+            // Cancel the other crate while the first task is running
+            let running = started_recv.recv().unwrap();
+            let cancelled = crates.iter().find(|&krate| *krate != running).unwrap().clone();
+            handle.cancel_crate(&cancelled, &ex, &db.inner).unwrap();
+            resume_send.send(()).unwrap();
+
+            run.join().unwrap();
+            cancelled
+        });
+
+        // Only the tasks of the crate that was already running are executed
+        let executed = db.executed.lock().unwrap();
+        assert_eq!(executed.len(), 2);
+        assert!(executed.iter().all(|krate| *krate != cancelled));
+        for tc in &ex.toolchains {
+            assert_eq!(
+                db.inner.get_result(&ex, tc, &executed[0]).unwrap(),
+                Some(TestResult::Error)
+            );
+            assert_eq!(
+                db.inner.get_result(&ex, tc, &cancelled).unwrap(),
+                Some(TestResult::Cancelled)
+            );
+        }
+        assert!(handle.graph.lock().unwrap().next_task(&ex, &db.inner).is_finished());
+    }
+
+    #[test]
+    fn test_crate_attempts_across_restarts() {
+        let mut config = Config::default();
+        config.crate_attempts = Some(1);
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+
+        let ex = Experiment {
+            name: "crate-attempts".to_string(),
+            crates: vec![krate.clone()],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let db = PausingDB {
+            inner: DummyDB::default(),
+            lookups: Mutex::new(HashMap::new()),
+            executed: Mutex::new(Vec::new()),
+            pause: Mutex::new(None),
+        };
+
+        // The prepare step is completed without downloading the crate, so the builds fail
+        let prepared = || {
+            let mut graph = build_graph(&ex, &config);
+            match graph.next_task(&ex, &db.inner) {
+                WalkResult::Task(id, ref task) if task.step.kind() == ExStep::Prepare => {
+                    graph.mark_as_completed(id);
+                }
+                res => panic!("unexpected walk result: {:?}", res),
+            }
+            Mutex::new(graph)
+        };
+
+        // The first run crashes after using the only attempt of the build step
+        let crashing = CrashingDB(&db.inner);
+        let cancel = CancelToken::default();
+        assert!(run_tasks(&prepared(), &ex, &crashing, 1, &config, &cancel).is_err());
+        for tc in &ex.toolchains {
+            assert_eq!(db.inner.get_result(&ex, tc, &krate).unwrap(), None);
+        }
+
+        // The attempts are still used after restarting the run, so the builds are not executed
+        // anymore and the crate is marked as failed
+        run_tasks(&prepared(), &ex, &db, 1, &config, &cancel).unwrap();
+        assert!(db.executed.lock().unwrap().is_empty());
+        for tc in &ex.toolchains {
+            assert_eq!(
+                db.inner.get_result(&ex, tc, &krate).unwrap(),
+                Some(TestResult::Error)
+            );
+        }
+    }
+
+    #[test]
+    fn test_dedupe_toolchains() {
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: ["lazy_static", "bitflags"]
+                .iter()
+                .map(|name| {
+                    Crate::Registry(RegistryCrate {
+                        name: name.to_string(),
+                        version: "1.0".into(),
+                    })
+                })
+                .collect(),
+            toolchains: [MAIN_TOOLCHAIN.clone(), MAIN_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: true,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let db = DummyDB::default();
+
+        let builds = |config: &Config| {
+            let mut graph = build_graph(&ex, config);
+            let mut builds = HashMap::new();
+            loop {
+                match graph.next_task(&ex, &db) {
+                    WalkResult::Task(id, task) => {
+                        if task.step.kind() == ExStep::BuildAndTest {
+                            *builds.entry(task.krate.clone()).or_insert(0) += 1;
+                        }
+                        graph.mark_as_completed(id);
+                    }
+                    WalkResult::Finished => break,
+                    res => panic!("unexpected walk result: {:?}", res),
+                }
+            }
+            builds
+        };
+
+        // By default the crates are built once per toolchain, even if they are identical
+        let mut config = Config::default();
+        assert!(builds(&config).values().all(|&count| count == 2));
+
+        config.dedupe_toolchains = true;
+        let deduped = builds(&config);
+        assert_eq!(deduped.len(), 2);
+        assert!(deduped.values().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn test_parallel_builds_of_one_crate() {
+        let config = Config::default();
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![krate.clone()],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let db = DummyDB::default();
+        let mut graph = build_graph(&ex, &config);
+
+        // The prepare step blocks everything else
+        let prepare = match graph.next_task(&ex, &db) {
+            WalkResult::Task(id, task) => {
+                assert_eq!(task.step.kind(), ExStep::Prepare);
+                id
+            }
+            res => panic!("unexpected walk result: {:?}", res),
+        };
+        assert!(match graph.next_task(&ex, &db) {
+            WalkResult::Blocked => true,
+            _ => false,
+        });
+        graph.mark_as_completed(prepare);
+
+        // Both builds are dispatched without waiting for the other one to complete
+        let mut running = Vec::new();
+        for _ in 0..2 {
+            match graph.next_task(&ex, &db) {
+                WalkResult::Task(id, task) => {
+                    assert_eq!(task.step.kind(), ExStep::BuildAndTest);
+                    running.push(id);
+                }
+                res => panic!("unexpected walk result: {:?}", res),
+            }
+        }
+        assert!(match graph.next_task(&ex, &db) {
+            WalkResult::Blocked => true,
+            _ => false,
+        });
+
+        // The two builds don't share their target directories
+        assert_ne!(
+            ex.toolchains[0].target_dir(&ex.name),
+            ex.toolchains[1].target_dir(&ex.name)
+        );
+
+        for id in running.drain(..) {
+            graph.mark_as_completed(id);
+        }
+        assert!(graph.next_task(&ex, &db).is_finished());
+    }
+
+    #[test]
+    fn test_unpark_only_runnable() {
+        let config = Config::default();
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![krate.clone()],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let db = DummyDB::default();
+        let mut graph = build_graph(&ex, &config);
+
+        // Unparking threads that aren't parked is harmless, so the handles of idle threads are
+        // enough to count the wakeups
+        let (stop, stopped) = mpsc::channel::<()>();
+        let stopped = Mutex::new(stopped);
+        crossbeam::scope(|scope| {
+            let mut parked = HashMap::new();
+            for _ in 0..4 {
+                let handle = scope.spawn(|| {
+                    let _ = stopped.lock().unwrap().recv();
+                });
+                parked.insert(handle.thread().id(), handle.thread().clone());
+            }
+
+            let next = |graph: &mut TasksGraph| match graph.next_task(&ex, &db) {
+                WalkResult::Task(id, _) => id,
+                res => panic!("unexpected walk result: {:?}", res),
+            };
+
+            // Only the prepare step can run at first
+            assert_eq!(graph.runnable_tasks(&ex), Some(1));
+
+            // Completing the prepare step makes the two builds runnable
+            let prepare = next(&mut graph);
+            assert_eq!(graph.runnable_tasks(&ex), Some(0));
+            graph.mark_as_completed(prepare);
+            assert_eq!(graph.runnable_tasks(&ex), Some(2));
+            assert_eq!(unpark_threads(&mut parked, graph.runnable_tasks(&ex)), 2);
+            assert_eq!(parked.len(), 2);
+
+            // Nothing new can run after the first build completes
+            let first = next(&mut graph);
+            let second = next(&mut graph);
+            graph.mark_as_completed(first);
+            assert_eq!(unpark_threads(&mut parked, graph.runnable_tasks(&ex)), 0);
+            assert_eq!(parked.len(), 2);
+
+            // Everyone is woken up when the graph finishes, to exit
+            graph.mark_as_completed(second);
+            assert!(graph.next_task(&ex, &db).is_finished());
+            assert_eq!(graph.runnable_tasks(&ex), None);
+            assert_eq!(unpark_threads(&mut parked, None), 2);
+            assert!(parked.is_empty());
+
+            drop(stop);
+        });
+    }
+
+    #[test]
+    fn test_graph_autosave() {
+        let config = Config::default();
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![krate.clone()],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let db = DummyDB::default();
+        let graph = Mutex::new(build_graph(&ex, &config));
+
+        let dir = TempDir::new("crater-autosave").unwrap();
+        let dest = dir.path().join("graph-progress.json");
+        let load = || -> Option<GraphProgress> {
+            file::read_string(&dest)
+                .ok()
+                .and_then(|content| serde_json::from_str(&content).ok())
+        };
+
+        crossbeam::scope(|scope| {
+            let (stop, stopped) = mpsc::channel();
+            {
+                let (graph, ex, dest) = (&graph, &ex, &dest);
+                scope.spawn(move || {
+                    autosave(graph, ex, dest, Duration::from_millis(10), &stopped)
+                });
+            }
+
+            // Complete the prepare task while the autosave is running
+            let next = graph.lock().unwrap().next_task(&ex, &db);
+            match next {
+                WalkResult::Task(id, task) => {
+                    assert_eq!(task.step.kind(), ExStep::Prepare);
+                    graph.lock().unwrap().mark_as_completed(id);
+                }
+                res => panic!("unexpected walk result: {:?}", res),
+            }
+
+            let mut saved = false;
+            for _ in 0..500 {
+                if load().map(|p| p.completed_tasks == 1).unwrap_or(false) {
+                    saved = true;
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert!(saved, "the progress wasn't saved during the run");
+
+            drop(stop);
+        });
+
+        // The builds of the crate are still pending
+        assert_eq!(
+            load(),
+            Some(GraphProgress {
+                completed_tasks: 1,
+                pending_crates: vec![krate],
+            })
+        );
+    }
+
+    #[test]
+    fn test_toolchain_order() {
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0".into(),
+        });
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![krate.clone()],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let db = DummyDB::default();
+
+        for &(order, ref first) in &[
+            (ToolchainOrder::EndFirst, &*TEST_TOOLCHAIN),
+            (ToolchainOrder::StartFirst, &*MAIN_TOOLCHAIN),
+        ] {
+            let mut config = Config::default();
+            config.toolchain_order = Some(order);
+            let mut graph = build_graph(&ex, &config);
+
+            match graph.next_task(&ex, &db) {
+                WalkResult::Task(id, task) => {
+                    assert_eq!(task.step.kind(), ExStep::Prepare);
+                    graph.mark_as_completed(id);
+                }
+                res => panic!("unexpected walk result: {:?}", res),
+            }
+
+            // The build of the preferred toolchain is dispatched first
+            match graph.next_task(&ex, &db) {
+                WalkResult::Task(_, task) => assert_eq!(task.step.toolchain(), Some(*first)),
+                res => panic!("unexpected walk result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_breadth_first_scheduling() {
+        let crates = (0..2)
+            .map(|i| {
+                Crate::Registry(RegistryCrate {
+                    name: format!("crate-{}", i),
+                    version: "1.0".into(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: crates.clone(),
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let db = DummyDB::default();
+
+        let next = |graph: &mut TasksGraph| match graph.next_task(&ex, &db) {
+            WalkResult::Task(id, task) => (id, task),
+            res => panic!("unexpected walk result: {:?}", res),
+        };
+
+        for &(scheduling, spread) in &[
+            (None, false),
+            (Some(Scheduling::DepthFirst), false),
+            (Some(Scheduling::BreadthFirst), true),
+        ] {
+            let mut config = Config::default();
+            config.scheduling = scheduling;
+            let mut graph = build_graph(&ex, &config);
+
+            // Prepare the first crate, and then dispatch three tasks at the same time
+            let (id, first) = next(&mut graph);
+            assert_eq!(first.step.kind(), ExStep::Prepare);
+            graph.mark_as_completed(id);
+            let in_flight = (0..3)
+                .map(|_| next(&mut graph).1.krate.clone())
+                .collect::<Vec<_>>();
+
+            assert_eq!(in_flight[0], first.krate);
+            if spread {
+                // The other crate is started while a build of the first one is running
+                assert_ne!(in_flight[1], first.krate);
+                assert_eq!(in_flight[2], first.krate);
+            } else {
+                // All the builds of the first crate are dispatched before moving on
+                assert_eq!(in_flight[1], first.krate);
+                assert_ne!(in_flight[2], first.krate);
+            }
+        }
+    }
+
+    #[test]
+    fn test_deadlock_watchdog() {
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: vec![Crate::Registry(RegistryCrate {
+                name: "lazy_static".into(),
+                version: "0.2.11".into(),
+            })],
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let db = DummyDB::default();
+        let mut config = Config::default();
+        config.deadlock_grace_period = Some(1);
+
+        // Mark the prepare task as running without ever completing it, so no worker can make
+        // progress anymore
+        let mut graph = build_graph(&ex, &config);
+        match graph.next_task(&ex, &db) {
+            WalkResult::Task(_, task) => assert_eq!(task.step.kind(), ExStep::Prepare),
+            res => panic!("unexpected walk result: {:?}", res),
+        }
+        let graph = Mutex::new(graph);
+
+        let cancel = CancelToken::default();
+        let err = run_tasks(&graph, &ex, &db, 2, &config, &cancel).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("deadlocked"));
+        assert!(message.contains("running: "));
+        assert!(message.contains("lazy_static"));
+    }
+
+    #[test]
+    fn test_windowed_graph() {
+        let mut config = Config::default();
+        let crates = (0..5)
+            .map(|i| {
+                Crate::Registry(RegistryCrate {
+                    name: format!("crate-{}", i),
+                    version: "1.0".into(),
+                })
+            })
+            .collect::<Vec<_>>();
+        config.crates.insert(
+            "crate-3".into(),
+            ::config::CrateConfig {
+                skip: true,
+                skip_tests: false,
+                quiet: false,
+                update_lockfile: false,
+                broken: false,
+                test_args: Vec::new(),
+            },
+        );
+
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: crates.clone(),
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let db = DummyDB::default();
+
+        let mut graph = build_windowed_graph(&ex, &config, 2, None);
+        let mut executed = HashSet::new();
+        loop {
+            // The graph never contains more crates than the window allows
+            assert!(graph.graph.neighbors(graph.root).count() <= 2);
+
+            match graph.next_task(&ex, &db) {
+                WalkResult::Task(id, task) => {
+                    assert!(executed.insert((task.krate.clone(), format!("{:?}", task.step))));
+                    graph.mark_as_completed(id);
+                }
+                WalkResult::Finished => break,
+                res => panic!("unexpected walk result: {:?}", res),
+            }
+        }
+
+        // The same tasks as the full graph are executed
+        let mut graph = build_graph(&ex, &config);
+        let mut expected = HashSet::new();
+        loop {
+            match graph.next_task(&ex, &db) {
+                WalkResult::Task(id, task) => {
+                    expected.insert((task.krate.clone(), format!("{:?}", task.step)));
+                    graph.mark_as_completed(id);
+                }
+                WalkResult::Finished => break,
+                res => panic!("unexpected walk result: {:?}", res),
+            }
+        }
+        assert_eq!(executed.len(), 12);
+        assert_eq!(executed, expected);
+    }
+
+    #[test]
+    fn test_prepare_only_graph() {
+        let mut config = Config::default();
+        config.prepare_only = true;
+        let crates = (0..2)
+            .map(|i| {
+                Crate::Registry(RegistryCrate {
+                    name: format!("crate-{}", i),
+                    version: "1.0".into(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: crates.clone(),
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        // Crates with results are still prepared again
+        let mut db = DummyDB::default();
+        for tc in &[MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()] {
+            db.add_dummy_result(&ex, crates[0].clone(), tc.clone(), TestResult::TestPass);
+        }
+
+        let mut graph = build_graph_incremental(&ex, &config, &db, None);
+        let mut executed = Vec::new();
+        loop {
+            match graph.next_task(&ex, &db) {
+                WalkResult::Task(id, task) => {
+                    executed.push((task.krate.clone(), task.step.kind()));
+                    graph.mark_as_completed(id);
+                }
+                WalkResult::Finished => break,
+                res => panic!("unexpected walk result: {:?}", res),
+            }
+        }
+        assert!(executed.iter().all(|&(_, step)| step == ExStep::Prepare));
+        let mut prepared = executed
+            .into_iter()
+            .map(|(krate, _)| krate)
+            .collect::<Vec<_>>();
+        prepared.sort();
+        assert_eq!(prepared, crates);
+    }
+
+    #[test]
+    fn test_incremental_graph() {
+        let config = Config::default();
+        let crates = (0..4)
+            .map(|i| {
+                Crate::Registry(RegistryCrate {
+                    name: format!("crate-{}", i),
+                    version: "1.0".into(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: crates.clone(),
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+
+        // The first two crates are completed, the third one only has a result for one toolchain
+        let mut db = DummyDB::default();
+        for krate in &crates[..2] {
+            for tc in &[MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()] {
+                db.add_dummy_result(&ex, krate.clone(), tc.clone(), TestResult::TestPass);
+            }
+        }
+        db.add_dummy_result(
+            &ex,
+            crates[2].clone(),
+            MAIN_TOOLCHAIN.clone(),
+            TestResult::TestPass,
+        );
+
+        let walk = |mut graph: TasksGraph| {
+            let mut executed = HashSet::new();
+            loop {
+                match graph.next_task(&ex, &db) {
+                    WalkResult::Task(id, task) => {
+                        executed.insert((task.krate.clone(), format!("{:?}", task.step)));
+                        graph.mark_as_completed(id);
+                    }
+                    WalkResult::Finished => break,
+                    res => panic!("unexpected walk result: {:?}", res),
+                }
+            }
+            executed
+        };
+
+        // Only the unfinished crates are added to the graph
+        let graph = build_graph_incremental(&ex, &config, &db, None);
+        let mut in_graph = graph
+            .graph
+            .node_indices()
+            .filter_map(|id| match graph.graph[id] {
+                Node::Task { ref task, .. } => Some(task.krate.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        in_graph.sort();
+        in_graph.dedup();
+        assert_eq!(in_graph, vec![crates[2].clone(), crates[3].clone()]);
+
+        // The remaining work is the same as the full graph
+        let executed = walk(graph);
+        assert_eq!(executed.len(), 5);
+        assert_eq!(executed, walk(build_graph(&ex, &config)));
+    }
+
+    #[test]
+    fn test_baseline_skips_start_toolchain() {
+        let config = Config::default();
+        let crates = vec![
+            Crate::Registry(RegistryCrate {
+                name: "lazy_static".into(),
+                version: "1.0".into(),
+            }),
+            Crate::Registry(RegistryCrate {
+                name: "bitflags".into(),
+                version: "1.0".into(),
+            }),
+        ];
+
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: crates.clone(),
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: Some("base".into()),
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let db = DummyDB::default();
+
+        // Only the prepare step and the build with the end toolchain are executed
+        let mut graph = build_graph(&ex, &config);
+        let mut builds = Vec::new();
+        loop {
+            match graph.next_task(&ex, &db) {
+                WalkResult::Task(id, task) => {
+                    match task.step {
+                        TaskStep::Prepare => {}
+                        TaskStep::BuildAndTest { ref tc, .. } => {
+                            builds.push((task.krate.clone(), tc.clone()))
+                        }
+                        ref other => panic!("unexpected step: {:?}", other),
+                    }
+                    graph.mark_as_completed(id);
+                }
+                WalkResult::Finished => break,
+                res => panic!("unexpected walk result: {:?}", res),
+            }
+        }
+
+        assert_eq!(builds.len(), crates.len());
+        for krate in &crates {
+            assert!(builds.contains(&(krate.clone(), TEST_TOOLCHAIN.clone())));
+        }
+    }
+
+    #[test]
+    fn test_dependency_order() {
+        let config = Config::default();
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0".into(),
+            })
+        };
+
+        // `b` depends on `a`, while `x` and `y` depend on each other
+        let mut dependencies = HashMap::new();
+        dependencies.insert(krate("b"), vec![krate("a")]);
+        dependencies.insert(krate("x"), vec![krate("y")]);
+        dependencies.insert(krate("y"), vec![krate("x")]);
+
+        let crates = dependency_order(
+            &[krate("y"), krate("b"), krate("x"), krate("a")],
+            &dependencies,
+        );
+        assert_eq!(crates, vec![krate("a"), krate("b"), krate("x"), krate("y")]);
+
+        // Repeated dependencies and the ones outside of the experiment don't block the crate
+        let mut repeated = HashMap::new();
+        repeated.insert(krate("w"), vec![krate("z"), krate("z"), krate("missing")]);
+        assert_eq!(
+            dependency_order(&[krate("w"), krate("z")], &repeated),
+            vec![krate("z"), krate("w")]
+        );
+
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates,
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let db = DummyDB::default();
+
+        let mut graph = build_graph_incremental(&ex, &config, &db, Some(dependencies));
+        let mut prepared = Vec::new();
+        loop {
+            match graph.next_task(&ex, &db) {
+                WalkResult::Task(id, task) => {
+                    if let TaskStep::Prepare = task.step {
+                        prepared.push(task.krate.clone());
+                    }
+                    graph.mark_as_completed(id);
+                }
+                WalkResult::Finished => break,
+                res => panic!("unexpected walk result: {:?}", res),
+            }
+        }
+
+        // Without the dependency `b` would be prepared first, as it was added to the graph later
+        let position = |name: &str| prepared.iter().position(|c| *c == krate(name)).unwrap();
+        assert!(position("a") < position("b"));
+        assert_eq!(prepared.len(), 4);
+    }
+}
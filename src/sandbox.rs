@@ -0,0 +1,93 @@
+use config::{Config, SandboxKind};
+use docker::{ContainerBuilder, MountPerms};
+use errors::*;
+use std::path::PathBuf;
+use util::{Secret, Size};
+
+/// Directory of the host made available inside the sandbox.
+#[derive(Debug, Clone)]
+pub struct Mount {
+    pub host_path: PathBuf,
+    pub sandbox_path: &'static str,
+    pub perm: MountPerms,
+}
+
+/// Resources the sandboxed command is allowed to use.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub memory: Option<Size>,
+    pub networking: bool,
+}
+
+/// Command to execute inside a sandbox, along with its environment.
+#[derive(Debug, Clone)]
+pub struct SandboxCommand {
+    pub image: String,
+    pub cmd: String,
+    pub env: Vec<(&'static str, String)>,
+    /// Environment variables whose values are redacted from the logs
+    pub secret_env: Vec<(&'static str, Secret)>,
+    pub tag_streams: bool,
+}
+
+/// Isolated environment the builds are executed in.
+pub trait Sandbox: Send + Sync {
+    /// Run the command, returning whether it exited successfully and what it wrote to stderr.
+    fn run(
+        &self,
+        cmd: &SandboxCommand,
+        mounts: &[Mount],
+        limits: &Limits,
+        quiet: bool,
+    ) -> Result<(bool, Vec<String>)>;
+}
+
+/// Sandbox selected in the configuration, used to execute all the builds.
+pub fn configured(config: &Config) -> Box<Sandbox> {
+    match config.sandbox.kind.unwrap_or(SandboxKind::Docker) {
+        SandboxKind::Docker => Box::new(DockerSandbox),
+    }
+}
+
+/// Sandbox executing the commands inside Docker containers.
+pub struct DockerSandbox;
+
+impl DockerSandbox {
+    pub fn container<'a>(
+        cmd: &'a SandboxCommand,
+        mounts: &'a [Mount],
+        limits: &Limits,
+    ) -> ContainerBuilder<'a> {
+        let mut container = ContainerBuilder::new(&cmd.image);
+
+        for mount in mounts {
+            container = container.mount(mount.host_path.clone(), mount.sandbox_path, mount.perm);
+        }
+        for &(key, ref value) in &cmd.secret_env {
+            container = container.secret_env(key, value.clone());
+        }
+        for &(key, ref value) in &cmd.env {
+            container = container.env(key, value.clone());
+        }
+        if let Some(limit) = limits.memory {
+            container = container.memory_limit(limit);
+        }
+
+        container
+            .env("CMD", cmd.cmd.clone())
+            .enable_networking(limits.networking)
+            .tag_streams(cmd.tag_streams)
+    }
+}
+
+impl Sandbox for DockerSandbox {
+    fn run(
+        &self,
+        cmd: &SandboxCommand,
+        mounts: &[Mount],
+        limits: &Limits,
+        quiet: bool,
+    ) -> Result<(bool, Vec<String>)> {
+        DockerSandbox::container(cmd, mounts, limits).run_capture_stderr(quiet)
+    }
+}
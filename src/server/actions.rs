@@ -0,0 +1,294 @@
+use crates::{Crate, CrateRequirement, CrateSelector, PriorRunSelector, RustVersion};
+use errors::*;
+use ex::{self, ExCapLints, ExCrateSelect, ExMode, ExStep};
+use server::db::Database;
+use server::experiments::GitHubIssue;
+use server::presets::Presets;
+use server::routes::webhooks::args::{Command, RunArgs};
+use toolchain::Toolchain;
+
+/// Full specification of a new experiment, as requested by an user.
+pub struct CreateExperiment {
+    pub name: Option<String>,
+    pub toolchains: [Toolchain; 2],
+    pub mode: ExMode,
+    pub crates: ExCrateSelect,
+    /// Seed used when sampling random crates, a random one is picked if missing
+    pub sample_seed: Option<u64>,
+    /// Rule to pick the crates from crates.io, overriding `crates` if present
+    pub crate_selector: Option<CrateSelector>,
+    /// Results of a previous experiment the selected crates are filtered with
+    pub prior_run: Option<PriorRunSelector>,
+    /// Lints cap, if missing the one in the configuration is used
+    pub cap_lints: Option<ExCapLints>,
+    pub priority: i32,
+    pub registry: Option<String>,
+    pub index_commit: Option<String>,
+    /// Prior experiment providing the results of the start toolchain
+    pub baseline: Option<String>,
+    /// Crates expected to regress, reported separately from the other regressions
+    pub expected_regressions: Vec<Crate>,
+    pub allow_same_toolchains: bool,
+    /// Steps executed even if a result for them is already present
+    pub force_steps: Vec<ExStep>,
+    /// Command executed in the source of each crate before building it
+    pub pre_build: Option<String>,
+    /// Remove the crates whose version is yanked from the experiment
+    pub skip_yanked: bool,
+    /// Keep only the crates declaring at least this `rust-version` in their manifest
+    pub min_rust_version: Option<RustVersion>,
+    /// Keep the crates without a `rust-version` when filtering by it
+    pub include_missing_rust_version: bool,
+    /// Crates whose version is resolved from the index, replacing the versions selected above
+    pub crate_versions: Vec<CrateRequirement>,
+    /// Allow the experiment to contain more crates than the configured maximum
+    pub ignore_crates_limit: bool,
+    /// Index of the toolchain the results of the other ones are compared against
+    pub control_index: usize,
+    /// Capture the `cargo metadata` output of each crate, to diff the dependencies
+    pub capture_metadata: bool,
+    /// Rustup components installed in the toolchains, including the ones required by the mode
+    pub components: Vec<String>,
+    pub github_issue: Option<GitHubIssue>,
+}
+
+impl CreateExperiment {
+    pub fn from_args(args: RunArgs) -> Result<Self> {
+        let mode = args.mode.unwrap_or(ExMode::BuildAndTest);
+        let mut components = args.components.map(|c| c.0).unwrap_or_default();
+        ex::add_required_components(mode, &mut components);
+
+        Ok(CreateExperiment {
+            name: args.name,
+            toolchains: [
+                args.start.ok_or_else(|| "missing start toolchain")?,
+                args.end.ok_or_else(|| "missing end toolchain")?,
+            ],
+            mode,
+            crates: args.crates.unwrap_or(ExCrateSelect::Full),
+            sample_seed: args.seed,
+            crate_selector: args.select,
+            prior_run: args.prior,
+            cap_lints: args.cap_lints,
+            priority: args.priority.unwrap_or(0),
+            registry: args.registry,
+            index_commit: args.index_commit,
+            baseline: args.baseline,
+            expected_regressions: Vec::new(),
+            allow_same_toolchains: args.allow_same_toolchains.unwrap_or(false),
+            force_steps: args.force_steps.map(|s| s.0).unwrap_or_default(),
+            pre_build: args.pre_build,
+            skip_yanked: args.skip_yanked.unwrap_or(false),
+            min_rust_version: args.min_rust_version,
+            include_missing_rust_version: args.include_missing_rust_version.unwrap_or(false),
+            crate_versions: args.crate_versions.map(|v| v.0).unwrap_or_default(),
+            ignore_crates_limit: false,
+            control_index: args.control_index.unwrap_or(0),
+            capture_metadata: args.capture_metadata.unwrap_or(false),
+            components,
+            github_issue: None,
+        })
+    }
+
+    /// Create the specification from the named preset, with the arguments provided by the user
+    /// overriding the values of the preset.
+    pub fn from_preset(db: &Database, preset: &str, args: RunArgs) -> Result<Self> {
+        let preset = Presets::new(db.clone())
+            .get(preset)?
+            .ok_or_else(|| format!("the preset `{}` doesn't exist", preset))?;
+        CreateExperiment::from_args(preset.apply(args))
+    }
+
+    #[cfg(test)]
+    pub fn dummy(name: &str) -> Self {
+        use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+
+        CreateExperiment {
+            name: Some(name.to_string()),
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            crates: ExCrateSelect::Demo,
+            sample_seed: None,
+            crate_selector: None,
+            prior_run: None,
+            cap_lints: None,
+            priority: 0,
+            registry: None,
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            allow_same_toolchains: false,
+            force_steps: Vec::new(),
+            pre_build: None,
+            skip_yanked: false,
+            min_rust_version: None,
+            include_missing_rust_version: false,
+            crate_versions: Vec::new(),
+            ignore_crates_limit: false,
+            control_index: 0,
+            capture_metadata: false,
+            components: Vec::new(),
+            github_issue: None,
+        }
+    }
+}
+
+/// Parse an experiment definition from the text of a bot command, such as
+/// `@craterbot run start=stable end=beta mode=check-only`.
+pub fn parse_experiment_command(body: &str) -> Result<CreateExperiment> {
+    let line = body
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| line.starts_with('@'))
+        .ok_or_else(|| "no bot command found")?;
+
+    // Strip the bot mention from the command
+    let command = line.splitn(2, ' ').nth(1).unwrap_or("").trim();
+    if command.split(' ').next() != Some("run") {
+        bail!("expected a `run` command, found: `{}`", command);
+    }
+
+    // Check the keys beforehand to show which ones are allowed
+    for part in command.split(' ').skip(1).filter(|p| !p.trim().is_empty()) {
+        let key = part.splitn(2, '=').next().unwrap();
+        if !RunArgs::keys().contains(&key) {
+            bail!(
+                "unknown key `{}` in the `run` command (valid keys are: {})",
+                key,
+                RunArgs::keys().join(", ")
+            );
+        }
+    }
+
+    match command.parse()? {
+        Command::Run(args) => CreateExperiment::from_args(args),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_experiment_command, CreateExperiment};
+    use ex::{ExCapLints, ExCrateSelect, ExMode};
+    use server::db::Database;
+    use server::presets::{ExperimentPreset, Presets};
+    use server::routes::webhooks::args::{Command, RunArgs};
+    use toolchain::Toolchain;
+
+    fn run_args(command: &str) -> RunArgs {
+        match command.parse().unwrap() {
+            Command::Run(args) => args,
+            _ => panic!("not a run command: {}", command),
+        }
+    }
+
+    #[test]
+    fn test_parse_experiment_command() {
+        let ex = parse_experiment_command(
+            "Let's test this change!\n\
+             @craterbot run name=foo start=stable end=beta mode=check-only crates=top-100 p=5",
+        ).unwrap();
+
+        assert_eq!(ex.name.as_ref().map(|n| n.as_str()), Some("foo"));
+        assert_eq!(
+            ex.toolchains,
+            [
+                "stable".parse::<Toolchain>().unwrap(),
+                "beta".parse::<Toolchain>().unwrap(),
+            ]
+        );
+        assert_eq!(ex.mode, ExMode::CheckOnly);
+        assert_eq!(ex.crates, ExCrateSelect::Top100);
+        assert!(ex.cap_lints.is_none());
+        assert_eq!(ex.priority, 5);
+        assert!(ex.registry.is_none());
+        assert!(ex.pre_build.is_none());
+        assert_eq!(ex.control_index, 0);
+        assert!(!ex.capture_metadata);
+
+        let ex = parse_experiment_command(
+            "@craterbot run start=stable end=beta pre-build=./generate.sh",
+        ).unwrap();
+        assert_eq!(ex.pre_build.as_ref().map(|p| p.as_str()), Some("./generate.sh"));
+
+        let ex = parse_experiment_command("@craterbot run start=stable end=beta control-index=1")
+            .unwrap();
+        assert_eq!(ex.control_index, 1);
+
+        let ex = parse_experiment_command(
+            "@craterbot run start=stable end=beta capture-metadata=true",
+        ).unwrap();
+        assert!(ex.capture_metadata);
+
+        // The components required by the mode are always installed
+        let ex = parse_experiment_command(
+            "@craterbot run start=stable end=beta mode=clippy components=rust-src",
+        ).unwrap();
+        assert_eq!(ex.components, vec!["rust-src".to_string(), "clippy".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_experiment_command_errors() {
+        let err = parse_experiment_command("@craterbot run start=stable end=beta foo=bar")
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.starts_with("unknown key `foo` in the `run` command"));
+        assert!(err.contains("start, end, mode"));
+
+        assert!(parse_experiment_command("@craterbot run start=stable").is_err());
+        assert!(parse_experiment_command("@craterbot abort").is_err());
+        assert!(parse_experiment_command("no commands here").is_err());
+    }
+
+    #[test]
+    fn test_from_preset() {
+        let db = Database::temp().unwrap();
+        Presets::new(db.clone())
+            .save(&ExperimentPreset {
+                name: "weekly".into(),
+                start: Some("stable".parse().unwrap()),
+                end: Some("beta".parse().unwrap()),
+                mode: Some(ExMode::CheckOnly),
+                crates: Some(ExCrateSelect::Top100),
+                select: None,
+                cap_lints: Some(ExCapLints::Warn),
+                priority: None,
+            })
+            .unwrap();
+
+        // Without overrides the experiment matches the preset
+        let ex = CreateExperiment::from_preset(&db, "weekly", run_args("run preset=weekly"))
+            .unwrap();
+        assert_eq!(
+            ex.toolchains,
+            [
+                "stable".parse::<Toolchain>().unwrap(),
+                "beta".parse::<Toolchain>().unwrap(),
+            ]
+        );
+        assert_eq!(ex.mode, ExMode::CheckOnly);
+        assert_eq!(ex.crates, ExCrateSelect::Top100);
+        assert_eq!(ex.cap_lints, Some(ExCapLints::Warn));
+        assert_eq!(ex.priority, 0);
+
+        // Each field can be overridden
+        let ex = CreateExperiment::from_preset(
+            &db,
+            "weekly",
+            run_args("run preset=weekly end=nightly mode=build-and-test p=3"),
+        ).unwrap();
+        assert_eq!(
+            ex.toolchains,
+            [
+                "stable".parse::<Toolchain>().unwrap(),
+                "nightly".parse::<Toolchain>().unwrap(),
+            ]
+        );
+        assert_eq!(ex.mode, ExMode::BuildAndTest);
+        assert_eq!(ex.crates, ExCrateSelect::Top100);
+        assert_eq!(ex.priority, 3);
+
+        assert!(CreateExperiment::from_preset(&db, "missing", run_args("run")).is_err());
+    }
+}
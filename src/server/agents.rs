@@ -3,11 +3,13 @@ use chrono::{DateTime, Utc};
 use errors::*;
 use server::db::{Database, QueryUtils};
 use server::experiments::{ExperimentData, Experiments};
+use serde_json;
 use server::tokens::Tokens;
 use std::collections::HashSet;
+use toolchain::ToolchainKind;
 
 /// Number of seconds without an heartbeat after an agent should be considered unreachable.
-const INACTIVE_AFTER: i64 = 300;
+pub const INACTIVE_AFTER: i64 = 300;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum AgentStatus {
@@ -141,17 +143,30 @@ impl Agents {
             &[&revision, &agent],
         )
     }
+
+    /// Record which kinds of toolchains the agent is able to prepare. An empty list, like agents
+    /// that never declared their supported kinds, means all of them are supported.
+    pub fn set_toolchain_kinds(&self, agent: &str, kinds: &[ToolchainKind]) -> Result<()> {
+        let kinds = if kinds.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(kinds)?)
+        };
+        self.db.execute(
+            "UPDATE agents SET toolchain_kinds = ?1 WHERE name = ?2;",
+            &[&kinds, &agent],
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::{AgentStatus, Agents};
     use config::Config;
-    use ex::{ExCapLints, ExCrateSelect, ExMode};
+    use server::actions::CreateExperiment;
     use server::db::Database;
     use server::experiments::Experiments;
     use server::tokens::Tokens;
-    use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
 
     #[test]
     fn test_agents_synchronize() {
@@ -229,19 +244,7 @@ mod tests {
 
         // Create a new experiment and assign it to the agent
         experiments
-            .create(
-                "test".into(),
-                &MAIN_TOOLCHAIN,
-                &TEST_TOOLCHAIN,
-                ExMode::BuildAndTest,
-                ExCrateSelect::Demo,
-                ExCapLints::Forbid,
-                &config,
-                None,
-                None,
-                None,
-                0,
-            )
+            .create(&CreateExperiment::dummy("test"), &config)
             .unwrap();
         experiments.next("agent").unwrap();
 
@@ -1,5 +1,7 @@
 use config::Config;
+use crates::Crate;
 use errors::*;
+use ex::ExStep;
 use http::header::{HeaderValue, CONTENT_TYPE};
 use http::Response;
 use http::StatusCode;
@@ -8,6 +10,7 @@ use reqwest::header::Scheme;
 use serde::Serialize;
 use std::fmt;
 use std::str::FromStr;
+use toolchain::Toolchain;
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -16,6 +19,33 @@ pub struct AgentConfig {
     pub crater_config: Config,
 }
 
+/// Latest lines logged by a task running on an agent.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunningLogTail {
+    #[serde(rename = "crate")]
+    pub krate: Crate,
+    pub toolchain: Toolchain,
+    pub lines: Vec<String>,
+}
+
+/// Attempt of executing a step of a crate, requested by the agents before running it.
+#[derive(Serialize, Deserialize)]
+pub struct CrateAttempt {
+    #[serde(rename = "crate")]
+    pub krate: Crate,
+    pub step: ExStep,
+    pub budget: u32,
+}
+
+/// Output of `cargo metadata` for a crate, uploaded by the agents after building it.
+#[derive(Serialize, Deserialize)]
+pub struct CrateMetadata {
+    #[serde(rename = "crate")]
+    pub krate: Crate,
+    pub toolchain: Toolchain,
+    pub metadata: String,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "status", rename_all = "kebab-case")]
 pub enum ApiResponse<T> {
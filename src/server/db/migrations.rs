@@ -181,6 +181,391 @@ fn migrations() -> Vec<(&'static str, MigrationKind)> {
         })),
     ));
 
+    migrations.push((
+        "store_custom_registry",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN registry TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "store_agents_toolchain_kinds",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE agents ADD COLUMN toolchain_kinds TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_result_overrides",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE result_overrides (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                result TEXT NOT NULL,
+                reason TEXT NOT NULL,
+                created_at DATETIME NOT NULL,
+
+                PRIMARY KEY (experiment, crate, toolchain) ON CONFLICT REPLACE,
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_abort_reason",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN abort_reason TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_crater_version",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN crater_version TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_report_attempts",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN report_attempts INTEGER NOT NULL DEFAULT 0;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_report_urls",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE report_urls (
+                experiment TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                url TEXT NOT NULL,
+
+                PRIMARY KEY (experiment, kind) ON CONFLICT REPLACE,
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_allow_same_toolchains",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN allow_same_toolchains INTEGER NOT NULL DEFAULT 0;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_index_commit",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN index_commit TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_sample_seed",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN sample_seed INTEGER;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_results_created_at",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE results ADD COLUMN created_at DATETIME;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_baseline",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN baseline TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_presets",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE presets (
+                name TEXT PRIMARY KEY,
+                toolchain_start TEXT,
+                toolchain_end TEXT,
+                mode TEXT,
+                crates TEXT,
+                cap_lints TEXT,
+                priority INTEGER
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_settings",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE settings (
+                name TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_crate_attempts",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE crate_attempts (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                step TEXT NOT NULL,
+                attempts INTEGER NOT NULL,
+
+                PRIMARY KEY (experiment, crate, step),
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_results_agent",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE results ADD COLUMN agent TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "create_results_history",
+        MigrationKind::SQL(
+            "
+            CREATE TABLE results_history (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                result TEXT NOT NULL,
+                created_at DATETIME NOT NULL,
+
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+
+            CREATE INDEX results_history__experiment
+            ON results_history (experiment);
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_expected_regressions",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN expected_regressions TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_note",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN note TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_id",
+        MigrationKind::Code(Box::new(|t| {
+            t.execute("ALTER TABLE experiments ADD COLUMN id TEXT;", &[])?;
+
+            let names = {
+                let mut prepared = t.prepare("SELECT name FROM experiments;")?;
+                let mut names = Vec::new();
+                for name in prepared.query_map(&[], |row| -> String { row.get("name") })? {
+                    names.push(name?);
+                }
+                names
+            };
+            for name in &names {
+                t.execute(
+                    "UPDATE experiments SET id = ?1 WHERE name = ?2;",
+                    &[&::util::random_uuid(), name],
+                )?;
+            }
+
+            t.execute("CREATE UNIQUE INDEX experiments__id ON experiments (id);", &[])?;
+            Ok(())
+        })),
+    ));
+
+    migrations.push((
+        "add_experiments_force_steps",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN force_steps TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_toolchain_images",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN toolchain_start_image TEXT;
+            ALTER TABLE experiments ADD COLUMN toolchain_end_image TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_report_retry_at",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN report_retry_at DATETIME;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_pre_build",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN pre_build TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_presets_crate_selector",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE presets ADD COLUMN crate_selector TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "normalize_results_crates",
+        MigrationKind::Code(Box::new(|t| {
+            let fn_name = format!(
+                "crater_migration__{}",
+                rand::thread_rng()
+                    .gen_ascii_chars()
+                    .take(10)
+                    .collect::<String>()
+            );
+            t.create_scalar_function(&fn_name, 1, true, |ctx| {
+                let krate = ctx.get::<String>(0)?;
+
+                // Results are now stored without the build metadata of the crate
+                if let Ok(parsed) = serde_json::from_str::<::crates::Crate>(&krate) {
+                    Ok(serde_json::to_string(&parsed.normalized()).unwrap_or(krate))
+                } else {
+                    Ok(krate)
+                }
+            })?;
+
+            for table in &["results", "results_history", "result_overrides"] {
+                t.execute(
+                    &format!("UPDATE {} SET crate = {}(crate);", table, fn_name),
+                    &[],
+                )?;
+            }
+
+            Ok(())
+        })),
+    ));
+
+    migrations.push((
+        "add_experiments_ignore_crates_limit",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN ignore_crates_limit INTEGER NOT NULL DEFAULT 0;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_progress_comment",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN progress_comment TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_results_test_counts",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE results ADD COLUMN test_counts TEXT;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_control_index",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN control_index INTEGER NOT NULL DEFAULT 0;
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_capture_metadata",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN capture_metadata INTEGER NOT NULL DEFAULT 0;
+
+            CREATE TABLE crate_metadata (
+                experiment TEXT NOT NULL,
+                crate TEXT NOT NULL,
+                toolchain TEXT NOT NULL,
+                metadata TEXT NOT NULL,
+
+                PRIMARY KEY (experiment, crate, toolchain),
+                FOREIGN KEY (experiment) REFERENCES experiments(name) ON DELETE CASCADE
+            );
+            ",
+        ),
+    ));
+
+    migrations.push((
+        "add_experiments_components",
+        MigrationKind::SQL(
+            "
+            ALTER TABLE experiments ADD COLUMN components TEXT;
+            ",
+        ),
+    ));
+
     migrations
 }
 
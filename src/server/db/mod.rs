@@ -5,11 +5,13 @@ use errors::*;
 use r2d2::{CustomizeConnection, Pool};
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::types::ToSql;
-use rusqlite::{Connection, Row, Transaction};
+use rusqlite::{Connection, Row, Transaction, TransactionBehavior};
 use std::sync::Arc;
+use std::time::Duration;
 use tempfile::NamedTempFile;
 
 static DATABASE_PATH: &'static str = "server.db";
+const BUSY_TIMEOUT_SECS: u64 = 5;
 
 #[derive(Debug)]
 struct ConnectionCustomizer;
@@ -17,6 +19,8 @@ struct ConnectionCustomizer;
 impl CustomizeConnection<Connection, ::rusqlite::Error> for ConnectionCustomizer {
     fn on_acquire(&self, conn: &mut Connection) -> ::std::result::Result<(), ::rusqlite::Error> {
         conn.execute("PRAGMA foreign_keys = ON;", &[])?;
+        // Wait for the other connections to release their locks instead of failing right away
+        conn.busy_timeout(Duration::from_secs(BUSY_TIMEOUT_SECS))?;
         Ok(())
     }
 }
@@ -57,9 +61,25 @@ impl Database {
     }
 
     pub fn transaction<T, F: FnOnce(&TransactionHandle) -> Result<T>>(&self, f: F) -> Result<T> {
+        self.transaction_with_behavior(TransactionBehavior::Deferred, f)
+    }
+
+    /// Run a transaction taking the write lock as soon as it starts, so that other connections
+    /// can't change what it reads before it writes.
+    pub fn immediate_transaction<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&TransactionHandle) -> Result<T>,
+    {
+        self.transaction_with_behavior(TransactionBehavior::Immediate, f)
+    }
+
+    fn transaction_with_behavior<T, F>(&self, behavior: TransactionBehavior, f: F) -> Result<T>
+    where
+        F: FnOnce(&TransactionHandle) -> Result<T>,
+    {
         let mut conn = self.pool.get()?;
         let handle = TransactionHandle {
-            transaction: conn.transaction()?,
+            transaction: conn.transaction_with_behavior(behavior)?,
         };
 
         let result = f(&handle);
@@ -1,12 +1,49 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use config::Config;
-use crates::Crate;
+use crates::{Crate, PriorResult, PriorRunSelector};
 use errors::*;
-use ex::{self, ExCapLints, ExCrateSelect, ExMode, Experiment};
+use ex::{self, ExCapLints, ExCrateSelect, ExMode, ExStep, Experiment};
+use report;
+use results::{classify_failure, ErrorCategory, ReadResults, TestResult};
 use rusqlite::Row;
 use serde_json;
+use server::actions::CreateExperiment;
+use server::agents;
+use server::results::{
+    result_key, test_counts_column, ExportedResult, ResultsDB, ResultsDump, ResultsExport,
+    TaskResult,
+};
 use server::db::{Database, QueryUtils};
-use toolchain::Toolchain;
+use std::collections::{HashMap, HashSet, VecDeque};
+use toolchain::{Toolchain, ToolchainKind};
+
+/// Number of crates fetched from the database at once by `Experiments::crates_iter`
+const CRATES_ITER_BATCH_SIZE: i64 = 1000;
+
+/// Number of minutes without new results after a running experiment needs attention.
+const STALLED_AFTER_MINUTES: i64 = 60;
+
+const QUEUE_PAUSED_SETTING: &str = "queue-paused";
+
+/// Stop (or resume) assigning new experiments to the agents. Experiments already running are
+/// still returned to the agents they're assigned to.
+pub fn set_queue_paused(db: &Database, paused: bool) -> Result<()> {
+    db.execute(
+        "INSERT OR REPLACE INTO settings (name, value) VALUES (?1, ?2);",
+        &[&QUEUE_PAUSED_SETTING, &paused.to_string()],
+    )
+}
+
+pub fn is_queue_paused(db: &Database) -> Result<bool> {
+    Ok(db
+        .get_row(
+            "SELECT value FROM settings WHERE name = ?1;",
+            &[&QUEUE_PAUSED_SETTING],
+            |row| -> String { row.get("value") },
+        )?
+        .map(|value| value == "true")
+        .unwrap_or(false))
+}
 
 string_enum!(pub enum Status {
     Queued => "queued",
@@ -15,8 +52,22 @@ string_enum!(pub enum Status {
     GeneratingReport => "generating-report",
     ReportFailed => "report-failed",
     Completed => "completed",
+    Aborted => "aborted",
+});
+
+string_enum!(pub enum AttentionReason {
+    ReportFailed => "report-failed",
+    Stalled => "stalled",
+    AgentUnreachable => "agent-unreachable",
 });
 
+/// Experiment requiring the intervention of an operator.
+#[derive(Debug, PartialEq)]
+pub struct AttentionItem {
+    pub experiment: String,
+    pub reason: AttentionReason,
+}
+
 pub struct GitHubIssue {
     pub api_url: String,
     pub html_url: String,
@@ -24,6 +75,9 @@ pub struct GitHubIssue {
 }
 
 pub struct ServerData {
+    /// Identifier assigned when the experiment is created, which never changes even if the
+    /// experiment is renamed
+    pub id: String,
     pub priority: i32,
     pub created_at: DateTime<Utc>,
     pub started_at: Option<DateTime<Utc>>,
@@ -32,6 +86,20 @@ pub struct ServerData {
     pub status: Status,
     pub assigned_to: Option<String>,
     pub report_url: Option<String>,
+    pub abort_reason: Option<String>,
+    pub crater_version: Option<String>,
+    pub report_attempts: u32,
+    /// When the generation of the report is attempted again, if the last attempt failed
+    pub report_retry_at: Option<DateTime<Utc>>,
+    /// Seed used to randomly sample the crates, if they were sampled
+    pub sample_seed: Option<u64>,
+    /// Freeform note shown at the top of the report, such as why the experiment was run
+    pub note: Option<String>,
+    /// Allow the experiment to contain more crates than the configured maximum, even when its
+    /// crates are edited after the creation
+    pub ignore_crates_limit: bool,
+    /// API URL of the GitHub comment edited with the progress of the experiment
+    pub progress_comment: Option<String>,
 }
 
 pub struct ExperimentData {
@@ -79,12 +147,62 @@ impl ExperimentData {
         Ok(())
     }
 
+    /// Stop the experiment on purpose, releasing the agent running it. Aborted experiments are
+    /// never assigned again and no report is generated for them.
+    pub fn abort(&mut self, db: &Database, reason: &str) -> Result<()> {
+        db.execute(
+            "UPDATE experiments SET abort_reason = ?1 WHERE name = ?2;",
+            &[&reason, &self.experiment.name.as_str()],
+        )?;
+        self.server_data.abort_reason = Some(reason.to_string());
+
+        self.set_status(db, Status::Aborted)?;
+        self.set_assigned_to(db, None)?;
+        Ok(())
+    }
+
+    pub fn set_report_attempts(&mut self, db: &Database, attempts: u32) -> Result<()> {
+        db.execute(
+            "UPDATE experiments SET report_attempts = ?1 WHERE name = ?2;",
+            &[&attempts, &self.experiment.name.as_str()],
+        )?;
+        self.server_data.report_attempts = attempts;
+        Ok(())
+    }
+
+    /// Queue the generation of the report again, to be attempted only after `retry_at`.
+    pub fn requeue_report(&mut self, db: &Database, retry_at: DateTime<Utc>) -> Result<()> {
+        self.set_report_retry_at(db, Some(retry_at))?;
+        self.set_status(db, Status::NeedsReport)
+    }
+
+    pub fn set_report_retry_at(
+        &mut self,
+        db: &Database,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        db.execute(
+            "UPDATE experiments SET report_retry_at = ?1 WHERE name = ?2;",
+            &[&retry_at, &self.experiment.name.as_str()],
+        )?;
+        self.server_data.report_retry_at = retry_at;
+        Ok(())
+    }
+
     pub fn set_mode(&mut self, db: &Database, mode: ExMode) -> Result<()> {
+        let mut components = self.experiment.components.clone();
+        ex::add_required_components(mode, &mut components);
+
         db.execute(
-            "UPDATE experiments SET mode = ?1 WHERE name = ?2;",
-            &[&mode.to_str(), &self.experiment.name.as_str()],
+            "UPDATE experiments SET mode = ?1, components = ?2 WHERE name = ?3;",
+            &[
+                &mode.to_str(),
+                &serde_json::to_string(&components)?,
+                &self.experiment.name.as_str(),
+            ],
         )?;
         self.experiment.mode = mode;
+        self.experiment.components = components;
         Ok(())
     }
 
@@ -106,7 +224,99 @@ impl ExperimentData {
         Ok(())
     }
 
+    pub fn set_sample_seed(&mut self, db: &Database, sample_seed: Option<u64>) -> Result<()> {
+        db.execute(
+            "UPDATE experiments SET sample_seed = ?1 WHERE name = ?2;",
+            &[
+                &sample_seed.map(|seed| seed as i64),
+                &self.experiment.name.as_str(),
+            ],
+        )?;
+        self.server_data.sample_seed = sample_seed;
+        Ok(())
+    }
+
+    pub fn set_note(&mut self, db: &Database, note: Option<&str>) -> Result<()> {
+        db.execute(
+            "UPDATE experiments SET note = ?1 WHERE name = ?2;",
+            &[&note, &self.experiment.name.as_str()],
+        )?;
+        self.server_data.note = note.map(|note| note.to_string());
+        Ok(())
+    }
+
+    pub fn set_progress_comment(&mut self, db: &Database, url: &str) -> Result<()> {
+        db.execute(
+            "UPDATE experiments SET progress_comment = ?1 WHERE name = ?2;",
+            &[&url, &self.experiment.name.as_str()],
+        )?;
+        self.server_data.progress_comment = Some(url.to_string());
+        Ok(())
+    }
+
+    /// Change the name of the experiment, updating all the data referring to it. The id of the
+    /// experiment is preserved.
+    pub fn rename(&mut self, db: &Database, new_name: &str) -> Result<()> {
+        if new_name == self.experiment.name {
+            return Ok(());
+        }
+
+        db.transaction(|transaction| {
+            if transaction.exists("SELECT rowid FROM experiments WHERE name = ?1;", &[&new_name])? {
+                bail!("an experiment named {} already exists", new_name);
+            }
+
+            // The foreign keys are checked only when the transaction is committed, after all the
+            // tables referencing the experiment are updated
+            transaction.execute("PRAGMA defer_foreign_keys = ON;", &[])?;
+
+            let old_name = self.experiment.name.as_str();
+            transaction.execute(
+                "UPDATE experiments SET name = ?1 WHERE name = ?2;",
+                &[&new_name, &old_name],
+            )?;
+            transaction.execute(
+                "UPDATE experiments SET baseline = ?1 WHERE baseline = ?2;",
+                &[&new_name, &old_name],
+            )?;
+            for table in &[
+                "experiment_crates",
+                "results",
+                "results_history",
+                "result_overrides",
+                "shas",
+                "report_urls",
+                "crate_attempts",
+                "crate_metadata",
+                "saved_names",
+            ] {
+                transaction.execute(
+                    &format!("UPDATE {} SET experiment = ?1 WHERE experiment = ?2;", table),
+                    &[&new_name, &old_name],
+                )?;
+            }
+
+            Ok(())
+        })?;
+        self.experiment.name = new_name.to_string();
+        Ok(())
+    }
+
+    pub fn set_registry(&mut self, db: &Database, registry: Option<String>) -> Result<()> {
+        self.experiment.registry = registry;
+        self.experiment.validate()?;
+
+        db.execute(
+            "UPDATE experiments SET registry = ?1 WHERE name = ?2;",
+            &[&self.experiment.registry, &self.experiment.name.as_str()],
+        )?;
+        Ok(())
+    }
+
     pub fn set_crates(&mut self, db: &Database, config: &Config, crates: Vec<Crate>) -> Result<()> {
+        if !self.server_data.ignore_crates_limit {
+            check_crates_limit(config, crates.len())?;
+        }
         db.transaction(|transaction| {
             transaction.execute(
                 "DELETE FROM experiment_crates WHERE experiment = ?1;",
@@ -119,7 +329,7 @@ impl ExperimentData {
                      VALUES (?1, ?2, ?3);",
                     &[
                         &self.experiment.name.as_str(),
-                        &serde_json::to_string(&krate)?,
+                        &serialize_crate_row(krate)?,
                         &config.should_skip(krate),
                     ],
                 )?;
@@ -131,14 +341,78 @@ impl ExperimentData {
         Ok(())
     }
 
+    /// Add new crates to the experiment, leaving the existing ones untouched.
+    pub fn add_crates(&mut self, db: &Database, config: &Config, crates: &[Crate]) -> Result<()> {
+        let mut added = Vec::new();
+        for krate in crates {
+            if !self.experiment.crates.contains(krate) && !added.contains(krate) {
+                added.push(krate.clone());
+            }
+        }
+        if !self.server_data.ignore_crates_limit {
+            check_crates_limit(config, self.experiment.crates.len() + added.len())?;
+        }
+
+        db.transaction(|transaction| {
+            for krate in &added {
+                transaction.execute(
+                    "INSERT INTO experiment_crates (experiment, crate, skipped) \
+                     VALUES (?1, ?2, ?3);",
+                    &[
+                        &self.experiment.name.as_str(),
+                        &serialize_crate_row(krate)?,
+                        &config.should_skip(krate),
+                    ],
+                )?;
+            }
+
+            Ok(())
+        })?;
+        self.experiment.crates.append(&mut added);
+        Ok(())
+    }
+
+    /// Remove some crates from the experiment, along with their results.
+    pub fn remove_crates(&mut self, db: &Database, crates: &[Crate]) -> Result<()> {
+        db.transaction(|transaction| {
+            for krate in crates {
+                // Rows stored before the versioned format was introduced are removed too
+                let serialized = serde_json::to_string(&krate)?;
+                transaction.execute(
+                    "DELETE FROM experiment_crates \
+                     WHERE experiment = ?1 AND (crate = ?2 OR crate = ?3);",
+                    &[
+                        &self.experiment.name.as_str(),
+                        &serialize_crate_row(krate)?,
+                        &serialized,
+                    ],
+                )?;
+                transaction.execute(
+                    "DELETE FROM results WHERE experiment = ?1 AND crate = ?2;",
+                    &[&self.experiment.name.as_str(), &result_key(krate)?],
+                )?;
+                transaction.execute(
+                    "DELETE FROM crate_metadata WHERE experiment = ?1 AND crate = ?2;",
+                    &[&self.experiment.name.as_str(), &result_key(krate)?],
+                )?;
+            }
+
+            Ok(())
+        })?;
+        self.experiment.crates.retain(|krate| !crates.contains(krate));
+        Ok(())
+    }
+
     pub fn set_start_toolchain(&mut self, db: &Database, start: Toolchain) -> Result<()> {
         self.experiment.toolchains[0] = start;
         self.experiment.validate()?;
 
         db.execute(
-            "UPDATE experiments SET toolchain_start = ?1 WHERE name = ?2;",
+            "UPDATE experiments SET toolchain_start = ?1, toolchain_start_image = ?2 \
+             WHERE name = ?3;",
             &[
                 &self.experiment.toolchains[0].to_string(),
+                &self.experiment.toolchains[0].image,
                 &self.experiment.name.as_str(),
             ],
         )?;
@@ -150,9 +424,11 @@ impl ExperimentData {
         self.experiment.validate()?;
 
         db.execute(
-            "UPDATE experiments SET toolchain_end = ?1 WHERE name = ?2;",
+            "UPDATE experiments SET toolchain_end = ?1, toolchain_end_image = ?2 \
+             WHERE name = ?3;",
             &[
                 &self.experiment.toolchains[1].to_string(),
+                &self.experiment.toolchains[1].image,
                 &self.experiment.name.as_str(),
             ],
         )?;
@@ -168,6 +444,268 @@ impl ExperimentData {
         Ok(())
     }
 
+    /// Record where a copy of the report was published. The `report_url` set with
+    /// `set_report_url` is still the primary one.
+    pub fn add_report_url(&self, db: &Database, kind: &str, url: &str) -> Result<()> {
+        db.execute(
+            "INSERT INTO report_urls (experiment, kind, url) VALUES (?1, ?2, ?3);",
+            &[&self.experiment.name.as_str(), &kind, &url],
+        )?;
+        Ok(())
+    }
+
+    /// Return all the places the report was published to, as `(kind, url)` pairs.
+    pub fn report_urls(&self, db: &Database) -> Result<Vec<(String, String)>> {
+        db.query(
+            "SELECT kind, url FROM report_urls WHERE experiment = ?1 ORDER BY kind;",
+            &[&self.experiment.name.as_str()],
+            |r| (r.get("kind"), r.get("url")),
+        )
+    }
+
+    /// Override the recorded result of a crate in the reports, keeping the original one stored.
+    pub fn override_result(
+        &self,
+        db: &Database,
+        krate: &Crate,
+        toolchain: &Toolchain,
+        result: TestResult,
+        reason: &str,
+    ) -> Result<()> {
+        db.execute(
+            "INSERT INTO result_overrides \
+             (experiment, crate, toolchain, result, reason, created_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+            &[
+                &self.experiment.name.as_str(),
+                &result_key(krate)?,
+                &toolchain.to_string(),
+                &result.to_str(),
+                &reason,
+                &Utc::now(),
+            ],
+        )
+    }
+
+    /// Exclude the results of a crate from the reports, without deleting them.
+    pub fn ignore_crate(&self, db: &Database, krate: &Crate, reason: &str) -> Result<()> {
+        for tc in &self.experiment.toolchains {
+            self.override_result(db, krate, tc, TestResult::Ignored, reason)?;
+        }
+        Ok(())
+    }
+
+    /// Export all the results of this experiment, including their logs, to be imported in another
+    /// instance with `import_results`. The location of each log in the report is also included.
+    pub fn export_results(&self, db: &Database) -> Result<serde_json::Value> {
+        let rows = db.query(
+            "SELECT crate, toolchain, result, log FROM results WHERE experiment = ?1 \
+             ORDER BY crate, toolchain;",
+            &[&self.experiment.name.as_str()],
+            |row| -> (String, String, String, Vec<u8>) {
+                (
+                    row.get("crate"),
+                    row.get("toolchain"),
+                    row.get("result"),
+                    row.get("log"),
+                )
+            },
+        )?;
+
+        let mut results = Vec::new();
+        for (krate, toolchain, result, log) in rows {
+            let krate: Crate = serde_json::from_str(&krate)?;
+            let toolchain: Toolchain = toolchain.parse()?;
+
+            let log_url = if log.is_empty() {
+                None
+            } else {
+                let path = report::log_url_path(&toolchain, &krate);
+                Some(match self.server_data.report_url {
+                    Some(ref url) => format!("{}/{}", url.trim_right_matches('/'), path),
+                    None => path,
+                })
+            };
+
+            results.push(ExportedResult {
+                result: TaskResult {
+                    krate,
+                    toolchain,
+                    result: result.parse()?,
+                    log: ::base64::encode(&log),
+                },
+                log_url,
+            });
+        }
+
+        Ok(serde_json::to_value(ResultsExport { results })?)
+    }
+
+    /// Import the results exported by `export_results`, skipping the ones already recorded.
+    /// Returns the number of imported results.
+    pub fn import_results(&self, db: &Database, json: &str) -> Result<u32> {
+        let dump: ResultsDump =
+            serde_json::from_str(json).chain_err(|| "invalid results dump provided")?;
+
+        db.transaction(|trans| {
+            let mut imported = 0;
+            for result in &dump.results {
+                let key = result_key(&result.krate)?;
+                let toolchain = result.toolchain.to_string();
+                let log = ::base64::decode(&result.log)
+                    .chain_err(|| "invalid base64 log provided")?;
+                if trans.exists(
+                    "SELECT rowid FROM results \
+                     WHERE experiment = ?1 AND crate = ?2 AND toolchain = ?3;",
+                    &[&self.experiment.name.as_str(), &key, &toolchain],
+                )? {
+                    continue;
+                }
+
+                trans.execute(
+                    "INSERT INTO results \
+                     (experiment, crate, toolchain, result, log, created_at, test_counts) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+                    &[
+                        &self.experiment.name.as_str(),
+                        &key,
+                        &toolchain,
+                        &result.result.to_str(),
+                        &log,
+                        &Utc::now(),
+                        &test_counts_column(&log)?,
+                    ],
+                )?;
+                imported += 1;
+            }
+
+            Ok(imported)
+        })
+    }
+
+    /// Count how many crates failed on the end toolchain with each error category. Only the
+    /// regressions shown in the report are counted, unless `include_existing` is set.
+    pub fn error_breakdown(
+        &self,
+        db: &Database,
+        config: &Config,
+        include_existing: bool,
+    ) -> Result<HashMap<ErrorCategory, u32>> {
+        let results_db = ResultsDB::new(db);
+        let start_results = results_db.final_per_crate(&self.experiment.results_source(0))?;
+        let end_results = results_db.final_per_crate(&self.experiment)?;
+        let start = &self.experiment.toolchains[0];
+        let end = &self.experiment.toolchains[1];
+
+        let mut breakdown = HashMap::new();
+        for krate in &self.experiment.crates {
+            let key = krate.normalized();
+            let end_result = match end_results.get(&(key.clone(), end.clone())) {
+                Some(&result) if result.is_failure() => result,
+                _ => continue,
+            };
+            let start_result = start_results.get(&(key, start.clone())).cloned();
+            let comparison = report::compare(config, krate, start_result, Some(end_result));
+            if !include_existing && comparison != report::Comparison::Regressed {
+                continue;
+            }
+
+            let log = results_db
+                .load_log(&self.experiment, end, krate)?
+                .unwrap_or_default();
+            if let Some(category) = classify_failure(end_result, &log) {
+                *breakdown.entry(category).or_insert(0) += 1;
+            }
+        }
+
+        Ok(breakdown)
+    }
+
+    /// Number of times the step of the crate was attempted, across all the runs.
+    pub fn attempts(&self, db: &Database, krate: &Crate, step: ExStep) -> Result<u32> {
+        Ok(db
+            .get_row(
+                "SELECT attempts FROM crate_attempts \
+                 WHERE experiment = ?1 AND crate = ?2 AND step = ?3;",
+                &[
+                    &self.experiment.name.as_str(),
+                    &result_key(krate)?,
+                    &step.to_str(),
+                ],
+                |row| -> u32 { row.get("attempts") },
+            )?
+            .unwrap_or(0))
+    }
+
+    /// Record a new attempt of the step of the crate, returning `false` without recording it if
+    /// the `budget` was already exhausted, in which case the crate should be marked as failed.
+    /// The attempts are stored in the database, so restarting the run doesn't reset them.
+    pub fn use_attempt(
+        &self,
+        db: &Database,
+        krate: &Crate,
+        step: ExStep,
+        budget: u32,
+    ) -> Result<bool> {
+        let key = result_key(krate)?;
+        db.transaction(|trans| {
+            let attempts = trans
+                .get_row(
+                    "SELECT attempts FROM crate_attempts \
+                     WHERE experiment = ?1 AND crate = ?2 AND step = ?3;",
+                    &[&self.experiment.name.as_str(), &key, &step.to_str()],
+                    |row| -> u32 { row.get("attempts") },
+                )?
+                .unwrap_or(0);
+            if attempts >= budget {
+                return Ok(false);
+            }
+
+            trans.execute(
+                "INSERT OR REPLACE INTO crate_attempts (experiment, crate, step, attempts) \
+                 VALUES (?1, ?2, ?3, ?4);",
+                &[
+                    &self.experiment.name.as_str(),
+                    &key,
+                    &step.to_str(),
+                    &(attempts + 1),
+                ],
+            )?;
+            Ok(true)
+        })
+    }
+
+    /// Return when the last result of this experiment was recorded, if any.
+    pub fn last_progress_at(&self, db: &Database) -> Result<Option<DateTime<Utc>>> {
+        Ok(db
+            .get_row(
+                "SELECT MAX(created_at) AS last FROM results WHERE experiment = ?1;",
+                &[&self.experiment.name.as_str()],
+                |r| r.get("last"),
+            )?
+            .unwrap_or(None))
+    }
+
+    /// Check whether the experiment is running but no results were recorded in the provided
+    /// amount of time. If no result was recorded at all, the start of the run is used instead.
+    pub fn is_stalled(&self, db: &Database, max_idle: Duration) -> Result<bool> {
+        self.is_stalled_at(db, max_idle, Utc::now())
+    }
+
+    fn is_stalled_at(
+        &self,
+        db: &Database,
+        max_idle: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
+        if self.server_data.status != Status::Running {
+            return Ok(false);
+        }
+
+        let last = self.last_progress_at(db)?.or(self.server_data.started_at);
+        Ok(last.map(|last| now - last > max_idle).unwrap_or(false))
+    }
+
     pub fn raw_progress(&self, db: &Database) -> Result<(u32, u32)> {
         let results_len: u32 = db
             .get_row(
@@ -186,17 +724,49 @@ impl ExperimentData {
             )?
             .unwrap();
 
-        Ok((results_len, crates_len * 2))
+        // Each crate is expected to have a result for every toolchain
+        Ok((
+            results_len,
+            crates_len * self.experiment.run_toolchains().len() as u32,
+        ))
     }
 
     pub fn progress(&self, db: &Database) -> Result<u8> {
         let (results_len, crates_len) = self.raw_progress(db)?;
+        Ok(percentage(results_len, crates_len))
+    }
 
-        if crates_len != 0 {
-            Ok((results_len as f32 * 100.0 / crates_len as f32).ceil() as u8)
-        } else {
-            Ok(0)
+    /// Progress of the experiment ignoring the crates marked as broken in the configuration,
+    /// which are expected to fail anyway.
+    pub fn effective_progress(&self, db: &Database, config: &Config) -> Result<u8> {
+        let crates: Vec<String> = db.query(
+            "SELECT crate FROM experiment_crates WHERE experiment = ?1 AND skipped = 0;",
+            &[&self.experiment.name.as_str()],
+            |r| r.get("crate"),
+        )?;
+        let results: HashMap<String, u32> = db
+            .query(
+                "SELECT crate, COUNT(*) AS count FROM results \
+                 WHERE experiment = ?1 GROUP BY crate;",
+                &[&self.experiment.name.as_str()],
+                |r| (r.get("crate"), r.get("count")),
+            )?
+            .into_iter()
+            .collect();
+
+        let toolchains_len = self.experiment.run_toolchains().len() as u32;
+        let (mut results_len, mut crates_len) = (0, 0);
+        for serialized in crates {
+            let krate = deserialize_crate_row(&serialized)?;
+            if config.is_broken(&krate) {
+                continue;
+            }
+
+            results_len += results.get(&result_key(&krate)?).cloned().unwrap_or(0);
+            crates_len += toolchains_len;
         }
+
+        Ok(percentage(results_len, crates_len))
     }
 
     pub fn remove_completed_crates(&mut self, db: &Database) -> Result<()> {
@@ -209,13 +779,13 @@ impl ExperimentData {
                      WHERE experiment = ?1 AND crate = ?2;",
                     &[
                         &self.experiment.name.as_str(),
-                        &serde_json::to_string(&krate)?,
+                        &result_key(&krate)?,
                     ],
                     |r| r.get("count"),
                 )?
                 .unwrap();
 
-            if results_len < 2 {
+            if results_len < self.experiment.run_toolchains().len() as u32 {
                 new_crates.push(krate);
             }
         }
@@ -225,12 +795,69 @@ impl ExperimentData {
     }
 }
 
+/// Version of the format used to store the crates in the `experiment_crates` table.
+const CRATE_ROW_VERSION: u64 = 1;
+
+/// Serialize the crate to store it in the `experiment_crates` table. The crate is wrapped in a
+/// versioned envelope, to allow migrating the rows if its representation changes.
+fn serialize_crate_row(krate: &Crate) -> Result<String> {
+    Ok(serde_json::to_string(&json!({
+        "v": CRATE_ROW_VERSION,
+        "crate": krate,
+    }))?)
+}
+
+/// Deserialize a crate stored in the `experiment_crates` table, either in the versioned format or
+/// in the legacy one (the bare crate).
+fn deserialize_crate_row(value: &str) -> Result<Crate> {
+    let value: serde_json::Value = serde_json::from_str(value)?;
+    let version = match value.get("v") {
+        Some(version) => Some(version.as_u64().ok_or_else(|| "invalid crate row version")?),
+        None => None,
+    };
+
+    match version {
+        None => Ok(serde_json::from_value(value)?),
+        Some(CRATE_ROW_VERSION) => Ok(serde_json::from_value(
+            value
+                .get("crate")
+                .cloned()
+                .ok_or_else(|| "missing crate in the crate row")?,
+        )?),
+        Some(version) => bail!("unsupported crate row version: {}", version),
+    }
+}
+
+fn check_crates_limit(config: &Config, count: usize) -> Result<()> {
+    if let Some(max) = config.server.max_crates {
+        if count > max {
+            bail!(
+                "the experiment contains {} crates, but at most {} are allowed",
+                count,
+                max
+            );
+        }
+    }
+    Ok(())
+}
+
+fn percentage(results_len: u32, crates_len: u32) -> u8 {
+    if crates_len != 0 {
+        (results_len as f32 * 100.0 / crates_len as f32).ceil() as u8
+    } else {
+        0
+    }
+}
+
 struct ExperimentDBRecord {
+    id: String,
     name: String,
     mode: String,
     cap_lints: String,
     toolchain_start: String,
+    toolchain_start_image: Option<String>,
     toolchain_end: String,
+    toolchain_end_image: Option<String>,
     priority: i32,
     created_at: DateTime<Utc>,
     started_at: Option<DateTime<Utc>>,
@@ -241,16 +868,37 @@ struct ExperimentDBRecord {
     status: String,
     assigned_to: Option<String>,
     report_url: Option<String>,
+    registry: Option<String>,
+    abort_reason: Option<String>,
+    crater_version: Option<String>,
+    report_attempts: u32,
+    report_retry_at: Option<DateTime<Utc>>,
+    allow_same_toolchains: bool,
+    index_commit: Option<String>,
+    sample_seed: Option<i64>,
+    baseline: Option<String>,
+    expected_regressions: Option<String>,
+    note: Option<String>,
+    force_steps: Option<String>,
+    pre_build: Option<String>,
+    ignore_crates_limit: bool,
+    progress_comment: Option<String>,
+    control_index: u32,
+    capture_metadata: bool,
+    components: Option<String>,
 }
 
 impl ExperimentDBRecord {
     fn from_row(row: &Row) -> Self {
         ExperimentDBRecord {
+            id: row.get("id"),
             name: row.get("name"),
             mode: row.get("mode"),
             cap_lints: row.get("cap_lints"),
             toolchain_start: row.get("toolchain_start"),
+            toolchain_start_image: row.get("toolchain_start_image"),
             toolchain_end: row.get("toolchain_end"),
+            toolchain_end_image: row.get("toolchain_end_image"),
             priority: row.get("priority"),
             created_at: row.get("created_at"),
             started_at: row.get("started_at"),
@@ -261,6 +909,24 @@ impl ExperimentDBRecord {
             github_issue_number: row.get("github_issue_number"),
             assigned_to: row.get("assigned_to"),
             report_url: row.get("report_url"),
+            registry: row.get("registry"),
+            abort_reason: row.get("abort_reason"),
+            crater_version: row.get("crater_version"),
+            report_attempts: row.get("report_attempts"),
+            report_retry_at: row.get("report_retry_at"),
+            allow_same_toolchains: row.get("allow_same_toolchains"),
+            index_commit: row.get("index_commit"),
+            sample_seed: row.get("sample_seed"),
+            baseline: row.get("baseline"),
+            expected_regressions: row.get("expected_regressions"),
+            note: row.get("note"),
+            force_steps: row.get("force_steps"),
+            pre_build: row.get("pre_build"),
+            ignore_crates_limit: row.get("ignore_crates_limit"),
+            progress_comment: row.get("progress_comment"),
+            control_index: row.get("control_index"),
+            capture_metadata: row.get("capture_metadata"),
+            components: row.get("components"),
         }
     }
 
@@ -271,21 +937,53 @@ impl ExperimentDBRecord {
                 &[&self.name],
                 |r| {
                     let value: String = r.get("crate");
-                    Ok(serde_json::from_str(&value)?)
+                    deserialize_crate_row(&value)
                 },
             )?
             .into_iter()
             .collect::<Result<Vec<Crate>>>()?;
 
+        let expected_regressions = match self.expected_regressions {
+            Some(ref json) => serde_json::from_str(json)?,
+            None => Vec::new(),
+        };
+        let force_steps = match self.force_steps {
+            Some(ref json) => serde_json::from_str(json)?,
+            None => Vec::new(),
+        };
+
+        let mode = self.mode.parse()?;
+        // Experiments created before the components were stored only installed the required ones
+        let components = match self.components {
+            Some(ref json) => serde_json::from_str(json)?,
+            None => ex::required_components(mode),
+        };
         Ok(ExperimentData {
             experiment: Experiment {
                 name: self.name,
                 crates,
-                toolchains: [self.toolchain_start.parse()?, self.toolchain_end.parse()?],
+                toolchains: [
+                    parse_toolchain(&self.toolchain_start, self.toolchain_start_image)?,
+                    parse_toolchain(&self.toolchain_end, self.toolchain_end_image)?,
+                ],
                 cap_lints: self.cap_lints.parse()?,
-                mode: self.mode.parse()?,
+                mode,
+                registry: self.registry,
+                // The token of the registry is configured in the tokens of the server, and it's
+                // sent to the agents separately by the `registry-token` endpoint
+                registry_token: None,
+                force_steps,
+                allow_same_toolchains: self.allow_same_toolchains,
+                pre_build: self.pre_build,
+                capture_metadata: self.capture_metadata,
+                components,
+                index_commit: self.index_commit,
+                baseline: self.baseline,
+                expected_regressions,
+                control_index: self.control_index as usize,
             },
             server_data: ServerData {
+                id: self.id,
                 priority: self.priority,
                 created_at: self.created_at,
                 started_at: self.started_at,
@@ -306,11 +1004,91 @@ impl ExperimentDBRecord {
                 assigned_to: self.assigned_to,
                 status: self.status.parse()?,
                 report_url: self.report_url,
+                abort_reason: self.abort_reason,
+                crater_version: self.crater_version,
+                report_attempts: self.report_attempts,
+                report_retry_at: self.report_retry_at,
+                // The seed is stored as a signed integer, since that's what SQLite supports
+                sample_seed: self.sample_seed.map(|seed| seed as u64),
+                note: self.note,
+                ignore_crates_limit: self.ignore_crates_limit,
+                progress_comment: self.progress_comment,
             },
         })
     }
 }
 
+fn parse_toolchain(name: &str, image: Option<String>) -> Result<Toolchain> {
+    let mut toolchain: Toolchain = name.parse()?;
+    // Experiments created before the images were stored separately have them in the name
+    if image.is_some() {
+        toolchain.image = image;
+    }
+    Ok(toolchain)
+}
+
+/// Iterator over the crates of an experiment, loading them from the database in batches instead
+/// of collecting all of them at once.
+pub struct CratesIter {
+    db: Database,
+    experiment: String,
+    last_rowid: i64,
+    buffer: VecDeque<Result<Crate>>,
+    finished: bool,
+}
+
+impl CratesIter {
+    fn new(db: Database, experiment: &str) -> Result<Self> {
+        let mut iter = CratesIter {
+            db,
+            experiment: experiment.to_string(),
+            last_rowid: 0,
+            buffer: VecDeque::new(),
+            finished: false,
+        };
+
+        // Fetch the first batch right away to report errors early
+        iter.fetch_batch()?;
+        Ok(iter)
+    }
+
+    fn fetch_batch(&mut self) -> Result<()> {
+        let rows = self.db.query(
+            "SELECT rowid, crate FROM experiment_crates \
+             WHERE experiment = ?1 AND rowid > ?2 \
+             ORDER BY rowid LIMIT ?3;",
+            &[&self.experiment, &self.last_rowid, &CRATES_ITER_BATCH_SIZE],
+            |r| -> (i64, String) { (r.get("rowid"), r.get("crate")) },
+        )?;
+
+        if (rows.len() as i64) < CRATES_ITER_BATCH_SIZE {
+            self.finished = true;
+        }
+
+        for (rowid, krate) in rows {
+            self.last_rowid = rowid;
+            self.buffer.push_back(deserialize_crate_row(&krate));
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for CratesIter {
+    type Item = Result<Crate>;
+
+    fn next(&mut self) -> Option<Result<Crate>> {
+        if self.buffer.is_empty() && !self.finished {
+            if let Err(err) = self.fetch_batch() {
+                self.finished = true;
+                return Some(Err(err));
+            }
+        }
+
+        self.buffer.pop_front()
+    }
+}
+
 #[derive(Clone)]
 pub struct Experiments {
     db: Database,
@@ -326,50 +1104,148 @@ impl Experiments {
             .exists("SELECT rowid FROM experiments WHERE name = ?1;", &[&name])
     }
 
-    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
-    pub fn create(
-        &self,
-        name: &str,
-        toolchain_start: &Toolchain,
-        toolchain_end: &Toolchain,
-        mode: ExMode,
-        crates: ExCrateSelect,
-        cap_lints: ExCapLints,
-        config: &Config,
-        github_issue: Option<&str>,
-        github_issue_url: Option<&str>,
-        github_issue_number: Option<i32>,
-        priority: i32,
-    ) -> Result<()> {
-        self.db.transaction(|transaction| {
-            let crates = ex::get_crates(crates, config)?;
+    /// Ensure the experiment can provide the results of the `start` toolchain as a baseline.
+    pub fn check_baseline(&self, baseline: &str, start: &Toolchain) -> Result<()> {
+        let baseline = match self.get(baseline)? {
+            Some(baseline) => baseline,
+            None => bail!("the baseline experiment {} doesn't exist", baseline),
+        };
+
+        match baseline.server_data.status {
+            Status::NeedsReport
+            | Status::GeneratingReport
+            | Status::ReportFailed
+            | Status::Completed => {}
+            Status::Queued | Status::Running | Status::Aborted => bail!(
+                "the baseline experiment {} is not completed",
+                baseline.experiment.name
+            ),
+        }
 
-            // First of all, validate if the experiment is valid
-            Experiment {
-                name: name.to_string(),
-                crates: crates.clone(),
-                toolchains: [toolchain_start.clone(), toolchain_end.clone()],
-                mode,
-                cap_lints,
-            }.validate()?;
+        // Results are looked up by toolchain name, so it can be either of the baseline's ones
+        let start = start.to_string();
+        if baseline.experiment.toolchains.iter().all(|tc| tc.to_string() != start) {
+            bail!(
+                "the baseline experiment {} didn't run the toolchain {}",
+                baseline.experiment.name,
+                start
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn create(&self, spec: &CreateExperiment, config: &Config) -> Result<()> {
+        let name = spec.name.as_ref().ok_or_else(|| "missing experiment name")?;
+        if let Some(ref baseline) = spec.baseline {
+            self.check_baseline(baseline, &spec.toolchains[0])?;
+        }
+        let prior_crates = match spec.prior_run {
+            Some(ref selector) => Some(self.prior_run_crates(selector, config)?),
+            None => None,
+        };
+
+        self.db.transaction(|transaction| {
+            let cap_lints = spec
+                .cap_lints
+                .or(config.server.default_cap_lints)
+                .unwrap_or(ExCapLints::Forbid);
+            let mut sample_seed = None;
+            let mut crates = if let Some(ref selector) = spec.crate_selector {
+                ::crates::select(selector)?
+            } else {
+                let seed = spec.sample_seed.unwrap_or_else(::rand::random);
+                if spec.crates == ExCrateSelect::SmallRandom {
+                    sample_seed = Some(seed);
+                }
+                ex::get_crates(spec.crates, config, seed)?
+            };
+            if let (&Some(ref selector), &Some(ref prior_crates)) =
+                (&spec.prior_run, &prior_crates)
+            {
+                crates.retain(|krate| prior_crates.contains(krate) != selector.exclude);
+            }
+            if !spec.crate_versions.is_empty() {
+                crates = ::registry::apply_requirements(
+                    &::registry::existing_index_dir()?,
+                    crates,
+                    &spec.crate_versions,
+                )?;
+            }
+            if spec.skip_yanked {
+                let index_dir = ::registry::existing_index_dir()?;
+                crates = ::registry::remove_yanked_crates(&index_dir, crates);
+            }
+            if let Some(min) = spec.min_rust_version {
+                crates = ::registry::select_by_rust_version(
+                    &::registry::existing_index_dir()?,
+                    crates,
+                    min,
+                    spec.include_missing_rust_version,
+                );
+            }
+            if !spec.ignore_crates_limit {
+                check_crates_limit(config, crates.len())?;
+            }
+
+            // First of all, validate if the experiment is valid
+            Experiment {
+                name: name.to_string(),
+                crates: crates.clone(),
+                toolchains: spec.toolchains.clone(),
+                mode: spec.mode,
+                cap_lints,
+                registry: spec.registry.clone(),
+                registry_token: None,
+                force_steps: spec.force_steps.clone(),
+                allow_same_toolchains: spec.allow_same_toolchains,
+                pre_build: spec.pre_build.clone(),
+                capture_metadata: spec.capture_metadata,
+                components: spec.components.clone(),
+                index_commit: spec.index_commit.clone(),
+                baseline: spec.baseline.clone(),
+                expected_regressions: spec.expected_regressions.clone(),
+                control_index: spec.control_index,
+            }.validate()?;
 
             transaction.execute(
                 "INSERT INTO experiments \
                  (name, mode, cap_lints, toolchain_start, toolchain_end, priority, created_at, \
-                 status, github_issue, github_issue_url, github_issue_number) \
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11);",
+                 status, github_issue, github_issue_url, github_issue_number, registry, \
+                 crater_version, allow_same_toolchains, index_commit, sample_seed, baseline, \
+                 expected_regressions, id, force_steps, toolchain_start_image, \
+                 toolchain_end_image, pre_build, ignore_crates_limit, control_index, \
+                 capture_metadata, components) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, \
+                 ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27);",
                 &[
-                    &name,
-                    &mode.to_str(),
+                    name,
+                    &spec.mode.to_str(),
                     &cap_lints.to_str(),
-                    &toolchain_start.to_string(),
-                    &toolchain_end.to_string(),
-                    &priority,
+                    &spec.toolchains[0].to_string(),
+                    &spec.toolchains[1].to_string(),
+                    &spec.priority,
                     &Utc::now(),
                     &"queued",
-                    &github_issue,
-                    &github_issue_url,
-                    &github_issue_number,
+                    &spec.github_issue.as_ref().map(|i| i.api_url.as_str()),
+                    &spec.github_issue.as_ref().map(|i| i.html_url.as_str()),
+                    &spec.github_issue.as_ref().map(|i| i.number),
+                    &spec.registry,
+                    &::GIT_REVISION.unwrap_or("unknown"),
+                    &spec.allow_same_toolchains,
+                    &spec.index_commit,
+                    &sample_seed.map(|seed: u64| seed as i64),
+                    &spec.baseline,
+                    &serde_json::to_string(&spec.expected_regressions)?,
+                    &::util::random_uuid(),
+                    &serde_json::to_string(&spec.force_steps)?,
+                    &spec.toolchains[0].image,
+                    &spec.toolchains[1].image,
+                    &spec.pre_build,
+                    &spec.ignore_crates_limit,
+                    &(spec.control_index as u32),
+                    &spec.capture_metadata,
+                    &serde_json::to_string(&spec.components)?,
                 ],
             )?;
 
@@ -377,7 +1253,7 @@ impl Experiments {
                 let skipped = config.should_skip(krate) as i32;
                 transaction.execute(
                     "INSERT INTO experiment_crates (experiment, crate, skipped) VALUES (?1, ?2, ?3);",
-                    &[&name, &serde_json::to_string(&krate)?, &skipped],
+                    &[&name, &serialize_crate_row(krate)?, &skipped],
                 )?;
             }
 
@@ -386,6 +1262,20 @@ impl Experiments {
     }
 
     pub fn delete(&self, name: &str) -> Result<()> {
+        // The results of the experiment are still needed by the ones using it as their baseline
+        let dependents = self.db.query(
+            "SELECT name FROM experiments WHERE baseline = ?1;",
+            &[&name],
+            |row| -> String { row.get("name") },
+        )?;
+        if !dependents.is_empty() {
+            bail!(
+                "the experiment {} is the baseline of {}",
+                name,
+                dependents.join(", ")
+            );
+        }
+
         // This will also delete all the data related to this experiment
         self.db
             .execute("DELETE FROM experiments WHERE name = ?1;", &[&name])
@@ -405,6 +1295,10 @@ impl Experiments {
         }
     }
 
+    pub fn crates_iter(&self, name: &str) -> Result<impl Iterator<Item = Result<Crate>>> {
+        CratesIter::new(self.db.clone(), name)
+    }
+
     pub fn all(&self) -> Result<Vec<ExperimentData>> {
         let records = self.db.query(
             "SELECT * FROM experiments ORDER BY priority DESC, created_at;",
@@ -417,6 +1311,104 @@ impl Experiments {
             .collect::<Result<_>>()
     }
 
+    /// Compact summary of all the experiments, meant to be polled by monitoring tools.
+    pub fn feed(&self) -> Result<serde_json::Value> {
+        let mut entries = Vec::new();
+        for ex in self.all()? {
+            entries.push(json!({
+                "name": ex.experiment.name,
+                "status": ex.server_data.status.to_str(),
+                "priority": ex.server_data.priority,
+                "progress": ex.progress(&self.db)?,
+                "assigned_to": ex.server_data.assigned_to,
+                "created_at": ex.server_data.created_at.to_rfc3339(),
+            }));
+        }
+
+        Ok(serde_json::Value::Array(entries))
+    }
+
+    /// Return the experiments whose report failed, the running experiments which stopped
+    /// recording results, and the ones assigned to agents not sending heartbeats anymore.
+    pub fn needs_attention(&self, now: DateTime<Utc>) -> Result<Vec<AttentionItem>> {
+        let mut items = Vec::new();
+        for ex in self.all()? {
+            let mut reasons = Vec::new();
+            if ex.server_data.status == Status::ReportFailed {
+                reasons.push(AttentionReason::ReportFailed);
+            }
+            if ex.is_stalled_at(&self.db, Duration::minutes(STALLED_AFTER_MINUTES), now)? {
+                reasons.push(AttentionReason::Stalled);
+            }
+            if ex.server_data.status == Status::Running {
+                if let Some(ref agent) = ex.server_data.assigned_to {
+                    let heartbeat: Option<DateTime<Utc>> = self
+                        .db
+                        .get_row(
+                            "SELECT last_heartbeat FROM agents WHERE name = ?1;",
+                            &[agent],
+                            |r| r.get("last_heartbeat"),
+                        )?
+                        .unwrap_or(None);
+                    let silent = heartbeat
+                        .map(|h| now - h > Duration::seconds(agents::INACTIVE_AFTER))
+                        .unwrap_or(true);
+                    if silent {
+                        reasons.push(AttentionReason::AgentUnreachable);
+                    }
+                }
+            }
+
+            for reason in reasons {
+                items.push(AttentionItem {
+                    experiment: ex.experiment.name.clone(),
+                    reason,
+                });
+            }
+        }
+
+        Ok(items)
+    }
+
+    /// Try to load all the experiments stored in the database, returning the name of the ones
+    /// which can't be loaded and the reason why.
+    pub fn validate_all(&self) -> Result<Vec<(String, Error)>> {
+        let records = self.db.query(
+            "SELECT * FROM experiments ORDER BY name;",
+            &[],
+            |r| ExperimentDBRecord::from_row(r),
+        )?;
+
+        let mut failed = Vec::new();
+        for record in records {
+            let name = record.name.clone();
+            if let Err(err) = record.into_experiment_data(&self.db) {
+                failed.push((name, err));
+            }
+        }
+
+        Ok(failed)
+    }
+
+    /// Return the experiments created between the two dates, including both of them. To get a
+    /// half-open range exclude the experiments created exactly at the end date.
+    pub fn created_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<ExperimentData>> {
+        let records = self.db.query(
+            "SELECT * FROM experiments WHERE created_at BETWEEN ?1 AND ?2 \
+             ORDER BY priority DESC, created_at;",
+            &[&from, &to],
+            |r| ExperimentDBRecord::from_row(r),
+        )?;
+        records
+            .into_iter()
+            .map(|record| record.into_experiment_data(&self.db))
+            .collect::<Result<_>>()
+    }
+
     pub fn run_by_agent(&self, agent: &str) -> Result<Option<ExperimentData>> {
         let record = self.db.get_row(
             "SELECT * FROM experiments \
@@ -432,6 +1424,44 @@ impl Experiments {
         }
     }
 
+    /// Queue again the generation of the reports that failed, for example after the reports
+    /// pipeline is fixed, returning the names of the requeued experiments.
+    pub fn retry_all_report_failed(&self) -> Result<Vec<String>> {
+        let records = self.db.query(
+            "SELECT * FROM experiments WHERE status = ?1 ORDER BY name;",
+            &[&Status::ReportFailed.to_str()],
+            |r| ExperimentDBRecord::from_row(r),
+        )?;
+
+        let mut requeued = Vec::new();
+        for record in records {
+            let mut ex = record.into_experiment_data(&self.db)?;
+            ex.set_status(&self.db, Status::NeedsReport)?;
+            requeued.push(ex.experiment.name);
+        }
+
+        Ok(requeued)
+    }
+
+    /// Abort all the running experiments, for example to drain the agents before a maintenance,
+    /// returning the names of the aborted experiments.
+    pub fn abort_all_running(&self, reason: &str) -> Result<Vec<String>> {
+        let records = self.db.query(
+            "SELECT * FROM experiments WHERE status = \"running\" ORDER BY name;",
+            &[],
+            |r| ExperimentDBRecord::from_row(r),
+        )?;
+
+        let mut aborted = Vec::new();
+        for record in records {
+            let mut ex = record.into_experiment_data(&self.db)?;
+            ex.abort(&self.db, reason)?;
+            aborted.push(ex.experiment.name);
+        }
+
+        Ok(aborted)
+    }
+
     pub fn first_by_status(&self, status: Status) -> Result<Option<ExperimentData>> {
         let record = self.db.get_row(
             "SELECT * FROM experiments \
@@ -448,16 +1478,209 @@ impl Experiments {
         }
     }
 
+    /// Atomically move the first experiment needing a report to `GeneratingReport`, returning it.
+    /// Concurrent callers never receive the same experiment.
+    pub fn claim_for_report(&self) -> Result<Option<ExperimentData>> {
+        let now = Utc::now();
+        let record = self.db.immediate_transaction(|trans| {
+            // Reports requeued after a failure are skipped until their backoff expires
+            let record = trans
+                .query(
+                    "SELECT * FROM experiments \
+                     WHERE status = ?1 \
+                     ORDER BY priority DESC, created_at;",
+                    &[&Status::NeedsReport.to_str()],
+                    |r| ExperimentDBRecord::from_row(r),
+                )?
+                .into_iter()
+                .find(|r| r.report_retry_at.map(|at| at <= now).unwrap_or(true));
+
+            if let Some(ref record) = record {
+                // Reports not requeued after a failure start counting the attempts again
+                let attempts = if record.report_retry_at.is_some() {
+                    record.report_attempts
+                } else {
+                    0
+                };
+                trans.execute(
+                    "UPDATE experiments SET status = ?1, report_attempts = ?2 WHERE name = ?3;",
+                    &[&Status::GeneratingReport.to_str(), &attempts, &record.name],
+                )?;
+            }
+
+            Ok(record)
+        })?;
+
+        if let Some(record) = record {
+            let mut ex = record.into_experiment_data(&self.db)?;
+            ex.server_data.status = Status::GeneratingReport;
+            if ex.server_data.report_retry_at.is_none() {
+                ex.server_data.report_attempts = 0;
+            }
+            Ok(Some(ex))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Return the distinct toolchains used by queued or running experiments, which are the only
+    /// ones agents still need to have installed.
+    pub fn active_toolchains(&self) -> Result<Vec<Toolchain>> {
+        let rows = self.db.query(
+            "SELECT toolchain_start, toolchain_end FROM experiments \
+             WHERE status = \"queued\" OR status = \"running\" \
+             ORDER BY priority DESC, created_at;",
+            &[],
+            |r| -> (String, String) { (r.get("toolchain_start"), r.get("toolchain_end")) },
+        )?;
+
+        let mut toolchains = Vec::new();
+        for (start, end) in rows {
+            for tc in &[start, end] {
+                let tc: Toolchain = tc.parse()?;
+                if !toolchains.contains(&tc) {
+                    toolchains.push(tc);
+                }
+            }
+        }
+
+        Ok(toolchains)
+    }
+
+    /// Return the 1-based position of the experiment in the queue, or `None` if the experiment
+    /// isn't queued. The order is the same one used by `next`.
+    pub fn queue_position(&self, name: &str) -> Result<Option<u32>> {
+        let queued = self.db.query(
+            "SELECT name FROM experiments \
+             WHERE status = \"queued\" \
+             ORDER BY priority DESC, created_at;",
+            &[],
+            |r| -> String { r.get("name") },
+        )?;
+
+        Ok(queued
+            .iter()
+            .position(|queued| queued == name)
+            .map(|pos| pos as u32 + 1))
+    }
+
+    /// Fraction of the crates tested by both experiments whose result changed between them, for
+    /// any of the toolchains they share. Comparing two reruns of the same experiment this
+    /// measures how flaky its crates are.
+    pub fn churn(&self, a: &str, b: &str) -> Result<f32> {
+        let results = ResultsDB::new(&self.db);
+        let load = |name: &str| -> Result<HashMap<(Crate, Toolchain), TestResult>> {
+            let ex = self
+                .get(name)?
+                .ok_or_else(|| format!("experiment {} doesn't exist", name))?;
+            results.latest_per_crate(&ex.experiment)
+        };
+        let a = load(a)?;
+        let b = load(b)?;
+
+        let mut changed: HashMap<&Crate, bool> = HashMap::new();
+        for (key, result_a) in &a {
+            if let Some(result_b) = b.get(key) {
+                *changed.entry(&key.0).or_insert(false) |= result_a != result_b;
+            }
+        }
+
+        if changed.is_empty() {
+            return Ok(0.0);
+        }
+        let changed_count = changed.values().filter(|changed| **changed).count();
+        Ok(changed_count as f32 / changed.len() as f32)
+    }
+
+    /// Crates present in all the named experiments, sorted. This is useful to find a stable set of
+    /// crates to compare the experiments on.
+    pub fn common_crates(&self, names: &[&str]) -> Result<Vec<Crate>> {
+        let mut common: Option<HashSet<Crate>> = None;
+        for name in names {
+            let ex = self
+                .get(name)?
+                .ok_or_else(|| format!("experiment {} doesn't exist", name))?;
+            let crates = ex.experiment.crates.into_iter().collect::<HashSet<_>>();
+
+            common = Some(match common {
+                Some(common) => common.intersection(&crates).cloned().collect(),
+                None => crates,
+            });
+        }
+
+        let mut common = common.unwrap_or_default().into_iter().collect::<Vec<_>>();
+        common.sort();
+        Ok(common)
+    }
+
+    /// Crates of a previous experiment whose results match the selector, ignoring whether the
+    /// selector excludes them. The results shown in the report are the ones checked: the end
+    /// toolchain ones for failures and passes, and the comparison of the report for regressions.
+    pub fn prior_run_crates(
+        &self,
+        selector: &PriorRunSelector,
+        config: &Config,
+    ) -> Result<HashSet<Crate>> {
+        let ex = self
+            .get(&selector.experiment)?
+            .ok_or_else(|| format!("the prior experiment {} doesn't exist", selector.experiment))?
+            .experiment;
+        let results_db = ResultsDB::new(&self.db);
+        let start_results = results_db.final_per_crate(&ex.results_source(0))?;
+        let end_results = results_db.final_per_crate(&ex)?;
+
+        fn passed(result: &TestResult) -> bool {
+            match *result {
+                TestResult::TestPass | TestResult::TestSkipped => true,
+                _ => false,
+            }
+        }
+        fn failed(result: &TestResult) -> bool {
+            result.is_failure()
+        }
+
+        Ok(ex
+            .crates
+            .iter()
+            .filter(|krate| {
+                let key = krate.normalized();
+                let start = start_results
+                    .get(&(key.clone(), ex.toolchains[0].clone()))
+                    .cloned();
+                let end = end_results.get(&(key, ex.toolchains[1].clone())).cloned();
+                match selector.result {
+                    PriorResult::Failed => end.as_ref().map(failed).unwrap_or(false),
+                    PriorResult::Passed => end.as_ref().map(passed).unwrap_or(false),
+                    PriorResult::Regressed => {
+                        let results = [start, end];
+                        report::compare_to_control(config, krate, &results, ex.control_index)
+                            == report::Comparison::Regressed
+                    }
+                }
+            })
+            .cloned()
+            .collect())
+    }
+
     pub fn next(&self, agent: &str) -> Result<Option<(bool, ExperimentData)>> {
         // Avoid assigning two experiments to the same agent
         if let Some(experiment) = self.run_by_agent(agent)? {
             return Ok(Some((false, experiment)));
         }
 
+        if is_queue_paused(&self.db)? {
+            return Ok(None);
+        }
+
+        let supported = self.agent_toolchain_kinds(agent)?;
+
         let record = self.db.get_row(
-            "SELECT * FROM experiments \
-             WHERE status = \"queued\" \
-             ORDER BY priority DESC, created_at;",
+            &format!(
+                "SELECT * FROM experiments \
+                 WHERE status = \"queued\" AND {} \
+                 ORDER BY priority DESC, created_at LIMIT 1;",
+                toolchain_kinds_condition(&supported)
+            ),
             &[],
             |r| ExperimentDBRecord::from_row(r),
         )?;
@@ -466,22 +1689,149 @@ impl Experiments {
             let mut experiment = record.into_experiment_data(&self.db)?;
             experiment.set_status(&self.db, Status::Running)?;
             experiment.set_assigned_to(&self.db, Some(agent.into()))?;
-            Ok(Some((true, experiment)))
+            return Ok(Some((true, experiment)));
+        }
+
+        Ok(None)
+    }
+
+    /// List the queued experiments the agent is able to run, in the order `next` would assign
+    /// them, without actually assigning any of them.
+    pub fn assignable_to(&self, agent: &str) -> Result<Vec<ExperimentData>> {
+        let supported = self.agent_toolchain_kinds(agent)?;
+
+        let records = self.db.query(
+            &format!(
+                "SELECT * FROM experiments \
+                 WHERE status = \"queued\" AND {} \
+                 ORDER BY priority DESC, created_at;",
+                toolchain_kinds_condition(&supported)
+            ),
+            &[],
+            |r| ExperimentDBRecord::from_row(r),
+        )?;
+
+        records
+            .into_iter()
+            .map(|record| record.into_experiment_data(&self.db))
+            .collect()
+    }
+
+    /// List the queued experiments with a toolchain none of the connected agents can prepare,
+    /// along with the first of those toolchains. Such experiments are never assigned until an
+    /// agent able to prepare the toolchain connects.
+    pub fn blocked_on_toolchain(&self) -> Result<Vec<(ExperimentData, Toolchain)>> {
+        let now = Utc::now();
+        let rows = self.db.query(
+            "SELECT last_heartbeat, toolchain_kinds FROM agents;",
+            &[],
+            |r| -> (Option<DateTime<Utc>>, Option<String>) {
+                (r.get("last_heartbeat"), r.get("toolchain_kinds"))
+            },
+        )?;
+
+        // Agents that didn't restrict their toolchain kinds can prepare all of them
+        let mut connected: Vec<Option<Vec<ToolchainKind>>> = Vec::new();
+        for (heartbeat, kinds) in rows {
+            match heartbeat {
+                Some(h) if now - h <= Duration::seconds(agents::INACTIVE_AFTER) => {}
+                _ => continue,
+            }
+            connected.push(match kinds {
+                Some(kinds) => Some(serde_json::from_str(&kinds)?),
+                None => None,
+            });
+        }
+
+        let records = self.db.query(
+            "SELECT * FROM experiments \
+             WHERE status = \"queued\" \
+             ORDER BY priority DESC, created_at;",
+            &[],
+            |r| ExperimentDBRecord::from_row(r),
+        )?;
+
+        let mut result = Vec::new();
+        for record in records {
+            let experiment = record.into_experiment_data(&self.db)?;
+            let blocked_on = experiment
+                .experiment
+                .toolchains
+                .iter()
+                .find(|tc| {
+                    !connected.iter().any(|supported| match *supported {
+                        Some(ref supported) => supported.contains(&tc.kind()),
+                        None => true,
+                    })
+                })
+                .cloned();
+
+            if let Some(toolchain) = blocked_on {
+                result.push((experiment, toolchain));
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn agent_toolchain_kinds(&self, agent: &str) -> Result<Option<Vec<ToolchainKind>>> {
+        let kinds: Option<String> = self
+            .db
+            .get_row(
+                "SELECT toolchain_kinds FROM agents WHERE name = ?1;",
+                &[&agent],
+                |r| r.get("toolchain_kinds"),
+            )?
+            .and_then(|kinds| kinds);
+
+        if let Some(kinds) = kinds {
+            Ok(Some(serde_json::from_str(&kinds)?))
         } else {
             Ok(None)
         }
     }
 }
 
+/// SQL condition matching the experiments an agent supporting the provided toolchain kinds can
+/// run. CI toolchains are the only ones with a `#` in their name.
+fn toolchain_kinds_condition(supported: &Option<Vec<ToolchainKind>>) -> &'static str {
+    let supported = match *supported {
+        Some(ref supported) => supported,
+        None => return "1",
+    };
+
+    match (
+        supported.contains(&ToolchainKind::Dist),
+        supported.contains(&ToolchainKind::CI),
+    ) {
+        (true, true) => "1",
+        (true, false) => "(toolchain_start NOT LIKE '%#%' AND toolchain_end NOT LIKE '%#%')",
+        (false, true) => "(toolchain_start LIKE '%#%' AND toolchain_end LIKE '%#%')",
+        (false, false) => "0",
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Experiments, Status};
-    use config::Config;
-    use ex::{ExCapLints, ExCrateSelect, ExMode};
+    use super::{
+        deserialize_crate_row, serialize_crate_row, set_queue_paused, AttentionItem,
+        AttentionReason, Experiments, GitHubIssue, Status,
+    };
+    use base64;
+    use chrono::{Duration, TimeZone, Utc};
+    use config::{Config, CrateConfig};
+    use crates::{Crate, RegistryCrate};
+    use ex::{ExCapLints, ExMode, ExStep};
+    use report;
+    use results::{ErrorCategory, ReadResults, TestResult};
+    use serde_json;
+    use server::actions::CreateExperiment;
     use server::agents::Agents;
-    use server::db::Database;
+    use server::db::{Database, QueryUtils};
+    use server::results::{ProgressData, ResultsDB, TaskResult};
     use server::tokens::Tokens;
-    use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+    use std::thread;
+    use toolchain::{Toolchain, ToolchainKind, ToolchainSource, MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
 
     #[test]
     fn test_experiment_creation() {
@@ -494,17 +1844,17 @@ mod tests {
         let config = Config::default();
         experiments
             .create(
-                "test".into(),
-                &MAIN_TOOLCHAIN,
-                &TEST_TOOLCHAIN,
-                ExMode::BuildAndTest,
-                ExCrateSelect::Demo,
-                ExCapLints::Forbid,
+                &CreateExperiment {
+                    priority: 5,
+                    registry: Some("https://example.com/index".into()),
+                    github_issue: Some(GitHubIssue {
+                        api_url: api_url.into(),
+                        html_url: html_url.into(),
+                        number: 10,
+                    }),
+                    ..CreateExperiment::dummy("test")
+                },
                 &config,
-                Some(api_url),
-                Some(html_url),
-                Some(10),
-                5,
             )
             .unwrap();
 
@@ -540,73 +1890,1885 @@ mod tests {
         assert_eq!(ex.server_data.priority, 5);
         assert_eq!(ex.server_data.status, Status::Queued);
         assert!(ex.server_data.assigned_to.is_none());
+        assert!(!ex.server_data.crater_version.unwrap().is_empty());
+        assert_eq!(
+            ex.experiment.registry.as_ref().map(|r| r.as_str()),
+            Some("https://example.com/index")
+        );
     }
 
     #[test]
-    fn test_assigning_experiment() {
+    fn test_progress_per_toolchain() {
         let db = Database::temp().unwrap();
         let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
 
-        let mut tokens = Tokens::default();
-        tokens.agents.insert("token1".into(), "agent-1".into());
-        tokens.agents.insert("token2".into(), "agent-2".into());
-        tokens.agents.insert("token3".into(), "agent-3".into());
+        let config = Config::default();
+        experiments
+            .create(&CreateExperiment::dummy("test"), &config)
+            .unwrap();
+        let mut ex = experiments.get("test").unwrap().unwrap();
+        let crates_len = ex.experiment.crates.len() as u32;
+        let toolchains_len = ex.experiment.toolchains.len() as u32;
 
-        // Populate the `agents` table
-        let _ = Agents::new(db.clone(), &tokens).unwrap();
+        assert_eq!(
+            ex.raw_progress(&db).unwrap(),
+            (0, crates_len * toolchains_len)
+        );
 
-        let config = Config::default();
+        // Record a result for every toolchain of the first crate
+        let krate = ex.experiment.crates[0].clone();
+        for tc in &ex.experiment.toolchains {
+            results
+                .store(
+                    &ex.experiment,
+                    &ProgressData {
+                        results: vec![TaskResult {
+                            krate: krate.clone(),
+                            toolchain: tc.clone(),
+                            result: TestResult::TestPass,
+                            log: base64::encode("foo"),
+                        }],
+                        shas: Vec::new(),
+                    },
+                )
+                .unwrap();
+        }
+
+        assert_eq!(
+            ex.raw_progress(&db).unwrap(),
+            (toolchains_len, crates_len * toolchains_len)
+        );
+
+        // The crate is completed only when all the toolchains have a result
+        ex.remove_completed_crates(&db).unwrap();
+        assert_eq!(ex.experiment.crates.len() as u32, crates_len - 1);
+        assert!(!ex.experiment.crates.contains(&krate));
+
+        // Experiments with a baseline only expect results for the end toolchain
         experiments
-            .create(
-                "test".into(),
-                &MAIN_TOOLCHAIN,
-                &TEST_TOOLCHAIN,
-                ExMode::BuildAndTest,
-                ExCrateSelect::Demo,
-                ExCapLints::Forbid,
-                &config,
-                None,
-                None,
-                None,
-                0,
-            )
+            .create(&CreateExperiment::dummy("base"), &config)
             .unwrap();
+        let mut base = experiments.get("base").unwrap().unwrap();
+        base.set_status(&db, Status::Completed).unwrap();
         experiments
             .create(
-                "important".into(),
-                &MAIN_TOOLCHAIN,
-                &TEST_TOOLCHAIN,
-                ExMode::BuildAndTest,
-                ExCrateSelect::Demo,
-                ExCapLints::Forbid,
+                &CreateExperiment {
+                    baseline: Some("base".into()),
+                    ..CreateExperiment::dummy("incremental")
+                },
                 &config,
-                None,
-                None,
-                None,
-                10,
             )
             .unwrap();
+        let ex = experiments.get("incremental").unwrap().unwrap();
+        assert_eq!(ex.experiment.run_toolchains().len(), 1);
+        assert_eq!(
+            ex.raw_progress(&db).unwrap(),
+            (0, ex.experiment.crates.len() as u32)
+        );
+    }
 
-        // Test the important experiment is correctly assigned
-        let (new, ex) = experiments.next("agent-1").unwrap().unwrap();
-        assert!(new);
-        assert_eq!(ex.experiment.name.as_str(), "important");
-        assert_eq!(ex.server_data.status, Status::Running);
-        assert_eq!(ex.server_data.assigned_to.unwrap().as_str(), "agent-1");
+    #[test]
+    fn test_feed() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
 
-        // Test the same experiment is returned to the agent
-        let (new, ex) = experiments.next("agent-1").unwrap().unwrap();
-        assert!(!new);
-        assert_eq!(ex.experiment.name.as_str(), "important");
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
 
-        // Test the less important experiment is assigned to the next agent
-        let (new, ex) = experiments.next("agent-2").unwrap().unwrap();
-        assert!(new);
-        assert_eq!(ex.experiment.name.as_str(), "test");
-        assert_eq!(ex.server_data.status, Status::Running);
-        assert_eq!(ex.server_data.assigned_to.unwrap().as_str(), "agent-2");
+        let config = Config::default();
+        let mut important = CreateExperiment::dummy("important");
+        important.priority = 10;
+        experiments.create(&important, &config).unwrap();
+        experiments
+            .create(&CreateExperiment::dummy("normal"), &config)
+            .unwrap();
+        experiments.next("agent-1").unwrap().unwrap();
 
-        // Test no other experiment is available for the other agents
-        assert!(experiments.next("agent-3").unwrap().is_none());
+        let feed = experiments.feed().unwrap();
+        let feed = feed.as_array().unwrap();
+        assert_eq!(feed.len(), 2);
+
+        let created_at = |name: &str| {
+            experiments
+                .get(name)
+                .unwrap()
+                .unwrap()
+                .server_data
+                .created_at
+                .to_rfc3339()
+        };
+        assert_eq!(
+            feed[0],
+            json!({
+                "name": "important",
+                "status": "running",
+                "priority": 10,
+                "progress": 0,
+                "assigned_to": "agent-1",
+                "created_at": created_at("important"),
+            })
+        );
+        assert_eq!(
+            feed[1],
+            json!({
+                "name": "normal",
+                "status": "queued",
+                "priority": 0,
+                "progress": 0,
+                "assigned_to": null,
+                "created_at": created_at("normal"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_effective_progress() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        let mut config = Config::default();
+        config.github_repos.insert(
+            "brson/hello-rs".into(),
+            CrateConfig {
+                skip: false,
+                skip_tests: false,
+                quiet: false,
+                update_lockfile: false,
+                broken: true,
+                test_args: Vec::new(),
+            },
+        );
+        experiments
+            .create(&CreateExperiment::dummy("test"), &config)
+            .unwrap();
+        let ex = experiments.get("test").unwrap().unwrap();
+        assert_eq!(ex.effective_progress(&db, &config).unwrap(), 0);
+
+        // Complete all the crates except the broken one
+        let krate = ex
+            .experiment
+            .crates
+            .iter()
+            .find(|c| c.name() == "lazy_static")
+            .unwrap()
+            .clone();
+        for tc in &ex.experiment.toolchains {
+            results
+                .store(
+                    &ex.experiment,
+                    &ProgressData {
+                        results: vec![TaskResult {
+                            krate: krate.clone(),
+                            toolchain: tc.clone(),
+                            result: TestResult::TestPass,
+                            log: base64::encode("foo"),
+                        }],
+                        shas: Vec::new(),
+                    },
+                )
+                .unwrap();
+        }
+
+        assert_eq!(ex.progress(&db).unwrap(), 50);
+        assert_eq!(ex.effective_progress(&db, &config).unwrap(), 100);
+    }
+
+    #[test]
+    fn test_export_import_results() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        let config = Config::default();
+        for name in &["old", "new"] {
+            experiments
+                .create(&CreateExperiment::dummy(name), &config)
+                .unwrap();
+        }
+        let old = experiments.get("old").unwrap().unwrap();
+        let new = experiments.get("new").unwrap().unwrap();
+
+        let krate = old.experiment.crates[0].clone();
+        results
+            .store(
+                &old.experiment,
+                &ProgressData {
+                    results: old
+                        .experiment
+                        .toolchains
+                        .iter()
+                        .map(|tc| TaskResult {
+                            krate: krate.clone(),
+                            toolchain: tc.clone(),
+                            result: TestResult::TestPass,
+                            log: base64::encode("foo"),
+                        })
+                        .collect(),
+                    shas: Vec::new(),
+                },
+            )
+            .unwrap();
+
+        let dump = old.export_results(&db).unwrap().to_string();
+        assert_eq!(new.import_results(&db, &dump).unwrap(), 2);
+        assert_eq!(new.export_results(&db).unwrap().to_string(), dump);
+        assert_eq!(
+            results
+                .load_log(&new.experiment, &new.experiment.toolchains[0], &krate)
+                .unwrap(),
+            Some(b"foo".to_vec())
+        );
+
+        // Results already present are not imported again
+        assert_eq!(new.import_results(&db, &dump).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_export_log_urls() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        experiments
+            .create(&CreateExperiment::dummy("test"), &Config::default())
+            .unwrap();
+        let mut ex = experiments.get("test").unwrap().unwrap();
+        ex.set_report_url(&db, "https://example.com/test/").unwrap();
+
+        // Only the first crate has a log
+        let crates = &ex.experiment.crates;
+        let tc = &ex.experiment.toolchains[0];
+        for (krate, log) in crates.iter().zip(&["foo", ""]) {
+            results
+                .store(
+                    &ex.experiment,
+                    &ProgressData {
+                        results: vec![TaskResult {
+                            krate: krate.clone(),
+                            toolchain: tc.clone(),
+                            result: TestResult::TestPass,
+                            log: base64::encode(log),
+                        }],
+                        shas: Vec::new(),
+                    },
+                )
+                .unwrap();
+        }
+
+        let dump = ex.export_results(&db).unwrap();
+        let log_url = |krate: &Crate| {
+            let key = serde_json::to_value(krate).unwrap();
+            dump["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|result| result["crate"] == key)
+                .unwrap()["log_url"]
+                .clone()
+        };
+
+        let expected = format!(
+            "https://example.com/test/{}",
+            report::log_url_path(tc, &crates[0])
+        );
+        assert_eq!(log_url(&crates[0]), json!(expected));
+        assert_eq!(log_url(&crates[1]), serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_error_breakdown() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        experiments
+            .create(&CreateExperiment::dummy("test"), &Config::default())
+            .unwrap();
+        let ex = experiments.get("test").unwrap().unwrap();
+        let find = |name: &str| {
+            ex.experiment
+                .crates
+                .iter()
+                .find(|c| c.name() == name)
+                .unwrap()
+                .clone()
+        };
+
+        // lazy_static regressed because of a network failure, while hello-rs already failed
+        let store = |krate: Crate, start: TestResult, end: TestResult, log: &str| {
+            let tcs = &ex.experiment.toolchains;
+            results
+                .store(
+                    &ex.experiment,
+                    &ProgressData {
+                        results: vec![
+                            TaskResult {
+                                krate: krate.clone(),
+                                toolchain: tcs[0].clone(),
+                                result: start,
+                                log: base64::encode(""),
+                            },
+                            TaskResult {
+                                krate,
+                                toolchain: tcs[1].clone(),
+                                result: end,
+                                log: base64::encode(log),
+                            },
+                        ],
+                        shas: Vec::new(),
+                    },
+                )
+                .unwrap();
+        };
+        store(
+            find("lazy_static"),
+            TestResult::TestPass,
+            TestResult::BuildFail,
+            "error: failed to download `bitflags v1.0.0`",
+        );
+        store(
+            find("hello-rs"),
+            TestResult::TestFail,
+            TestResult::TestTimeout,
+            "test foo has been running for over 60 seconds",
+        );
+
+        let config = Config::default();
+        let breakdown = ex.error_breakdown(&db, &config, false).unwrap();
+        assert_eq!(breakdown.len(), 1);
+        assert_eq!(breakdown[&ErrorCategory::Spurious], 1);
+
+        let breakdown = ex.error_breakdown(&db, &config, true).unwrap();
+        assert_eq!(breakdown.len(), 2);
+        assert_eq!(breakdown[&ErrorCategory::Spurious], 1);
+        assert_eq!(breakdown[&ErrorCategory::Timeout], 1);
+
+        // The overrides set by the reviewers are taken into account, like in the report
+        ex.override_result(
+            &db,
+            &find("hello-rs"),
+            &ex.experiment.toolchains[0],
+            TestResult::TestPass,
+            "flaky test",
+        ).unwrap();
+        let breakdown = ex.error_breakdown(&db, &config, false).unwrap();
+        assert_eq!(breakdown.len(), 2);
+        assert_eq!(breakdown[&ErrorCategory::Timeout], 1);
+    }
+
+    #[test]
+    fn test_crate_attempts() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        experiments
+            .create(&CreateExperiment::dummy("test"), &Config::default())
+            .unwrap();
+
+        let ex = experiments.get("test").unwrap().unwrap();
+        let krate = ex.experiment.crates[0].clone();
+        assert!(ex.use_attempt(&db, &krate, ExStep::BuildAndTest, 2).unwrap());
+        assert_eq!(ex.attempts(&db, &krate, ExStep::BuildAndTest).unwrap(), 1);
+
+        // The attempts are preserved when the experiment is loaded again after a restart
+        let ex = Experiments::new(db.clone()).get("test").unwrap().unwrap();
+        assert_eq!(ex.attempts(&db, &krate, ExStep::BuildAndTest).unwrap(), 1);
+        assert!(ex.use_attempt(&db, &krate, ExStep::BuildAndTest, 2).unwrap());
+
+        // Once the budget is exhausted no more attempts are allowed
+        assert!(!ex.use_attempt(&db, &krate, ExStep::BuildAndTest, 2).unwrap());
+        assert_eq!(ex.attempts(&db, &krate, ExStep::BuildAndTest).unwrap(), 2);
+
+        // Other steps have their own budget
+        assert!(ex.use_attempt(&db, &krate, ExStep::Prepare, 2).unwrap());
+    }
+
+    #[test]
+    fn test_stalled_experiment() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        experiments
+            .create(&CreateExperiment::dummy("test"), &Config::default())
+            .unwrap();
+        let mut ex = experiments.get("test").unwrap().unwrap();
+        assert!(ex.last_progress_at(&db).unwrap().is_none());
+
+        ex.set_status(&db, Status::Running).unwrap();
+        let krate = ex.experiment.crates[0].clone();
+        results
+            .store(
+                &ex.experiment,
+                &ProgressData {
+                    results: vec![TaskResult {
+                        krate: krate.clone(),
+                        toolchain: ex.experiment.toolchains[0].clone(),
+                        result: TestResult::TestPass,
+                        log: base64::encode("foo"),
+                    }],
+                    shas: Vec::new(),
+                },
+            )
+            .unwrap();
+        assert!(ex.last_progress_at(&db).unwrap().is_some());
+        assert!(!ex.is_stalled(&db, Duration::minutes(30)).unwrap());
+
+        // Pretend the newest result was recorded a long time ago
+        let old = Utc.ymd(2018, 1, 1).and_hms(0, 0, 0);
+        db.execute(
+            "UPDATE results SET created_at = ?1 WHERE experiment = ?2;",
+            &[&old, &"test"],
+        ).unwrap();
+        assert_eq!(ex.last_progress_at(&db).unwrap(), Some(old));
+        assert!(ex.is_stalled(&db, Duration::minutes(30)).unwrap());
+
+        // Experiments not running are never stalled
+        ex.set_status(&db, Status::Queued).unwrap();
+        assert!(!ex.is_stalled(&db, Duration::minutes(30)).unwrap());
+    }
+
+    #[test]
+    fn test_needs_attention() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        tokens.agents.insert("token2".into(), "agent-2".into());
+        let agents = Agents::new(db.clone(), &tokens).unwrap();
+
+        let config = Config::default();
+        // The priorities ensure the experiments are assigned to the expected agents
+        for &(name, priority) in &[
+            ("stalled", 3),
+            ("silent", 2),
+            ("report-failed", 1),
+            ("healthy", 0),
+        ] {
+            let mut spec = CreateExperiment::dummy(name);
+            spec.priority = priority;
+            experiments.create(&spec, &config).unwrap();
+        }
+
+        // Running for a long time without results, on an agent still sending heartbeats
+        let (_, ex) = experiments.next("agent-1").unwrap().unwrap();
+        assert_eq!(ex.experiment.name.as_str(), "stalled");
+        db.execute(
+            "UPDATE experiments SET started_at = ?1 WHERE name = ?2;",
+            &[&(Utc::now() - Duration::days(1)), &"stalled"],
+        ).unwrap();
+        agents.record_heartbeat("agent-1").unwrap();
+
+        // Running on an agent which never sent an heartbeat
+        let (_, ex) = experiments.next("agent-2").unwrap().unwrap();
+        assert_eq!(ex.experiment.name.as_str(), "silent");
+
+        let mut ex = experiments.get("report-failed").unwrap().unwrap();
+        ex.set_status(&db, Status::ReportFailed).unwrap();
+
+        let mut items = experiments.needs_attention(Utc::now()).unwrap();
+        items.sort_by(|a, b| a.experiment.cmp(&b.experiment));
+        assert_eq!(
+            items,
+            vec![
+                AttentionItem {
+                    experiment: "report-failed".into(),
+                    reason: AttentionReason::ReportFailed,
+                },
+                AttentionItem {
+                    experiment: "silent".into(),
+                    reason: AttentionReason::AgentUnreachable,
+                },
+                AttentionItem {
+                    experiment: "stalled".into(),
+                    reason: AttentionReason::Stalled,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report_urls() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let config = Config::default();
+        experiments
+            .create(&CreateExperiment::dummy("test"), &config)
+            .unwrap();
+        let mut ex = experiments.get("test").unwrap().unwrap();
+        assert!(ex.report_urls(&db).unwrap().is_empty());
+
+        ex.set_report_url(&db, "https://s3.example.com/test/index.html")
+            .unwrap();
+        ex.add_report_url(&db, "s3", "https://s3.example.com/test/index.html")
+            .unwrap();
+        ex.add_report_url(&db, "mirror", "https://mirror.example.com/test/index.html")
+            .unwrap();
+
+        let ex = experiments.get("test").unwrap().unwrap();
+        assert_eq!(
+            ex.server_data.report_url.as_ref().map(|u| u.as_str()),
+            Some("https://s3.example.com/test/index.html")
+        );
+        assert_eq!(
+            ex.report_urls(&db).unwrap(),
+            vec![
+                (
+                    "mirror".to_string(),
+                    "https://mirror.example.com/test/index.html".to_string(),
+                ),
+                (
+                    "s3".to_string(),
+                    "https://s3.example.com/test/index.html".to_string(),
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_same_toolchains() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let config = Config::default();
+        experiments
+            .create(&CreateExperiment::dummy("test"), &config)
+            .unwrap();
+        experiments
+            .create(
+                &CreateExperiment {
+                    allow_same_toolchains: true,
+                    ..CreateExperiment::dummy("flaky")
+                },
+                &config,
+            )
+            .unwrap();
+
+        // Using the same toolchain twice is rejected by default
+        let mut ex = experiments.get("test").unwrap().unwrap();
+        assert!(ex.set_end_toolchain(&db, MAIN_TOOLCHAIN.clone()).is_err());
+
+        // ...but it's accepted if explicitly allowed
+        let mut ex = experiments.get("flaky").unwrap().unwrap();
+        ex.set_end_toolchain(&db, MAIN_TOOLCHAIN.clone()).unwrap();
+        let ex = experiments.get("flaky").unwrap().unwrap();
+        assert_eq!(
+            ex.experiment.toolchains,
+            [MAIN_TOOLCHAIN.clone(), MAIN_TOOLCHAIN.clone()]
+        );
+    }
+
+    #[test]
+    fn test_pinned_index_commit() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        experiments
+            .create(
+                &CreateExperiment {
+                    index_commit: Some(commit.into()),
+                    ..CreateExperiment::dummy("pinned")
+                },
+                &Config::default(),
+            )
+            .unwrap();
+        experiments
+            .create(&CreateExperiment::dummy("latest"), &Config::default())
+            .unwrap();
+
+        let ex = experiments.get("pinned").unwrap().unwrap();
+        assert_eq!(
+            ex.experiment.index_commit.as_ref().map(|c| c.as_str()),
+            Some(commit)
+        );
+        let ex = experiments.get("latest").unwrap().unwrap();
+        assert!(ex.experiment.index_commit.is_none());
+    }
+
+    #[test]
+    fn test_toolchain_images() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        experiments
+            .create(
+                &CreateExperiment {
+                    toolchains: [
+                        "stable".parse().unwrap(),
+                        "beta+image=foo/bar".parse().unwrap(),
+                    ],
+                    ..CreateExperiment::dummy("images")
+                },
+                &Config::default(),
+            )
+            .unwrap();
+
+        // The image is kept out of the toolchain name stored in the database
+        let name: String = db
+            .get_row(
+                "SELECT toolchain_end FROM experiments WHERE name = ?1;",
+                &[&"images"],
+                |r| r.get("toolchain_end"),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(name, "beta");
+
+        let ex = experiments.get("images").unwrap().unwrap();
+        assert!(ex.experiment.toolchains[0].image.is_none());
+        assert_eq!(
+            ex.experiment.toolchains[1].image.as_ref().map(|i| i.as_str()),
+            Some("foo/bar")
+        );
+    }
+
+    #[test]
+    fn test_force_steps() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        experiments
+            .create(
+                &CreateExperiment {
+                    force_steps: vec![ExStep::Prepare, ExStep::BuildAndTest],
+                    ..CreateExperiment::dummy("forced")
+                },
+                &Config::default(),
+            )
+            .unwrap();
+
+        // The steps are sent to the agents along with the rest of the experiment
+        let ex = experiments.get("forced").unwrap().unwrap();
+        assert_eq!(
+            ex.experiment.force_steps,
+            vec![ExStep::Prepare, ExStep::BuildAndTest]
+        );
+    }
+
+    #[test]
+    fn test_components() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        experiments
+            .create(
+                &CreateExperiment {
+                    mode: ExMode::Clippy,
+                    components: vec!["clippy".into(), "rust-src".into()],
+                    ..CreateExperiment::dummy("components")
+                },
+                &Config::default(),
+            )
+            .unwrap();
+        let ex = experiments.get("components").unwrap().unwrap();
+        assert_eq!(
+            ex.experiment.components,
+            vec!["clippy".to_string(), "rust-src".to_string()]
+        );
+
+        // Switching to a mode requiring more components adds them
+        experiments
+            .create(
+                &CreateExperiment {
+                    components: vec!["rust-src".into()],
+                    ..CreateExperiment::dummy("switched")
+                },
+                &Config::default(),
+            )
+            .unwrap();
+        let mut ex = experiments.get("switched").unwrap().unwrap();
+        ex.set_mode(&db, ExMode::Clippy).unwrap();
+        let ex = experiments.get("switched").unwrap().unwrap();
+        assert_eq!(ex.experiment.mode, ExMode::Clippy);
+        assert_eq!(
+            ex.experiment.components,
+            vec!["rust-src".to_string(), "clippy".to_string()]
+        );
+
+        // The components required by the mode can't be left out
+        assert!(
+            experiments
+                .create(
+                    &CreateExperiment {
+                        mode: ExMode::Clippy,
+                        ..CreateExperiment::dummy("missing")
+                    },
+                    &Config::default(),
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_pre_build() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        experiments
+            .create(
+                &CreateExperiment {
+                    pre_build: Some("./generate.sh".into()),
+                    ..CreateExperiment::dummy("pre-build")
+                },
+                &Config::default(),
+            )
+            .unwrap();
+
+        // The hook is sent to the agents along with the rest of the experiment
+        let ex = experiments.get("pre-build").unwrap().unwrap();
+        assert_eq!(
+            ex.experiment.pre_build.as_ref().map(|p| p.as_str()),
+            Some("./generate.sh")
+        );
+    }
+
+    #[test]
+    fn test_control_index() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let config = Config::default();
+
+        experiments
+            .create(
+                &CreateExperiment {
+                    control_index: 1,
+                    ..CreateExperiment::dummy("control")
+                },
+                &config,
+            )
+            .unwrap();
+        let ex = experiments.get("control").unwrap().unwrap();
+        assert_eq!(ex.experiment.control_index, 1);
+
+        // The control toolchain must be one of the toolchains of the experiment
+        assert!(
+            experiments
+                .create(
+                    &CreateExperiment {
+                        control_index: 2,
+                        ..CreateExperiment::dummy("invalid")
+                    },
+                    &config,
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_crate_rows() {
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1.0.0".into(),
+        });
+
+        let v1 = serialize_crate_row(&krate).unwrap();
+        assert_eq!(
+            v1,
+            r#"{"crate":{"Registry":{"name":"lazy_static","version":"1.0.0"}},"v":1}"#
+        );
+        assert_eq!(deserialize_crate_row(&v1).unwrap(), krate);
+
+        // Rows stored before the versioned format are still supported
+        let legacy = r#"{"Registry":{"name":"lazy_static","version":"1.0.0"}}"#;
+        assert_eq!(deserialize_crate_row(legacy).unwrap(), krate);
+
+        assert!(deserialize_crate_row(r#"{"v":2,"crate":"lazy_static"}"#).is_err());
+
+        // Experiments containing both kinds of rows can be loaded
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        experiments
+            .create(&CreateExperiment::dummy("test"), &Config::default())
+            .unwrap();
+        let crates = experiments.get("test").unwrap().unwrap().experiment.crates;
+        db.execute(
+            "UPDATE experiment_crates SET crate = ?1 WHERE experiment = ?2 AND crate = ?3;",
+            &[
+                &serde_json::to_string(&crates[0]).unwrap(),
+                &"test",
+                &serialize_crate_row(&crates[0]).unwrap(),
+            ],
+        ).unwrap();
+        let ex = experiments.get("test").unwrap().unwrap();
+        assert_eq!(ex.experiment.crates, crates);
+    }
+
+    #[test]
+    fn test_max_crates() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        // The demo list contains two crates
+        let mut config = Config::default();
+        config.server.max_crates = Some(3);
+        experiments
+            .create(&CreateExperiment::dummy("under"), &config)
+            .unwrap();
+
+        config.server.max_crates = Some(1);
+        assert!(
+            experiments
+                .create(&CreateExperiment::dummy("over"), &config)
+                .is_err()
+        );
+        assert!(experiments.get("over").unwrap().is_none());
+
+        // Editing the crates of an experiment is limited too
+        let mut ex = experiments.get("under").unwrap().unwrap();
+        let crates = ex.experiment.crates.clone();
+        assert!(ex.set_crates(&db, &config, crates.clone()).is_err());
+        ex.set_crates(&db, &config, crates[..1].to_vec()).unwrap();
+        assert!(ex.add_crates(&db, &config, &crates).is_err());
+        let ex = experiments.get("under").unwrap().unwrap();
+        assert_eq!(ex.experiment.crates, crates[..1].to_vec());
+
+        // The limit can be ignored explicitly, and that's remembered when editing the crates
+        experiments
+            .create(
+                &CreateExperiment {
+                    ignore_crates_limit: true,
+                    ..CreateExperiment::dummy("privileged")
+                },
+                &config,
+            )
+            .unwrap();
+        let mut ex = experiments.get("privileged").unwrap().unwrap();
+        assert!(ex.server_data.ignore_crates_limit);
+        ex.set_crates(&db, &config, crates.clone()).unwrap();
+        ex.set_crates(&db, &config, crates[..1].to_vec()).unwrap();
+        ex.add_crates(&db, &config, &crates).unwrap();
+        let ex = experiments.get("privileged").unwrap().unwrap();
+        assert_eq!(ex.experiment.crates.len(), 2);
+    }
+
+    #[test]
+    fn test_created_between() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let date = |day| Utc.ymd(2018, 9, day).and_hms(12, 0, 0);
+        let config = Config::default();
+        let days = &[
+            ("before", 1),
+            ("start", 3),
+            ("middle", 5),
+            ("end", 7),
+            ("after", 9),
+        ];
+        for &(name, day) in days {
+            experiments
+                .create(&CreateExperiment::dummy(name), &config)
+                .unwrap();
+            db.execute(
+                "UPDATE experiments SET created_at = ?1 WHERE name = ?2;",
+                &[&date(day), &name],
+            ).unwrap();
+        }
+
+        let names = experiments
+            .created_between(date(3), date(7))
+            .unwrap()
+            .into_iter()
+            .map(|ex| ex.experiment.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["start", "middle", "end"]);
+    }
+
+    #[test]
+    fn test_add_remove_crates() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        let config = Config::default();
+        experiments
+            .create(&CreateExperiment::dummy("test"), &config)
+            .unwrap();
+        let mut ex = experiments.get("test").unwrap().unwrap();
+        let existing = ex.experiment.crates[0].clone();
+
+        results
+            .store(
+                &ex.experiment,
+                &ProgressData {
+                    results: vec![TaskResult {
+                        krate: existing.clone(),
+                        toolchain: MAIN_TOOLCHAIN.clone(),
+                        result: TestResult::TestPass,
+                        log: base64::encode("foo"),
+                    }],
+                    shas: Vec::new(),
+                },
+            )
+            .unwrap();
+
+        // Adding a crate keeps the results of the existing ones
+        let new = Crate::Registry(RegistryCrate {
+            name: "bitflags".into(),
+            version: "1.0.4".into(),
+        });
+        ex.add_crates(&db, &config, &[new.clone(), existing.clone()])
+            .unwrap();
+
+        let ex = experiments.get("test").unwrap().unwrap();
+        let mut expected = ::ex::demo_list(&config).unwrap();
+        expected.push(new.clone());
+        assert_eq!(ex.experiment.crates, expected);
+        assert_eq!(
+            results
+                .load_test_result(&ex.experiment, &MAIN_TOOLCHAIN, &existing)
+                .unwrap(),
+            Some(TestResult::TestPass)
+        );
+
+        // Removing a crate only removes that crate
+        let mut ex = ex;
+        ex.remove_crates(&db, &[new.clone()]).unwrap();
+        let ex = experiments.get("test").unwrap().unwrap();
+        assert_eq!(ex.experiment.crates, ::ex::demo_list(&config).unwrap());
+        assert_eq!(
+            results
+                .load_test_result(&ex.experiment, &MAIN_TOOLCHAIN, &existing)
+                .unwrap(),
+            Some(TestResult::TestPass)
+        );
+    }
+
+    #[test]
+    fn test_validate_all() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let config = Config::default();
+
+        experiments
+            .create(&CreateExperiment::dummy("valid"), &config)
+            .unwrap();
+        experiments
+            .create(&CreateExperiment::dummy("corrupt"), &config)
+            .unwrap();
+        assert!(experiments.validate_all().unwrap().is_empty());
+
+        db.execute(
+            "UPDATE experiments SET toolchain_end = ?1 WHERE name = ?2;",
+            &[&"beta+unknown-flag", &"corrupt"],
+        ).unwrap();
+
+        let failed = experiments.validate_all().unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0.as_str(), "corrupt");
+    }
+
+    #[test]
+    fn test_sample_seed() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let config = Config::default();
+
+        // The seed is not recorded if the crates were not sampled
+        experiments
+            .create(
+                &CreateExperiment {
+                    sample_seed: Some(42),
+                    ..CreateExperiment::dummy("foo")
+                },
+                &config,
+            )
+            .unwrap();
+        let mut ex = experiments.get("foo").unwrap().unwrap();
+        assert!(ex.server_data.sample_seed.is_none());
+
+        // Seeds using all the 64 bits are stored correctly
+        ex.set_sample_seed(&db, Some(u64::max_value())).unwrap();
+        let ex = experiments.get("foo").unwrap().unwrap();
+        assert_eq!(ex.server_data.sample_seed, Some(u64::max_value()));
+    }
+
+    #[test]
+    fn test_note() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        experiments
+            .create(&CreateExperiment::dummy("foo"), &Config::default())
+            .unwrap();
+        let mut ex = experiments.get("foo").unwrap().unwrap();
+        assert!(ex.server_data.note.is_none());
+
+        ex.set_note(&db, Some("Contact <foo@example.com>")).unwrap();
+        let mut ex = experiments.get("foo").unwrap().unwrap();
+        assert_eq!(
+            ex.server_data.note.as_ref().map(|n| n.as_str()),
+            Some("Contact <foo@example.com>")
+        );
+
+        ex.set_note(&db, None).unwrap();
+        let ex = experiments.get("foo").unwrap().unwrap();
+        assert!(ex.server_data.note.is_none());
+    }
+
+    #[test]
+    fn test_baseline_validation() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let config = Config::default();
+
+        let with_baseline = |name: &str| CreateExperiment {
+            baseline: Some("base".into()),
+            ..CreateExperiment::dummy(name)
+        };
+
+        // The baseline has to exist and be completed
+        assert!(experiments.create(&with_baseline("a"), &config).is_err());
+        experiments
+            .create(&CreateExperiment::dummy("base"), &config)
+            .unwrap();
+        assert!(experiments.create(&with_baseline("a"), &config).is_err());
+
+        let mut base = experiments.get("base").unwrap().unwrap();
+        base.set_status(&db, Status::Completed).unwrap();
+        experiments.create(&with_baseline("a"), &config).unwrap();
+
+        // The baseline has to provide the results of the start toolchain
+        let other: Toolchain = "nightly-2018-01-01".parse().unwrap();
+        assert!(experiments.check_baseline("base", &other).is_err());
+        assert!(experiments.check_baseline("base", &TEST_TOOLCHAIN).is_ok());
+
+        // The baseline can't be deleted while other experiments use it
+        assert!(experiments.delete("base").is_err());
+        experiments.delete("a").unwrap();
+        experiments.delete("base").unwrap();
+    }
+
+    #[test]
+    fn test_rename_preserves_id() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        let config = Config::default();
+        experiments
+            .create(&CreateExperiment::dummy("foo"), &config)
+            .unwrap();
+        let mut ex = experiments.get("foo").unwrap().unwrap();
+        ex.set_status(&db, Status::Completed).unwrap();
+        experiments
+            .create(
+                &CreateExperiment {
+                    baseline: Some("foo".into()),
+                    ..CreateExperiment::dummy("bar")
+                },
+                &config,
+            )
+            .unwrap();
+        let krate = ex.experiment.crates[0].clone();
+        results
+            .store(
+                &ex.experiment,
+                &ProgressData {
+                    results: vec![TaskResult {
+                        krate: krate.clone(),
+                        toolchain: MAIN_TOOLCHAIN.clone(),
+                        result: TestResult::TestPass,
+                        log: base64::encode("foo"),
+                    }],
+                    shas: Vec::new(),
+                },
+            )
+            .unwrap();
+
+        let id = ex.server_data.id.clone();
+        assert_ne!(id, experiments.get("bar").unwrap().unwrap().server_data.id);
+
+        // Renaming to the name of another experiment is rejected
+        assert!(ex.rename(&db, "bar").is_err());
+        assert_eq!(ex.experiment.name, "foo");
+
+        ex.rename(&db, "baz").unwrap();
+        assert_eq!(ex.experiment.name, "baz");
+        assert_eq!(ex.server_data.id, id);
+        assert!(experiments.get("foo").unwrap().is_none());
+
+        // The data related to the experiment follows the new name
+        let ex = experiments.get("baz").unwrap().unwrap();
+        assert_eq!(ex.server_data.id, id);
+        assert_eq!(
+            ex.experiment.crates.len(),
+            ::ex::demo_list(&config).unwrap().len()
+        );
+        assert_eq!(
+            results
+                .load_test_result(&ex.experiment, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(TestResult::TestPass)
+        );
+        let bar = experiments.get("bar").unwrap().unwrap();
+        assert_eq!(bar.experiment.baseline.as_ref().map(|b| b.as_str()), Some("baz"));
+    }
+
+    #[test]
+    fn test_default_cap_lints() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let mut config = Config::default();
+        config.server.default_cap_lints = Some(ExCapLints::Warn);
+
+        experiments
+            .create(&CreateExperiment::dummy("default"), &config)
+            .unwrap();
+        experiments
+            .create(
+                &CreateExperiment {
+                    cap_lints: Some(ExCapLints::Forbid),
+                    ..CreateExperiment::dummy("explicit")
+                },
+                &config,
+            )
+            .unwrap();
+
+        let ex = experiments.get("default").unwrap().unwrap();
+        assert_eq!(ex.experiment.cap_lints, ExCapLints::Warn);
+        let ex = experiments.get("explicit").unwrap().unwrap();
+        assert_eq!(ex.experiment.cap_lints, ExCapLints::Forbid);
+    }
+
+    #[test]
+    fn test_set_registry() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let config = Config::default();
+        experiments
+            .create(&CreateExperiment::dummy("test"), &config)
+            .unwrap();
+
+        let mut ex = experiments.get("test").unwrap().unwrap();
+        assert!(ex.experiment.registry.is_none());
+
+        // Invalid registries are rejected
+        assert!(ex.set_registry(&db, Some("not a url".into())).is_err());
+
+        ex.set_registry(&db, Some("https://example.com/index".into()))
+            .unwrap();
+        let ex = experiments.get("test").unwrap().unwrap();
+        assert_eq!(
+            ex.experiment.registry.as_ref().map(|r| r.as_str()),
+            Some("https://example.com/index")
+        );
+    }
+
+    #[test]
+    fn test_assigning_experiment() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        tokens.agents.insert("token2".into(), "agent-2".into());
+        tokens.agents.insert("token3".into(), "agent-3".into());
+
+        // Populate the `agents` table
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
+
+        let config = Config::default();
+        experiments
+            .create(&CreateExperiment::dummy("test"), &config)
+            .unwrap();
+        experiments
+            .create(
+                &CreateExperiment {
+                    priority: 10,
+                    ..CreateExperiment::dummy("important")
+                },
+                &config,
+            )
+            .unwrap();
+
+        // Test the important experiment is correctly assigned
+        let (new, ex) = experiments.next("agent-1").unwrap().unwrap();
+        assert!(new);
+        assert_eq!(ex.experiment.name.as_str(), "important");
+        assert_eq!(ex.server_data.status, Status::Running);
+        assert_eq!(ex.server_data.assigned_to.unwrap().as_str(), "agent-1");
+
+        // Test the same experiment is returned to the agent
+        let (new, ex) = experiments.next("agent-1").unwrap().unwrap();
+        assert!(!new);
+        assert_eq!(ex.experiment.name.as_str(), "important");
+
+        // Test the less important experiment is assigned to the next agent
+        let (new, ex) = experiments.next("agent-2").unwrap().unwrap();
+        assert!(new);
+        assert_eq!(ex.experiment.name.as_str(), "test");
+        assert_eq!(ex.server_data.status, Status::Running);
+        assert_eq!(ex.server_data.assigned_to.unwrap().as_str(), "agent-2");
+
+        // Test no other experiment is available for the other agents
+        assert!(experiments.next("agent-3").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_aborting_experiment() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        tokens.agents.insert("token2".into(), "agent-2".into());
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
+
+        let config = Config::default();
+        experiments
+            .create(&CreateExperiment::dummy("test"), &config)
+            .unwrap();
+
+        let (_, mut ex) = experiments.next("agent-1").unwrap().unwrap();
+        ex.abort(&db, "broken toolchain").unwrap();
+
+        // The reason and the status are persisted, and the agent is released
+        let ex = experiments.get("test").unwrap().unwrap();
+        assert_eq!(ex.server_data.status, Status::Aborted);
+        assert_eq!(
+            ex.server_data.abort_reason.as_ref().map(|r| r.as_str()),
+            Some("broken toolchain")
+        );
+        assert!(ex.server_data.assigned_to.is_none());
+        assert!(ex.server_data.completed_at.is_some());
+
+        // Aborted experiments are never picked up again
+        assert!(experiments.next("agent-1").unwrap().is_none());
+        assert!(experiments.next("agent-2").unwrap().is_none());
+        assert!(
+            experiments
+                .first_by_status(Status::NeedsReport)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_retry_all_report_failed() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let config = Config::default();
+        for &(name, status) in &[
+            ("failed-1", Status::ReportFailed),
+            ("failed-2", Status::ReportFailed),
+            ("completed", Status::Completed),
+        ] {
+            experiments
+                .create(&CreateExperiment::dummy(name), &config)
+                .unwrap();
+            let mut ex = experiments.get(name).unwrap().unwrap();
+            ex.set_status(&db, status).unwrap();
+        }
+
+        assert_eq!(
+            experiments.retry_all_report_failed().unwrap(),
+            vec!["failed-1".to_string(), "failed-2".to_string()]
+        );
+        for &(name, status) in &[
+            ("failed-1", Status::NeedsReport),
+            ("failed-2", Status::NeedsReport),
+            ("completed", Status::Completed),
+        ] {
+            let ex = experiments.get(name).unwrap().unwrap();
+            assert_eq!(ex.server_data.status, status);
+        }
+
+        // The requeued experiments are picked up by the reports workers
+        assert!(experiments.claim_for_report().unwrap().is_some());
+        assert!(experiments.claim_for_report().unwrap().is_some());
+        assert!(experiments.claim_for_report().unwrap().is_none());
+        assert!(experiments.retry_all_report_failed().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_abort_all_running() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        tokens.agents.insert("token2".into(), "agent-2".into());
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
+
+        let config = Config::default();
+        for name in &["test-1", "test-2", "test-3"] {
+            experiments
+                .create(&CreateExperiment::dummy(name), &config)
+                .unwrap();
+        }
+        experiments.next("agent-1").unwrap().unwrap();
+        experiments.next("agent-2").unwrap().unwrap();
+
+        assert_eq!(
+            experiments.abort_all_running("maintenance").unwrap(),
+            vec!["test-1".to_string(), "test-2".to_string()]
+        );
+
+        // No experiment is running anymore, and the queued one is left alone
+        assert!(
+            experiments
+                .first_by_status(Status::Running)
+                .unwrap()
+                .is_none()
+        );
+        for name in &["test-1", "test-2"] {
+            let ex = experiments.get(name).unwrap().unwrap();
+            assert_eq!(ex.server_data.status, Status::Aborted);
+            assert!(ex.server_data.assigned_to.is_none());
+        }
+        let ex = experiments.get("test-3").unwrap().unwrap();
+        assert_eq!(ex.server_data.status, Status::Queued);
+
+        // Calling it again is a no-op
+        assert!(
+            experiments
+                .abort_all_running("maintenance")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_concurrent_report_claims() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let config = Config::default();
+        for name in &["test-1", "test-2"] {
+            experiments
+                .create(&CreateExperiment::dummy(name), &config)
+                .unwrap();
+            let mut ex = experiments.get(name).unwrap().unwrap();
+            ex.set_status(&db, Status::NeedsReport).unwrap();
+        }
+
+        // Two workers claiming at the same time receive different experiments
+        let handles = (0..2)
+            .map(|_| {
+                let experiments = experiments.clone();
+                thread::spawn(move || experiments.claim_for_report().unwrap().unwrap())
+            })
+            .collect::<Vec<_>>();
+        let mut claimed = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .map(|ex| {
+                assert_eq!(ex.server_data.status, Status::GeneratingReport);
+                ex.experiment.name
+            })
+            .collect::<Vec<_>>();
+        claimed.sort();
+        assert_eq!(claimed, vec!["test-1".to_string(), "test-2".to_string()]);
+
+        // Nothing is left to claim
+        assert!(experiments.claim_for_report().unwrap().is_none());
+        for name in &["test-1", "test-2"] {
+            let ex = experiments.get(name).unwrap().unwrap();
+            assert_eq!(ex.server_data.status, Status::GeneratingReport);
+        }
+    }
+
+    #[test]
+    fn test_common_crates() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let config = Config::default();
+
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0.0".into(),
+            })
+        };
+        for &(name, ref crates) in &[
+            ("first", vec!["a", "b", "c", "d"]),
+            ("second", vec!["b", "c", "d", "e"]),
+            ("third", vec!["c", "d", "f"]),
+        ] {
+            experiments
+                .create(&CreateExperiment::dummy(name), &config)
+                .unwrap();
+            let mut ex = experiments.get(name).unwrap().unwrap();
+            let demo = ex.experiment.crates.clone();
+            ex.remove_crates(&db, &demo).unwrap();
+            let crates = crates.iter().map(|name| krate(name)).collect::<Vec<_>>();
+            ex.add_crates(&db, &config, &crates).unwrap();
+        }
+
+        assert_eq!(
+            experiments
+                .common_crates(&["first", "second", "third"])
+                .unwrap(),
+            vec![krate("c"), krate("d")]
+        );
+        assert_eq!(
+            experiments.common_crates(&["first", "second"]).unwrap(),
+            vec![krate("b"), krate("c"), krate("d")]
+        );
+        assert!(experiments.common_crates(&["first", "missing"]).is_err());
+    }
+
+    #[test]
+    fn test_prior_run_selector() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let config = Config::default();
+
+        experiments
+            .create(&CreateExperiment::dummy("prior"), &config)
+            .unwrap();
+        let prior = experiments.get("prior").unwrap().unwrap().experiment;
+        let (failed, passed) = (prior.crates[0].clone(), prior.crates[1].clone());
+
+        let mut task_results = Vec::new();
+        for &(ref krate, end) in &[
+            (&failed, TestResult::BuildFail),
+            (&passed, TestResult::TestPass),
+        ] {
+            for (tc, result) in prior.toolchains.iter().zip(&[TestResult::TestPass, end]) {
+                task_results.push(TaskResult {
+                    krate: (*krate).clone(),
+                    toolchain: tc.clone(),
+                    result: *result,
+                    log: base64::encode(""),
+                });
+            }
+        }
+        ResultsDB::new(&db)
+            .store(
+                &prior,
+                &ProgressData {
+                    results: task_results,
+                    shas: Vec::new(),
+                },
+            )
+            .unwrap();
+
+        for &(name, selector, ref expected) in &[
+            ("failed", "failed:prior", vec![failed.clone()]),
+            ("regressed", "regressed:prior", vec![failed.clone()]),
+            ("not-failed", "!failed:prior", vec![passed.clone()]),
+            ("passed", "passed:prior", vec![passed.clone()]),
+        ] {
+            experiments
+                .create(
+                    &CreateExperiment {
+                        prior_run: Some(selector.parse().unwrap()),
+                        ..CreateExperiment::dummy(name)
+                    },
+                    &config,
+                )
+                .unwrap();
+            let ex = experiments.get(name).unwrap().unwrap();
+            assert_eq!(&ex.experiment.crates, expected);
+        }
+
+        // The overrides set by the reviewers are taken into account, like in the report
+        experiments
+            .get("prior")
+            .unwrap()
+            .unwrap()
+            .override_result(
+                &db,
+                &failed,
+                &prior.toolchains[0],
+                TestResult::BuildFail,
+                "flaky test",
+            )
+            .unwrap();
+        experiments
+            .create(
+                &CreateExperiment {
+                    prior_run: Some("regressed:prior".parse().unwrap()),
+                    ..CreateExperiment::dummy("overridden")
+                },
+                &config,
+            )
+            .unwrap();
+        let ex = experiments.get("overridden").unwrap().unwrap();
+        assert!(ex.experiment.crates.is_empty());
+
+        // The prior experiment must exist
+        assert!(
+            experiments
+                .create(
+                    &CreateExperiment {
+                        prior_run: Some("failed:missing".parse().unwrap()),
+                        ..CreateExperiment::dummy("invalid")
+                    },
+                    &config,
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_churn() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1".into(),
+            })
+        };
+        let store = |name: &str, crates: &[(&str, TestResult)]| {
+            experiments
+                .create(&CreateExperiment::dummy(name), &Config::default())
+                .unwrap();
+            let ex = experiments.get(name).unwrap().unwrap().experiment;
+
+            let mut task_results = Vec::new();
+            for &(krate_name, result) in crates {
+                for tc in &ex.toolchains {
+                    task_results.push(TaskResult {
+                        krate: krate(krate_name),
+                        toolchain: tc.clone(),
+                        result,
+                        log: base64::encode(""),
+                    });
+                }
+            }
+            results
+                .store(
+                    &ex,
+                    &ProgressData {
+                        results: task_results,
+                        shas: Vec::new(),
+                    },
+                )
+                .unwrap();
+        };
+
+        // Four crates are tested by both runs, and only one of them changed its result
+        store(
+            "first",
+            &[
+                ("a", TestResult::TestPass),
+                ("b", TestResult::TestPass),
+                ("c", TestResult::BuildFail),
+                ("d", TestResult::TestFail),
+                ("only-first", TestResult::TestPass),
+            ],
+        );
+        store(
+            "second",
+            &[
+                ("a", TestResult::TestPass),
+                ("b", TestResult::TestFail),
+                ("c", TestResult::BuildFail),
+                ("d", TestResult::TestFail),
+                ("only-second", TestResult::BuildFail),
+            ],
+        );
+
+        assert_eq!(experiments.churn("first", "second").unwrap(), 0.25);
+        assert_eq!(experiments.churn("first", "first").unwrap(), 0.0);
+        assert!(experiments.churn("first", "missing").is_err());
+    }
+
+    #[test]
+    fn test_queue_position() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token".into(), "agent".into());
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
+
+        let config = Config::default();
+        experiments
+            .create(&CreateExperiment::dummy("first"), &config)
+            .unwrap();
+        experiments
+            .create(&CreateExperiment::dummy("second"), &config)
+            .unwrap();
+        experiments
+            .create(
+                &CreateExperiment {
+                    priority: 10,
+                    ..CreateExperiment::dummy("important")
+                },
+                &config,
+            )
+            .unwrap();
+
+        assert_eq!(experiments.queue_position("important").unwrap(), Some(1));
+        assert_eq!(experiments.queue_position("first").unwrap(), Some(2));
+        assert_eq!(experiments.queue_position("second").unwrap(), Some(3));
+        assert_eq!(experiments.queue_position("missing").unwrap(), None);
+
+        // Running experiments aren't in the queue anymore
+        experiments.next("agent").unwrap();
+        assert_eq!(experiments.queue_position("important").unwrap(), None);
+        assert_eq!(experiments.queue_position("first").unwrap(), Some(1));
+        assert_eq!(experiments.queue_position("second").unwrap(), Some(2));
+    }
+
+    #[test]
+    fn test_active_toolchains() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let config = Config::default();
+
+        let nightly: Toolchain = "nightly".parse().unwrap();
+        let old: Toolchain = "1.20.0".parse().unwrap();
+
+        experiments
+            .create(&CreateExperiment::dummy("queued"), &config)
+            .unwrap();
+        experiments
+            .create(
+                &CreateExperiment {
+                    toolchains: [MAIN_TOOLCHAIN.clone(), nightly.clone()],
+                    ..CreateExperiment::dummy("running")
+                },
+                &config,
+            )
+            .unwrap();
+        experiments
+            .create(
+                &CreateExperiment {
+                    toolchains: [old.clone(), nightly.clone()],
+                    ..CreateExperiment::dummy("completed")
+                },
+                &config,
+            )
+            .unwrap();
+
+        let mut running = experiments.get("running").unwrap().unwrap();
+        running.set_status(&db, Status::Running).unwrap();
+        let mut completed = experiments.get("completed").unwrap().unwrap();
+        completed.set_status(&db, Status::Completed).unwrap();
+
+        // Toolchains used only by completed experiments aren't returned
+        assert_eq!(
+            experiments.active_toolchains().unwrap(),
+            vec![MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone(), nightly]
+        );
+    }
+
+    #[test]
+    fn test_paused_queue() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        tokens.agents.insert("token2".into(), "agent-2".into());
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
+
+        let config = Config::default();
+        for name in &["first", "second"] {
+            experiments
+                .create(&CreateExperiment::dummy(name), &config)
+                .unwrap();
+        }
+
+        let (new, ex) = experiments.next("agent-1").unwrap().unwrap();
+        assert!(new);
+        assert_eq!(ex.experiment.name.as_str(), "first");
+
+        // While paused no new work is assigned, but running experiments are still returned
+        set_queue_paused(&db, true).unwrap();
+        assert!(experiments.next("agent-2").unwrap().is_none());
+        let (new, ex) = experiments.next("agent-1").unwrap().unwrap();
+        assert!(!new);
+        assert_eq!(ex.experiment.name.as_str(), "first");
+
+        set_queue_paused(&db, false).unwrap();
+        let (new, ex) = experiments.next("agent-2").unwrap().unwrap();
+        assert!(new);
+        assert_eq!(ex.experiment.name.as_str(), "second");
+    }
+
+    #[test]
+    fn test_assigning_experiment_with_unsupported_toolchains() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "dist-only".into());
+        tokens.agents.insert("token2".into(), "agent".into());
+        let agents = Agents::new(db.clone(), &tokens).unwrap();
+        agents
+            .set_toolchain_kinds("dist-only", &[ToolchainKind::Dist])
+            .unwrap();
+
+        let ci_toolchain = Toolchain {
+            source: ToolchainSource::CI {
+                sha: "0000000000000000000000000000000000000000".into(),
+                try: false,
+            },
+            image: None,
+        };
+
+        let config = Config::default();
+        experiments
+            .create(
+                &CreateExperiment {
+                    toolchains: [MAIN_TOOLCHAIN.clone(), ci_toolchain.clone()],
+                    ..CreateExperiment::dummy("ci")
+                },
+                &config,
+            )
+            .unwrap();
+
+        // The agent can't prepare CI toolchains, so the experiment isn't assigned to it
+        assert!(experiments.next("dist-only").unwrap().is_none());
+
+        // Agents that didn't restrict their toolchains get the experiment
+        let (new, ex) = experiments.next("agent").unwrap().unwrap();
+        assert!(new);
+        assert_eq!(ex.experiment.name.as_str(), "ci");
+
+        // Restarting the agent without restrictions clears the stored ones
+        experiments
+            .create(
+                &CreateExperiment {
+                    toolchains: [MAIN_TOOLCHAIN.clone(), ci_toolchain.clone()],
+                    ..CreateExperiment::dummy("ci-2")
+                },
+                &config,
+            )
+            .unwrap();
+        agents.set_toolchain_kinds("dist-only", &[]).unwrap();
+        let (new, ex) = experiments.next("dist-only").unwrap().unwrap();
+        assert!(new);
+        assert_eq!(ex.experiment.name.as_str(), "ci-2");
+    }
+
+    #[test]
+    fn test_blocked_on_toolchain() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "dist-only".into());
+        tokens.agents.insert("token2".into(), "disconnected".into());
+        let agents = Agents::new(db.clone(), &tokens).unwrap();
+        agents
+            .set_toolchain_kinds("dist-only", &[ToolchainKind::Dist])
+            .unwrap();
+        agents.record_heartbeat("dist-only").unwrap();
+
+        let ci_toolchain = Toolchain {
+            source: ToolchainSource::CI {
+                sha: "0000000000000000000000000000000000000000".into(),
+                try: false,
+            },
+            image: None,
+        };
+
+        let config = Config::default();
+        experiments
+            .create(&CreateExperiment::dummy("dist"), &config)
+            .unwrap();
+        experiments
+            .create(
+                &CreateExperiment {
+                    toolchains: [MAIN_TOOLCHAIN.clone(), ci_toolchain.clone()],
+                    ..CreateExperiment::dummy("ci")
+                },
+                &config,
+            )
+            .unwrap();
+
+        // The agent able to prepare CI toolchains never sent an heartbeat
+        let blocked = experiments.blocked_on_toolchain().unwrap();
+        assert_eq!(blocked.len(), 1);
+        assert_eq!(blocked[0].0.experiment.name.as_str(), "ci");
+        assert_eq!(blocked[0].1, ci_toolchain);
+
+        agents.record_heartbeat("disconnected").unwrap();
+        assert!(experiments.blocked_on_toolchain().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_assignable_to() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "dist-only".into());
+        tokens.agents.insert("token2".into(), "agent".into());
+        let agents = Agents::new(db.clone(), &tokens).unwrap();
+        agents
+            .set_toolchain_kinds("dist-only", &[ToolchainKind::Dist])
+            .unwrap();
+
+        let ci_toolchain = Toolchain {
+            source: ToolchainSource::CI {
+                sha: "0000000000000000000000000000000000000000".into(),
+                try: false,
+            },
+            image: None,
+        };
+
+        let config = Config::default();
+        experiments
+            .create(&CreateExperiment::dummy("dist"), &config)
+            .unwrap();
+        experiments
+            .create(
+                &CreateExperiment {
+                    toolchains: [MAIN_TOOLCHAIN.clone(), ci_toolchain],
+                    priority: 10,
+                    ..CreateExperiment::dummy("ci")
+                },
+                &config,
+            )
+            .unwrap();
+
+        let names = |agent| {
+            experiments
+                .assignable_to(agent)
+                .unwrap()
+                .into_iter()
+                .map(|ex| ex.experiment.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names("dist-only"), vec!["dist".to_string()]);
+        assert_eq!(names("agent"), vec!["ci".to_string(), "dist".to_string()]);
+
+        // Nothing was assigned while listing the experiments
+        for ex in experiments.all().unwrap() {
+            assert_eq!(ex.server_data.status, Status::Queued);
+            assert!(ex.server_data.assigned_to.is_none());
+        }
+    }
+
+    #[test]
+    fn test_crates_iter() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+
+        let config = Config::default();
+        experiments
+            .create(&CreateExperiment::dummy("test"), &config)
+            .unwrap();
+
+        // The streamed crates must match the ones loaded eagerly
+        let ex = experiments.get("test").unwrap().unwrap();
+        let streamed = experiments
+            .crates_iter("test")
+            .unwrap()
+            .collect::<::errors::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(streamed, ex.experiment.crates);
+
+        // Missing experiments don't have any crate
+        assert_eq!(experiments.crates_iter("missing").unwrap().count(), 0);
     }
 }
@@ -39,7 +39,8 @@ impl GitHubApi {
         Ok(response.login)
     }
 
-    pub fn post_comment(&self, issue_url: &str, body: &str) -> Result<()> {
+    /// Post a new comment on the issue, returning the API URL of the comment.
+    pub fn post_comment(&self, issue_url: &str, body: &str) -> Result<String> {
         let mut response = self
             .build_request(Method::Post, &format!("{}/comments", issue_url))
             .json(&json!({
@@ -48,7 +49,8 @@ impl GitHubApi {
             .send()?;
 
         if response.status() == StatusCode::Created {
-            Ok(())
+            let comment: PostedComment = response.json()?;
+            Ok(comment.url)
         } else {
             let error: Error = response.json()?;
             bail!(
@@ -60,6 +62,28 @@ impl GitHubApi {
         }
     }
 
+    /// Replace the body of a comment, identified by its API URL.
+    pub fn edit_comment(&self, comment_url: &str, body: &str) -> Result<()> {
+        let mut response = self
+            .build_request(Method::Patch, comment_url)
+            .json(&json!({
+                "body": body,
+            }))
+            .send()?;
+
+        if response.status() == StatusCode::Ok {
+            Ok(())
+        } else {
+            let error: Error = response.json()?;
+            bail!(
+                "failed to edit comment {} (status code {}): {}",
+                comment_url,
+                response.status(),
+                error.message
+            );
+        }
+    }
+
     pub fn list_labels(&self, issue_url: &str) -> Result<Vec<Label>> {
         let mut response = self
             .build_request(Method::Get, &format!("{}/labels", issue_url))
@@ -198,6 +222,11 @@ pub struct Comment {
     pub body: String,
 }
 
+#[derive(Deserialize)]
+struct PostedComment {
+    url: String,
+}
+
 #[derive(Deserialize)]
 pub struct Team {
     pub id: usize,
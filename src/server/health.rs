@@ -0,0 +1,122 @@
+use dirs::WORK_DIR;
+use errors::*;
+use server::db::{Database, QueryUtils};
+use std::path::{Path, PathBuf};
+use tempfile;
+
+/// Free disk space below which the server is considered degraded (5 GB)
+const LOW_DISK_THRESHOLD: u64 = 5 * 1024 * 1024 * 1024;
+
+string_enum!(pub enum HealthStatus {
+    Healthy => "healthy",
+    Degraded => "degraded",
+    Down => "down",
+});
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DirHealth {
+    pub path: PathBuf,
+    pub exists: bool,
+    pub writable: bool,
+    pub free_bytes: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub database: bool,
+    pub dirs: Vec<DirHealth>,
+}
+
+pub fn check(db: &Database) -> Result<HealthReport> {
+    check_inner(db, &[WORK_DIR.as_path()], LOW_DISK_THRESHOLD)
+}
+
+fn check_inner(db: &Database, dirs: &[&Path], low_disk: u64) -> Result<HealthReport> {
+    let database = match db.get_row("SELECT 1 AS alive;", &[], |r| -> i32 { r.get("alive") }) {
+        Ok(Some(1)) => true,
+        Ok(_) => false,
+        Err(err) => {
+            warn!("health check: the database is unreachable: {}", err);
+            false
+        }
+    };
+
+    let dirs = dirs
+        .iter()
+        .map(|path| {
+            let exists = path.is_dir();
+            DirHealth {
+                path: path.to_path_buf(),
+                exists,
+                writable: exists && tempfile::tempfile_in(path).is_ok(),
+                free_bytes: if exists { free_space(path) } else { None },
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let status = if !database {
+        HealthStatus::Down
+    } else if dirs.iter().any(|dir| {
+        !dir.writable || dir.free_bytes.map(|free| free < low_disk).unwrap_or(false)
+    }) {
+        HealthStatus::Degraded
+    } else {
+        HealthStatus::Healthy
+    };
+
+    Ok(HealthReport {
+        status,
+        database,
+        dirs,
+    })
+}
+
+#[cfg(unix)]
+fn free_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    unsafe {
+        let mut stat: ::libc::statvfs = ::std::mem::zeroed();
+        if ::libc::statvfs(path.as_ptr(), &mut stat) == 0 {
+            Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(windows)]
+fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_inner, HealthStatus};
+    use server::db::Database;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_healthy_report() {
+        let db = Database::temp().unwrap();
+        let work_dir = TempDir::new("crater").unwrap();
+
+        let report = check_inner(&db, &[work_dir.path()], 0).unwrap();
+        assert_eq!(report.status, HealthStatus::Healthy);
+        assert!(report.database);
+        assert_eq!(report.dirs.len(), 1);
+        assert!(report.dirs[0].exists);
+        assert!(report.dirs[0].writable);
+        assert!(report.dirs[0].free_bytes.is_some());
+
+        // A missing work directory degrades the server
+        let missing = work_dir.path().join("missing");
+        let report = check_inner(&db, &[missing.as_path()], 0).unwrap();
+        assert_eq!(report.status, HealthStatus::Degraded);
+    }
+}
@@ -0,0 +1,18 @@
+use registry;
+use std::thread;
+use std::time::Duration;
+use util;
+
+/// Interval between the updates of the local copy of the crates.io index, in seconds.
+const UPDATE_INTERVAL: u64 = 60 * 60;
+
+/// Keep the local copy of the crates.io index up to date in the background, so the requests reading
+/// it don't have to wait for it to be fetched.
+pub fn spawn_updater() {
+    thread::spawn(|| loop {
+        if let Err(err) = registry::crates_index_registry() {
+            util::report_error(&err);
+        }
+        thread::sleep(Duration::from_secs(UPDATE_INTERVAL));
+    });
+}
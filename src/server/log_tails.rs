@@ -0,0 +1,82 @@
+use server::api_types::RunningLogTail;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Tails of the logs of the tasks running on each agent, as last uploaded by them. They're only
+/// kept in memory, since the whole logs are stored with the results once the tasks finish.
+#[derive(Clone, Default)]
+pub struct LogTails {
+    agents: Arc<Mutex<HashMap<String, (String, Vec<RunningLogTail>)>>>,
+}
+
+impl LogTails {
+    /// Replace the tails uploaded by the agent, forgetting the tasks that are not running anymore.
+    pub fn update(&self, agent: &str, experiment: Option<&str>, tails: Vec<RunningLogTail>) {
+        let mut agents = self.agents.lock().unwrap();
+        if let Some(experiment) = experiment {
+            agents.insert(agent.to_string(), (experiment.to_string(), tails));
+        } else {
+            agents.remove(agent);
+        }
+    }
+
+    /// Get the tails of all the tasks of the experiment currently running.
+    pub fn for_experiment(&self, experiment: &str) -> Vec<RunningLogTail> {
+        let mut tails = self
+            .agents
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|&&(ref ex, _)| ex == experiment)
+            .flat_map(|&(_, ref tails)| tails.iter().cloned())
+            .collect::<Vec<_>>();
+        tails.sort_by_key(|tail| (tail.krate.clone(), tail.toolchain.to_string()));
+        tails
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LogTails;
+    use crates::{Crate, RegistryCrate};
+    use server::api_types::RunningLogTail;
+    use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+
+    fn tail(name: &str, lines: &[&str]) -> RunningLogTail {
+        RunningLogTail {
+            krate: Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1.0".into(),
+            }),
+            toolchain: MAIN_TOOLCHAIN.clone(),
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_log_tails() {
+        let tails = LogTails::default();
+        tails.update("agent-1", Some("foo"), vec![tail("b", &["building b"])]);
+        tails.update("agent-2", Some("foo"), vec![tail("a", &["building a"])]);
+        tails.update("agent-3", Some("bar"), vec![tail("c", &["building c"])]);
+        assert_eq!(
+            tails.for_experiment("foo"),
+            vec![tail("a", &["building a"]), tail("b", &["building b"])]
+        );
+
+        // Each upload replaces the previous tails of the agent
+        let mut test_tail = tail("b", &["testing b"]);
+        test_tail.toolchain = TEST_TOOLCHAIN.clone();
+        tails.update("agent-1", Some("foo"), vec![test_tail.clone()]);
+        assert_eq!(
+            tails.for_experiment("foo"),
+            vec![tail("a", &["building a"]), test_tail]
+        );
+
+        // Agents without an experiment don't run any task
+        tails.update("agent-2", None, Vec::new());
+        tails.update("agent-3", None, Vec::new());
+        assert_eq!(tails.for_experiment("foo").len(), 1);
+        assert!(tails.for_experiment("bar").is_empty());
+    }
+}
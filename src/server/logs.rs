@@ -0,0 +1,142 @@
+use chrono::{Duration, Utc};
+use errors::*;
+use server::db::{Database, QueryUtils};
+use server::experiments::Status;
+use std::thread;
+use std::time::Duration as StdDuration;
+use util;
+
+// Enforce the retention policy every hour
+const RETENTION_INTERVAL: u64 = 60 * 60;
+
+/// Rules deciding when the logs of an experiment are deleted.
+pub struct RetentionPolicy {
+    /// Logs are deleted once the experiment has been completed for this amount of time
+    pub max_age: Duration,
+}
+
+/// Delete the logs of the experiments completed before the limit set by the policy, keeping
+/// their results. Returns the size in bytes of the deleted logs.
+///
+/// The database file doesn't shrink, since it's not vacuumed: SQLite reuses the pages of the
+/// deleted logs for the data stored afterwards.
+pub fn enforce_retention(db: &Database, policy: &RetentionPolicy) -> Result<u64> {
+    let limit = Utc::now() - policy.max_age;
+
+    db.transaction(|trans| {
+        let deleted: i64 = trans
+            .get_row(
+                "SELECT COALESCE(SUM(LENGTH(log)), 0) AS deleted FROM results \
+                 WHERE experiment IN ( \
+                     SELECT name FROM experiments WHERE status = ?1 AND completed_at < ?2 \
+                 );",
+                &[&Status::Completed.to_str(), &limit],
+                |row| row.get("deleted"),
+            )?
+            .unwrap_or(0);
+
+        trans.execute(
+            "UPDATE results SET log = X'' \
+             WHERE LENGTH(log) > 0 AND experiment IN ( \
+                 SELECT name FROM experiments WHERE status = ?1 AND completed_at < ?2 \
+             );",
+            &[&Status::Completed.to_str(), &limit],
+        )?;
+
+        Ok(deleted as u64)
+    })
+}
+
+/// Enforce the retention policy periodically in a background thread.
+pub fn spawn_retention_worker(db: Database, policy: RetentionPolicy) {
+    thread::spawn(move || loop {
+        match enforce_retention(&db, &policy) {
+            Ok(0) => {}
+            Ok(deleted) => info!("deleted {} bytes of logs of old experiments", deleted),
+            Err(err) => util::report_error(&err),
+        }
+        thread::sleep(StdDuration::from_secs(RETENTION_INTERVAL));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{enforce_retention, RetentionPolicy};
+    use base64;
+    use chrono::{Duration, Utc};
+    use config::Config;
+    use results::{ReadResults, TestResult};
+    use server::actions::CreateExperiment;
+    use server::db::{Database, QueryUtils};
+    use server::experiments::{Experiments, Status};
+    use server::results::{ProgressData, ResultsDB, TaskResult};
+
+    #[test]
+    fn test_enforce_retention() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        for name in &["old", "recent", "running"] {
+            experiments
+                .create(&CreateExperiment::dummy(name), &Config::default())
+                .unwrap();
+            let mut ex = experiments.get(name).unwrap().unwrap();
+            let krate = ex.experiment.crates[0].clone();
+            results
+                .store(
+                    &ex.experiment,
+                    &ProgressData {
+                        results: vec![TaskResult {
+                            krate,
+                            toolchain: ex.experiment.toolchains[0].clone(),
+                            result: TestResult::TestPass,
+                            log: base64::encode("foo"),
+                        }],
+                        shas: Vec::new(),
+                    },
+                )
+                .unwrap();
+
+            ex.set_status(&db, Status::Running).unwrap();
+            if *name != "running" {
+                ex.set_status(&db, Status::Completed).unwrap();
+            }
+        }
+        db.execute(
+            "UPDATE experiments SET completed_at = ?1 WHERE name = \"old\";",
+            &[&(Utc::now() - Duration::days(30))],
+        ).unwrap();
+        db.execute(
+            "UPDATE experiments SET started_at = ?1 WHERE name = \"running\";",
+            &[&(Utc::now() - Duration::days(30))],
+        ).unwrap();
+
+        let policy = RetentionPolicy {
+            max_age: Duration::days(7),
+        };
+        assert_eq!(enforce_retention(&db, &policy).unwrap(), 3);
+
+        let log = |name: &str| {
+            let ex = experiments.get(name).unwrap().unwrap().experiment;
+            let krate = ex.crates[0].clone();
+            results
+                .load_log(&ex, &ex.toolchains[0], &krate)
+                .unwrap()
+                .unwrap()
+        };
+        assert!(log("old").is_empty());
+        assert_eq!(log("recent"), b"foo".to_vec());
+        assert_eq!(log("running"), b"foo".to_vec());
+
+        // The results are kept, and nothing is deleted the second time
+        let old = experiments.get("old").unwrap().unwrap().experiment;
+        assert_eq!(
+            results
+                .load_test_result(&old, &old.toolchains[0], &old.crates[0])
+                .unwrap(),
+            Some(TestResult::TestPass)
+        );
+        assert_eq!(enforce_retention(&db, &policy).unwrap(), 0);
+    }
+}
@@ -1,5 +1,6 @@
 use errors::*;
 use server::Data;
+use util;
 
 pub enum Label {
     ExperimentQueued,
@@ -47,7 +48,20 @@ impl Message {
         self
     }
 
-    pub fn send(mut self, issue_url: &str, data: &Data) -> Result<()> {
+    pub fn send(self, issue_url: &str, data: &Data) -> Result<()> {
+        self.send_or_edit(issue_url, None, data)?;
+        Ok(())
+    }
+
+    /// Send the message by editing the comment at `comment_url`, or as a new comment on the issue
+    /// if there is no comment to edit or editing it failed. The API URL of the comment containing
+    /// the message is returned.
+    pub fn send_or_edit(
+        mut self,
+        issue_url: &str,
+        comment_url: Option<&str>,
+        data: &Data,
+    ) -> Result<String> {
         // Always add a note at the bottom explaining what this is
         self = self.note(
             "information_source",
@@ -63,7 +77,17 @@ impl Message {
             message.push_str(&format!("\n:{}: {}", line.emoji, line.content));
         }
 
-        data.github.post_comment(issue_url, &message)?;
+        let comment_url = match comment_url {
+            Some(url) => match data.github.edit_comment(url, &message) {
+                Ok(()) => url.to_string(),
+                Err(err) => {
+                    // The comment might have been deleted in the meantime
+                    util::report_error(&err);
+                    data.github.post_comment(issue_url, &message)?
+                }
+            },
+            None => data.github.post_comment(issue_url, &message)?,
+        };
 
         if let Some(label) = self.new_label {
             let label = match label {
@@ -89,6 +113,6 @@ impl Message {
             }
         }
 
-        Ok(())
+        Ok(comment_url)
     }
 }
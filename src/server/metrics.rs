@@ -0,0 +1,99 @@
+use errors::*;
+use server::db::{Database, QueryUtils};
+use server::experiments::Status;
+use std::fmt::Write;
+
+/// Gather the metrics about the queue state, in the Prometheus text exposition format.
+pub fn gather(db: &Database) -> Result<String> {
+    let mut out = String::new();
+
+    let counts = db.query(
+        "SELECT status, COUNT(*) AS count FROM experiments GROUP BY status;",
+        &[],
+        |r| -> (String, i64) { (r.get("status"), r.get("count")) },
+    )?;
+
+    writeln!(out, "# HELP crater_experiments Number of experiments per status.")?;
+    writeln!(out, "# TYPE crater_experiments gauge")?;
+    for status in Status::possible_values() {
+        let count = counts
+            .iter()
+            .find(|&&(ref s, _)| s == status)
+            .map(|&(_, count)| count)
+            .unwrap_or(0);
+        writeln!(out, "crater_experiments{{status=\"{}\"}} {}", status, count)?;
+    }
+
+    let assigned: i64 = db
+        .get_row(
+            "SELECT COUNT(DISTINCT assigned_to) AS count FROM experiments \
+             WHERE status = ?1 AND assigned_to IS NOT NULL;",
+            &[&Status::Running.to_str()],
+            |r| r.get("count"),
+        )?
+        .unwrap_or(0);
+
+    writeln!(out, "# HELP crater_agents_assigned Number of agents running an experiment.")?;
+    writeln!(out, "# TYPE crater_agents_assigned gauge")?;
+    writeln!(out, "crater_agents_assigned {}", assigned)?;
+
+    // Each crate produces one result per toolchain, and there are two toolchains per experiment
+    let progress: Option<f64> = db
+        .get_row(
+            "SELECT AVG(progress) AS progress FROM ( \
+                 SELECT MIN(100.0, \
+                     (SELECT COUNT(*) FROM results WHERE experiment = ex.name) * 100.0 / \
+                     MAX(1, (SELECT COUNT(*) FROM experiment_crates \
+                             WHERE experiment = ex.name AND skipped = 0) * 2) \
+                 ) AS progress \
+                 FROM experiments ex WHERE ex.status = ?1 \
+             );",
+            &[&Status::Running.to_str()],
+            |r| r.get("progress"),
+        )?
+        .and_then(|p| p);
+
+    writeln!(out, "# HELP crater_running_progress Average progress of the running experiments.")?;
+    writeln!(out, "# TYPE crater_running_progress gauge")?;
+    writeln!(out, "crater_running_progress {}", progress.unwrap_or(0.0))?;
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::gather;
+    use config::Config;
+    use server::actions::CreateExperiment;
+    use server::agents::Agents;
+    use server::db::Database;
+    use server::experiments::Experiments;
+    use server::tokens::Tokens;
+
+    #[test]
+    fn test_gather() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let config = Config::default();
+
+        let mut tokens = Tokens::default();
+        tokens.agents.insert("token1".into(), "agent-1".into());
+        tokens.agents.insert("token2".into(), "agent-2".into());
+        let _ = Agents::new(db.clone(), &tokens).unwrap();
+
+        for name in &["foo", "bar", "baz"] {
+            experiments
+                .create(&CreateExperiment::dummy(name), &config)
+                .unwrap();
+        }
+        experiments.next("agent-1").unwrap();
+        experiments.next("agent-2").unwrap();
+
+        let metrics = gather(&db).unwrap();
+        assert!(metrics.contains("\ncrater_experiments{status=\"running\"} 2\n"));
+        assert!(metrics.contains("\ncrater_experiments{status=\"queued\"} 1\n"));
+        assert!(metrics.contains("\ncrater_experiments{status=\"completed\"} 0\n"));
+        assert!(metrics.contains("\ncrater_agents_assigned 2\n"));
+        assert!(metrics.contains("\ncrater_running_progress 0\n"));
+    }
+}
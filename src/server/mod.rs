@@ -1,10 +1,18 @@
+mod actions;
 mod agents;
 pub mod api_types;
 mod auth;
 mod db;
 mod experiments;
 mod github;
+mod health;
+mod index;
+mod log_tails;
+mod logs;
 mod messages;
+mod metrics;
+mod presets;
+mod progress;
 mod reports;
 mod results;
 mod routes;
@@ -18,8 +26,11 @@ use server::agents::Agents;
 use server::auth::ACL;
 use server::experiments::Experiments;
 use server::github::GitHubApi;
+use server::log_tails::LogTails;
+use server::progress::ProgressLimiter;
 use server::tokens::Tokens;
 use std::sync::Arc;
+use std::time::Duration;
 use warp::{self, Filter};
 
 lazy_static! {
@@ -37,6 +48,26 @@ pub struct Data {
     pub db: db::Database,
     pub reports_worker: reports::ReportsWorker,
     pub acl: ACL,
+    pub progress_limiter: Option<ProgressLimiter>,
+    pub log_tails: LogTails,
+}
+
+/// Check whether all the experiments stored in the database can still be loaded.
+pub fn validate_experiments() -> Result<()> {
+    let db = db::Database::open()?;
+    let failed = Experiments::new(db).validate_all()?;
+
+    for &(ref name, ref err) in &failed {
+        error!("experiment {} can't be loaded", name);
+        ::util::report_error(err);
+    }
+
+    if failed.is_empty() {
+        info!("all the experiments can be loaded");
+        Ok(())
+    } else {
+        bail!("{} experiments can't be loaded", failed.len());
+    }
 }
 
 pub fn run(config: Config) -> Result<()> {
@@ -46,6 +77,10 @@ pub fn run(config: Config) -> Result<()> {
     let agents = Agents::new(db.clone(), &tokens)?;
     let bot_username = github.username()?;
     let acl = ACL::new(&config, &github)?;
+    let progress_limiter = config
+        .server
+        .progress_comment_interval
+        .map(|secs| ProgressLimiter::new(Duration::from_secs(secs)));
 
     info!("bot username: {}", bot_username);
 
@@ -59,9 +94,21 @@ pub fn run(config: Config) -> Result<()> {
         db: db.clone(),
         reports_worker: reports::ReportsWorker::new(),
         acl,
+        progress_limiter,
+        log_tails: LogTails::default(),
     };
 
     data.reports_worker.spawn(data.clone());
+    index::spawn_updater();
+
+    if let Some(days) = data.config.server.log_retention_days {
+        logs::spawn_retention_worker(
+            db.clone(),
+            logs::RetentionPolicy {
+                max_age: ::chrono::Duration::days(days),
+            },
+        );
+    }
 
     info!("running server...");
 
@@ -73,6 +120,12 @@ pub fn run(config: Config) -> Result<()> {
                 .and(warp::path("webhooks").and(routes::webhooks::routes(data.clone())))
                 .or(warp::path("agent-api").and(routes::agent::routes(data.clone())))
                 .unify()
+                .or(warp::path("health").and(routes::health::routes(data.clone())))
+                .unify()
+                .or(warp::path("metrics").and(routes::metrics::routes(data.clone())))
+                .unify()
+                .or(warp::path("log-tails").and(routes::log_tails::routes(data.clone())))
+                .unify()
                 .or(routes::ui::routes(data.clone()))
                 .unify(),
         )
@@ -0,0 +1,169 @@
+use crates::CrateSelector;
+use errors::*;
+use ex::{ExCapLints, ExCrateSelect, ExMode};
+use rusqlite::Row;
+use server::db::{Database, QueryUtils};
+use server::routes::webhooks::args::{RunArgs, SavePresetArgs};
+use std::str::FromStr;
+use toolchain::Toolchain;
+
+/// Configuration shared by multiple experiments, used as the default values when creating one of
+/// them. Fields missing from the preset have to be provided when the experiment is created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExperimentPreset {
+    pub name: String,
+    pub start: Option<Toolchain>,
+    pub end: Option<Toolchain>,
+    pub mode: Option<ExMode>,
+    pub crates: Option<ExCrateSelect>,
+    pub select: Option<CrateSelector>,
+    pub cap_lints: Option<ExCapLints>,
+    pub priority: Option<i32>,
+}
+
+impl ExperimentPreset {
+    pub fn from_args(args: SavePresetArgs) -> Result<Self> {
+        Ok(ExperimentPreset {
+            name: args.name.ok_or_else(|| "missing preset name")?,
+            start: args.start,
+            end: args.end,
+            mode: args.mode,
+            crates: args.crates,
+            select: args.select,
+            cap_lints: args.cap_lints,
+            priority: args.priority,
+        })
+    }
+
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(ExperimentPreset {
+            name: row.get("name"),
+            start: parse_column(row.get("toolchain_start"))?,
+            end: parse_column(row.get("toolchain_end"))?,
+            mode: parse_column(row.get("mode"))?,
+            crates: parse_column(row.get("crates"))?,
+            select: parse_column(row.get("crate_selector"))?,
+            cap_lints: parse_column(row.get("cap_lints"))?,
+            priority: row.get("priority"),
+        })
+    }
+
+    /// Fill the arguments not provided by the user with the values of the preset.
+    pub fn apply(&self, mut args: RunArgs) -> RunArgs {
+        args.start = args.start.or_else(|| self.start.clone());
+        args.end = args.end.or_else(|| self.end.clone());
+        args.mode = args.mode.or(self.mode);
+        args.crates = args.crates.or(self.crates);
+        args.select = args.select.or_else(|| self.select.clone());
+        args.cap_lints = args.cap_lints.or(self.cap_lints);
+        args.priority = args.priority.or(self.priority);
+        args
+    }
+}
+
+fn parse_column<T: FromStr<Err = Error>>(value: Option<String>) -> Result<Option<T>> {
+    if let Some(value) = value {
+        Ok(Some(value.parse()?))
+    } else {
+        Ok(None)
+    }
+}
+
+pub struct Presets {
+    db: Database,
+}
+
+impl Presets {
+    pub fn new(db: Database) -> Self {
+        Presets { db }
+    }
+
+    /// Store the preset, replacing any existing one with the same name.
+    pub fn save(&self, preset: &ExperimentPreset) -> Result<()> {
+        self.db.execute(
+            "INSERT OR REPLACE INTO presets \
+             (name, toolchain_start, toolchain_end, mode, crates, cap_lints, priority, \
+             crate_selector) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8);",
+            &[
+                &preset.name,
+                &preset.start.as_ref().map(|t| t.to_string()),
+                &preset.end.as_ref().map(|t| t.to_string()),
+                &preset.mode.map(|m| m.to_str()),
+                &preset.crates.map(|c| c.to_str()),
+                &preset.cap_lints.map(|c| c.to_str()),
+                &preset.priority,
+                &preset.select.as_ref().map(|s| s.to_string()),
+            ],
+        )
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<ExperimentPreset>> {
+        self.db
+            .get_row("SELECT * FROM presets WHERE name = ?1;", &[&name], |row| {
+                ExperimentPreset::from_row(row)
+            })?
+            .map_or(Ok(None), |preset| preset.map(Some))
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        self.db.execute("DELETE FROM presets WHERE name = ?1;", &[&name])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExperimentPreset, Presets};
+    use ex::{ExCrateSelect, ExMode};
+    use server::db::Database;
+    use server::routes::webhooks::args::Command;
+    use toolchain::MAIN_TOOLCHAIN;
+
+    #[test]
+    fn test_presets() {
+        let db = Database::temp().unwrap();
+        let presets = Presets::new(db.clone());
+        assert!(presets.get("weekly").unwrap().is_none());
+
+        let mut preset = ExperimentPreset {
+            name: "weekly".into(),
+            start: Some(MAIN_TOOLCHAIN.clone()),
+            end: None,
+            mode: Some(ExMode::CheckOnly),
+            crates: Some(ExCrateSelect::Full),
+            select: Some("top-downloads:100".parse().unwrap()),
+            cap_lints: None,
+            priority: Some(5),
+        };
+        presets.save(&preset).unwrap();
+        assert_eq!(presets.get("weekly").unwrap(), Some(preset.clone()));
+
+        // Saving a preset again replaces it
+        preset.mode = None;
+        presets.save(&preset).unwrap();
+        assert_eq!(presets.get("weekly").unwrap(), Some(preset));
+
+        presets.delete("weekly").unwrap();
+        assert!(presets.get("weekly").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_preset_from_args() {
+        let args = match "save-preset name=weekly mode=check-only p=2".parse().unwrap() {
+            Command::SavePreset(args) => args,
+            _ => panic!("not a save-preset command"),
+        };
+        let preset = ExperimentPreset::from_args(args).unwrap();
+        assert_eq!(preset.name.as_str(), "weekly");
+        assert_eq!(preset.mode, Some(ExMode::CheckOnly));
+        assert_eq!(preset.priority, Some(2));
+        assert!(preset.start.is_none());
+
+        // Presets need a name to be referenced by
+        let args = match "save-preset mode=check-only".parse().unwrap() {
+            Command::SavePreset(args) => args,
+            _ => panic!("not a save-preset command"),
+        };
+        assert!(ExperimentPreset::from_args(args).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Limits how often the progress of each experiment is reported on its GitHub issue. Progress
+/// received before the interval elapses isn't reported on its own, but it's included in the
+/// next update.
+#[derive(Clone)]
+pub struct ProgressLimiter {
+    interval: Duration,
+    last_updates: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl ProgressLimiter {
+    pub fn new(interval: Duration) -> Self {
+        ProgressLimiter {
+            interval,
+            last_updates: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Check whether the progress of the experiment can be reported at `now`, recording the
+    /// update if that's the case.
+    pub fn should_update(&self, experiment: &str, now: Instant) -> bool {
+        let mut last_updates = self.last_updates.lock().unwrap();
+        if let Some(last) = last_updates.get(experiment) {
+            if now.duration_since(*last) < self.interval {
+                return false;
+            }
+        }
+
+        last_updates.insert(experiment.to_string(), now);
+        true
+    }
+
+    /// Forget about the experiment, for example because it's not running anymore.
+    pub fn forget(&self, experiment: &str) {
+        self.last_updates.lock().unwrap().remove(experiment);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProgressLimiter;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_progress_limiter() {
+        let limiter = ProgressLimiter::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        // Only the first progress change in the interval results in an update
+        assert!(limiter.should_update("foo", start));
+        for secs in &[1, 10, 59] {
+            assert!(!limiter.should_update("foo", start + Duration::from_secs(*secs)));
+        }
+
+        // Other experiments are limited separately
+        assert!(limiter.should_update("bar", start + Duration::from_secs(10)));
+
+        assert!(limiter.should_update("foo", start + Duration::from_secs(60)));
+        assert!(!limiter.should_update("foo", start + Duration::from_secs(61)));
+
+        limiter.forget("foo");
+        assert!(limiter.should_update("foo", start + Duration::from_secs(62)));
+    }
+}
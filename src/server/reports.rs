@@ -1,7 +1,10 @@
+use chrono::{self, Utc};
 use errors::*;
 use report;
+use results::{ReadResults, ResultCache};
 use rusoto_core::request::default_tls_client;
 use rusoto_s3::S3Client;
+use server::db::Database;
 use server::experiments::{ExperimentData, Status};
 use server::messages::{Label, Message};
 use server::results::ResultsDB;
@@ -13,30 +16,84 @@ use util;
 
 // Automatically wake up the reports generator thread every 10 minutes to check for new jobs
 const AUTOMATIC_THREAD_WAKEUP: u64 = 600;
+// Wait one minute before the first retry of a failed report, doubling it after every attempt
+const REPORT_RETRY_BACKOFF: i64 = 60;
 
-fn generate_report(data: &Data, ex: &ExperimentData, results: &ResultsDB) -> Result<()> {
+fn generate_report<DB: ReadResults>(data: &Data, ex: &ExperimentData, results: &DB) -> Result<()> {
     let client = S3Client::new(
         default_tls_client()?,
         data.tokens.reports_bucket.clone(),
         data.tokens.reports_bucket.region.to_region()?,
     );
+    // Reports are stored under the id, so their URLs don't change if the experiment is renamed
     let dest = format!(
         "s3://{}/{}",
-        data.tokens.reports_bucket.bucket, &ex.experiment.name
+        data.tokens.reports_bucket.bucket, &ex.server_data.id
     );
     let writer = report::S3Writer::create(Box::new(client), dest.parse()?)?;
 
-    report::gen(results, &ex.experiment, &writer, &data.config)?;
+    report::gen(
+        results,
+        &ex.experiment,
+        &writer,
+        &data.config,
+        ex.server_data.crater_version.as_ref().map(|v| v.as_str()),
+        ex.server_data.note.as_ref().map(|n| n.as_str()),
+    )?;
 
     Ok(())
 }
 
+/// Make an attempt at generating the report with the provided function, which returns the URL of
+/// the report. Failed attempts are queued again with an exponential backoff, until `retries`
+/// retries failed and the experiment is marked as `ReportFailed`.
+fn generate_attempt<F>(
+    db: &Database,
+    ex: &mut ExperimentData,
+    retries: u32,
+    backoff: chrono::Duration,
+    generate: F,
+) -> Result<Option<String>>
+where
+    F: FnOnce(&ExperimentData) -> Result<String>,
+{
+    let result = generate(ex);
+    let attempts = ex.server_data.report_attempts + 1;
+    ex.set_report_attempts(db, attempts)?;
+
+    match result {
+        Ok(report_url) => {
+            // The URL must be recorded before the experiment is shown as completed
+            ex.set_report_url(db, &report_url)?;
+            ex.add_report_url(db, "s3", &report_url)?;
+            ex.set_report_retry_at(db, None)?;
+            ex.set_status(db, Status::Completed)?;
+            Ok(Some(report_url))
+        }
+        Err(err) => {
+            if attempts > retries {
+                ex.set_report_retry_at(db, None)?;
+                ex.set_status(db, Status::ReportFailed)?;
+                return Err(err);
+            }
+
+            warn!(
+                "report generation of {} failed (attempt {}), retrying later...",
+                ex.experiment.name, attempts
+            );
+            util::report_error(&err);
+            ex.requeue_report(db, Utc::now() + backoff * 2i32.pow(attempts - 1))?;
+            Ok(None)
+        }
+    }
+}
+
 fn reports_thread(data: &Data, wakes: &mpsc::Receiver<()>) -> Result<()> {
     let timeout = Duration::from_secs(AUTOMATIC_THREAD_WAKEUP);
     let results = ResultsDB::new(&data.db);
 
     loop {
-        let mut ex = match data.experiments.first_by_status(Status::NeedsReport)? {
+        let mut ex = match data.experiments.claim_for_report()? {
             Some(ex) => ex,
             None => {
                 // This will sleep AUTOMATIC_THREAD_WAKEUP seconds *or* until a wake is received
@@ -50,42 +107,53 @@ fn reports_thread(data: &Data, wakes: &mpsc::Receiver<()>) -> Result<()> {
         let name = ex.experiment.name.clone();
 
         info!("generating report for experiment {}...", name);
-        ex.set_status(&data.db, Status::GeneratingReport)?;
+        let cache = ResultCache::new(&results);
+        let result = generate_attempt(
+            &data.db,
+            &mut ex,
+            data.config.server.report_retries,
+            chrono::Duration::seconds(REPORT_RETRY_BACKOFF),
+            |ex| {
+                generate_report(data, ex, &cache)?;
 
-        if let Err(err) = generate_report(data, &ex, &results) {
-            ex.set_status(&data.db, Status::ReportFailed)?;
-            error!("failed to generate the report of {}", name);
-            util::report_error(&err);
+                let base_url = data
+                    .tokens
+                    .reports_bucket
+                    .public_url
+                    .replace("{bucket}", &data.tokens.reports_bucket.bucket);
+                Ok(format!("{}/{}/index.html", base_url, ex.server_data.id))
+            },
+        );
 
-            if let Some(ref github_issue) = ex.server_data.github_issue {
-                Message::new()
-                    .line(
-                        "rotating_light",
-                        format!("Report generation of **`{}`** failed: {}", name, err),
-                    )
-                    .line(
-                        "hammer_and_wrench",
-                        "If the error is fixed use the `retry-report` command.",
-                    )
-                    .note(
-                        "sos",
-                        "Can someone from the infra team check in on this? @rust-lang/infra",
-                    )
-                    .send(&github_issue.api_url, data)?;
-            }
+        let report_url = match result {
+            Ok(Some(report_url)) => report_url,
+            // The generation will be attempted again after the backoff
+            Ok(None) => continue,
+            Err(err) => {
+                error!("failed to generate the report of {}", name);
+                util::report_error(&err);
 
-            continue;
-        }
+                if let Some(ref github_issue) = ex.server_data.github_issue {
+                    Message::new()
+                        .line(
+                            "rotating_light",
+                            format!("Report generation of **`{}`** failed: {}", name, err),
+                        )
+                        .line(
+                            "hammer_and_wrench",
+                            "If the error is fixed use the `retry-report` command.",
+                        )
+                        .note(
+                            "sos",
+                            "Can someone from the infra team check in on this? @rust-lang/infra",
+                        )
+                        .send(&github_issue.api_url, data)?;
+                }
 
-        let base_url = data
-            .tokens
-            .reports_bucket
-            .public_url
-            .replace("{bucket}", &data.tokens.reports_bucket.bucket);
-        let report_url = format!("{}/{}/index.html", base_url, name);
+                continue;
+            }
+        };
 
-        ex.set_status(&data.db, Status::Completed)?;
-        ex.set_report_url(&data.db, &report_url)?;
         info!("report for the experiment {} generated successfully!", name);
 
         if let Some(ref github_issue) = ex.server_data.github_issue {
@@ -104,45 +172,124 @@ fn reports_thread(data: &Data, wakes: &mpsc::Receiver<()>) -> Result<()> {
 }
 
 #[derive(Clone, Default)]
-pub struct ReportsWorker(Arc<Mutex<Option<mpsc::Sender<()>>>>);
+pub struct ReportsWorker(Arc<Mutex<Vec<mpsc::Sender<()>>>>);
 
 impl ReportsWorker {
     pub fn new() -> Self {
-        ReportsWorker(Arc::new(Mutex::new(None)))
+        ReportsWorker(Arc::new(Mutex::new(Vec::new())))
     }
 
+    /// Spawn the reports generator threads, one for each report that can be generated
+    /// concurrently.
     pub fn spawn(&self, data: Data) {
-        let waker = self.0.clone();
-        thread::spawn(move || {
-            // Set up a new waker channel
+        let workers = data.config.server.report_workers.unwrap_or(1).max(1);
+        for id in 0..workers {
+            // Set up a new waker channel for each thread
             let (wake_send, wake_recv) = mpsc::channel();
-            {
-                let mut waker = waker.lock().unwrap();
-                *waker = Some(wake_send);
-            }
+            self.0.lock().unwrap().push(wake_send);
 
-            loop {
-                let result = reports_thread(&data.clone(), &wake_recv)
-                    .chain_err(|| "the reports generator thread crashed");
+            let data = data.clone();
+            thread::spawn(move || loop {
+                let result = reports_thread(&data, &wake_recv)
+                    .chain_err(|| format!("the reports generator thread {} crashed", id));
                 if let Err(e) = result {
                     util::report_error(&e);
                 }
 
-                warn!("the reports generator thread will be respawned in one minute");
+                warn!("the reports generator thread {} will be respawned in one minute", id);
                 thread::sleep(Duration::from_secs(60));
-            }
-        });
+            });
+        }
     }
 
     pub fn wake(&self) {
-        // We don't really care if the wake fails: the reports generator thread wakes up on its own
-        // every few minutes, so this just speeds up the process
-        if let Some(waker) = self.0.lock().ok().as_ref().and_then(|opt| opt.as_ref()) {
+        // We don't really care if the wake fails: the reports generator threads wake up on their
+        // own every few minutes, so this just speeds up the process
+        let wakers = match self.0.lock() {
+            Ok(wakers) => wakers,
+            Err(_) => return,
+        };
+
+        if wakers.is_empty() {
+            warn!("no report generator to wake up!");
+        }
+        for waker in wakers.iter() {
             if waker.send(()).is_err() {
                 warn!("can't wake the reports generator, will have to wait");
             }
-        } else {
-            warn!("no report generator to wake up!");
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::generate_attempt;
+    use chrono::Duration;
+    use config::Config;
+    use server::actions::CreateExperiment;
+    use server::db::Database;
+    use server::experiments::{Experiments, Status};
+    use std::cell::Cell;
+
+    #[test]
+    fn test_generate_attempt() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let config = Config::default();
+
+        experiments
+            .create(&CreateExperiment::dummy("flaky"), &config)
+            .unwrap();
+        experiments
+            .create(&CreateExperiment::dummy("broken"), &config)
+            .unwrap();
+
+        // A report failing twice is generated at the third attempt
+        let mut ex = experiments.get("flaky").unwrap().unwrap();
+        ex.set_status(&db, Status::NeedsReport).unwrap();
+        let failures = Cell::new(0);
+        let mut url = None;
+        while url.is_none() {
+            let mut ex = experiments.claim_for_report().unwrap().unwrap();
+            url = generate_attempt(&db, &mut ex, 2, Duration::seconds(0), |_| {
+                if failures.get() < 2 {
+                    failures.set(failures.get() + 1);
+                    bail!("transient failure");
+                }
+                Ok("https://example.com/flaky".into())
+            }).unwrap();
+        }
+
+        let ex = experiments.get("flaky").unwrap().unwrap();
+        assert_eq!(ex.server_data.status, Status::Completed);
+        assert_eq!(ex.server_data.report_attempts, 3);
+        assert_eq!(
+            ex.server_data.report_url.as_ref().map(|u| u.as_str()),
+            Some("https://example.com/flaky")
+        );
+
+        // Failed attempts are queued again, and not retried until the backoff expires
+        let mut ex = experiments.get("broken").unwrap().unwrap();
+        ex.set_status(&db, Status::NeedsReport).unwrap();
+        let mut ex = experiments.claim_for_report().unwrap().unwrap();
+        let result = generate_attempt(&db, &mut ex, 2, Duration::hours(1), |_| {
+            bail!("permanent failure")
+        });
+        assert!(result.unwrap().is_none());
+        assert_eq!(ex.server_data.status, Status::NeedsReport);
+        assert!(experiments.claim_for_report().unwrap().is_none());
+
+        // A report always failing is given up after the retries
+        let mut result = Ok(None);
+        for _ in 0..2 {
+            result = generate_attempt(&db, &mut ex, 2, Duration::hours(1), |_| {
+                bail!("permanent failure")
+            });
+        }
+        assert!(result.is_err());
+
+        let ex = experiments.get("broken").unwrap().unwrap();
+        assert_eq!(ex.server_data.status, Status::ReportFailed);
+        assert_eq!(ex.server_data.report_attempts, 3);
+    }
+}
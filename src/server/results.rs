@@ -1,14 +1,16 @@
 use base64;
+use chrono::{Duration, Utc};
 use crates::{Crate, GitHubRepo};
 use errors::*;
 use ex::Experiment;
-use results::{ReadResults, TestResult};
+use results::{self, ReadResults, ResultOverride, TestCounts, TestResult};
 use serde_json;
+use server::api_types::CrateMetadata;
 use server::db::{Database, QueryUtils};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use toolchain::Toolchain;
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct TaskResult {
     #[serde(rename = "crate")]
     pub krate: Crate,
@@ -23,6 +25,145 @@ pub struct ProgressData {
     pub shas: Vec<(GitHubRepo, String)>,
 }
 
+/// Results of an experiment, as exported by one instance and imported by another one.
+#[derive(Serialize, Deserialize)]
+pub struct ResultsDump {
+    pub results: Vec<TaskResult>,
+}
+
+/// Result included in an export, along with the location of its log in the report.
+#[derive(Serialize)]
+pub struct ExportedResult {
+    #[serde(flatten)]
+    pub result: TaskResult,
+    /// Absolute URL if the report was published, `None` if the log is missing
+    pub log_url: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ResultsExport {
+    pub results: Vec<ExportedResult>,
+}
+
+/// Serialized crate used to store and look up its results. Build metadata in the version is
+/// ignored, so the results are found even if the crate was listed with different metadata.
+pub fn result_key(krate: &Crate) -> Result<String> {
+    Ok(serde_json::to_string(&krate.normalized())?)
+}
+
+/// Serialized test counts stored alongside a result. The log is parsed once when the result is
+/// stored, instead of every time a report is generated.
+pub fn test_counts_column(log: &[u8]) -> Result<Option<String>> {
+    match results::parse_test_counts(log) {
+        Some(counts) => Ok(Some(serde_json::to_string(&counts)?)),
+        None => Ok(None),
+    }
+}
+
+/// Load the results of the experiment produced by the agent.
+pub fn by_agent(
+    db: &Database,
+    experiment: &str,
+    agent: &str,
+) -> Result<Vec<(Crate, Toolchain, TestResult)>> {
+    let rows = db.query(
+        "SELECT crate, toolchain, result FROM results \
+         WHERE experiment = ?1 AND agent = ?2 \
+         ORDER BY crate, toolchain;",
+        &[&experiment, &agent],
+        |row| -> (String, String, String) {
+            (row.get("crate"), row.get("toolchain"), row.get("result"))
+        },
+    )?;
+
+    let mut results = Vec::new();
+    for (krate, toolchain, result) in rows {
+        results.push((
+            serde_json::from_str(&krate)?,
+            toolchain.parse()?,
+            result.parse()?,
+        ));
+    }
+
+    Ok(results)
+}
+
+/// Compute how many results per hour each agent recorded during the last `window`, across all
+/// the experiments. Agents without results in the window are not included.
+pub fn agent_throughput(db: &Database, window: Duration) -> Result<HashMap<String, f32>> {
+    if window <= Duration::zero() {
+        bail!("the throughput window must be positive");
+    }
+
+    let rows = db.query(
+        "SELECT agent, COUNT(*) AS count FROM results \
+         WHERE agent IS NOT NULL AND created_at >= ?1 \
+         GROUP BY agent;",
+        &[&(Utc::now() - window)],
+        |row| -> (String, i64) { (row.get("agent"), row.get("count")) },
+    )?;
+
+    let hours = window.num_seconds() as f32 / 3600.0;
+    Ok(rows
+        .into_iter()
+        .map(|(agent, count)| (agent, count as f32 / hours))
+        .collect())
+}
+
+/// Find the crates with inconsistent results for the same toolchain across reruns of the
+/// experiment, which likely means they're flaky.
+pub fn detect_flapping(db: &Database, experiment: &str) -> Result<Vec<Crate>> {
+    let crates = db.query(
+        "SELECT DISTINCT crate FROM ( \
+             SELECT crate FROM results_history WHERE experiment = ?1 \
+             GROUP BY crate, toolchain HAVING COUNT(DISTINCT result) > 1 \
+         ) ORDER BY crate;",
+        &[&experiment],
+        |row| -> String { row.get("crate") },
+    )?;
+
+    let mut result = Vec::new();
+    for krate in crates {
+        result.push(serde_json::from_str(&krate)?);
+    }
+    Ok(result)
+}
+
+/// Aggregate the errors of the failed builds in the experiment, returning the `limit` most common
+/// messages along with the number of crates hitting each one.
+pub fn top_errors(db: &Database, experiment: &str, limit: usize) -> Result<Vec<(String, u32)>> {
+    let rows = db.query(
+        "SELECT crate, result, log FROM results WHERE experiment = ?1;",
+        &[&experiment],
+        |row| -> (String, String, Vec<u8>) {
+            (row.get("crate"), row.get("result"), row.get("log"))
+        },
+    )?;
+
+    let mut crates_by_error = HashMap::new();
+    for (krate, result, log) in rows {
+        if !result.parse::<TestResult>()?.is_failure() {
+            continue;
+        }
+
+        if let Some(error) = results::primary_error(&log) {
+            crates_by_error
+                .entry(error)
+                .or_insert_with(HashSet::new)
+                .insert(krate);
+        }
+    }
+
+    let mut errors = crates_by_error
+        .into_iter()
+        .map(|(error, crates)| (error, crates.len() as u32))
+        .collect::<Vec<_>>();
+    errors.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    errors.truncate(limit);
+
+    Ok(errors)
+}
+
 pub struct ResultsDB<'a> {
     db: &'a Database,
 }
@@ -35,15 +176,36 @@ impl<'a> ResultsDB<'a> {
     pub fn store(&self, ex: &Experiment, data: &ProgressData) -> Result<()> {
         self.db.transaction(|trans| {
             for result in &data.results {
+                let log = base64::decode(&result.log).chain_err(|| "invalid base64 log provided")?;
+
+                // The result is tagged with the agent currently running the experiment
                 trans.execute(
-                    "INSERT INTO results (experiment, crate, toolchain, result, log) \
+                    "INSERT INTO results \
+                     (experiment, crate, toolchain, result, log, created_at, agent, test_counts) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, \
+                     (SELECT assigned_to FROM experiments WHERE name = ?1), ?7);",
+                    &[
+                        &ex.name,
+                        &result_key(&result.krate)?,
+                        &result.toolchain.to_string(),
+                        &result.result.to_str(),
+                        &log,
+                        &Utc::now(),
+                        &test_counts_column(&log)?,
+                    ],
+                )?;
+
+                // Results replaced by reruns are kept in the history
+                trans.execute(
+                    "INSERT INTO results_history \
+                     (experiment, crate, toolchain, result, created_at) \
                      VALUES (?1, ?2, ?3, ?4, ?5);",
                     &[
                         &ex.name,
-                        &serde_json::to_string(&result.krate)?,
+                        &result_key(&result.krate)?,
                         &result.toolchain.to_string(),
                         &result.result.to_str(),
-                        &base64::decode(&result.log).chain_err(|| "invalid base64 log provided")?,
+                        &Utc::now(),
                     ],
                 )?;
             }
@@ -58,6 +220,73 @@ impl<'a> ResultsDB<'a> {
             Ok(())
         })
     }
+
+    /// Store the metadata of a crate uploaded by an agent, replacing the one of previous runs.
+    pub fn store_metadata(&self, ex: &Experiment, data: &CrateMetadata) -> Result<()> {
+        self.db.execute(
+            "INSERT OR REPLACE INTO crate_metadata (experiment, crate, toolchain, metadata) \
+             VALUES (?1, ?2, ?3, ?4);",
+            &[
+                &ex.name,
+                &result_key(&data.krate)?,
+                &data.toolchain.to_string(),
+                &data.metadata,
+            ],
+        )
+    }
+
+    /// Load the most recent result of each crate and toolchain in the experiment. This should be
+    /// preferred over loading the results one by one when all of them are needed.
+    pub fn latest_per_crate(
+        &self,
+        ex: &Experiment,
+    ) -> Result<HashMap<(Crate, Toolchain), TestResult>> {
+        // SQLite returns the other columns from the row with the maximum value
+        let rows = self.db.query(
+            "SELECT crate, toolchain, result, MAX(created_at) FROM results \
+             WHERE experiment = ?1 \
+             GROUP BY crate, toolchain;",
+            &[&ex.name],
+            |row| -> (String, String, String) {
+                (row.get("crate"), row.get("toolchain"), row.get("result"))
+            },
+        )?;
+
+        let mut results = HashMap::new();
+        for (krate, toolchain, result) in rows {
+            results.insert(
+                (serde_json::from_str(&krate)?, toolchain.parse()?),
+                result.parse()?,
+            );
+        }
+
+        Ok(results)
+    }
+
+    /// Load the results shown in the report for each crate and toolchain in the experiment: the
+    /// most recent one, unless the reviewers overrode it.
+    pub fn final_per_crate(
+        &self,
+        ex: &Experiment,
+    ) -> Result<HashMap<(Crate, Toolchain), TestResult>> {
+        let mut results = self.latest_per_crate(ex)?;
+
+        let overrides = self.db.query(
+            "SELECT crate, toolchain, result FROM result_overrides WHERE experiment = ?1;",
+            &[&ex.name],
+            |row| -> (String, String, String) {
+                (row.get("crate"), row.get("toolchain"), row.get("result"))
+            },
+        )?;
+        for (krate, toolchain, result) in overrides {
+            results.insert(
+                (serde_json::from_str(&krate)?, toolchain.parse()?),
+                result.parse()?,
+            );
+        }
+
+        Ok(results)
+    }
 }
 
 impl<'a> ReadResults for ResultsDB<'a> {
@@ -94,7 +323,7 @@ impl<'a> ReadResults for ResultsDB<'a> {
             &[
                 &ex.name,
                 &toolchain.to_string(),
-                &serde_json::to_string(krate)?,
+                &result_key(krate)?,
             ],
             |row| row.get("log"),
         )
@@ -115,7 +344,7 @@ impl<'a> ReadResults for ResultsDB<'a> {
                 &[
                     &ex.name,
                     &toolchain.to_string(),
-                    &serde_json::to_string(krate)?,
+                    &result_key(krate)?,
                 ],
                 |row| row.get("result"),
             )?
@@ -127,19 +356,121 @@ impl<'a> ReadResults for ResultsDB<'a> {
             Ok(None)
         }
     }
+
+    fn load_result_override(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<ResultOverride>> {
+        let row: Option<(String, String)> = self.db.get_row(
+            "SELECT result, reason FROM result_overrides \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+             LIMIT 1;",
+            &[
+                &ex.name,
+                &toolchain.to_string(),
+                &result_key(krate)?,
+            ],
+            |row| (row.get("result"), row.get("reason")),
+        )?;
+
+        if let Some((result, reason)) = row {
+            Ok(Some(ResultOverride {
+                result: result.parse()?,
+                reason,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn load_test_counts(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<TestCounts>> {
+        let counts: Option<Option<String>> = self.db.get_row(
+            "SELECT test_counts FROM results \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3 \
+             LIMIT 1;",
+            &[
+                &ex.name,
+                &toolchain.to_string(),
+                &result_key(krate)?,
+            ],
+            |row| row.get("test_counts"),
+        )?;
+
+        if let Some(Some(counts)) = counts {
+            Ok(Some(serde_json::from_str(&counts)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn load_metadata(
+        &self,
+        ex: &Experiment,
+        toolchain: &Toolchain,
+        krate: &Crate,
+    ) -> Result<Option<String>> {
+        self.db.get_row(
+            "SELECT metadata FROM crate_metadata \
+             WHERE experiment = ?1 AND toolchain = ?2 AND crate = ?3;",
+            &[
+                &ex.name,
+                &toolchain.to_string(),
+                &result_key(krate)?,
+            ],
+            |row| row.get("metadata"),
+        )
+    }
+
+    fn load_all_results(&self, ex: &Experiment) -> Result<HashMap<(Crate, Toolchain), TestResult>> {
+        let rows: HashMap<(String, String), String> = self
+            .db
+            .query(
+                "SELECT crate, toolchain, result FROM results WHERE experiment = ?1;",
+                &[&ex.name],
+                |row| -> ((String, String), String) {
+                    ((row.get("crate"), row.get("toolchain")), row.get("result"))
+                },
+            )?
+            .into_iter()
+            .collect();
+
+        let mut results = HashMap::new();
+        for krate in &ex.crates {
+            let key = result_key(krate)?;
+            for tc in &ex.toolchains {
+                if let Some(res) = rows.get(&(key.clone(), tc.to_string())) {
+                    results.insert((krate.clone(), tc.clone()), res.parse()?);
+                }
+            }
+        }
+
+        Ok(results)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{ProgressData, ResultsDB, TaskResult};
+    use super::{
+        agent_throughput, by_agent, detect_flapping, result_key, top_errors, ProgressData,
+        ResultsDB, TaskResult,
+    };
     use base64;
+    use chrono::{Duration, Utc};
     use config::Config;
     use crates::{Crate, GitHubRepo, RegistryCrate};
-    use ex::{ExCapLints, ExCrateSelect, ExMode};
-    use results::{ReadResults, TestResult};
-    use server::db::Database;
+    use results::{ReadResults, ResultOverride, TestCounts, TestResult};
+    use server::actions::CreateExperiment;
+    use server::api_types::CrateMetadata;
+    use server::db::{Database, QueryUtils};
     use server::experiments::Experiments;
-    use toolchain::{MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
+    use toolchain::{Toolchain, MAIN_TOOLCHAIN, TEST_TOOLCHAIN};
 
     #[test]
     fn test_results_db() {
@@ -149,19 +480,7 @@ mod tests {
 
         // Create a dummy experiment to attach the results to
         experiments
-            .create(
-                "test",
-                &MAIN_TOOLCHAIN,
-                &TEST_TOOLCHAIN,
-                ExMode::BuildAndTest,
-                ExCrateSelect::Demo,
-                ExCapLints::Forbid,
-                &Config::default(),
-                None,
-                None,
-                None,
-                0,
-            )
+            .create(&CreateExperiment::dummy("test"), &Config::default())
             .unwrap();
         let ex = experiments.get("test").unwrap().unwrap().experiment;
 
@@ -170,17 +489,26 @@ mod tests {
             version: "1".into(),
         });
 
-        // Store a result and some SHAs
+        // Store some results and SHAs
+        let test_log = "test result: FAILED. 3 passed; 1 failed; 2 ignored";
         results
             .store(
                 &ex,
                 &ProgressData {
-                    results: vec![TaskResult {
-                        krate: krate.clone(),
-                        toolchain: MAIN_TOOLCHAIN.clone(),
-                        result: TestResult::TestPass,
-                        log: base64::encode("foo"),
-                    }],
+                    results: vec![
+                        TaskResult {
+                            krate: krate.clone(),
+                            toolchain: MAIN_TOOLCHAIN.clone(),
+                            result: TestResult::TestPass,
+                            log: base64::encode("foo"),
+                        },
+                        TaskResult {
+                            krate: krate.clone(),
+                            toolchain: TEST_TOOLCHAIN.clone(),
+                            result: TestResult::TestFail,
+                            log: base64::encode(test_log),
+                        },
+                    ],
                     shas: vec![
                         (
                             GitHubRepo {
@@ -211,5 +539,479 @@ mod tests {
                 .unwrap(),
             Some(TestResult::TestPass)
         );
+
+        // The test counts are parsed from the log when the result is stored
+        assert_eq!(
+            results
+                .load_test_counts(&ex, &MAIN_TOOLCHAIN, &krate)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            results
+                .load_test_counts(&ex, &TEST_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(TestCounts {
+                passed: 3,
+                failed: 1,
+                ignored: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn test_result_overrides() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        experiments
+            .create(&CreateExperiment::dummy("test"), &Config::default())
+            .unwrap();
+        let ex = experiments.get("test").unwrap().unwrap();
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+
+        results
+            .store(
+                &ex.experiment,
+                &ProgressData {
+                    results: vec![TaskResult {
+                        krate: krate.clone(),
+                        toolchain: TEST_TOOLCHAIN.clone(),
+                        result: TestResult::BuildFail,
+                        log: base64::encode("foo"),
+                    }],
+                    shas: vec![],
+                },
+            )
+            .unwrap();
+        assert!(
+            results
+                .load_result_override(&ex.experiment, &TEST_TOOLCHAIN, &krate)
+                .unwrap()
+                .is_none()
+        );
+
+        ex.override_result(
+            &db,
+            &krate,
+            &TEST_TOOLCHAIN,
+            TestResult::TestPass,
+            "spurious failure",
+        ).unwrap();
+
+        // The override is stored alongside the original result
+        assert_eq!(
+            results
+                .load_result_override(&ex.experiment, &TEST_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(ResultOverride {
+                result: TestResult::TestPass,
+                reason: "spurious failure".into(),
+            })
+        );
+        assert_eq!(
+            results
+                .load_test_result(&ex.experiment, &TEST_TOOLCHAIN, &krate)
+                .unwrap(),
+            Some(TestResult::BuildFail)
+        );
+
+        // The override replaces the original result when all of them are loaded
+        let key = (krate.normalized(), TEST_TOOLCHAIN.clone());
+        assert_eq!(
+            results.latest_per_crate(&ex.experiment).unwrap()[&key],
+            TestResult::BuildFail
+        );
+        assert_eq!(
+            results.final_per_crate(&ex.experiment).unwrap()[&key],
+            TestResult::TestPass
+        );
+    }
+
+    #[test]
+    fn test_crate_metadata() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        experiments
+            .create(
+                &CreateExperiment {
+                    capture_metadata: true,
+                    ..CreateExperiment::dummy("test")
+                },
+                &Config::default(),
+            )
+            .unwrap();
+        let ex = experiments.get("test").unwrap().unwrap().experiment;
+        assert!(ex.capture_metadata);
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+        let store = |metadata: &str| {
+            results
+                .store_metadata(
+                    &ex,
+                    &CrateMetadata {
+                        krate: krate.clone(),
+                        toolchain: MAIN_TOOLCHAIN.clone(),
+                        metadata: metadata.into(),
+                    },
+                )
+                .unwrap();
+        };
+
+        assert_eq!(
+            results.load_metadata(&ex, &MAIN_TOOLCHAIN, &krate).unwrap(),
+            None
+        );
+
+        // The metadata of a rerun replaces the previous one
+        store("{\"packages\":[]}");
+        store("{\"packages\":[1]}");
+        assert_eq!(
+            results.load_metadata(&ex, &MAIN_TOOLCHAIN, &krate).unwrap(),
+            Some("{\"packages\":[1]}".into())
+        );
+        assert_eq!(
+            results.load_metadata(&ex, &TEST_TOOLCHAIN, &krate).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_latest_per_crate() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        experiments
+            .create(&CreateExperiment::dummy("test"), &Config::default())
+            .unwrap();
+        let ex = experiments.get("test").unwrap().unwrap().experiment;
+
+        let krate = Crate::Registry(RegistryCrate {
+            name: "lazy_static".into(),
+            version: "1".into(),
+        });
+        let store = |toolchain: &Toolchain, result| {
+            results
+                .store(
+                    &ex,
+                    &ProgressData {
+                        results: vec![TaskResult {
+                            krate: krate.clone(),
+                            toolchain: toolchain.clone(),
+                            result,
+                            log: base64::encode("foo"),
+                        }],
+                        shas: vec![],
+                    },
+                )
+                .unwrap();
+        };
+
+        // Rerun the crate on the main toolchain, getting a different result
+        store(&MAIN_TOOLCHAIN, TestResult::BuildFail);
+        store(&TEST_TOOLCHAIN, TestResult::TestFail);
+        store(&MAIN_TOOLCHAIN, TestResult::TestPass);
+
+        let latest = results.latest_per_crate(&ex).unwrap();
+        assert_eq!(latest.len(), 2);
+        assert_eq!(
+            latest[&(krate.clone(), MAIN_TOOLCHAIN.clone())],
+            TestResult::TestPass
+        );
+        assert_eq!(
+            latest[&(krate.clone(), TEST_TOOLCHAIN.clone())],
+            TestResult::TestFail
+        );
+    }
+
+    #[test]
+    fn test_results_ignore_build_metadata() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        experiments
+            .create(&CreateExperiment::dummy("test"), &Config::default())
+            .unwrap();
+        let ex = experiments.get("test").unwrap().unwrap().experiment;
+
+        let krate = |version: &str| {
+            Crate::Registry(RegistryCrate {
+                name: "lazy_static".into(),
+                version: version.into(),
+            })
+        };
+
+        results
+            .store(
+                &ex,
+                &ProgressData {
+                    results: vec![TaskResult {
+                        krate: krate("1.0.0+build.1"),
+                        toolchain: MAIN_TOOLCHAIN.clone(),
+                        result: TestResult::TestPass,
+                        log: base64::encode("foo"),
+                    }],
+                    shas: Vec::new(),
+                },
+            )
+            .unwrap();
+
+        // Crates differing only in the build metadata share the same results
+        for version in &["1.0.0", "1.0.0+build.1", "1.0.0+build.2"] {
+            assert_eq!(
+                results
+                    .load_test_result(&ex, &MAIN_TOOLCHAIN, &krate(version))
+                    .unwrap(),
+                Some(TestResult::TestPass)
+            );
+        }
+        assert!(
+            results
+                .load_test_result(&ex, &MAIN_TOOLCHAIN, &krate("1.0.0-beta"))
+                .unwrap()
+                .is_none()
+        );
+
+        // Loading all the results at once maps them back to the crates of the experiment
+        let mut ex = ex;
+        ex.crates = vec![krate("1.0.0+build.2"), krate("1.0.0-beta")];
+        let all = results.load_all_results(&ex).unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(
+            all[&(krate("1.0.0+build.2"), MAIN_TOOLCHAIN.clone())],
+            TestResult::TestPass
+        );
+    }
+
+    #[test]
+    fn test_results_by_agent() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        experiments
+            .create(&CreateExperiment::dummy("test"), &Config::default())
+            .unwrap();
+        let mut ex = experiments.get("test").unwrap().unwrap();
+
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1".into(),
+            })
+        };
+
+        // Each result is tagged with the agent running the experiment when it was recorded
+        for &(agent, name) in &[("agent-1", "lazy_static"), ("agent-2", "bitflags")] {
+            ex.set_assigned_to(&db, Some(agent.into())).unwrap();
+            results
+                .store(
+                    &ex.experiment,
+                    &ProgressData {
+                        results: vec![TaskResult {
+                            krate: krate(name),
+                            toolchain: MAIN_TOOLCHAIN.clone(),
+                            result: TestResult::BuildFail,
+                            log: base64::encode("foo"),
+                        }],
+                        shas: Vec::new(),
+                    },
+                )
+                .unwrap();
+        }
+
+        assert_eq!(
+            by_agent(&db, "test", "agent-1").unwrap(),
+            vec![(
+                krate("lazy_static"),
+                MAIN_TOOLCHAIN.clone(),
+                TestResult::BuildFail
+            )]
+        );
+        assert_eq!(
+            by_agent(&db, "test", "agent-2").unwrap(),
+            vec![(krate("bitflags"), MAIN_TOOLCHAIN.clone(), TestResult::BuildFail)]
+        );
+        assert!(by_agent(&db, "test", "agent-3").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_agent_throughput() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        experiments
+            .create(&CreateExperiment::dummy("test"), &Config::default())
+            .unwrap();
+        let mut ex = experiments.get("test").unwrap().unwrap();
+
+        // agent-1 recorded four results in the last two hours, agent-2 one recent and one older
+        let seeded = &[
+            ("agent-1", "a", 10),
+            ("agent-1", "b", 30),
+            ("agent-1", "c", 60),
+            ("agent-1", "d", 110),
+            ("agent-2", "e", 20),
+            ("agent-2", "f", 200),
+        ];
+        for &(agent, name, minutes_ago) in seeded {
+            let krate = Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1".into(),
+            });
+            ex.set_assigned_to(&db, Some(agent.into())).unwrap();
+            results
+                .store(
+                    &ex.experiment,
+                    &ProgressData {
+                        results: vec![TaskResult {
+                            krate: krate.clone(),
+                            toolchain: MAIN_TOOLCHAIN.clone(),
+                            result: TestResult::TestPass,
+                            log: base64::encode("foo"),
+                        }],
+                        shas: Vec::new(),
+                    },
+                )
+                .unwrap();
+            db.execute(
+                "UPDATE results SET created_at = ?1 WHERE crate = ?2;",
+                &[
+                    &(Utc::now() - Duration::minutes(minutes_ago)),
+                    &result_key(&krate).unwrap(),
+                ],
+            ).unwrap();
+        }
+
+        let throughput = agent_throughput(&db, Duration::hours(2)).unwrap();
+        assert_eq!(throughput.len(), 2);
+        assert!((throughput["agent-1"] - 2.0).abs() < 0.001);
+        assert!((throughput["agent-2"] - 0.5).abs() < 0.001);
+
+        // Results older than the window are ignored
+        assert!(agent_throughput(&db, Duration::minutes(5)).unwrap().is_empty());
+        assert!(agent_throughput(&db, Duration::zero()).is_err());
+    }
+
+    #[test]
+    fn test_detect_flapping() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        experiments
+            .create(&CreateExperiment::dummy("test"), &Config::default())
+            .unwrap();
+        let ex = experiments.get("test").unwrap().unwrap().experiment;
+
+        let krate = |name: &str| {
+            Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1".into(),
+            })
+        };
+
+        // Both crates are run twice, but only one of them changes its result
+        let reruns = [
+            (TestResult::TestPass, TestResult::TestPass),
+            (TestResult::TestFail, TestResult::TestPass),
+        ];
+        for &(flaky, stable) in &reruns {
+            results
+                .store(
+                    &ex,
+                    &ProgressData {
+                        results: vec![
+                            TaskResult {
+                                krate: krate("flaky"),
+                                toolchain: MAIN_TOOLCHAIN.clone(),
+                                result: flaky,
+                                log: base64::encode("foo"),
+                            },
+                            TaskResult {
+                                krate: krate("stable"),
+                                toolchain: MAIN_TOOLCHAIN.clone(),
+                                result: stable,
+                                log: base64::encode("foo"),
+                            },
+                        ],
+                        shas: Vec::new(),
+                    },
+                )
+                .unwrap();
+        }
+
+        assert_eq!(detect_flapping(&db, "test").unwrap(), vec![krate("flaky")]);
+    }
+
+    #[test]
+    fn test_top_errors() {
+        let db = Database::temp().unwrap();
+        let experiments = Experiments::new(db.clone());
+        let results = ResultsDB::new(&db);
+
+        experiments
+            .create(&CreateExperiment::dummy("test"), &Config::default())
+            .unwrap();
+        let ex = experiments.get("test").unwrap().unwrap().experiment;
+
+        let task = |name: &str, toolchain: &Toolchain, result, log: &str| TaskResult {
+            krate: Crate::Registry(RegistryCrate {
+                name: name.into(),
+                version: "1".into(),
+            }),
+            toolchain: toolchain.clone(),
+            result,
+            log: base64::encode(log),
+        };
+
+        let missing = "[stderr] error[E0425]: cannot find value `foo` in this scope\n\
+                       [stderr]  --> src/lib.rs:3:5\n\
+                       [stderr] error: aborting due to previous error\n";
+        let unreadable = "error: couldn't read /source/src/main.rs: No such file or directory\n";
+        results
+            .store(
+                &ex,
+                &ProgressData {
+                    results: vec![
+                        // The same crate failing with both toolchains is only counted once
+                        task("a", &MAIN_TOOLCHAIN, TestResult::BuildFail, missing),
+                        task("a", &TEST_TOOLCHAIN, TestResult::BuildFail, missing),
+                        task("b", &MAIN_TOOLCHAIN, TestResult::TestFail, missing),
+                        task("c", &MAIN_TOOLCHAIN, TestResult::BuildFail, unreadable),
+                        // Errors in the logs of passing builds are ignored
+                        task("d", &MAIN_TOOLCHAIN, TestResult::TestPass, unreadable),
+                        task("e", &MAIN_TOOLCHAIN, TestResult::BuildFail, "no errors"),
+                    ],
+                    shas: Vec::new(),
+                },
+            )
+            .unwrap();
+
+        let expected = vec![
+            (
+                "error[E0425]: cannot find value `foo` in this scope".to_string(),
+                2,
+            ),
+            (
+                "error: couldn't read <path>: No such file or directory".to_string(),
+                1,
+            ),
+        ];
+        assert_eq!(top_errors(&db, "test", 10).unwrap(), expected);
+        assert_eq!(top_errors(&db, "test", 1).unwrap(), expected[..1].to_vec());
     }
 }
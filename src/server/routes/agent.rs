@@ -1,13 +1,17 @@
 use errors::*;
 use http::{Response, StatusCode};
 use hyper::Body;
-use server::api_types::{AgentConfig, ApiResponse};
+use server::api_types::{AgentConfig, ApiResponse, CrateAttempt, CrateMetadata, RunningLogTail};
 use server::auth::{auth_filter, AuthDetails, TokenType};
-use server::experiments::Status;
+use server::experiments::{ExperimentData, Status};
 use server::messages::Message;
 use server::results::{ProgressData, ResultsDB};
 use server::Data;
 use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use toolchain::ToolchainKind;
+use util;
 use warp::{self, Filter, Rejection};
 
 pub fn routes(
@@ -30,6 +34,13 @@ pub fn routes(
         .and(auth_filter(data.clone(), TokenType::Agent))
         .map(endpoint_next_experiment);
 
+    let registry_token = warp::get2()
+        .and(warp::path("registry-token"))
+        .and(warp::path::index())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .map(endpoint_registry_token);
+
     let complete_experiment = warp::post2()
         .and(warp::path("complete-experiment"))
         .and(warp::path::index())
@@ -45,6 +56,22 @@ pub fn routes(
         .and(auth_filter(data.clone(), TokenType::Agent))
         .map(endpoint_record_progress);
 
+    let use_attempt = warp::post2()
+        .and(warp::path("use-attempt"))
+        .and(warp::path::index())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .map(endpoint_use_attempt);
+
+    let record_metadata = warp::post2()
+        .and(warp::path("record-metadata"))
+        .and(warp::path::index())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .map(endpoint_record_metadata);
+
     let heartbeat = warp::post2()
         .and(warp::path("heartbeat"))
         .and(warp::path::index())
@@ -52,16 +79,42 @@ pub fn routes(
         .and(auth_filter(data.clone(), TokenType::Agent))
         .map(endpoint_heartbeat);
 
+    let log_tails = warp::post2()
+        .and(warp::path("log-tails"))
+        .and(warp::path::index())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .map(endpoint_log_tails);
+
+    let toolchain_kinds = warp::post2()
+        .and(warp::path("toolchain-kinds"))
+        .and(warp::path::index())
+        .and(warp::body::json())
+        .and(data_filter.clone())
+        .and(auth_filter(data.clone(), TokenType::Agent))
+        .map(endpoint_toolchain_kinds);
+
     warp::any()
         .and(
             config
                 .or(next_experiment)
                 .unify()
+                .or(registry_token)
+                .unify()
                 .or(complete_experiment)
                 .unify()
                 .or(record_progress)
                 .unify()
+                .or(use_attempt)
+                .unify()
+                .or(record_metadata)
+                .unify()
                 .or(heartbeat)
+                .unify()
+                .or(log_tails)
+                .unify()
+                .or(toolchain_kinds)
                 .unify(),
         )
         .map(handle_results)
@@ -105,6 +158,23 @@ fn endpoint_next_experiment(data: Arc<Data>, auth: AuthDetails) -> Result<Respon
     Ok(ApiResponse::Success { result }.into_response()?)
 }
 
+/// Token of the custom registry used by the experiment run by the agent. It's not included in the
+/// experiment itself, since that's stored by the agents and published with the report.
+fn endpoint_registry_token(data: Arc<Data>, auth: AuthDetails) -> Result<Response<Body>> {
+    let ex = data
+        .experiments
+        .run_by_agent(&auth.name)?
+        .ok_or("no experiment run by this agent")?;
+
+    let token = ex
+        .experiment
+        .registry
+        .as_ref()
+        .and_then(|registry| data.tokens.registries.get(registry))
+        .map(|token| token.expose());
+    Ok(ApiResponse::Success { result: token }.into_response()?)
+}
+
 fn endpoint_complete_experiment(data: Arc<Data>, auth: AuthDetails) -> Result<Response<Body>> {
     let mut ex = data
         .experiments
@@ -112,6 +182,9 @@ fn endpoint_complete_experiment(data: Arc<Data>, auth: AuthDetails) -> Result<Re
         .ok_or("no experiment run by this agent")?;
 
     ex.set_status(&data.db, Status::NeedsReport)?;
+    if let Some(ref limiter) = data.progress_limiter {
+        limiter.forget(&ex.experiment.name);
+    }
     info!(
         "experiment {} completed, marked as needs-report",
         ex.experiment.name
@@ -139,6 +212,90 @@ fn endpoint_record_progress(
     let db = ResultsDB::new(&data.db);
     db.store(&experiment.experiment, &result)?;
 
+    // Computing the progress and posting it on GitHub is done in the background, to avoid slowing
+    // down the agent. The results are already stored, so failing to post it is only logged.
+    if should_post_progress(&experiment, &data) {
+        let data = data.clone();
+        thread::spawn(move || {
+            if let Err(err) = post_progress(experiment, &data) {
+                util::report_error(&err);
+            }
+        });
+    }
+
+    Ok(ApiResponse::Success { result: true }.into_response()?)
+}
+
+fn should_post_progress(ex: &ExperimentData, data: &Data) -> bool {
+    match (&data.progress_limiter, &ex.server_data.github_issue) {
+        (&Some(ref limiter), &Some(_)) => {
+            limiter.should_update(&ex.experiment.name, Instant::now())
+        }
+        _ => false,
+    }
+}
+
+/// Report the progress of the experiment on its GitHub issue, editing the comment posted the
+/// previous time instead of adding a new one.
+fn post_progress(mut ex: ExperimentData, data: &Data) -> Result<()> {
+    let issue_url = match ex.server_data.github_issue {
+        Some(ref github_issue) => github_issue.api_url.clone(),
+        None => return Ok(()),
+    };
+
+    let comment_url = Message::new()
+        .line(
+            "hourglass",
+            format!(
+                "Experiment **`{}`** is **{}%** done.",
+                ex.experiment.name,
+                ex.progress(&data.db)?,
+            ),
+        )
+        .send_or_edit(
+            &issue_url,
+            ex.server_data.progress_comment.as_ref().map(|url| url.as_str()),
+            data,
+        )?;
+
+    if ex.server_data.progress_comment.as_ref() != Some(&comment_url) {
+        ex.set_progress_comment(&data.db, &comment_url)?;
+    }
+
+    Ok(())
+}
+
+/// Record a new attempt of a step of a crate, which the agent must not execute if the budget of
+/// attempts was already exhausted. The attempts are stored with the experiment, so they're kept
+/// when the agent restarts the run.
+fn endpoint_use_attempt(
+    attempt: CrateAttempt,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Result<Response<Body>> {
+    let ex = data
+        .experiments
+        .run_by_agent(&auth.name)?
+        .ok_or("no experiment run by this agent")?;
+
+    let result = ex.use_attempt(&data.db, &attempt.krate, attempt.step, attempt.budget)?;
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn endpoint_record_metadata(
+    metadata: CrateMetadata,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Result<Response<Body>> {
+    let ex = data
+        .experiments
+        .run_by_agent(&auth.name)?
+        .ok_or("no experiment run by this agent")?;
+    if !ex.experiment.capture_metadata {
+        bail!("the experiment doesn't capture the metadata of the crates");
+    }
+
+    ResultsDB::new(&data.db).store_metadata(&ex.experiment, &metadata)?;
     Ok(ApiResponse::Success { result: true }.into_response()?)
 }
 
@@ -151,6 +308,29 @@ fn endpoint_heartbeat(data: Arc<Data>, auth: AuthDetails) -> Result<Response<Bod
     Ok(ApiResponse::Success { result: true }.into_response()?)
 }
 
+fn endpoint_log_tails(
+    tails: Vec<RunningLogTail>,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Result<Response<Body>> {
+    let ex = data.experiments.run_by_agent(&auth.name)?;
+    data.log_tails.update(
+        &auth.name,
+        ex.as_ref().map(|ex| ex.experiment.name.as_str()),
+        tails,
+    );
+    Ok(ApiResponse::Success { result: true }.into_response()?)
+}
+
+fn endpoint_toolchain_kinds(
+    kinds: Vec<ToolchainKind>,
+    data: Arc<Data>,
+    auth: AuthDetails,
+) -> Result<Response<Body>> {
+    data.agents.set_toolchain_kinds(&auth.name, &kinds)?;
+    Ok(ApiResponse::Success { result: true }.into_response()?)
+}
+
 fn handle_results(resp: Result<Response<Body>>) -> Response<Body> {
     match resp {
         Ok(resp) => resp,
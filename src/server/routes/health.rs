@@ -0,0 +1,38 @@
+use http::{Response, StatusCode};
+use hyper::Body;
+use server::api_types::ApiResponse;
+use server::health::{self, HealthStatus};
+use server::Data;
+use std::sync::Arc;
+use warp::{self, Filter, Rejection};
+
+pub fn routes(
+    data: Arc<Data>,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+    let data_filter = warp::any().map(move || data.clone());
+
+    warp::get2()
+        .and(warp::path::index())
+        .and(data_filter)
+        .map(endpoint_health)
+}
+
+fn endpoint_health(data: Arc<Data>) -> Response<Body> {
+    let report = match health::check(&data.db) {
+        Ok(report) => report,
+        Err(err) => {
+            return ApiResponse::internal_error(err.to_string())
+                .into_response()
+                .unwrap()
+        }
+    };
+
+    let down = report.status == HealthStatus::Down;
+    let mut resp = ApiResponse::Success { result: report }
+        .into_response()
+        .unwrap();
+    if down {
+        *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+    resp
+}
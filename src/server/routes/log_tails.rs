@@ -0,0 +1,40 @@
+use errors::*;
+use http::Response;
+use hyper::Body;
+use server::api_types::ApiResponse;
+use server::Data;
+use std::sync::Arc;
+use warp::{self, Filter, Rejection};
+
+pub fn routes(
+    data: Arc<Data>,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+    let data_filter = warp::any().map(move || data.clone());
+
+    warp::get2()
+        .and(warp::path::param())
+        .and(warp::path::index())
+        .and(data_filter)
+        .map(endpoint_log_tails)
+        .map(handle_results)
+}
+
+/// Latest lines logged by the tasks of the experiment currently running on the agents, as of their
+/// last upload.
+fn endpoint_log_tails(name: String, data: Arc<Data>) -> Result<Response<Body>> {
+    if !data.experiments.exists(&name)? {
+        return ApiResponse::not_found().into_response();
+    }
+
+    let result = data.log_tails.for_experiment(&name);
+    Ok(ApiResponse::Success { result }.into_response()?)
+}
+
+fn handle_results(resp: Result<Response<Body>>) -> Response<Body> {
+    match resp {
+        Ok(resp) => resp,
+        Err(err) => ApiResponse::internal_error(err.to_string())
+            .into_response()
+            .unwrap(),
+    }
+}
@@ -0,0 +1,39 @@
+use http::header::{HeaderValue, CONTENT_TYPE};
+use http::{Response, StatusCode};
+use hyper::Body;
+use server::metrics;
+use server::Data;
+use std::sync::Arc;
+use util;
+use warp::{self, Filter, Rejection};
+
+pub fn routes(
+    data: Arc<Data>,
+) -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+    let data_filter = warp::any().map(move || data.clone());
+
+    warp::get2()
+        .and(warp::path::index())
+        .and(data_filter)
+        .map(endpoint_metrics)
+}
+
+fn endpoint_metrics(data: Arc<Data>) -> Response<Body> {
+    match metrics::gather(&data.db) {
+        Ok(metrics) => {
+            let mut resp = Response::new(metrics.into());
+            resp.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; version=0.0.4"),
+            );
+            resp
+        }
+        Err(err) => {
+            util::report_error(&err);
+
+            let mut resp = Response::new("failed to gather the metrics".into());
+            *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            resp
+        }
+    }
+}
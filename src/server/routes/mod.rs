@@ -1,3 +1,6 @@
 pub mod agent;
+pub mod health;
+pub mod log_tails;
+pub mod metrics;
 pub mod ui;
 pub mod webhooks;
@@ -29,6 +29,7 @@ impl ExperimentData {
             Status::GeneratingReport => ("orange", "Generating report"),
             Status::ReportFailed => ("red", "Report failed"),
             Status::Completed => ("green", "Completed"),
+            Status::Aborted => ("red", "Aborted"),
         };
 
         Ok(ExperimentData {
@@ -40,6 +41,7 @@ impl ExperimentData {
                 ExMode::BuildOnly => "cargo build",
                 ExMode::CheckOnly => "cargo check",
                 ExMode::UnstableFeatures => "unstable features",
+                ExMode::Clippy => "cargo clippy",
             },
             assigned_to: experiment.server_data.assigned_to.clone(),
             priority: experiment.server_data.priority,
@@ -62,8 +64,10 @@ pub fn endpoint_queue(data: Arc<Data>) -> Result<Response<Body>> {
     let mut report_failed = Vec::new();
 
     for experiment in data.experiments.all()? {
-        // Don't include completed experiments in the queue
-        if experiment.server_data.status == Status::Completed {
+        // Don't include completed or aborted experiments in the queue
+        if experiment.server_data.status == Status::Completed
+            || experiment.server_data.status == Status::Aborted
+        {
             continue;
         }
 
@@ -75,7 +79,7 @@ pub fn endpoint_queue(data: Arc<Data>) -> Result<Response<Body>> {
             Status::NeedsReport => needs_report.push(ex),
             Status::GeneratingReport => generating_report.push(ex),
             Status::ReportFailed => report_failed.push(ex),
-            Status::Completed => unreachable!(),
+            Status::Completed | Status::Aborted => unreachable!(),
         };
     }
 
@@ -11,6 +11,11 @@ use warp::{self, Filter, Rejection};
 mod agents;
 mod experiments;
 
+/// Error page shown when even the 500 template can't be rendered.
+const FALLBACK_500: &str = "<!DOCTYPE html>\n\
+<html><head><title>500</title></head>\n\
+<body><p><b>500</b> Internal server error</p></body></html>\n";
+
 #[derive(Serialize)]
 struct LayoutContext {
     git_revision: Option<&'static str>,
@@ -89,12 +94,12 @@ struct ErrorContext {
 }
 
 fn error_404() -> Result<Response<Body>> {
-    let mut resp = render_template(
+    let mut resp = html_response(assets::render_template(
         "404.html",
         &ErrorContext {
             layout: LayoutContext::new(),
         },
-    )?;
+    )?);
 
     *resp.status_mut() = StatusCode::NOT_FOUND;
     Ok(resp)
@@ -102,17 +107,17 @@ fn error_404() -> Result<Response<Body>> {
 
 fn error_500() -> Response<Body> {
     // Ensure the 500 error page always renders
-    let mut resp = match render_template(
+    let mut resp = match assets::render_template(
         "500.html",
         &ErrorContext {
             layout: LayoutContext::new(),
         },
     ) {
-        Ok(resp) => resp,
+        Ok(content) => html_response(content),
         Err(err) => {
             error!("failed to render 500 error page!");
             ::util::report_error(&err);
-            Response::new("500: Internal Server Error\n".into())
+            html_response(FALLBACK_500.into())
         }
     };
 
@@ -153,8 +158,40 @@ fn handle_errors(err: Rejection) -> ::std::result::Result<Response<Body>, Reject
 }
 
 fn render_template<C: Serialize>(name: &str, context: &C) -> Result<Response<Body>> {
-    let mut resp = Response::new(assets::render_template(name, context)?.into());
+    // A broken template shows the error page instead of failing the whole request
+    match assets::render_template(name, context) {
+        Ok(content) => Ok(html_response(content)),
+        Err(err) => {
+            error!("failed to render template {}", name);
+            ::util::report_error(&err);
+            Ok(error_500())
+        }
+    }
+}
+
+fn html_response(content: String) -> Response<Body> {
+    let mut resp = Response::new(content.into());
     resp.headers_mut()
         .insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
-    Ok(resp)
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_template;
+    use futures::{Future, Stream};
+    use http::StatusCode;
+
+    #[derive(Serialize)]
+    struct EmptyContext {}
+
+    #[test]
+    fn test_render_template_fallback() {
+        let resp = render_template("missing.html", &EmptyContext {}).unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body = resp.into_body().concat2().wait().unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("Internal server error"));
+    }
 }
@@ -1,4 +1,5 @@
-use ex::{ExCapLints, ExCrateSelect, ExMode};
+use crates::{CrateRequirements, CrateSelector, PriorRunSelector, RustVersion};
+use ex::{ExCapLints, ExComponents, ExCrateSelect, ExMode, ExSteps};
 use toolchain::Toolchain;
 
 macro_rules! generate_parser {
@@ -16,6 +17,13 @@ macro_rules! generate_parser {
             pub struct $var_struct {
                 $(pub $flag: $type,)*
             }
+
+            impl $var_struct {
+                #[allow(dead_code)]
+                pub fn keys() -> &'static [&'static str] {
+                    &[$($name,)*]
+                }
+            }
         )*
 
         #[cfg_attr(test, derive(Debug, PartialEq))]
@@ -93,8 +101,25 @@ generate_parser!(pub enum Command {
         end: Option<Toolchain> = "end",
         mode: Option<ExMode> = "mode",
         crates: Option<ExCrateSelect> = "crates",
+        seed: Option<u64> = "seed",
+        select: Option<CrateSelector> = "select",
+        prior: Option<PriorRunSelector> = "prior",
         cap_lints: Option<ExCapLints> = "cap-lints",
         priority: Option<i32> = "p",
+        registry: Option<String> = "registry",
+        index_commit: Option<String> = "index-commit",
+        baseline: Option<String> = "baseline",
+        allow_same_toolchains: Option<bool> = "allow-same-toolchains",
+        force_steps: Option<ExSteps> = "force-steps",
+        pre_build: Option<String> = "pre-build",
+        skip_yanked: Option<bool> = "skip-yanked",
+        min_rust_version: Option<RustVersion> = "min-rust-version",
+        include_missing_rust_version: Option<bool> = "include-missing-rust-version",
+        crate_versions: Option<CrateRequirements> = "crate-versions",
+        control_index: Option<usize> = "control-index",
+        capture_metadata: Option<bool> = "capture-metadata",
+        components: Option<ExComponents> = "components",
+        preset: Option<String> = "preset",
     })
 
     "abort" => Abort(AbortArgs {
@@ -109,14 +134,27 @@ generate_parser!(pub enum Command {
 
     "reload-acl" => ReloadACL(ReloadACLArgs {})
 
+    "save-preset" => SavePreset(SavePresetArgs {
+        name: Option<String> = "name",
+        start: Option<Toolchain> = "start",
+        end: Option<Toolchain> = "end",
+        mode: Option<ExMode> = "mode",
+        crates: Option<ExCrateSelect> = "crates",
+        select: Option<CrateSelector> = "select",
+        cap_lints: Option<ExCapLints> = "cap-lints",
+        priority: Option<i32> = "p",
+    })
+
     _ => Edit(EditArgs {
         name: Option<String> = "name",
         start: Option<Toolchain> = "start",
         end: Option<Toolchain> = "end",
         mode: Option<ExMode> = "mode",
         crates: Option<ExCrateSelect> = "crates",
+        crate_versions: Option<CrateRequirements> = "crate-versions",
         cap_lints: Option<ExCapLints> = "cap-lints",
         priority: Option<i32> = "p",
+        registry: Option<String> = "registry",
     })
 });
 
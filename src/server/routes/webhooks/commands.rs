@@ -1,10 +1,14 @@
 use errors::*;
-use ex::{self, ExCapLints, ExCrateSelect, ExMode};
+use ex::{self, ExCrateSelect};
+use server::actions::CreateExperiment;
 use server::db::{Database, QueryUtils};
-use server::experiments::Status;
+use server::experiments::{GitHubIssue, Status};
 use server::github::Issue;
 use server::messages::{Label, Message};
-use server::routes::webhooks::args::{AbortArgs, EditArgs, RetryReportArgs, RunArgs};
+use server::presets::{ExperimentPreset, Presets};
+use server::routes::webhooks::args::{
+    AbortArgs, EditArgs, RetryReportArgs, RunArgs, SavePresetArgs,
+};
 use server::Data;
 
 pub fn ping(data: &Data, issue: &Issue) -> Result<()> {
@@ -16,31 +20,36 @@ pub fn ping(data: &Data, issue: &Issue) -> Result<()> {
 }
 
 pub fn run(data: &Data, issue: &Issue, args: RunArgs) -> Result<()> {
-    let name = get_name(&data.db, issue, args.name)?;
+    let mut spec = if let Some(preset) = args.preset.clone() {
+        CreateExperiment::from_preset(&data.db, &preset, args)?
+    } else {
+        CreateExperiment::from_args(args)?
+    };
+    let name = get_name(&data.db, issue, spec.name.take())?;
 
     if data.experiments.exists(&name)? {
         bail!("an experiment named **`{}`** already exists!", name);
     }
 
-    data.experiments.create(
-        &name,
-        &args.start.ok_or_else(|| "missing start toolchain")?,
-        &args.end.ok_or_else(|| "missing end toolchain")?,
-        args.mode.unwrap_or(ExMode::BuildAndTest),
-        args.crates.unwrap_or(ExCrateSelect::Full),
-        args.cap_lints.unwrap_or(ExCapLints::Forbid),
-        &data.config,
-        Some(&issue.url),
-        Some(&issue.html_url),
-        Some(issue.number),
-        args.priority.unwrap_or(0),
-    )?;
-
-    Message::new()
-        .line(
-            "ok_hand",
-            format!("Experiment **`{}`** created and queued.", name),
-        )
+    spec.name = Some(name.clone());
+    spec.github_issue = Some(GitHubIssue {
+        api_url: issue.url.clone(),
+        html_url: issue.html_url.clone(),
+        number: issue.number,
+    });
+    data.experiments.create(&spec, &data.config)?;
+
+    let mut message = Message::new().line(
+        "ok_hand",
+        format!("Experiment **`{}`** created and queued.", name),
+    );
+    if let Some(position) = data.experiments.queue_position(&name)? {
+        message = message.line(
+            "hourglass",
+            format!("It's number **{}** in the queue.", position),
+        );
+    }
+    message
         .set_label(Label::ExperimentQueued)
         .send(&issue.url, data)?;
 
@@ -58,6 +67,9 @@ pub fn edit(data: &Data, issue: &Issue, args: EditArgs) -> Result<()> {
         let mut changed = false;
 
         if let Some(start) = args.start {
+            if let Some(ref baseline) = experiment.experiment.baseline {
+                data.experiments.check_baseline(baseline, &start)?;
+            }
             experiment.set_start_toolchain(&data.db, start)?;
             changed = true;
         }
@@ -74,14 +86,39 @@ pub fn edit(data: &Data, issue: &Issue, args: EditArgs) -> Result<()> {
             changed = true;
         }
         if let Some(crates) = args.crates {
-            let crates = ex::get_crates(crates, &data.config)?;
-            experiment.set_crates(&data.db, &data.config, crates)?;
+            // Reuse the existing seed, so random samples stay reproducible after edits
+            let seed = experiment
+                .server_data
+                .sample_seed
+                .unwrap_or_else(::rand::random);
+            let list = ex::get_crates(crates, &data.config, seed)?;
+            experiment.set_crates(&data.db, &data.config, list)?;
+            // Don't keep the seed of a previous random sample around after switching to a list
+            let seed = if crates == ExCrateSelect::SmallRandom {
+                Some(seed)
+            } else {
+                None
+            };
+            experiment.set_sample_seed(&data.db, seed)?;
+            changed = true;
+        }
+        if let Some(versions) = args.crate_versions {
+            let list = ::registry::apply_requirements(
+                &::registry::existing_index_dir()?,
+                experiment.experiment.crates.clone(),
+                &versions.0,
+            )?;
+            experiment.set_crates(&data.db, &data.config, list)?;
             changed = true;
         }
         if let Some(priority) = args.priority {
             experiment.set_priority(&data.db, priority)?;
             changed = true;
         }
+        if let Some(registry) = args.registry {
+            experiment.set_registry(&data.db, Some(registry))?;
+            changed = true;
+        }
 
         if changed {
             Message::new()
@@ -157,6 +194,17 @@ pub fn reload_acl(data: &Data, issue: &Issue) -> Result<()> {
     Ok(())
 }
 
+pub fn save_preset(data: &Data, issue: &Issue, args: SavePresetArgs) -> Result<()> {
+    let preset = ExperimentPreset::from_args(args)?;
+    Presets::new(data.db.clone()).save(&preset)?;
+
+    Message::new()
+        .line("floppy_disk", format!("Preset **`{}`** saved!", preset.name))
+        .send(&issue.url, data)?;
+
+    Ok(())
+}
+
 fn get_name(db: &Database, issue: &Issue, name: Option<String>) -> Result<String> {
     if let Some(name) = name {
         store_experiment_name(db, issue, &name)?;
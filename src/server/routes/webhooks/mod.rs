@@ -1,4 +1,4 @@
-mod args;
+pub mod args;
 mod commands;
 
 use bytes::buf::Buf;
@@ -102,6 +102,10 @@ fn process_command(sender: &str, body: &str, issue: &Issue, data: &Data) -> Resu
             Command::ReloadACL(_) => {
                 commands::reload_acl(data, issue)?;
             }
+
+            Command::SavePreset(args) => {
+                commands::save_preset(data, issue, args)?;
+            }
         }
 
         break;
@@ -5,6 +5,7 @@ use rusoto_core::Region;
 use rusoto_core::{AwsCredentials, CredentialsError, ProvideAwsCredentials};
 use std::collections::HashMap;
 use std::path::Path;
+use util::Secret;
 
 static TOKENS_PATH: &'static str = "tokens.toml";
 
@@ -65,6 +66,10 @@ pub struct Tokens {
     pub bot: BotTokens,
     pub reports_bucket: ReportsBucket,
     pub agents: HashMap<String, String>,
+    /// Tokens used to authenticate with the custom registries, keyed by the URL of their index.
+    /// They're only sent to the agents running an experiment using that registry.
+    #[serde(default)]
+    pub registries: HashMap<String, Secret>,
 }
 
 #[cfg(test)]
@@ -85,6 +90,7 @@ impl Default for Tokens {
                 secret_key: String::new(),
             },
             agents: HashMap::new(),
+            registries: HashMap::new(),
         }
     }
 }
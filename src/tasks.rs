@@ -1,7 +1,7 @@
 use config::Config;
 use crates::{self, Crate};
 use errors::*;
-use ex::{self, Experiment};
+use ex::{self, ExStep, Experiment};
 use ex_run;
 use git;
 use results::{TestResult, WriteResults};
@@ -11,17 +11,22 @@ use util;
 
 pub enum TaskStep {
     Prepare,
-    BuildAndTest { tc: Toolchain, quiet: bool },
+    BuildAndTest {
+        tc: Toolchain,
+        quiet: bool,
+        test_args: Vec<String>,
+    },
     BuildOnly { tc: Toolchain, quiet: bool },
     CheckOnly { tc: Toolchain, quiet: bool },
     UnstableFeatures { tc: Toolchain },
+    Clippy { tc: Toolchain, quiet: bool },
 }
 
 impl fmt::Debug for TaskStep {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             TaskStep::Prepare => write!(f, "prepare")?,
-            TaskStep::BuildAndTest { ref tc, quiet } => {
+            TaskStep::BuildAndTest { ref tc, quiet, .. } => {
                 write!(f, "build and test {}", tc.to_string())?;
                 if quiet {
                     write!(f, " (quiet)")?;
@@ -42,11 +47,53 @@ impl fmt::Debug for TaskStep {
             TaskStep::UnstableFeatures { ref tc } => {
                 write!(f, "find unstable features on {}", tc.to_string())?;
             }
+            TaskStep::Clippy { ref tc, quiet } => {
+                write!(f, "clippy {}", tc.to_string())?;
+                if quiet {
+                    write!(f, " (quiet)")?;
+                }
+            }
         }
         Ok(())
     }
 }
 
+impl TaskStep {
+    pub fn kind(&self) -> ExStep {
+        match *self {
+            TaskStep::Prepare => ExStep::Prepare,
+            TaskStep::BuildAndTest { .. } => ExStep::BuildAndTest,
+            TaskStep::BuildOnly { .. } => ExStep::BuildOnly,
+            TaskStep::CheckOnly { .. } => ExStep::CheckOnly,
+            TaskStep::UnstableFeatures { .. } => ExStep::UnstableFeatures,
+            TaskStep::Clippy { .. } => ExStep::Clippy,
+        }
+    }
+
+    pub fn toolchain(&self) -> Option<&Toolchain> {
+        match *self {
+            TaskStep::Prepare => None,
+            TaskStep::BuildAndTest { ref tc, .. }
+            | TaskStep::BuildOnly { ref tc, .. }
+            | TaskStep::CheckOnly { ref tc, .. }
+            | TaskStep::UnstableFeatures { ref tc }
+            | TaskStep::Clippy { ref tc, .. } => Some(tc),
+        }
+    }
+
+    /// Result recorded when the step is interrupted by a timeout.
+    pub fn timeout_result(&self) -> TestResult {
+        match *self {
+            TaskStep::BuildAndTest { .. } => TestResult::TestTimeout,
+            TaskStep::Prepare
+            | TaskStep::BuildOnly { .. }
+            | TaskStep::CheckOnly { .. }
+            | TaskStep::UnstableFeatures { .. }
+            | TaskStep::Clippy { .. } => TestResult::BuildTimeout,
+        }
+    }
+}
+
 pub struct Task {
     pub krate: Crate,
     pub step: TaskStep,
@@ -59,7 +106,16 @@ impl fmt::Debug for Task {
 }
 
 impl Task {
+    fn is_forced(&self, ex: &Experiment) -> bool {
+        ex.force_steps.contains(&self.step.kind())
+    }
+
     pub fn needs_exec<DB: WriteResults>(&self, ex: &Experiment, db: &DB) -> bool {
+        // Forced steps are always executed, even if their results are already present
+        if self.is_forced(ex) {
+            return true;
+        }
+
         // If an error happens while checking if the task should be executed, the error is ignored
         // and the function returns true.
         match self.step {
@@ -71,7 +127,8 @@ impl Task {
             TaskStep::BuildAndTest { ref tc, .. }
             | TaskStep::BuildOnly { ref tc, .. }
             | TaskStep::CheckOnly { ref tc, .. }
-            | TaskStep::UnstableFeatures { ref tc } => {
+            | TaskStep::UnstableFeatures { ref tc }
+            | TaskStep::Clippy { ref tc, .. } => {
                 db.get_result(ex, tc, &self.krate).unwrap_or(None).is_none()
             }
         }
@@ -89,7 +146,8 @@ impl Task {
             TaskStep::BuildAndTest { ref tc, .. }
             | TaskStep::BuildOnly { ref tc, .. }
             | TaskStep::CheckOnly { ref tc, .. }
-            | TaskStep::UnstableFeatures { ref tc } => {
+            | TaskStep::UnstableFeatures { ref tc }
+            | TaskStep::Clippy { ref tc, .. } => {
                 db.record_result(ex, tc, &self.krate, || {
                     error!("this task or one of its parent failed!");
                     util::report_error(err);
@@ -104,12 +162,15 @@ impl Task {
     pub fn run<DB: WriteResults>(&self, config: &Config, ex: &Experiment, db: &DB) -> Result<()> {
         match self.step {
             TaskStep::Prepare => self.run_prepare(config, ex, db),
-            TaskStep::BuildAndTest { ref tc, quiet } => {
-                self.run_build_and_test(config, ex, tc, db, quiet)
-            }
+            TaskStep::BuildAndTest {
+                ref tc,
+                quiet,
+                ref test_args,
+            } => self.run_build_and_test(config, ex, tc, db, quiet, test_args),
             TaskStep::BuildOnly { ref tc, quiet } => self.run_build_only(config, ex, tc, db, quiet),
             TaskStep::CheckOnly { ref tc, quiet } => self.run_check_only(config, ex, tc, db, quiet),
             TaskStep::UnstableFeatures { ref tc } => self.run_unstable_features(config, ex, db, tc),
+            TaskStep::Clippy { ref tc, quiet } => self.run_clippy(config, ex, tc, db, quiet),
         }
     }
 
@@ -143,6 +204,7 @@ impl Task {
         tc: &Toolchain,
         db: &DB,
         quiet: bool,
+        test_args: &[String],
     ) -> Result<()> {
         ex_run::run_test(
             config,
@@ -151,8 +213,11 @@ impl Task {
             tc,
             &self.krate,
             db,
+            self.is_forced(ex),
             quiet,
-            ex_run::test_build_and_test,
+            |config, ex, source_path, tc, quiet| {
+                ex_run::test_build_and_test(config, ex, source_path, tc, quiet, test_args)
+            },
         ).map(|_| ())
     }
 
@@ -171,6 +236,7 @@ impl Task {
             tc,
             &self.krate,
             db,
+            self.is_forced(ex),
             quiet,
             ex_run::test_build_only,
         ).map(|_| ())
@@ -191,11 +257,33 @@ impl Task {
             tc,
             &self.krate,
             db,
+            self.is_forced(ex),
             quiet,
             ex_run::test_check_only,
         ).map(|_| ())
     }
 
+    fn run_clippy<DB: WriteResults>(
+        &self,
+        config: &Config,
+        ex: &Experiment,
+        tc: &Toolchain,
+        db: &DB,
+        quiet: bool,
+    ) -> Result<()> {
+        ex_run::run_test(
+            config,
+            "linting",
+            ex,
+            tc,
+            &self.krate,
+            db,
+            self.is_forced(ex),
+            quiet,
+            ex_run::test_clippy,
+        ).map(|_| ())
+    }
+
     fn run_unstable_features<DB: WriteResults>(
         &self,
         config: &Config,
@@ -210,6 +298,7 @@ impl Task {
             tc,
             &self.krate,
             db,
+            self.is_forced(ex),
             false,
             ex_run::test_find_unstable_features,
         ).map(|_| ())
@@ -1,10 +1,13 @@
 use config::Config;
 use dirs::{CARGO_HOME, RUSTUP_HOME, TARGET_DIR};
 use dl;
-use docker::{ContainerBuilder, MountPerms, IMAGE_NAME};
+use docker::{MountPerms, IMAGE_NAME};
 use errors::*;
 use ex::Experiment;
+use registry;
+use results;
 use run::RunCommand;
+use sandbox::{self, Limits, Mount, Sandbox, SandboxCommand};
 use std::env::consts::EXE_SUFFIX;
 use std::fs::{self, File};
 use std::io;
@@ -15,6 +18,12 @@ use util;
 
 const RUSTUP_BASE_URL: &str = "https://static.rust-lang.org/rustup/dist";
 
+/// Path the pinned crates.io index is mounted to inside the containers
+pub const INDEX_SNAPSHOT_MOUNT: &str = "/index-snapshot";
+
+/// Environment variable used by Cargo to authenticate with the custom registry of an experiment
+const REGISTRY_TOKEN_ENV: &str = "CARGO_REGISTRIES_CRATER_REGISTRY_TOKEN";
+
 pub fn ex_target_dir(ex_name: &str) -> PathBuf {
     TARGET_DIR.join(ex_name)
 }
@@ -32,6 +41,7 @@ lazy_static! {
         source: ToolchainSource::Dist {
             name: "stable".to_string()
         },
+        image: None,
     };
 }
 
@@ -42,9 +52,15 @@ lazy_static! {
         source: ToolchainSource::Dist {
             name: "beta".to_string()
         },
+        image: None,
     };
 }
 
+string_enum!(pub enum ToolchainKind {
+    Dist => "dist",
+    CI => "ci",
+});
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone)]
 #[serde(rename_all = "kebab-case", tag = "type")]
 pub enum ToolchainSource {
@@ -61,15 +77,28 @@ pub enum ToolchainSource {
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone)]
 pub struct Toolchain {
     pub source: ToolchainSource,
+    /// Docker image used to run the builds, instead of the default one
+    #[serde(default)]
+    pub image: Option<String>,
 }
 
 impl Toolchain {
     pub fn prepare(&self) -> Result<()> {
+        self.prepare_with_components(&[])
+    }
+
+    /// Install the toolchain along with the additional rustup components.
+    pub fn prepare_with_components(&self, components: &[String]) -> Result<()> {
         init_rustup()?;
 
         match self.source {
-            ToolchainSource::Dist { ref name } => init_toolchain_from_dist(name)?,
-            ToolchainSource::CI { ref sha, .. } => init_toolchain_from_ci(true, sha)?,
+            ToolchainSource::Dist { ref name } => {
+                init_toolchain_from_dist(name)?;
+                if !components.is_empty() {
+                    add_components_from_dist(name, components)?;
+                }
+            }
+            ToolchainSource::CI { ref sha, .. } => init_toolchain_from_ci(true, sha, components)?,
         }
 
         self.prep_offline_registry()?;
@@ -77,6 +106,17 @@ impl Toolchain {
         Ok(())
     }
 
+    pub fn kind(&self) -> ToolchainKind {
+        match self.source {
+            ToolchainSource::Dist { .. } => ToolchainKind::Dist,
+            ToolchainSource::CI { .. } => ToolchainKind::CI,
+        }
+    }
+
+    pub fn image(&self) -> &str {
+        self.image.as_ref().map(|i| i.as_str()).unwrap_or(IMAGE_NAME)
+    }
+
     pub fn rustup_name(&self) -> String {
         match self.source {
             ToolchainSource::Dist { ref name } => name.clone(),
@@ -106,12 +146,35 @@ impl Toolchain {
         cargo_state: CargoState,
         quiet: bool,
         unstable_cargo: bool,
+    ) -> Result<()> {
+        self.run_cargo_in(
+            &*sandbox::configured(config),
+            config,
+            ex,
+            source_dir,
+            args,
+            cargo_state,
+            quiet,
+            unstable_cargo,
+        )
+    }
+
+    /// Run cargo inside the provided sandbox.
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+    pub fn run_cargo_in(
+        &self,
+        sandbox: &Sandbox,
+        config: &Config,
+        ex: &Experiment,
+        source_dir: &Path,
+        args: &[&str],
+        cargo_state: CargoState,
+        quiet: bool,
+        unstable_cargo: bool,
     ) -> Result<()> {
         let toolchain_name = self.rustup_name();
         let ex_target_dir = self.target_dir(&ex.name);
 
-        fs::create_dir_all(&ex_target_dir)?;
-
         let toolchain_arg = "+".to_string() + &toolchain_name;
         let mut full_args = vec!["cargo", &*toolchain_arg];
         full_args.extend_from_slice(args);
@@ -126,29 +189,118 @@ impl Toolchain {
             CargoState::Unlocked => MountPerms::ReadWrite,
         };
 
-        let mut container = ContainerBuilder::new(IMAGE_NAME)
-            // Setup all the mount points
-            .mount(source_dir.into(), "/source", perm)
-            .mount(ex_target_dir, "/target", MountPerms::ReadWrite)
-            .mount(Path::new(&*CARGO_HOME).into(), "/cargo-home", perm)
-            .mount(Path::new(&*RUSTUP_HOME).into(), "/rustup-home", MountPerms::ReadOnly)
-            // Add environment variables
-            .env("USER_ID", user_id().to_string())
-            .env("CMD", full_args.join(" "))
-            .env("CARGO_INCREMENTAL", "0".to_string())
-            .env("RUST_BACKTRACE", "full".to_string())
-            .env("RUSTFLAGS", format!("--cap-lints={}", ex.cap_lints.to_str()))
-            // Add some limits to the container
-            .memory_limit(config.sandbox.memory_limit);
+        let (mut cmd, mounts, mut limits) =
+            self.sandbox_command(config, ex, source_dir, ex_target_dir, perm, full_args.join(" "));
 
         if enable_unstable_cargo_features {
-            container = container.env(
+            cmd.env.push((
                 "__CARGO_TEST_CHANNEL_OVERRIDE_DO_NOT_USE_THIS",
                 "nightly".to_string(),
-            );
+            ));
+        }
+
+        let offline = match cargo_state {
+            CargoState::Locked => config.sandbox.offline_builds,
+            CargoState::Unlocked => false,
+        };
+        if offline {
+            limits.networking = false;
+        }
+
+        let (success, stderr) = sandbox.run(&cmd, &mounts, &limits, quiet)?;
+        if !success {
+            if offline {
+                check_offline_failure(&stderr)?;
+            }
+            bail!("cargo failed");
+        }
+        Ok(())
+    }
+
+    /// Run an arbitrary shell command in the source directory, inside the sandbox.
+    pub fn run_command(
+        &self,
+        config: &Config,
+        ex: &Experiment,
+        source_dir: &Path,
+        command: &str,
+        quiet: bool,
+    ) -> Result<()> {
+        let ex_target_dir = self.target_dir(&ex.name);
+
+        info!("running: {}", command);
+        let script = format!("cd /source && {}", command).replace('\'', "'\\''");
+
+        let (cmd, mounts, limits) = self.sandbox_command(
+            config,
+            ex,
+            source_dir,
+            ex_target_dir,
+            MountPerms::ReadWrite,
+            format!("sh -c '{}'", script),
+        );
+        let (success, _) = sandbox::configured(config).run(&cmd, &mounts, &limits, quiet)?;
+        if !success {
+            bail!("command `{}` failed", command);
+        }
+        Ok(())
+    }
+
+    fn sandbox_command(
+        &self,
+        config: &Config,
+        ex: &Experiment,
+        source_dir: &Path,
+        target_dir: PathBuf,
+        perm: MountPerms,
+        cmd: String,
+    ) -> (SandboxCommand, Vec<Mount>, Limits) {
+        let mount = |host_path: PathBuf, sandbox_path, perm| Mount {
+            host_path,
+            sandbox_path,
+            perm,
+        };
+
+        let mut mounts = Vec::new();
+        if let Some(ref commit) = ex.index_commit {
+            mounts.push(mount(
+                registry::index_snapshot_dir(commit),
+                INDEX_SNAPSHOT_MOUNT,
+                MountPerms::ReadOnly,
+            ));
         }
+        mounts.push(mount(source_dir.into(), "/source", perm));
+        mounts.push(mount(target_dir, "/target", MountPerms::ReadWrite));
+        mounts.push(mount(Path::new(&*CARGO_HOME).into(), "/cargo-home", perm));
+        mounts.push(mount(
+            Path::new(&*RUSTUP_HOME).into(),
+            "/rustup-home",
+            MountPerms::ReadOnly,
+        ));
+
+        let cmd = SandboxCommand {
+            image: self.image().to_string(),
+            cmd,
+            env: vec![
+                ("USER_ID", user_id().to_string()),
+                ("CARGO_INCREMENTAL", "0".to_string()),
+                ("RUST_BACKTRACE", "full".to_string()),
+                ("RUSTFLAGS", format!("--cap-lints={}", ex.cap_lints.to_str())),
+            ],
+            secret_env: ex
+                .registry_token
+                .iter()
+                .map(|token| (REGISTRY_TOKEN_ENV, token.clone()))
+                .collect(),
+            tag_streams: config.sandbox.tag_output_streams,
+        };
 
-        container.run(quiet)
+        let limits = Limits {
+            memory: Some(config.sandbox.memory_limit),
+            networking: true,
+        };
+
+        (cmd, mounts, limits)
     }
 
     pub fn prep_offline_registry(&self) -> Result<()> {
@@ -176,6 +328,8 @@ impl Toolchain {
     }
 }
 
+/// The image isn't part of the name, since the name is used in the paths and database keys of
+/// the results: it's serialized separately.
 impl ToString for Toolchain {
     fn to_string(&self) -> String {
         match self.source {
@@ -192,7 +346,21 @@ impl ToString for Toolchain {
 impl FromStr for Toolchain {
     type Err = Error;
 
-    fn from_str(s: &str) -> Result<Self> {
+    fn from_str(input: &str) -> Result<Self> {
+        let mut parts = input.split('+');
+        let s = parts.next().unwrap();
+
+        let mut image = None;
+        for flag in parts {
+            let mut segments = flag.splitn(2, '=');
+            match (segments.next(), segments.next()) {
+                (Some("image"), Some(value)) if !value.is_empty() && image.is_none() => {
+                    image = Some(value.to_string());
+                }
+                _ => return Err(ErrorKind::InvalidToolchainFlag(flag.to_string()).into()),
+            }
+        }
+
         let source = if let Some(hash_idx) = s.find('#') {
             let (source_name, sha_with_hash) = s.split_at(hash_idx);
 
@@ -214,7 +382,7 @@ impl FromStr for Toolchain {
             }
         };
 
-        Ok(Toolchain { source })
+        Ok(Toolchain { source, image })
     }
 }
 
@@ -305,7 +473,20 @@ fn init_toolchain_from_dist(toolchain: &str) -> Result<()> {
     })
 }
 
-fn init_toolchain_from_ci(alt: bool, sha: &str) -> Result<()> {
+fn add_components_from_dist(toolchain: &str, components: &[String]) -> Result<()> {
+    info!("installing components {} for {}", components.join(", "), toolchain);
+    let mut args = vec!["component", "add", "--toolchain", toolchain];
+    args.extend(components.iter().map(|c| c.as_str()));
+
+    util::try_hard(|| {
+        RunCommand::new(&installed_binary("rustup"), &args)
+            .local_rustup()
+            .run()
+            .chain_err(|| format!("unable to install the components of {}", toolchain))
+    })
+}
+
+fn init_toolchain_from_ci(alt: bool, sha: &str, components: &[String]) -> Result<()> {
     // Ensure rustup-toolchain-install-master is installed
     let bin = installed_binary("rustup-toolchain-install-master");
     if !Path::new(&bin).exists() {
@@ -327,6 +508,10 @@ fn init_toolchain_from_ci(alt: bool, sha: &str) -> Result<()> {
     }
 
     let mut args = vec![sha, "-c", "cargo"];
+    for component in components {
+        args.push("-c");
+        args.push(component);
+    }
     if alt {
         args.push("--alt");
     }
@@ -354,10 +539,31 @@ fn user_id() -> u32 {
     unimplemented!();
 }
 
+/// Detect builds which failed because a dependency wasn't fetched during the prepare step, so the
+/// failure isn't blamed on the crate.
+fn check_offline_failure(stderr: &[String]) -> Result<()> {
+    if let Some(line) = stderr.iter().find(|line| results::is_fetch_failure(line)) {
+        bail!(ErrorKind::PrepareIncomplete(line.trim().to_string()));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Toolchain, ToolchainSource};
+    use super::{
+        check_offline_failure, CargoState, Toolchain, ToolchainSource, MAIN_TOOLCHAIN,
+        REGISTRY_TOKEN_ENV, TEST_TOOLCHAIN,
+    };
+    use config::Config;
+    use docker::{MountPerms, IMAGE_NAME};
+    use errors::*;
+    use ex::{ExCapLints, ExMode, Experiment};
+    use sandbox::{DockerSandbox, Limits, Mount, Sandbox, SandboxCommand};
+    use serde_json;
+    use std::path::{Path, PathBuf};
     use std::str::FromStr;
+    use std::sync::Mutex;
+    use util::Secret;
 
     #[test]
     fn test_string_repr() {
@@ -366,6 +572,7 @@ mod tests {
                 $(
                     test_from_str!($str => Toolchain {
                         source: $source,
+                        image: None,
                     });
                 )*
             };
@@ -400,9 +607,210 @@ mod tests {
             },
         };
 
+        // Test reprs with a custom image, which is left out when dumping the toolchain
+        let custom = Toolchain::from_str("stable+image=crater-old").unwrap();
+        assert_eq!(
+            custom,
+            Toolchain {
+                source: ToolchainSource::Dist {
+                    name: "stable".into(),
+                },
+                image: Some("crater-old".into()),
+            }
+        );
+        assert_eq!(custom.to_string(), "stable");
+        let sha = "0000000000000000000000000000000000000000";
+        let custom = Toolchain::from_str(&format!("try#{}+image=foo/bar", sha)).unwrap();
+        assert_eq!(
+            custom,
+            Toolchain {
+                source: ToolchainSource::CI {
+                    sha: sha.into(),
+                    try: true,
+                },
+                image: Some("foo/bar".into()),
+            }
+        );
+        assert_eq!(custom.to_string(), format!("try#{}", sha));
+
         // Test invalid reprs
         assert!(Toolchain::from_str("").is_err());
+        assert!(Toolchain::from_str("stable+image=").is_err());
+        assert!(Toolchain::from_str("stable+foo=bar").is_err());
+        assert!(Toolchain::from_str("stable+image=a+image=b").is_err());
         assert!(Toolchain::from_str("master#").is_err());
         assert!(Toolchain::from_str("foo#0000000000000000000000000000000000000000").is_err());
     }
+
+    #[test]
+    fn test_image() {
+        let default = Toolchain::from_str("stable").unwrap();
+        assert_eq!(default.image(), IMAGE_NAME);
+
+        let custom = Toolchain::from_str("stable+image=crater-old").unwrap();
+        assert_eq!(custom.image(), "crater-old");
+    }
+
+    #[test]
+    fn test_check_offline_failure() {
+        let lines = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+
+        // Builds with all the dependencies prefetched are not affected, even if they fail
+        assert!(
+            check_offline_failure(&lines(&[
+                "   Compiling lazy_static v1.0.0",
+                "    Finished dev [unoptimized + debuginfo] target(s) in 1.2 secs",
+            ])).is_ok()
+        );
+        assert!(
+            check_offline_failure(&lines(&[
+                "   Compiling foo v0.1.0 (file:///source)",
+                "error[E0425]: cannot find value `bar` in this scope",
+            ])).is_ok()
+        );
+
+        // Only the errors emitted by Cargo are considered
+        assert!(
+            check_offline_failure(&lines(&[
+                "warning: failed to download the optional test data",
+                "thread 'main' panicked at 'failed to fetch https://example.com'",
+            ])).is_ok()
+        );
+
+        // Builds missing a dependency fail with a specific error
+        let err = check_offline_failure(&lines(&[
+            "error: failed to download `lazy_static v1.0.0`",
+            "",
+            "Caused by:",
+            "  attempting to make an HTTP request, but --frozen was specified",
+        ])).unwrap_err();
+        match *err.kind() {
+            ErrorKind::PrepareIncomplete(ref details) => assert_eq!(
+                details.as_str(),
+                "error: failed to download `lazy_static v1.0.0`"
+            ),
+            _ => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_registry_token() {
+        let mut ex = Experiment {
+            name: "foo".to_string(),
+            crates: Vec::new(),
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: Some("https://example.com/index".into()),
+            registry_token: Some(Secret::new("hunter2".into())),
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        ex.validate().unwrap();
+
+        let (cmd, mounts, limits) = MAIN_TOOLCHAIN.sandbox_command(
+            &Config::default(),
+            &ex,
+            Path::new("source"),
+            "target".into(),
+            MountPerms::ReadOnly,
+            "cargo build".into(),
+        );
+        let args = DockerSandbox::container(&cmd, &mounts, &limits).create_args();
+        let env = format!("{}=hunter2", REGISTRY_TOKEN_ENV);
+        let pos = args.iter().position(|arg| *arg == env).unwrap();
+        assert_eq!(args[pos - 1], "-e");
+
+        // The token is never shown when the experiment is debug-printed or stored
+        assert!(!format!("{:?}", ex.registry_token).contains("hunter2"));
+        let json = serde_json::to_string(&ex).unwrap();
+        assert!(!json.contains("hunter2"));
+        let loaded: Experiment = serde_json::from_str(&json).unwrap();
+        assert!(loaded.registry_token.is_none());
+
+        // A token without a custom registry is rejected
+        ex.registry = None;
+        assert!(ex.validate().is_err());
+    }
+
+    #[derive(Default)]
+    struct StubSandbox {
+        runs: Mutex<Vec<(String, Vec<Mount>, Limits)>>,
+    }
+
+    impl Sandbox for StubSandbox {
+        fn run(
+            &self,
+            cmd: &SandboxCommand,
+            mounts: &[Mount],
+            limits: &Limits,
+            _quiet: bool,
+        ) -> Result<(bool, Vec<String>)> {
+            self.runs
+                .lock()
+                .unwrap()
+                .push((cmd.cmd.clone(), mounts.to_vec(), limits.clone()));
+            Ok((true, Vec::new()))
+        }
+    }
+
+    #[test]
+    fn test_custom_sandbox() {
+        let ex = Experiment {
+            name: "foo".to_string(),
+            crates: Vec::new(),
+            toolchains: [MAIN_TOOLCHAIN.clone(), TEST_TOOLCHAIN.clone()],
+            mode: ExMode::BuildAndTest,
+            cap_lints: ExCapLints::Forbid,
+            registry: None,
+            registry_token: None,
+            force_steps: Vec::new(),
+            allow_same_toolchains: false,
+            pre_build: None,
+            capture_metadata: false,
+            components: Vec::new(),
+            index_commit: None,
+            baseline: None,
+            expected_regressions: Vec::new(),
+            control_index: 0,
+        };
+        let mut config = Config::default();
+        config.sandbox.offline_builds = true;
+
+        let sandbox = StubSandbox::default();
+        MAIN_TOOLCHAIN
+            .run_cargo_in(
+                &sandbox,
+                &config,
+                &ex,
+                Path::new("source"),
+                &["build", "--frozen"],
+                CargoState::Locked,
+                false,
+                false,
+            )
+            .unwrap();
+
+        let runs = sandbox.runs.lock().unwrap();
+        assert_eq!(runs.len(), 1);
+        let (ref cmd, ref mounts, ref limits) = runs[0];
+        assert_eq!(cmd, "cargo +stable build --frozen");
+
+        let mount = |path: &str| mounts.iter().find(|m| m.sandbox_path == path).unwrap();
+        assert_eq!(mount("/source").host_path, PathBuf::from("source"));
+        assert_eq!(mount("/source").perm, MountPerms::ReadOnly);
+        assert_eq!(mount("/target").host_path, MAIN_TOOLCHAIN.target_dir("foo"));
+        assert_eq!(mount("/target").perm, MountPerms::ReadWrite);
+
+        // Locked builds don't have network access when offline builds are enabled
+        assert!(!limits.networking);
+        assert_eq!(limits.memory, Some(config.sandbox.memory_limit));
+    }
 }
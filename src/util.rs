@@ -9,6 +9,8 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::result::Result as StdResult;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use std::sync::{Arc, Condvar, Mutex, Once, ONCE_INIT};
 use std::thread;
 use std::time::Duration;
 
@@ -203,6 +205,26 @@ pub fn from_hex(input: &str) -> Result<Vec<u8>> {
     }
 }
 
+/// Generate a random (version 4) UUID, formatted as a lowercase hyphenated string.
+pub fn random_uuid() -> String {
+    let mut bytes: [u8; 16] = ::rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Size {
     Bytes(usize),
@@ -212,6 +234,18 @@ pub enum Size {
     Terabytes(usize),
 }
 
+impl Size {
+    pub fn to_bytes(&self) -> u64 {
+        match self {
+            Size::Bytes(count) => *count as u64,
+            Size::Kilobytes(count) => *count as u64 * 1024,
+            Size::Megabytes(count) => *count as u64 * 1024 * 1024,
+            Size::Gigabytes(count) => *count as u64 * 1024 * 1024 * 1024,
+            Size::Terabytes(count) => *count as u64 * 1024 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
 impl fmt::Display for Size {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -276,9 +310,167 @@ impl Serialize for Size {
     }
 }
 
+/// Counting semaphore, allowing only a limited number of threads to hold a permit at once.
+#[derive(Default)]
+pub struct Semaphore {
+    held: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    /// Wait until less than `limit` permits are held, and then acquire one. The permit is
+    /// released when the returned guard is dropped.
+    pub fn acquire(&self, limit: usize) -> SemaphoreGuard {
+        let mut held = self.held.lock().unwrap();
+        while *held >= limit {
+            held = self.released.wait(held).unwrap();
+        }
+        *held += 1;
+
+        SemaphoreGuard { semaphore: self }
+    }
+
+    /// Same as `acquire`, but stop waiting and return an error as soon as `cancel` is triggered.
+    pub fn acquire_cancellable(
+        &self,
+        limit: usize,
+        cancel: &CancelToken,
+    ) -> Result<SemaphoreGuard> {
+        let mut held = self.held.lock().unwrap();
+        while *held >= limit {
+            if cancel.is_cancelled() {
+                bail!("cancelled while waiting for a permit");
+            }
+            held = self
+                .released
+                .wait_timeout(held, Duration::from_millis(CANCEL_POLL_INTERVAL_MS))
+                .unwrap()
+                .0;
+        }
+        *held += 1;
+
+        Ok(SemaphoreGuard { semaphore: self })
+    }
+}
+
+pub struct SemaphoreGuard<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl<'a> Drop for SemaphoreGuard<'a> {
+    fn drop(&mut self) {
+        *self.semaphore.held.lock().unwrap() -= 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+/// How often waits that can be cancelled check whether they should stop.
+const CANCEL_POLL_INTERVAL_MS: u64 = 500;
+
+/// Set by the signal handler installed by `CancelToken::on_signals`.
+static TERMINATION_REQUESTED: AtomicBool = ATOMIC_BOOL_INIT;
+static INSTALL_SIGNAL_HANDLERS: Once = ONCE_INIT;
+
+#[cfg(unix)]
+extern "C" fn handle_termination_signal(signal: ::libc::c_int) {
+    // A second signal kills the process as usual, in case the cleanup is stuck
+    if TERMINATION_REQUESTED.swap(true, Ordering::SeqCst) {
+        unsafe {
+            ::libc::signal(signal, ::libc::SIG_DFL);
+            ::libc::raise(signal);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn install_signal_handlers() {
+    let handler = handle_termination_signal as extern "C" fn(::libc::c_int);
+    unsafe {
+        ::libc::signal(::libc::SIGINT, handler as ::libc::sighandler_t);
+        ::libc::signal(::libc::SIGTERM, handler as ::libc::sighandler_t);
+    }
+}
+
+#[cfg(windows)]
+fn install_signal_handlers() {}
+
+/// Shared flag used to ask a long running operation to stop as soon as possible.
+#[derive(Default, Clone)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    on_signals: bool,
+}
+
+impl CancelToken {
+    /// Create a token which is also cancelled when the process receives SIGINT or SIGTERM, so the
+    /// current run can stop and clean up after itself.
+    pub fn on_signals() -> Self {
+        INSTALL_SIGNAL_HANDLERS.call_once(install_signal_handlers);
+        CancelToken {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            on_signals: true,
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+            || (self.on_signals && TERMINATION_REQUESTED.load(Ordering::SeqCst))
+    }
+}
+
+/// Sensitive value, such as a token, which is hidden when the value is debug-printed. It can't be
+/// serialized, so it never ends up in the stored experiments or in the reports, but it's kept
+/// as-is in memory and must not be displayed anywhere.
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret({})", ::run::REDACTED)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Size;
+    use super::{CancelToken, Semaphore, Size};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_semaphore_acquire_cancellable() {
+        let semaphore = Semaphore::default();
+        let cancel = CancelToken::default();
+
+        let permit = semaphore.acquire_cancellable(1, &cancel).unwrap();
+        let canceller = cancel.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            canceller.cancel();
+        });
+
+        // The permit is never released, so only the cancellation can stop the wait
+        assert!(semaphore.acquire_cancellable(1, &cancel).is_err());
+        handle.join().unwrap();
+
+        drop(permit);
+        assert!(semaphore
+            .acquire_cancellable(1, &CancelToken::default())
+            .is_ok());
+    }
 
     #[test]
     fn test_size() {